@@ -0,0 +1,451 @@
+/// Show what `execute_goal` would do without touching the device.
+///
+/// The recipes are resolved in the same order a real run uses, every payload
+/// is expanded and shown with the command or port it would go to, and the
+/// success/failure markers are evaluated against sample output supplied by
+/// the user, e.g. transcripts captured from a real device.
+use super::preconditions::DeviceContext;
+use super::sequence::{self, Variables};
+use super::{
+    autodetect_diag_port, check_markers, goal_string, plan, recipes, stats_label, step_order,
+    ExploitRecipe, ExploitStep, FuzzGoal, StepKind,
+};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Sample device output keyed by `"<recipe name>#<step>"` (1-based) or by
+/// the step's expanded payload, which applies to every recipe.
+pub type Samples = BTreeMap<String, String>;
+
+/// Read a JSON object mapping sample keys to output text.
+pub fn load_samples(path: &Path) -> Result<Samples, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| {
+        format!(
+            "{}: expected a JSON object of strings at line {} column {}: {}",
+            path.display(),
+            e.line(),
+            e.column(),
+            e
+        )
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Succeeds(usize),
+    Fails,
+    /// A step that decides the outcome has no sample output.
+    Unknown,
+}
+
+struct Simulation<'a> {
+    serial: &'a str,
+    diag_port: Option<String>,
+    samples: &'a Samples,
+    out: String,
+}
+
+impl Simulation<'_> {
+    /// Where the step would be sent.
+    fn target(&self, step: &ExploitStep) -> String {
+        let port = || {
+            self.diag_port
+                .as_deref()
+                .map(|p| format!("diag port {}", p))
+                .unwrap_or_else(|| "no diag port detected".into())
+        };
+        match &step.kind {
+            StepKind::AdbShell => format!("adb -s {} shell", self.serial),
+            StepKind::Fastboot => match super::steps::fastboot_args(&step.payload) {
+                Ok(args) => format!("fastboot -s {} {:?}", self.serial, args),
+                Err(e) => format!("fastboot -s {} (invalid: {})", self.serial, e),
+            },
+            StepKind::AtCommand | StepKind::RawDiag => port(),
+            StepKind::Reboot(_) => format!("adb or fastboot reboot of {}", self.serial),
+            StepKind::WaitForMode { .. } => format!("polling {}", self.serial),
+            StepKind::PushFile { .. } => format!("adb -s {} push", self.serial),
+            StepKind::Sleep { .. } => "local timer".into(),
+            StepKind::AssertProp { .. } => format!("adb -s {} shell getprop", self.serial),
+        }
+    }
+
+    fn sample(&self, recipe: &ExploitRecipe, number: usize, description: &str) -> Option<&str> {
+        self.samples
+            .get(&format!("{}#{}", recipe.name, number))
+            .or_else(|| self.samples.get(description))
+            .map(String::as_str)
+    }
+
+    /// Print one step; returns its sample output when the markers pass,
+    /// `Some(Err)` when they fail or the payload cannot be expanded, and
+    /// `None` without a sample.
+    fn step(
+        &mut self,
+        recipe: &ExploitRecipe,
+        number: usize,
+        step: &ExploitStep,
+        vars: &Variables,
+    ) -> Option<Result<String, String>> {
+        let expanded = match sequence::expand_step(step, vars) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                self.out.push_str(&format!(
+                    "  Step {} {}\n    cannot expand: {}\n",
+                    number,
+                    sequence::describe(step),
+                    e
+                ));
+                return Some(Err(e));
+            }
+        };
+        let description = sequence::describe(&expanded);
+        self.out.push_str(&format!(
+            "  Step {} {}\n    target: {}\n",
+            number,
+            description,
+            self.target(&expanded)
+        ));
+        let sample = self.sample(recipe, number, &description)?.to_string();
+        let result = check_markers(step, &sample).map(|()| sample);
+        match &result {
+            Ok(_) => self.out.push_str("    sample: markers pass\n"),
+            Err(e) => self.out.push_str(&format!(
+                "    sample: {}\n",
+                e.lines().next().unwrap_or("").trim_end_matches(':')
+            )),
+        }
+        Some(result)
+    }
+
+    fn no_sample(&mut self) {
+        self.out
+            .push_str("    sample: none, markers not evaluated\n");
+    }
+
+    fn sequential(&mut self, recipe: &ExploitRecipe) -> Verdict {
+        let mut vars = Variables::new();
+        let mut verdict = Verdict::Succeeds(0);
+        for (i, step) in recipe.steps.iter().enumerate() {
+            match self.step(recipe, i + 1, step, &vars) {
+                Some(Ok(out)) => {
+                    if let Err(e) = sequence::capture(step, &out, &mut vars) {
+                        self.out.push_str(&format!("    sample: {}\n", e));
+                        self.rollback(recipe, &vars);
+                        return Verdict::Fails;
+                    }
+                }
+                Some(Err(_)) => {
+                    self.rollback(recipe, &vars);
+                    return Verdict::Fails;
+                }
+                None => {
+                    self.no_sample();
+                    verdict = Verdict::Unknown;
+                }
+            }
+        }
+        verdict
+    }
+
+    fn rollback(&mut self, recipe: &ExploitRecipe, vars: &Variables) {
+        if recipe.on_failure.is_empty() {
+            return;
+        }
+        self.out.push_str("  Rollback:\n");
+        for step in &recipe.on_failure {
+            let line = match sequence::expand_step(step, vars) {
+                Ok(expanded) => format!(
+                    "    {} -> {}\n",
+                    sequence::describe(&expanded),
+                    self.target(&expanded)
+                ),
+                Err(e) => format!("    {} -- cannot expand: {}\n", sequence::describe(step), e),
+            };
+            self.out.push_str(&line);
+        }
+    }
+
+    fn alternatives(&mut self, recipe: &ExploitRecipe, order: &[usize]) -> Verdict {
+        let mut verdict = Verdict::Fails;
+        for &index in order {
+            match self.step(recipe, index + 1, &recipe.steps[index], &Variables::new()) {
+                Some(Ok(_)) if verdict == Verdict::Fails => verdict = Verdict::Succeeds(index),
+                Some(_) => {}
+                None => {
+                    self.no_sample();
+                    if verdict == Verdict::Fails {
+                        verdict = Verdict::Unknown;
+                    }
+                }
+            }
+        }
+        verdict
+    }
+}
+
+/// Describe how `execute_goal` would handle `goal` on this device and
+/// evaluate markers against `samples`. Nothing is sent to the device and the
+/// knowledge base is left untouched.
+pub fn dry_run(
+    serial: &str,
+    goal: FuzzGoal,
+    fingerprint: &str,
+    device: &DeviceContext,
+    diag_port_hint: Option<&str>,
+    samples: &Samples,
+) -> String {
+    let set = recipes::load_recipes();
+    let mut notes = String::new();
+    let candidates = plan(&set, &goal, fingerprint, device, &mut notes);
+    let mut sim = Simulation {
+        serial,
+        diag_port: diag_port_hint
+            .map(str::to_string)
+            .or_else(autodetect_diag_port),
+        samples,
+        out: format!(
+            "Dry run of {} for {} -- nothing is sent to the device.\n{}",
+            goal_string(&goal),
+            fingerprint,
+            notes
+        ),
+    };
+
+    let mut result = None;
+    for (n, (recipe, stats)) in candidates.iter().enumerate() {
+        sim.out.push_str(&format!(
+            "{}. {}{}{}\n",
+            n + 1,
+            recipe.name,
+            if recipe.sequential {
+                " [sequential]"
+            } else {
+                ""
+            },
+            stats_label(stats.as_ref())
+        ));
+        let verdict = if recipe.sequential {
+            sim.sequential(recipe)
+        } else {
+            sim.alternatives(recipe, &step_order(recipe, stats.as_ref()))
+        };
+        if result.is_none() && verdict != Verdict::Fails {
+            result = Some((recipe.name.as_str(), verdict));
+        }
+    }
+
+    let summary = match result {
+        _ if candidates.is_empty() => "No matching recipe would run.".to_string(),
+        Some((name, Verdict::Succeeds(step))) if step > 0 => {
+            format!(
+                "With these samples, {} would succeed at step {}.",
+                name,
+                step + 1
+            )
+        }
+        Some((name, Verdict::Succeeds(_))) => {
+            format!("With these samples, {} would succeed.", name)
+        }
+        Some((name, _)) => format!(
+            "Outcome depends on {}, which has steps without sample output.",
+            name
+        ),
+        None => "With these samples, all recipes would fail.".to_string(),
+    };
+    sim.out.push_str(&summary);
+    sim.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive_engine::knowledge::MOCK_KB_PATH;
+    use crate::adaptive_engine::preconditions::DeviceMode;
+    use crate::adaptive_engine::recipes::MOCK_RECIPES_DIR;
+    use std::fs;
+
+    const SEQUENCE: &str = r#"{
+        "goal": "EnableDiagPort",
+        "name": "USB Sequence",
+        "sequential": true,
+        "steps": [
+            {"kind": "AdbShell", "payload": "getprop sys.usb.config",
+             "success_markers": [], "failure_markers": [], "captures": {"old": "(\\S+)"}},
+            {"kind": "AdbShell", "payload": "setprop sys.usb.config diag,adb",
+             "success_markers": [], "failure_markers": ["denied"]},
+            {"kind": "Fastboot", "payload": "oem config set 'usb diag'",
+             "success_markers": ["OKAY"], "failure_markers": []}
+        ],
+        "on_failure": [
+            {"kind": "AdbShell", "payload": "setprop sys.usb.config ${old}",
+             "success_markers": [], "failure_markers": []}
+        ]
+    }"#;
+
+    struct MockGuard;
+    impl Drop for MockGuard {
+        fn drop(&mut self) {
+            MOCK_RECIPES_DIR.with(|m| *m.borrow_mut() = None);
+            MOCK_KB_PATH.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    fn setup(name: &str) -> std::io::Result<(std::path::PathBuf, MockGuard)> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("usb.json"), SEQUENCE)?;
+        MOCK_RECIPES_DIR.with(|m| *m.borrow_mut() = Some(dir.clone()));
+        MOCK_KB_PATH.with(|m| *m.borrow_mut() = Some(dir.join("kb.json")));
+        Ok((dir, MockGuard))
+    }
+
+    fn device() -> DeviceContext {
+        DeviceContext {
+            manufacturer: "TECNO".into(),
+            modes: vec![DeviceMode::Adb, DeviceMode::Diag],
+            ..Default::default()
+        }
+    }
+
+    fn samples(pairs: &[(&str, &str)]) -> Samples {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// The built-in recipes that apply to `device()` fail on these samples.
+    fn with_failing_builtins(pairs: &[(&str, &str)]) -> Samples {
+        let mut all = samples(pairs);
+        all.insert("USB Config Toggle#1".into(), "Permission denied".into());
+        all.insert("AT+DIAG=1".into(), "ERROR".into());
+        all
+    }
+
+    #[test]
+    fn test_dry_run_failed_sequence_shows_expanded_rollback(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (dir, _guard) = setup("foem_dry_run_rollback")?;
+        let samples = with_failing_builtins(&[
+            ("USB Sequence#1", "mtp,adb\n"),
+            ("setprop sys.usb.config diag,adb", ""),
+            ("USB Sequence#3", "FAILED (remote: unknown command)"),
+        ]);
+        let out = dry_run(
+            "SER",
+            FuzzGoal::EnableDiagPort,
+            "fp",
+            &device(),
+            Some("COM7"),
+            &samples,
+        );
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(
+            out.starts_with("Dry run of EnableDiagPort for fp -- nothing is sent"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(
+                "1. USB Config Toggle\n  Step 1 setprop sys.usb.config diag,adb\n    \
+             target: adb -s SER shell\n    sample: reported failure markers\n"
+            ),
+            "{}",
+            out
+        );
+        assert!(out.contains("2. AT Diag Enable\n  Step 1 AT+DIAG=1\n    target: diag port COM7\n"));
+        assert!(out.contains("3. USB Sequence [sequential]\n"));
+        assert!(
+            out.contains(
+                "  Step 3 oem config set 'usb diag'\n    \
+             target: fastboot -s SER [\"oem\", \"config\", \"set\", \"usb diag\"]\n    \
+             sample: matched no success markers\n  \
+             Rollback:\n    setprop sys.usb.config 'mtp,adb' -> adb -s SER shell\n"
+            ),
+            "{}",
+            out
+        );
+        assert!(
+            out.ends_with("With these samples, all recipes would fail."),
+            "{}",
+            out
+        );
+        assert!(!dir.join("kb.json").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_success_and_missing_samples() -> Result<(), Box<dyn std::error::Error>> {
+        let (dir, _guard) = setup("foem_dry_run_success")?;
+        let passing = with_failing_builtins(&[
+            ("USB Sequence#1", "mtp,adb"),
+            ("USB Sequence#2", ""),
+            ("USB Sequence#3", "OKAY [  0.010s]"),
+        ]);
+        let out = dry_run(
+            "SER",
+            FuzzGoal::EnableDiagPort,
+            "fp",
+            &device(),
+            Some("COM7"),
+            &passing,
+        );
+        assert!(
+            out.ends_with("With these samples, USB Sequence would succeed."),
+            "{}",
+            out
+        );
+
+        // Without the first capture, the rollback cannot be expanded either.
+        let partial = with_failing_builtins(&[("USB Sequence#1", "")]);
+        let out = dry_run(
+            "SER",
+            FuzzGoal::EnableDiagPort,
+            "fp",
+            &device(),
+            Some("COM7"),
+            &partial,
+        );
+        assert!(
+            out.contains(
+                "    sample: capture 'old': /(\\S+)/ did not match\n  Rollback:\n    \
+             setprop sys.usb.config ${old} -- cannot expand: variable 'old' is not set"
+            ),
+            "{}",
+            out
+        );
+
+        let none = Samples::new();
+        let out = dry_run(
+            "SER",
+            FuzzGoal::EnableDiagPort,
+            "fp",
+            &device(),
+            Some("COM7"),
+            &none,
+        );
+        let _ = fs::remove_dir_all(&dir);
+        assert!(out.contains("  Step 1 setprop sys.usb.config diag,adb\n    target: adb -s SER shell\n    sample: none"));
+        assert!(out.ends_with(
+            "Outcome depends on USB Config Toggle, which has steps without sample output."
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_samples_rejects_non_strings() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join("foem_dry_run_samples.json");
+        fs::write(&path, r#"{"USB Sequence#1": 5}"#)?;
+        let err = load_samples(&path).unwrap_err();
+        assert!(err.contains("expected a JSON object of strings"), "{}", err);
+        fs::write(&path, r#"{"USB Sequence#1": "mtp,adb"}"#)?;
+        assert_eq!(load_samples(&path)?.len(), 1);
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+}
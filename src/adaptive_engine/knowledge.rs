@@ -0,0 +1,762 @@
+/// What FOEM has learned about which recipes work on which devices.
+///
+/// Every attempt is recorded per device fingerprint and recipe, successful or
+/// not, so candidates can be ranked by how often they worked instead of by
+/// whichever happened to work last. Statistics are tied to a hash of the
+/// recipe they were recorded against and are ignored once the recipe changes.
+///
+/// `learned_methods.json` is only changed under an advisory lock and is
+/// replaced atomically, so several FOEM windows (or a crash mid-write) cannot
+/// corrupt it. Knowledge can be shared between workstations as checksummed
+/// bundles.
+use super::ExploitRecipe;
+use crate::exec;
+use crate::features::vault::now_secs;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Version of the `learned_methods.json` layout written by this build.
+///
+/// 1. `learned`: fingerprint -> last successful recipe and step.
+/// 2. `entries`: fingerprint -> recipe -> `RecipeStats`.
+pub const SCHEMA_VERSION: u32 = 2;
+
+const BUNDLE_FORMAT: &str = "foem-knowledge";
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Fingerprint -> recipe name -> statistics.
+pub type Entries = BTreeMap<String, BTreeMap<String, RecipeStats>>;
+
+/// Outcome history of one recipe on one device fingerprint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecipeStats {
+    pub attempts: u32,
+    pub successes: u32,
+    /// Step that worked last time, for recipes whose steps are alternatives.
+    pub step_index: usize,
+    /// Seconds since the Unix epoch of the latest attempt.
+    pub last_seen: u64,
+    pub foem_version: String,
+    /// `recipe_hash` of the recipe these numbers were recorded against;
+    /// empty for entries migrated from schema 1.
+    pub recipe_hash: String,
+}
+
+impl RecipeStats {
+    /// Laplace-smoothed success rate: an untried recipe scores 0.5, one that
+    /// failed once drops below it and steady successes approach 1.0.
+    pub fn confidence(&self) -> f64 {
+        (self.successes as f64 + 1.0) / (self.attempts as f64 + 2.0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KnowledgeBase {
+    pub entries: Entries,
+}
+
+#[derive(Serialize)]
+struct Stored<'a> {
+    version: u32,
+    entries: &'a Entries,
+}
+
+#[derive(Deserialize)]
+struct StoredV2 {
+    entries: Entries,
+}
+
+#[derive(Deserialize)]
+struct LearnedStepV1 {
+    recipe_name: String,
+    step_index: usize,
+}
+
+#[derive(Deserialize)]
+struct StoredV1 {
+    learned: HashMap<String, LearnedStepV1>,
+}
+
+/// Knowledge exported from one workstation for import on another.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KnowledgeBundle {
+    pub format: String,
+    pub version: u32,
+    /// Seconds since the Unix epoch.
+    pub exported_at: u64,
+    pub foem_version: String,
+    /// SHA-256 of `entries` serialized as compact JSON.
+    pub sha256: String,
+    pub entries: Entries,
+}
+
+#[derive(Debug)]
+enum ParseError {
+    /// Written by a newer FOEM; valid, but must not be touched.
+    Newer(String),
+    Invalid(String),
+}
+
+fn entries_checksum(entries: &Entries) -> String {
+    let json = serde_json::to_string(entries).unwrap_or_default();
+    hex::encode(Sha256::digest(json.as_bytes()))
+}
+
+/// Stable identity of a recipe's content; any edit changes it.
+pub fn recipe_hash(recipe: &ExploitRecipe) -> String {
+    let json = serde_json::to_string(recipe).unwrap_or_default();
+    hex::encode(&Sha256::digest(json.as_bytes())[..8])
+}
+
+fn model_of(fingerprint: &str) -> &str {
+    fingerprint.split('|').next().unwrap_or("").trim()
+}
+
+impl KnowledgeBase {
+    /// Parse any supported schema version, migrating older layouts.
+    fn parse(text: &str) -> Result<Self, ParseError> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(|e| {
+            ParseError::Invalid(format!(
+                "invalid JSON at line {} column {}: {}",
+                e.line(),
+                e.column(),
+                e
+            ))
+        })?;
+        let version = match value.get("version") {
+            Some(v) => v
+                .as_u64()
+                .ok_or_else(|| ParseError::Invalid(format!("version is not a number: {}", v)))?,
+            None if value.get("learned").is_some() => 1,
+            // Schema 2 files written before the version field existed.
+            None => 2,
+        };
+        match version {
+            1 => serde_json::from_value::<StoredV1>(value)
+                .map(Self::from_v1)
+                .map_err(|e| ParseError::Invalid(format!("schema 1: {}", e))),
+            2 => serde_json::from_value::<StoredV2>(value)
+                .map(|v2| Self {
+                    entries: v2.entries,
+                })
+                .map_err(|e| ParseError::Invalid(format!("schema 2: {}", e))),
+            v => Err(ParseError::Newer(format!(
+                "schema {} is newer than this FOEM supports ({}); update FOEM",
+                v, SCHEMA_VERSION
+            ))),
+        }
+    }
+
+    /// Each remembered method becomes one successful attempt. Migrated
+    /// entries have no recipe hash and are trusted until recorded again.
+    fn from_v1(v1: StoredV1) -> Self {
+        let mut entries = Entries::new();
+        for (fingerprint, learned) in v1.learned {
+            entries.entry(fingerprint).or_default().insert(
+                learned.recipe_name,
+                RecipeStats {
+                    attempts: 1,
+                    successes: 1,
+                    step_index: learned.step_index,
+                    ..Default::default()
+                },
+            );
+        }
+        Self { entries }
+    }
+
+    pub fn to_json(&self) -> String {
+        let stored = Stored {
+            version: SCHEMA_VERSION,
+            entries: &self.entries,
+        };
+        serde_json::to_string_pretty(&stored).unwrap_or_default()
+    }
+
+    /// Load the knowledge base; a missing file is an empty one. A file that
+    /// cannot be parsed is moved aside (never overwritten) and reported.
+    pub fn load() -> Result<Self, String> {
+        load_file(&kb_path())
+    }
+
+    /// Apply `change` to the on-disk knowledge base under the lock, so
+    /// updates from other FOEM windows are not lost.
+    pub fn update<T>(change: impl FnOnce(&mut Self) -> T) -> Result<T, String> {
+        update_file(&kb_path(), change)
+    }
+
+    /// Record one attempt of `recipe`; `succeeded_step` is the step that
+    /// worked, or `None` when the recipe failed.
+    pub fn record(
+        fingerprint: &str,
+        recipe: &ExploitRecipe,
+        succeeded_step: Option<usize>,
+    ) -> Result<(), String> {
+        let now = now_secs();
+        Self::update(|kb| kb.record_at(fingerprint, recipe, succeeded_step, now))
+    }
+
+    fn record_at(
+        &mut self,
+        fingerprint: &str,
+        recipe: &ExploitRecipe,
+        succeeded_step: Option<usize>,
+        now: u64,
+    ) {
+        let hash = recipe_hash(recipe);
+        let stats = self
+            .entries
+            .entry(fingerprint.to_string())
+            .or_default()
+            .entry(recipe.name.clone())
+            .or_default();
+        if stats.recipe_hash.is_empty() {
+            stats.recipe_hash = hash;
+        } else if stats.recipe_hash != hash {
+            *stats = RecipeStats {
+                recipe_hash: hash,
+                ..Default::default()
+            };
+        }
+        stats.attempts += 1;
+        if let Some(step_index) = succeeded_step {
+            stats.successes += 1;
+            stats.step_index = step_index;
+        }
+        stats.last_seen = now;
+        stats.foem_version = crate::VERSION.to_string();
+    }
+
+    /// The recorded fingerprint closest to `fingerprint`: same model, most
+    /// other fields in common, most recently seen.
+    pub fn nearest_fingerprint(&self, fingerprint: &str) -> Option<&str> {
+        let model = model_of(fingerprint);
+        if model.is_empty() {
+            return None;
+        }
+        let wanted: Vec<&str> = fingerprint.split('|').collect();
+        self.entries
+            .iter()
+            .filter(|(fp, _)| fp.as_str() != fingerprint && model_of(fp) == model)
+            .max_by_key(|(fp, recipes)| {
+                let shared = fp
+                    .split('|')
+                    .zip(&wanted)
+                    .filter(|(a, b)| a.trim() == b.trim())
+                    .count();
+                let last_seen = recipes.values().map(|s| s.last_seen).max().unwrap_or(0);
+                (shared, last_seen)
+            })
+            .map(|(fp, _)| fp.as_str())
+    }
+
+    /// Statistics for `fingerprint`, or for its nearest neighbour when the
+    /// exact device has no history. Returns the fingerprint they belong to.
+    pub fn history(&self, fingerprint: &str) -> Option<(&str, &BTreeMap<String, RecipeStats>)> {
+        if let Some((fp, recipes)) = self.entries.get_key_value(fingerprint) {
+            return Some((fp.as_str(), recipes));
+        }
+        let nearest = self.nearest_fingerprint(fingerprint)?;
+        self.entries.get(nearest).map(|recipes| (nearest, recipes))
+    }
+
+    /// Current statistics for `recipe`; stale entries recorded against a
+    /// different version of the recipe are ignored.
+    pub fn stats(&self, fingerprint: &str, recipe: &ExploitRecipe) -> Option<&RecipeStats> {
+        let (_, recipes) = self.history(fingerprint)?;
+        recipes
+            .get(&recipe.name)
+            .filter(|s| s.recipe_hash.is_empty() || s.recipe_hash == recipe_hash(recipe))
+    }
+
+    /// `recipes` ordered by confidence, highest first; ties keep their order.
+    pub fn rank<'a>(
+        &self,
+        fingerprint: &str,
+        recipes: &[&'a ExploitRecipe],
+    ) -> Vec<(&'a ExploitRecipe, Option<RecipeStats>)> {
+        let mut ranked: Vec<_> = recipes
+            .iter()
+            .map(|r| (*r, self.stats(fingerprint, r).cloned()))
+            .collect();
+        ranked.sort_by(|(_, a), (_, b)| {
+            let score = |s: &Option<RecipeStats>| s.as_ref().map_or(0.5, RecipeStats::confidence);
+            score(b).total_cmp(&score(a))
+        });
+        ranked
+    }
+
+    /// Merge imported entries. Counts are never added together, so importing
+    /// the same bundle twice changes nothing: for each device and recipe the
+    /// record for the current recipe version with more attempts wins, then
+    /// the more recent one. Returns (added, replaced, kept).
+    pub fn merge(&mut self, incoming: Entries) -> (usize, usize, usize) {
+        let (mut added, mut replaced, mut kept) = (0, 0, 0);
+        for (fingerprint, recipes) in incoming {
+            let local = self.entries.entry(fingerprint).or_default();
+            for (name, theirs) in recipes {
+                match local.get(&name) {
+                    None => {
+                        local.insert(name, theirs);
+                        added += 1;
+                    }
+                    Some(ours) => {
+                        let newer = |s: &RecipeStats| (s.last_seen, s.attempts);
+                        let better = if ours.recipe_hash == theirs.recipe_hash {
+                            (theirs.attempts, theirs.last_seen) > (ours.attempts, ours.last_seen)
+                        } else {
+                            newer(&theirs) > newer(ours)
+                        };
+                        if better {
+                            local.insert(name, theirs);
+                            replaced += 1;
+                        } else {
+                            kept += 1;
+                        }
+                    }
+                }
+            }
+        }
+        (added, replaced, kept)
+    }
+
+    pub fn to_bundle(&self) -> KnowledgeBundle {
+        KnowledgeBundle {
+            format: BUNDLE_FORMAT.into(),
+            version: SCHEMA_VERSION,
+            exported_at: now_secs(),
+            foem_version: crate::VERSION.to_string(),
+            sha256: entries_checksum(&self.entries),
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl KnowledgeBundle {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let bundle: Self = serde_json::from_str(text).map_err(|e| {
+            format!(
+                "invalid bundle at line {} column {}: {}",
+                e.line(),
+                e.column(),
+                e
+            )
+        })?;
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(format!(
+                "not a FOEM knowledge bundle (format '{}')",
+                bundle.format
+            ));
+        }
+        if bundle.version > SCHEMA_VERSION {
+            return Err(format!(
+                "bundle schema {} is newer than this FOEM supports ({}); update FOEM",
+                bundle.version, SCHEMA_VERSION
+            ));
+        }
+        let actual = entries_checksum(&bundle.entries);
+        if !actual.eq_ignore_ascii_case(&bundle.sha256) {
+            return Err(format!(
+                "checksum mismatch (expected {}, got {}); the bundle is damaged or was edited",
+                bundle.sha256, actual
+            ));
+        }
+        Ok(bundle)
+    }
+}
+
+fn read_limited(path: &Path) -> Result<Option<String>, String> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Cannot open {}: {}", path.display(), e)),
+    };
+    let mut text = String::new();
+    file.take(MAX_FILE_SIZE + 1)
+        .read_to_string(&mut text)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    if text.len() as u64 > MAX_FILE_SIZE {
+        return Err(format!(
+            "{} is larger than {} bytes",
+            path.display(),
+            MAX_FILE_SIZE
+        ));
+    }
+    Ok(Some(text))
+}
+
+fn read_file(path: &Path) -> Result<KnowledgeBase, String> {
+    let Some(text) = read_limited(path)? else {
+        return Ok(KnowledgeBase::default());
+    };
+    match KnowledgeBase::parse(&text) {
+        Ok(kb) => Ok(kb),
+        Err(ParseError::Newer(e)) => Err(format!("{}: {}", path.display(), e)),
+        Err(ParseError::Invalid(e)) => {
+            let aside = path.with_extension(format!("json.corrupt-{}", now_secs()));
+            match fs::rename(path, &aside) {
+                Ok(()) => Err(format!(
+                    "{} is unreadable ({}); moved to {}",
+                    path.display(),
+                    e,
+                    aside.display()
+                )),
+                Err(re) => Err(format!(
+                    "{} is unreadable ({}) and could not be moved aside: {}",
+                    path.display(),
+                    e,
+                    re
+                )),
+            }
+        }
+    }
+}
+
+/// Read under the lock, so a file another window is about to replace is
+/// never mistaken for a corrupt one and moved aside.
+fn load_file(path: &Path) -> Result<KnowledgeBase, String> {
+    let _lock = lock(path)?;
+    read_file(path)
+}
+
+fn update_file<T>(path: &Path, change: impl FnOnce(&mut KnowledgeBase) -> T) -> Result<T, String> {
+    let _lock = lock(path)?;
+    let mut kb = read_file(path)?;
+    let result = change(&mut kb);
+    write_atomic(path, &kb.to_json())?;
+    Ok(result)
+}
+
+/// Write to a temporary file in the same directory, flush it to disk and
+/// rename it over `path`, so readers see either the old or the new file.
+fn write_atomic(path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    let written = fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(format!("Cannot write {}: {}", path.display(), e));
+    }
+    Ok(())
+}
+
+/// Exclusive advisory lock held until the returned file is dropped. A
+/// separate lock file is used because the data file is replaced on write.
+fn lock(path: &Path) -> Result<fs::File, String> {
+    let lock_path = path.with_extension("lock");
+    if let Some(parent) = lock_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| format!("Cannot open {}: {}", lock_path.display(), e))?;
+    file.lock()
+        .map_err(|e| format!("Cannot lock {}: {}", lock_path.display(), e))?;
+    Ok(file)
+}
+
+/// Write the whole knowledge base to `path` as a bundle.
+pub fn export_bundle(path: &Path) -> Result<String, String> {
+    let kb = KnowledgeBase::load()?;
+    let bundle = kb.to_bundle();
+    let json = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+    write_atomic(path, &json)?;
+    let records: usize = kb.entries.values().map(BTreeMap::len).sum();
+    Ok(format!(
+        "Exported {} record(s) for {} device(s) to {}",
+        records,
+        kb.entries.len(),
+        path.display()
+    ))
+}
+
+/// Verify the bundle at `path` and merge it into the knowledge base.
+pub fn import_bundle(path: &Path) -> Result<String, String> {
+    let text = read_limited(path)?.ok_or_else(|| format!("{} not found", path.display()))?;
+    let bundle = KnowledgeBundle::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (added, replaced, kept) = KnowledgeBase::update(|kb| kb.merge(bundle.entries))?;
+    Ok(format!(
+        "Imported {} (FOEM {}): {} added, {} updated, {} already up to date",
+        path.display(),
+        bundle.foem_version,
+        added,
+        replaced,
+        kept
+    ))
+}
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_KB_PATH: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+fn kb_path() -> PathBuf {
+    #[cfg(test)]
+    {
+        if let Some(path) = MOCK_KB_PATH.with(|m| m.borrow().clone()) {
+            return path;
+        }
+    }
+    exec::foem_home().join("learned_methods.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive_engine::recipes::builtin_recipes;
+    use std::error::Error;
+
+    struct KbPathMockGuard;
+
+    impl Drop for KbPathMockGuard {
+        fn drop(&mut self) {
+            MOCK_KB_PATH.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    fn temp_dir(name: &str) -> std::io::Result<PathBuf> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// The error message of `result`, or an error if it succeeded.
+    fn err_of<T: std::fmt::Debug>(result: Result<T, String>) -> Result<String, Box<dyn Error>> {
+        match result {
+            Ok(v) => Err(format!("expected an error, got {:?}", v).into()),
+            Err(e) => Ok(e),
+        }
+    }
+
+    #[test]
+    fn test_knowledge_base_save_and_load() -> Result<(), Box<dyn Error>> {
+        let dir = temp_dir("foem_kb_save")?;
+        let db_path = dir.join("learned_methods.json");
+
+        MOCK_KB_PATH.with(|m| *m.borrow_mut() = Some(db_path.clone()));
+        let _guard = KbPathMockGuard; // Ensure cleanup
+
+        let recipe = &builtin_recipes()[0];
+        KnowledgeBase::record("test_fingerprint", recipe, Some(0))?;
+
+        assert!(db_path.exists());
+        assert!(fs::read_to_string(&db_path)?.contains("\"version\": 2"));
+
+        let loaded_kb = KnowledgeBase::load()?;
+        assert_eq!(loaded_kb.entries.len(), 1);
+        let stats = loaded_kb
+            .stats("test_fingerprint", recipe)
+            .ok_or("missing stats")?;
+        assert_eq!(
+            (stats.attempts, stats.successes, stats.step_index),
+            (1, 1, 0)
+        );
+        assert_eq!(stats.foem_version, crate::VERSION);
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_schema_1() -> Result<(), Box<dyn Error>> {
+        let v1 = r#"{"learned": {"KI7|13|mt6789": {"recipe_name": "USB Config Toggle", "step_index": 0}}}"#;
+        let kb = KnowledgeBase::parse(v1).map_err(|e| format!("{:?}", e))?;
+        let recipe = &builtin_recipes()[0];
+        let stats = kb.stats("KI7|13|mt6789", recipe).ok_or("no stats")?;
+        assert_eq!((stats.attempts, stats.successes), (1, 1));
+        assert!(stats.recipe_hash.is_empty());
+
+        // The next recording adopts the current recipe hash instead of resetting.
+        let mut kb = kb;
+        kb.record_at("KI7|13|mt6789", recipe, Some(0), 5);
+        let stats = kb.stats("KI7|13|mt6789", recipe).ok_or("no stats")?;
+        assert_eq!((stats.attempts, stats.successes), (2, 2));
+        assert_eq!(stats.recipe_hash, recipe_hash(recipe));
+
+        let again = KnowledgeBase::parse(&kb.to_json()).map_err(|e| format!("{:?}", e))?;
+        assert_eq!(again, kb);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_file_is_moved_aside() -> Result<(), Box<dyn Error>> {
+        let dir = temp_dir("foem_kb_corrupt")?;
+        let path = dir.join("learned_methods.json");
+        fs::write(&path, "{\"entries\": {")?;
+
+        let err = err_of(load_file(&path))?;
+        assert!(
+            err.contains("is unreadable (invalid JSON at line 1"),
+            "{}",
+            err
+        );
+        assert!(!path.exists());
+        let aside: Vec<_> = fs::read_dir(&dir)?
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().contains(".corrupt-"))
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(fs::read_to_string(aside[0].path())?, "{\"entries\": {");
+        assert_eq!(load_file(&path), Ok(KnowledgeBase::default()));
+
+        // A file from a newer FOEM is left alone and never overwritten.
+        fs::write(&path, "{\"version\": 9, \"entries\": {}}")?;
+        let err = err_of(update_file(&path, |_| ()))?;
+        assert!(err.contains("schema 9 is newer"), "{}", err);
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "{\"version\": 9, \"entries\": {}}"
+        );
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_updates_are_not_lost() -> Result<(), Box<dyn Error>> {
+        let dir = temp_dir("foem_kb_concurrent")?;
+        let path = dir.join("learned_methods.json");
+        let recipe = builtin_recipes().remove(0);
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let (path, recipe) = (path.clone(), recipe.clone());
+                std::thread::spawn(move || {
+                    (0..10).try_for_each(|i| {
+                        update_file(&path, |kb| kb.record_at("fp", &recipe, None, i))
+                    })
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().map_err(|_| "update worker panicked")??;
+        }
+        let kb = read_file(&path)?;
+        assert_eq!(kb.stats("fp", &recipe).map(|s| s.attempts), Some(40));
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bundle_round_trip_and_checksum() -> Result<(), Box<dyn Error>> {
+        let recipes = builtin_recipes();
+        let mut kb = KnowledgeBase::default();
+        kb.record_at("fp", &recipes[0], Some(0), 1);
+        kb.record_at("fp", &recipes[2], None, 2);
+        let json = serde_json::to_string_pretty(&kb.to_bundle())?;
+
+        let bundle = KnowledgeBundle::parse(&json)?;
+        assert_eq!(bundle.entries, kb.entries);
+
+        let tampered = json.replacen("\"successes\": 1", "\"successes\": 7", 1);
+        let err = err_of(KnowledgeBundle::parse(&tampered))?;
+        assert!(err.starts_with("checksum mismatch"), "{}", err);
+
+        let err = err_of(KnowledgeBundle::parse(
+            &json.replace(BUNDLE_FORMAT, "other"),
+        ))?;
+        assert_eq!(err, "not a FOEM knowledge bundle (format 'other')");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_is_idempotent() {
+        let recipes = builtin_recipes();
+        let mut shop = KnowledgeBase::default();
+        for i in 0..3 {
+            shop.record_at("fp", &recipes[0], Some(0), i);
+        }
+        shop.record_at("other", &recipes[1], Some(0), 4);
+
+        let mut local = KnowledgeBase::default();
+        local.record_at("fp", &recipes[0], None, 10);
+        assert_eq!(local.merge(shop.entries.clone()), (1, 1, 0));
+        assert_eq!(local.stats("fp", &recipes[0]).map(|s| s.attempts), Some(3));
+        assert_eq!(local.merge(shop.entries.clone()), (0, 0, 2));
+        assert_eq!(local, {
+            let mut expected = shop.clone();
+            expected.merge(KnowledgeBase::default().entries);
+            expected
+        });
+    }
+
+    #[test]
+    fn test_rank_by_confidence() {
+        let recipes = builtin_recipes();
+        let (usb, samsung, at) = (&recipes[0], &recipes[1], &recipes[2]);
+        let mut kb = KnowledgeBase::default();
+        // USB toggle worked once, then failed twice: 2/5 = 0.4.
+        kb.record_at("fp", usb, Some(0), 1);
+        kb.record_at("fp", usb, None, 2);
+        kb.record_at("fp", usb, None, 3);
+        // AT works reliably: 4/5 = 0.8.
+        kb.record_at("fp", at, Some(0), 4);
+        kb.record_at("fp", at, Some(0), 5);
+        kb.record_at("fp", at, Some(0), 6);
+
+        let ranked = kb.rank("fp", &[usb, samsung, at]);
+        let names: Vec<&str> = ranked.iter().map(|(r, _)| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["AT Diag Enable", "Samsung Diag", "USB Config Toggle"]
+        );
+        assert_eq!(ranked[0].1.as_ref().map(|s| s.attempts), Some(3));
+        assert!(ranked[1].1.is_none());
+    }
+
+    #[test]
+    fn test_changed_recipe_expires_stats() -> Result<(), Box<dyn Error>> {
+        let mut recipe = builtin_recipes().remove(0);
+        let mut kb = KnowledgeBase::default();
+        kb.record_at("fp", &recipe, None, 1);
+        kb.record_at("fp", &recipe, None, 2);
+        assert_eq!(kb.stats("fp", &recipe).map(|s| s.attempts), Some(2));
+
+        recipe.steps[0].payload = "setprop sys.usb.config diag,serial_cdev,adb".into();
+        assert!(kb.stats("fp", &recipe).is_none());
+        kb.record_at("fp", &recipe, Some(0), 3);
+        let stats = kb.stats("fp", &recipe).ok_or("no stats")?;
+        assert_eq!((stats.attempts, stats.successes), (1, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nearest_fingerprint_same_model() {
+        let recipe = &builtin_recipes()[0];
+        let mut kb = KnowledgeBase::default();
+        kb.record_at("KI7|12|mt6789|TECNO", recipe, Some(0), 10);
+        kb.record_at("KI7|13|mt6789|TECNO", recipe, Some(0), 5);
+        kb.record_at("KI7|13|mt6768|TECNO", recipe, Some(0), 20);
+        kb.record_at("KI5|14|mt6789|TECNO", recipe, Some(0), 30);
+
+        // Same model and platform beats a newer entry with a different platform.
+        assert_eq!(
+            kb.nearest_fingerprint("KI7|14|mt6789|TECNO"),
+            Some("KI7|12|mt6789|TECNO")
+        );
+        assert_eq!(
+            kb.history("KI7|14|mt6789|TECNO").map(|(fp, _)| fp),
+            Some("KI7|12|mt6789|TECNO")
+        );
+        assert_eq!(
+            kb.history("KI7|13|mt6789|TECNO").map(|(fp, _)| fp),
+            Some("KI7|13|mt6789|TECNO")
+        );
+        assert!(kb.nearest_fingerprint("X1|14|mt6789|TECNO").is_none());
+        assert!(kb.nearest_fingerprint("|14|mt6789|TECNO").is_none());
+    }
+}
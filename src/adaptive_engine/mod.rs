@@ -0,0 +1,553 @@
+use crate::exec;
+use crate::features::repair::{open_diag_port, send_at_command};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub mod dry_run;
+pub mod knowledge;
+pub mod preconditions;
+pub mod recipes;
+pub mod sequence;
+pub mod steps;
+
+use knowledge::{KnowledgeBase, RecipeStats};
+use preconditions::{DeviceContext, DeviceMode, Preconditions};
+use recipes::RecipeSet;
+use steps::RebootTarget;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum FuzzGoal {
+    EnableDiagPort,
+    BypassRsaAuth,
+    Custom(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StepKind {
+    AdbShell,
+    Fastboot,
+    AtCommand,
+    RawDiag,
+    Reboot(RebootTarget),
+    WaitForMode {
+        mode: DeviceMode,
+        timeout_ms: u64,
+    },
+    PushFile {
+        local: String,
+        remote: String,
+    },
+    Sleep {
+        ms: u64,
+    },
+    /// `expected` may use `*` and `?` wildcards.
+    AssertProp {
+        name: String,
+        expected: String,
+    },
+}
+
+impl StepKind {
+    /// Kinds that send `ExploitStep::payload` to the device.
+    pub fn uses_payload(&self) -> bool {
+        matches!(
+            self,
+            Self::AdbShell | Self::Fastboot | Self::AtCommand | Self::RawDiag
+        )
+    }
+
+    /// Text fields of the kind itself, which may reference `${variables}`.
+    pub fn texts(&self) -> Vec<&str> {
+        match self {
+            Self::PushFile { local, remote } => vec![local, remote],
+            Self::AssertProp { name, expected } => vec![name, expected],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Copy of the kind with `f` applied to every field listed by `texts`.
+    pub fn map_texts(&self, f: impl Fn(&str) -> Result<String, String>) -> Result<Self, String> {
+        Ok(match self {
+            Self::PushFile { local, remote } => Self::PushFile {
+                local: f(local)?,
+                remote: f(remote)?,
+            },
+            Self::AssertProp { name, expected } => Self::AssertProp {
+                name: f(name)?,
+                expected: f(expected)?,
+            },
+            other => other.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExploitStep {
+    pub kind: StepKind,
+    /// Command text for AdbShell, Fastboot, AtCommand and RawDiag steps.
+    #[serde(default)]
+    pub payload: String,
+    pub success_markers: Vec<String>,
+    pub failure_markers: Vec<String>,
+    #[serde(default)]
+    pub retries: u8,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Variable name -> regex; the first group (or whole match) of the
+    /// step's output is stored for `${name}` in later payloads.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub captures: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExploitRecipe {
+    pub goal: FuzzGoal,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Preconditions::is_empty")]
+    pub preconditions: Preconditions,
+    /// Run `steps` in order as one procedure instead of as alternatives.
+    #[serde(default)]
+    pub sequential: bool,
+    pub steps: Vec<ExploitStep>,
+    /// Cleanup run when a sequential recipe fails part-way.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<ExploitStep>,
+}
+
+pub fn fingerprint(model: &str, release: &str, platform: &str) -> String {
+    format!("{}|{}|{}", model.trim(), release.trim(), platform.trim())
+}
+
+/// The enabled recipes for `goal` whose preconditions `device` meets, most
+/// likely to work on `fingerprint` first. Skipped recipes, recipe file errors
+/// and knowledge base problems are appended to `notes`.
+pub fn plan<'a>(
+    set: &'a RecipeSet,
+    goal: &FuzzGoal,
+    fingerprint: &str,
+    device: &DeviceContext,
+    notes: &mut String,
+) -> Vec<(&'a ExploitRecipe, Option<RecipeStats>)> {
+    let kb = KnowledgeBase::load().unwrap_or_else(|e| {
+        notes.push_str(&format!("Knowledge base error: {}\n", e));
+        KnowledgeBase::default()
+    });
+    for e in &set.errors {
+        notes.push_str(&format!("Recipe error: {}\n", e));
+    }
+
+    let goal_recipes: Vec<&ExploitRecipe> = set
+        .enabled()
+        .filter(|r| &r.goal == goal)
+        .filter(|r| match r.preconditions.check(device) {
+            Ok(()) => true,
+            Err(reason) => {
+                notes.push_str(&format!("Skipped {}: {}\n", r.name, reason));
+                false
+            }
+        })
+        .collect();
+
+    if let Some((source, _)) = kb.history(fingerprint).filter(|(fp, _)| *fp != fingerprint) {
+        notes.push_str(&format!(
+            "No history for {}; ranking by similar device {}\n",
+            fingerprint, source
+        ));
+    }
+    kb.rank(fingerprint, &goal_recipes)
+}
+
+/// Order in which the alternative steps of a non-sequential recipe are
+/// tried: the step that worked before first, the rest in file order.
+pub fn step_order(recipe: &ExploitRecipe, stats: Option<&RecipeStats>) -> Vec<usize> {
+    let learned = stats
+        .filter(|s| s.successes > 0 && s.step_index < recipe.steps.len())
+        .map_or(0, |s| s.step_index);
+    std::iter::once(learned)
+        .chain((0..recipe.steps.len()).filter(|i| *i != learned))
+        .take(recipe.steps.len())
+        .collect()
+}
+
+/// " (3/4 successes, confidence 67%)", or empty without history.
+pub fn stats_label(stats: Option<&RecipeStats>) -> String {
+    stats
+        .map(|s| {
+            format!(
+                " ({}/{} successes, confidence {:.0}%)",
+                s.successes,
+                s.attempts,
+                s.confidence() * 100.0
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Try the enabled recipes for `goal` whose preconditions `device` meets,
+/// most likely to work on `fingerprint` first, and record every outcome.
+pub fn execute_goal(
+    serial: &str,
+    goal: FuzzGoal,
+    fingerprint: &str,
+    device: &DeviceContext,
+    diag_port_hint: Option<&str>,
+) -> String {
+    let set = recipes::load_recipes();
+    let mut notes = String::new();
+    let candidates = plan(&set, &goal, fingerprint, device, &mut notes);
+
+    let mut last_error = String::new();
+    for (recipe, stats) in candidates {
+        let record = stats_label(stats.as_ref());
+        if recipe.sequential {
+            match sequence::run_sequence(serial, recipe, diag_port_hint) {
+                Ok(out) => {
+                    record_outcome(&mut notes, fingerprint, recipe, Some(0));
+                    return format!(
+                        "{}{} succeeded via {}{}:\n{}",
+                        notes,
+                        goal_string(&goal),
+                        recipe.name,
+                        record,
+                        out
+                    );
+                }
+                Err(transcript) => {
+                    record_outcome(&mut notes, fingerprint, recipe, None);
+                    last_error = format!("{} failed:\n{}", recipe.name, transcript);
+                }
+            }
+            continue;
+        }
+        for step_index in step_order(recipe, stats.as_ref()) {
+            let step = &recipe.steps[step_index];
+            match execute_step(serial, step, diag_port_hint) {
+                Ok(out) => match check_markers(step, &out) {
+                    Ok(()) => {
+                        record_outcome(&mut notes, fingerprint, recipe, Some(step_index));
+                        return format!(
+                            "{}{} succeeded via {}{}:\n{}",
+                            notes,
+                            goal_string(&goal),
+                            recipe.name,
+                            record,
+                            out
+                        );
+                    }
+                    Err(e) => last_error = format!("Step {} {}", recipe.name, e),
+                },
+                Err(e) => {
+                    last_error = e;
+                }
+            }
+        }
+        if !recipe.steps.is_empty() {
+            record_outcome(&mut notes, fingerprint, recipe, None);
+        }
+    }
+
+    if last_error.is_empty() {
+        format!("{}No matching recipe executed.", notes)
+    } else {
+        format!("{}All recipes failed. Last error: {}", notes, last_error)
+    }
+}
+
+fn record_outcome(
+    notes: &mut String,
+    fingerprint: &str,
+    recipe: &ExploitRecipe,
+    step: Option<usize>,
+) {
+    if let Err(e) = KnowledgeBase::record(fingerprint, recipe, step) {
+        notes.push_str(&format!("Knowledge base not updated: {}\n", e));
+    }
+}
+
+/// AT responses are matched line by line so that a marker such as "OK" cannot
+/// hit information text like `+COPS: 0,0,"BOOK"`.
+fn marker_matches(step: &ExploitStep, out: &str, marker: &str) -> bool {
+    match step.kind {
+        StepKind::AtCommand => out.lines().any(|line| line.trim().starts_with(marker)),
+        _ => out.contains(marker),
+    }
+}
+
+/// Failure markers take precedence; a step without success markers passes
+/// unless a failure marker matched.
+fn check_markers(step: &ExploitStep, out: &str) -> Result<(), String> {
+    if step
+        .failure_markers
+        .iter()
+        .any(|m| marker_matches(step, out, m))
+    {
+        return Err(format!("reported failure markers:\n{}", out));
+    }
+    if !step.success_markers.is_empty()
+        && !step
+            .success_markers
+            .iter()
+            .any(|m| marker_matches(step, out, m))
+    {
+        return Err(format!("matched no success markers:\n{}", out));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+type MockStepFn = Box<dyn Fn(&ExploitStep) -> Result<String, String>>;
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_STEP_IMPL: std::cell::RefCell<Option<MockStepFn>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Pause between attempts of a step with `retries`.
+const RETRY_DELAY: Duration = Duration::from_millis(150);
+
+fn execute_step(
+    serial: &str,
+    step: &ExploitStep,
+    diag_port_hint: Option<&str>,
+) -> Result<String, String> {
+    let retries = if step.retries == 0 {
+        1
+    } else {
+        step.retries as usize
+    };
+    let mut last_error = String::new();
+    for attempt in 0..retries {
+        #[cfg(test)]
+        {
+            let mocked = MOCK_STEP_IMPL.with(|m| m.borrow().as_ref().map(|f| f(step)));
+            if let Some(res) = mocked {
+                return res;
+            }
+        }
+        let timeout = step
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(exec::COMMAND_TIMEOUT);
+        let res = std::panic::catch_unwind(|| match &step.kind {
+            StepKind::AdbShell => exec::run_with_timeout(
+                "adb",
+                &["-s", serial, "shell", &step.payload],
+                "ADB shell",
+                timeout,
+            ),
+            StepKind::Fastboot => {
+                let tokens = steps::fastboot_args(&step.payload)?;
+                let mut args = vec!["-s", serial];
+                args.extend(tokens.iter().map(String::as_str));
+                exec::run_with_timeout("fastboot", &args, "Fastboot", timeout)
+            }
+            StepKind::AtCommand => {
+                let autodetected = autodetect_diag_port();
+                let port_name = diag_port_hint
+                    .or(autodetected.as_deref())
+                    .ok_or_else(|| "No diagnostic port available for AT command".to_string())?;
+                let mut port = open_diag_port(port_name)?;
+                send_at_command(&mut port, &step.payload)
+                    .and_then(crate::features::at::AtResponse::into_result)
+                    .map(|resp| resp.to_string())
+            }
+            StepKind::RawDiag => {
+                let autodetected = autodetect_diag_port();
+                let port_name = diag_port_hint
+                    .or(autodetected.as_deref())
+                    .ok_or_else(|| "No diagnostic port available for DIAG command".to_string())?;
+                let mut port = open_diag_port(port_name)?;
+                let bytes = hex::decode(step.payload.replace([' ', '\n', '\r'], ""))
+                    .map_err(|e| format!("Hex decode failed: {}", e))?;
+                crate::features::repair::send_diag_bytes(&mut port, &bytes).map(hex::encode)
+            }
+            StepKind::Reboot(target) => steps::reboot(serial, *target, timeout),
+            StepKind::WaitForMode { mode, timeout_ms } => steps::wait_for_mode(
+                serial,
+                *mode,
+                Duration::from_millis(*timeout_ms),
+                diag_port_hint,
+            ),
+            StepKind::PushFile { local, remote } => {
+                steps::push_file(serial, local, remote, timeout)
+            }
+            StepKind::Sleep { ms } => {
+                std::thread::sleep(Duration::from_millis(*ms));
+                Ok(format!("Slept {} ms", ms))
+            }
+            StepKind::AssertProp { name, expected } => {
+                steps::assert_prop(serial, name, expected, timeout)
+            }
+        });
+
+        match res {
+            Ok(Ok(val)) => return Ok(val),
+            Ok(Err(e)) => last_error = e,
+            Err(_) => {
+                last_error = format!("Step {:?} panicked during execution", step.kind);
+            }
+        };
+
+        if attempt + 1 < retries {
+            std::thread::sleep(RETRY_DELAY);
+        }
+    }
+    Err(last_error)
+}
+
+fn goal_string(goal: &FuzzGoal) -> String {
+    match goal {
+        FuzzGoal::EnableDiagPort => "EnableDiagPort".to_string(),
+        FuzzGoal::BypassRsaAuth => "BypassRsaAuth".to_string(),
+        FuzzGoal::Custom(s) => s.clone(),
+    }
+}
+
+#[cfg(not(test))]
+fn get_available_ports() -> Result<Vec<serialport::SerialPortInfo>, serialport::Error> {
+    serialport::available_ports()
+}
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_AVAILABLE_PORTS: std::cell::RefCell<Option<Result<Vec<serialport::SerialPortInfo>, String>>> = std::cell::RefCell::new(None);
+}
+
+#[cfg(test)]
+fn get_available_ports() -> Result<Vec<serialport::SerialPortInfo>, serialport::Error> {
+    MOCK_AVAILABLE_PORTS.with(|m| {
+        if let Some(res) = m.borrow().as_ref() {
+            match res {
+                Ok(ports) => Ok(ports.clone()),
+                Err(e) => Err(serialport::Error::new(serialport::ErrorKind::Unknown, e.clone())),
+            }
+        } else {
+            serialport::available_ports()
+        }
+    })
+}
+
+pub fn autodetect_diag_port() -> Option<String> {
+    if let Ok(ports) = get_available_ports() {
+        for p in ports {
+            if matches!(p.port_type, serialport::SerialPortType::UsbPort(_)) {
+                return Some(p.port_name);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use knowledge::MOCK_KB_PATH;
+    use std::fs;
+
+    struct PortsMockGuard;
+    impl Drop for PortsMockGuard {
+        fn drop(&mut self) {
+            MOCK_AVAILABLE_PORTS.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    #[test]
+    fn test_autodetect_diag_port_none_when_error() {
+        MOCK_AVAILABLE_PORTS.with(|m| *m.borrow_mut() = Some(Err("mock error".into())));
+        let _guard = PortsMockGuard;
+        let port = autodetect_diag_port();
+        assert_eq!(port, None);
+    }
+
+    #[test]
+    fn test_autodetect_diag_port_none_when_no_ports() {
+        MOCK_AVAILABLE_PORTS.with(|m| *m.borrow_mut() = Some(Ok(vec![])));
+        let _guard = PortsMockGuard;
+        let port = autodetect_diag_port();
+        assert_eq!(port, None);
+    }
+
+    #[test]
+    fn test_autodetect_diag_port_none_when_no_usb_ports() {
+        let port_info = serialport::SerialPortInfo {
+            port_name: "COM1".into(),
+            port_type: serialport::SerialPortType::Unknown,
+        };
+        MOCK_AVAILABLE_PORTS.with(|m| *m.borrow_mut() = Some(Ok(vec![port_info])));
+        let _guard = PortsMockGuard;
+        let port = autodetect_diag_port();
+        assert_eq!(port, None);
+    }
+
+    #[test]
+    fn test_autodetect_diag_port_some_when_usb_port_found() {
+        let usb_info = serialport::UsbPortInfo {
+            vid: 0x1234,
+            pid: 0x5678,
+            serial_number: None,
+            manufacturer: None,
+            product: None,
+        };
+        let port_info = serialport::SerialPortInfo {
+            port_name: "COM1".into(),
+            port_type: serialport::SerialPortType::UsbPort(usb_info),
+        };
+        MOCK_AVAILABLE_PORTS.with(|m| *m.borrow_mut() = Some(Ok(vec![port_info])));
+        let _guard = PortsMockGuard;
+        let port = autodetect_diag_port();
+        assert_eq!(port, Some("COM1".into()));
+    }
+
+    struct KbPathMockGuard;
+
+    impl Drop for KbPathMockGuard {
+        fn drop(&mut self) {
+            MOCK_KB_PATH.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    #[test]
+    fn test_execute_goal_skips_recipes_failing_preconditions(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_execute_goal_preconditions");
+        let _ = fs::remove_dir_all(&dir);
+        recipes::MOCK_RECIPES_DIR.with(|m| *m.borrow_mut() = Some(dir.clone()));
+        MOCK_KB_PATH.with(|m| *m.borrow_mut() = Some(dir.join("kb.json")));
+        let _guard = KbPathMockGuard;
+        recipes::set_enabled("USB Config Toggle", false)?;
+
+        let device = DeviceContext {
+            manufacturer: "TECNO".into(),
+            platform: "mt6789".into(),
+            modes: vec![preconditions::DeviceMode::Adb],
+            ..Default::default()
+        };
+        let out = execute_goal("SER", FuzzGoal::EnableDiagPort, "fp", &device, None);
+        recipes::MOCK_RECIPES_DIR.with(|m| *m.borrow_mut() = None);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(out.contains("Skipped Samsung Diag: manufacturer 'TECNO' is not one of samsung"));
+        assert!(out.contains("Skipped AT Diag Enable: requires Diag mode"));
+        assert!(out.ends_with("No matching recipe executed."), "{}", out);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(
+            fingerprint("  Pixel 6  ", "  12  ", "  android  "),
+            "Pixel 6|12|android"
+        );
+        assert_eq!(
+            fingerprint("Galaxy S21", "11", "android"),
+            "Galaxy S21|11|android"
+        );
+        assert_eq!(
+            fingerprint("  ", "  ", "  "),
+            "||"
+        );
+    }
+}
@@ -0,0 +1,274 @@
+/// Recipe preconditions and the device facts they are checked against.
+///
+/// A recipe only runs when every condition it sets holds: manufacturer (any
+/// of, case-insensitive), board platform glob, Android SDK range and a device
+/// mode that must currently be reachable.
+use super::{autodetect_diag_port, fingerprint};
+use crate::exec;
+use crate::features::batch;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DeviceMode {
+    Adb,
+    Fastboot,
+    Diag,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preconditions {
+    /// `ro.product.manufacturer` values, any of which matches.
+    pub manufacturer: Vec<String>,
+    /// Glob (`*`, `?`) matched against `ro.board.platform`.
+    pub platform: Option<String>,
+    pub min_sdk: Option<u32>,
+    pub max_sdk: Option<u32>,
+    pub mode: Option<DeviceMode>,
+}
+
+impl Preconditions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.manufacturer.iter().any(|m| m.trim().is_empty()) {
+            return Err("preconditions: manufacturer entries must not be empty".into());
+        }
+        if self
+            .platform
+            .as_deref()
+            .is_some_and(|p| p.trim().is_empty())
+        {
+            return Err("preconditions: platform must not be empty".into());
+        }
+        if let (Some(min), Some(max)) = (self.min_sdk, self.max_sdk) {
+            if min > max {
+                return Err(format!(
+                    "preconditions: min_sdk {} is above max_sdk {}",
+                    min, max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// `Err` carries the first condition the device does not meet.
+    pub fn check(&self, device: &DeviceContext) -> Result<(), String> {
+        if let Some(mode) = self.mode {
+            if !device.modes.contains(&mode) {
+                return Err(format!("requires {:?} mode", mode));
+            }
+        }
+        if !self.manufacturer.is_empty()
+            && !self
+                .manufacturer
+                .iter()
+                .any(|m| m.trim().eq_ignore_ascii_case(device.manufacturer.trim()))
+        {
+            return Err(format!(
+                "manufacturer '{}' is not one of {}",
+                device.manufacturer,
+                self.manufacturer.join(", ")
+            ));
+        }
+        if let Some(pattern) = &self.platform {
+            if !glob_match(pattern, &device.platform) {
+                return Err(format!(
+                    "platform '{}' does not match '{}'",
+                    device.platform, pattern
+                ));
+            }
+        }
+        if self.min_sdk.is_some() || self.max_sdk.is_some() {
+            let Some(sdk) = device.sdk else {
+                return Err("Android SDK level unknown".into());
+            };
+            if self.min_sdk.is_some_and(|min| sdk < min)
+                || self.max_sdk.is_some_and(|max| sdk > max)
+            {
+                return Err(format!(
+                    "SDK {} outside {}..={}",
+                    sdk,
+                    self.min_sdk.map(|v| v.to_string()).unwrap_or_default(),
+                    self.max_sdk.map(|v| v.to_string()).unwrap_or_default()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Case-insensitive glob with `*` (any run) and `?` (one character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.trim().to_lowercase().chars().collect();
+    let t: Vec<char> = text.trim().to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+/// What is known about the target device when a goal is executed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceContext {
+    pub manufacturer: String,
+    pub model: String,
+    pub release: String,
+    pub sdk: Option<u32>,
+    pub platform: String,
+    /// Modes the device can currently be reached in.
+    pub modes: Vec<DeviceMode>,
+}
+
+impl DeviceContext {
+    /// Read the properties over ADB and probe which modes are reachable.
+    pub fn query(serial: &str, diag_port_hint: Option<&str>) -> Self {
+        let props = batch::run_commands(
+            serial,
+            &[
+                ("manufacturer", "getprop ro.product.manufacturer"),
+                ("model", "getprop ro.product.model"),
+                ("release", "getprop ro.build.version.release"),
+                ("sdk", "getprop ro.build.version.sdk"),
+                ("platform", "getprop ro.board.platform"),
+            ],
+        );
+        let adb_ok = props.iter().any(|(_, res)| res.is_ok());
+        let values: Vec<String> = props
+            .into_iter()
+            .map(|(_, res)| res.unwrap_or_default())
+            .collect();
+
+        let mut modes = Vec::new();
+        if adb_ok {
+            modes.push(DeviceMode::Adb);
+        } else if exec::run("fastboot", &["devices"], "Fastboot").is_ok_and(|out| {
+            out.lines()
+                .any(|l| l.split_whitespace().next() == Some(serial))
+        }) {
+            modes.push(DeviceMode::Fastboot);
+        }
+        if diag_port_hint.is_some() || autodetect_diag_port().is_some() {
+            modes.push(DeviceMode::Diag);
+        }
+
+        Self {
+            manufacturer: values[0].clone(),
+            model: values[1].clone(),
+            release: values[2].clone(),
+            sdk: values[3].trim().parse().ok(),
+            platform: values[4].clone(),
+            modes,
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.model, &self.release, &self.platform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tecno() -> DeviceContext {
+        DeviceContext {
+            manufacturer: "TECNO".into(),
+            model: "TECNO KI7".into(),
+            release: "13".into(),
+            sdk: Some(33),
+            platform: "mt6789".into(),
+            modes: vec![DeviceMode::Adb],
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("mt*", "MT6789"));
+        assert!(glob_match("sm?350", "sm8350"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*67*9", "mt6789"));
+        assert!(!glob_match("mt*", "kona"));
+        assert!(!glob_match("sm?350", "sm83500"));
+    }
+
+    #[test]
+    fn test_check_reports_first_mismatch() {
+        let device = tecno();
+        assert_eq!(Preconditions::default().check(&device), Ok(()));
+
+        let samsung = Preconditions {
+            manufacturer: vec!["samsung".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            samsung.check(&device),
+            Err("manufacturer 'TECNO' is not one of samsung".into())
+        );
+
+        let mtk = Preconditions {
+            manufacturer: vec!["Tecno".into(), "Infinix".into()],
+            platform: Some("mt*".into()),
+            min_sdk: Some(28),
+            max_sdk: Some(33),
+            mode: Some(DeviceMode::Adb),
+        };
+        assert_eq!(mtk.check(&device), Ok(()));
+
+        let old = Preconditions {
+            max_sdk: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(old.check(&device), Err("SDK 33 outside ..=30".into()));
+
+        let diag = Preconditions {
+            mode: Some(DeviceMode::Diag),
+            ..Default::default()
+        };
+        assert_eq!(diag.check(&device), Err("requires Diag mode".into()));
+    }
+
+    #[test]
+    fn test_unknown_sdk_fails_range() {
+        let device = DeviceContext {
+            sdk: None,
+            ..tecno()
+        };
+        let pre = Preconditions {
+            min_sdk: Some(26),
+            ..Default::default()
+        };
+        assert_eq!(pre.check(&device), Err("Android SDK level unknown".into()));
+    }
+
+    #[test]
+    fn test_validate() {
+        let pre = Preconditions {
+            min_sdk: Some(34),
+            max_sdk: Some(30),
+            ..Default::default()
+        };
+        assert!(pre
+            .validate()
+            .unwrap_err()
+            .contains("min_sdk 34 is above max_sdk 30"));
+        assert!(Preconditions::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,584 @@
+/// Recipe sources for the adaptive engine.
+///
+/// Besides the built-in set, every `*.json` file in `~/.foem/recipes` is read
+/// as either a single `ExploitRecipe` or an array of them. Files are loaded in
+/// name order; a recipe whose name is already taken is reported and skipped.
+/// Recipes listed in `~/.foem/recipes/disabled.txt` (one name per line) are
+/// loaded but not executed.
+use super::preconditions::{DeviceMode, Preconditions};
+use super::{sequence, steps, ExploitRecipe, ExploitStep, FuzzGoal, StepKind};
+use crate::exec;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DISABLED_FILE: &str = "disabled.txt";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecipeSource {
+    Builtin,
+    File(PathBuf),
+}
+
+impl RecipeSource {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Builtin => "built-in".to_string(),
+            Self::File(path) => path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedRecipe {
+    pub recipe: ExploitRecipe,
+    pub source: RecipeSource,
+    pub enabled: bool,
+}
+
+/// All recipes plus the problems found while loading them.
+#[derive(Debug, Clone, Default)]
+pub struct RecipeSet {
+    pub recipes: Vec<LoadedRecipe>,
+    pub errors: Vec<String>,
+}
+
+impl RecipeSet {
+    /// Enabled recipes, in load order.
+    pub fn enabled(&self) -> impl Iterator<Item = &ExploitRecipe> {
+        self.recipes.iter().filter(|r| r.enabled).map(|r| &r.recipe)
+    }
+
+    pub fn summary(&self) -> String {
+        let mut out = format!("{} recipes loaded:\n", self.recipes.len());
+        for r in &self.recipes {
+            out.push_str(&format!(
+                "  [{}] {} ({:?}, {} steps) -- {}\n",
+                if r.enabled { "x" } else { " " },
+                r.recipe.name,
+                r.recipe.goal,
+                r.recipe.steps.len(),
+                r.source.label()
+            ));
+        }
+        if !self.errors.is_empty() {
+            out.push_str("\nRecipe errors:\n");
+            for e in &self.errors {
+                out.push_str(&format!("  {}\n", e));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_RECIPES_DIR: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+pub fn recipes_dir() -> PathBuf {
+    #[cfg(test)]
+    {
+        if let Some(dir) = MOCK_RECIPES_DIR.with(|m| m.borrow().clone()) {
+            return dir;
+        }
+    }
+    exec::foem_home().join("recipes")
+}
+
+pub(crate) fn builtin_recipes() -> Vec<ExploitRecipe> {
+    vec![
+        ExploitRecipe {
+            goal: FuzzGoal::EnableDiagPort,
+            name: "USB Config Toggle".into(),
+            preconditions: Preconditions {
+                mode: Some(DeviceMode::Adb),
+                ..Default::default()
+            },
+            sequential: false,
+            steps: vec![ExploitStep {
+                kind: StepKind::AdbShell,
+                payload: "setprop sys.usb.config diag,adb".into(),
+                success_markers: vec![],
+                failure_markers: vec!["Permission denied".into()],
+                retries: 0,
+                timeout_ms: None,
+                captures: BTreeMap::new(),
+            }],
+            on_failure: vec![],
+        },
+        ExploitRecipe {
+            goal: FuzzGoal::EnableDiagPort,
+            name: "Samsung Diag".into(),
+            preconditions: Preconditions {
+                manufacturer: vec!["samsung".into()],
+                mode: Some(DeviceMode::Adb),
+                ..Default::default()
+            },
+            sequential: false,
+            steps: vec![ExploitStep {
+                kind: StepKind::AdbShell,
+                payload: "setprop sys.usb.config diag,adb; setprop persist.sys.usb.config diag,adb"
+                    .into(),
+                success_markers: vec![],
+                failure_markers: vec![],
+                retries: 0,
+                timeout_ms: None,
+                captures: BTreeMap::new(),
+            }],
+            on_failure: vec![],
+        },
+        ExploitRecipe {
+            goal: FuzzGoal::EnableDiagPort,
+            name: "AT Diag Enable".into(),
+            preconditions: Preconditions {
+                mode: Some(DeviceMode::Diag),
+                ..Default::default()
+            },
+            sequential: false,
+            steps: vec![ExploitStep {
+                kind: StepKind::AtCommand,
+                payload: "AT+DIAG=1".into(),
+                success_markers: vec!["OK".into()],
+                failure_markers: vec!["ERROR".into()],
+                retries: 0,
+                timeout_ms: None,
+                captures: BTreeMap::new(),
+            }],
+            on_failure: vec![],
+        },
+    ]
+}
+
+/// Parse a recipe file holding one recipe object or an array of them.
+pub fn parse_recipe_file(text: &str) -> Result<Vec<ExploitRecipe>, String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| {
+        format!(
+            "invalid JSON at line {} column {}: {}",
+            e.line(),
+            e.column(),
+            e
+        )
+    })?;
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        other => vec![other],
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let name = item
+                .get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| format!("'{}'", n))
+                .unwrap_or_else(|| format!("#{}", i + 1));
+            let recipe: ExploitRecipe =
+                serde_json::from_value(item).map_err(|e| format!("recipe {}: {}", name, e))?;
+            validate_recipe(&recipe).map_err(|e| format!("recipe {}: {}", name, e))?;
+            Ok(recipe)
+        })
+        .collect()
+}
+
+/// Checks serde cannot express: non-empty names, steps and payloads,
+/// payloads that the step kind can actually send, and variables that are
+/// captured before they are used.
+pub fn validate_recipe(recipe: &ExploitRecipe) -> Result<(), String> {
+    if recipe.name.trim().is_empty() {
+        return Err("name must not be empty".into());
+    }
+    if let FuzzGoal::Custom(goal) = &recipe.goal {
+        if goal.trim().is_empty() {
+            return Err("custom goal must not be empty".into());
+        }
+    }
+    recipe.preconditions.validate()?;
+    if recipe.steps.is_empty() {
+        return Err("at least one step is required".into());
+    }
+    if !recipe.sequential && !recipe.on_failure.is_empty() {
+        return Err("on_failure steps require \"sequential\": true".into());
+    }
+
+    let mut captured = BTreeSet::new();
+    for (i, step) in recipe.steps.iter().enumerate() {
+        let label = format!("step {}", i + 1);
+        validate_step(step).map_err(|e| format!("{}: {}", label, e))?;
+        check_references(recipe, step, &captured).map_err(|e| format!("{}: {}", label, e))?;
+        captured.extend(step.captures.keys().map(String::as_str));
+    }
+    for (i, step) in recipe.on_failure.iter().enumerate() {
+        let label = format!("on_failure step {}", i + 1);
+        validate_step(step).map_err(|e| format!("{}: {}", label, e))?;
+        check_references(recipe, step, &captured).map_err(|e| format!("{}: {}", label, e))?;
+    }
+    Ok(())
+}
+
+fn validate_step(step: &ExploitStep) -> Result<(), String> {
+    if step.kind.uses_payload() && step.payload.trim().is_empty() {
+        return Err("payload must not be empty".into());
+    }
+    if !step.kind.uses_payload() && !step.payload.trim().is_empty() {
+        return Err(format!("payload is not used by {:?} steps", step.kind));
+    }
+    match &step.kind {
+        StepKind::Fastboot => {
+            // Variables are substituted before tokenizing, so only check quoting.
+            steps::fastboot_args(&step.payload)?;
+        }
+        StepKind::WaitForMode { timeout_ms: 0, .. } => {
+            return Err("WaitForMode timeout_ms must be greater than 0".into());
+        }
+        StepKind::PushFile { local, remote }
+            if local.trim().is_empty() || remote.trim().is_empty() =>
+        {
+            return Err("PushFile needs both local and remote paths".into());
+        }
+        StepKind::AssertProp { name, .. } if name.trim().is_empty() => {
+            return Err("AssertProp name must not be empty".into());
+        }
+        _ => {}
+    }
+    if step
+        .success_markers
+        .iter()
+        .chain(&step.failure_markers)
+        .any(|m| m.is_empty())
+    {
+        return Err("markers must not be empty strings".into());
+    }
+    if step.timeout_ms == Some(0) {
+        return Err("timeout_ms must be greater than 0".into());
+    }
+    if matches!(step.kind, StepKind::RawDiag)
+        && hex::decode(step.payload.replace([' ', '\n', '\r'], "")).is_err()
+    {
+        return Err("RawDiag payload must be hex bytes".into());
+    }
+    for (name, pattern) in &step.captures {
+        if !sequence::is_valid_var_name(name) {
+            return Err(format!(
+                "capture name '{}' may only use A-Z, a-z, 0-9 and _",
+                name
+            ));
+        }
+        regex::Regex::new(pattern).map_err(|e| format!("capture '{}': {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn check_references(
+    recipe: &ExploitRecipe,
+    step: &ExploitStep,
+    captured: &BTreeSet<&str>,
+) -> Result<(), String> {
+    let texts = std::iter::once(step.payload.as_str()).chain(step.kind.texts());
+    for name in texts.flat_map(sequence::referenced_vars) {
+        if !recipe.sequential {
+            return Err(format!("${{{}}} requires \"sequential\": true", name));
+        }
+        if !captured.contains(name) {
+            return Err(format!("${{{}}} is not captured by an earlier step", name));
+        }
+    }
+    Ok(())
+}
+
+fn read_disabled(dir: &Path) -> BTreeSet<String> {
+    fs::read_to_string(dir.join(DISABLED_FILE))
+        .map(|text| {
+            text.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Built-in recipes followed by those in `recipes_dir()`.
+pub fn load_recipes() -> RecipeSet {
+    let dir = recipes_dir();
+    let disabled = read_disabled(&dir);
+    let mut set = RecipeSet::default();
+    let add = |set: &mut RecipeSet, recipe: ExploitRecipe, source: RecipeSource| {
+        if let Some(existing) = set.recipes.iter().find(|r| r.recipe.name == recipe.name) {
+            set.errors.push(format!(
+                "{}: duplicate recipe name '{}' (already defined in {}), skipped",
+                source.label(),
+                recipe.name,
+                existing.source.label()
+            ));
+            return;
+        }
+        set.recipes.push(LoadedRecipe {
+            enabled: !disabled.contains(&recipe.name),
+            recipe,
+            source,
+        });
+    };
+
+    for recipe in builtin_recipes() {
+        add(&mut set, recipe, RecipeSource::Builtin);
+    }
+
+    let mut files: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    files.sort();
+    for path in files {
+        let source = RecipeSource::File(path.clone());
+        let parsed = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| parse_recipe_file(&text));
+        match parsed {
+            Ok(recipes) => {
+                for recipe in recipes {
+                    add(&mut set, recipe, source.clone());
+                }
+            }
+            Err(e) => set.errors.push(format!("{}: {}", source.label(), e)),
+        }
+    }
+    set
+}
+
+/// Enable or disable a recipe by name; persisted in `disabled.txt`.
+pub fn set_enabled(name: &str, enabled: bool) -> Result<(), String> {
+    let dir = recipes_dir();
+    let mut disabled = read_disabled(&dir);
+    if enabled {
+        disabled.remove(name);
+    } else {
+        disabled.insert(name.to_string());
+    }
+    fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    let mut text = String::from("# Recipes listed here are not executed.\n");
+    for name in &disabled {
+        text.push_str(name);
+        text.push('\n');
+    }
+    let path = dir.join(DISABLED_FILE);
+    fs::write(&path, text).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    struct DirGuard;
+    impl Drop for DirGuard {
+        fn drop(&mut self) {
+            MOCK_RECIPES_DIR.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    fn use_dir(name: &str) -> std::io::Result<(PathBuf, DirGuard)> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        MOCK_RECIPES_DIR.with(|m| *m.borrow_mut() = Some(dir.clone()));
+        Ok((dir, DirGuard))
+    }
+
+    /// The error `text` is rejected with, or an error if it parses.
+    fn parse_err(text: &str) -> Result<String, Box<dyn Error>> {
+        match parse_recipe_file(text) {
+            Ok(_) => Err("recipe file unexpectedly parsed".into()),
+            Err(e) => Ok(e),
+        }
+    }
+
+    const RECIPE: &str = r#"{
+        "goal": "EnableDiagPort",
+        "name": "Tecno Diag",
+        "steps": [{
+            "kind": "AdbShell",
+            "payload": "setprop sys.usb.config diag,adb",
+            "success_markers": [],
+            "failure_markers": ["denied"]
+        }]
+    }"#;
+
+    #[test]
+    fn test_parse_recipe_file_single_and_array() -> Result<(), Box<dyn Error>> {
+        assert_eq!(parse_recipe_file(RECIPE)?[0].name, "Tecno Diag");
+        let array = format!("[{}, {}]", RECIPE, RECIPE.replace("Tecno Diag", "Other"));
+        assert_eq!(parse_recipe_file(&array)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_recipe_file_errors() -> Result<(), Box<dyn Error>> {
+        let err = parse_err("{\n  \"name\": ")?;
+        assert!(err.starts_with("invalid JSON at line 2"), "{}", err);
+
+        let err = parse_err(&RECIPE.replace("AdbShell", "Telnet"))?;
+        assert!(
+            err.starts_with("recipe 'Tecno Diag': unknown variant `Telnet`"),
+            "{}",
+            err
+        );
+
+        let err = parse_err(&RECIPE.replace("\"payload\"", "\"paylod\""))?;
+        assert!(err.contains("unknown field `paylod`"), "{}", err);
+
+        let err = parse_err(
+            &RECIPE
+                .replace("AdbShell", "RawDiag")
+                .replace("setprop sys.usb.config diag,adb", "4b 0z"),
+        )?;
+        assert_eq!(
+            err,
+            "recipe 'Tecno Diag': step 1: RawDiag payload must be hex bytes"
+        );
+
+        let err = parse_err(&RECIPE.replace("\"denied\"", "\"\""))?;
+        assert!(
+            err.ends_with("markers must not be empty strings"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_sequential_variables() -> Result<(), Box<dyn Error>> {
+        let seq = r#"{
+            "goal": "EnableDiagPort",
+            "name": "Seq",
+            "sequential": true,
+            "steps": [
+                {"kind": "AdbShell", "payload": "getprop sys.usb.config",
+                 "success_markers": [], "failure_markers": [], "captures": {"old": "(.+)"}},
+                {"kind": "AdbShell", "payload": "setprop sys.usb.config diag,adb",
+                 "success_markers": [], "failure_markers": []}
+            ],
+            "on_failure": [
+                {"kind": "AdbShell", "payload": "setprop sys.usb.config ${old}",
+                 "success_markers": [], "failure_markers": []}
+            ]
+        }"#;
+        let recipe = &parse_recipe_file(seq)?[0];
+        assert!(recipe.sequential);
+        assert_eq!(recipe.on_failure.len(), 1);
+
+        let err = parse_err(&seq.replace("diag,adb\"", "${old2}\""))?;
+        assert!(
+            err.ends_with("step 2: ${old2} is not captured by an earlier step"),
+            "{}",
+            err
+        );
+
+        let err = parse_err(&seq.replace("\"sequential\": true", "\"sequential\": false"))?;
+        assert!(err.contains("on_failure steps require"), "{}", err);
+
+        let err = parse_err(&seq.replace("\"(.+)\"", "\"(.+\""))?;
+        assert!(err.contains("step 1: capture 'old':"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_procedural_step_kinds() -> Result<(), Box<dyn Error>> {
+        let flow = r#"{
+            "goal": "EnableDiagPort",
+            "name": "Fastboot Diag",
+            "sequential": true,
+            "steps": [
+                {"kind": {"Reboot": "Bootloader"}, "success_markers": [], "failure_markers": []},
+                {"kind": {"WaitForMode": {"mode": "Fastboot", "timeout_ms": 30000}},
+                 "success_markers": [], "failure_markers": []},
+                {"kind": "Fastboot", "payload": "oem config set 'usb diag'",
+                 "success_markers": [], "failure_markers": ["FAILED"]},
+                {"kind": {"Sleep": {"ms": 500}}, "success_markers": [], "failure_markers": []},
+                {"kind": {"Reboot": "System"}, "success_markers": [], "failure_markers": []},
+                {"kind": {"AssertProp": {"name": "sys.usb.config", "expected": "diag*"}},
+                 "success_markers": [], "failure_markers": []}
+            ]
+        }"#;
+        let recipe = &parse_recipe_file(flow)?[0];
+        assert_eq!(
+            recipe.steps[0].kind,
+            StepKind::Reboot(steps::RebootTarget::Bootloader)
+        );
+        assert_eq!(recipe.steps[3].kind, StepKind::Sleep { ms: 500 });
+
+        let err = parse_err(&flow.replace("30000", "0"))?;
+        assert!(
+            err.ends_with("step 2: WaitForMode timeout_ms must be greater than 0"),
+            "{}",
+            err
+        );
+
+        let err = parse_err(&flow.replace("'usb diag'", "'usb diag"))?;
+        assert!(
+            err.contains("step 3: cannot tokenize fastboot payload"),
+            "{}",
+            err
+        );
+
+        let err = parse_err(&flow.replace(
+            "{\"Sleep\": {\"ms\": 500}}",
+            "{\"Sleep\": {\"ms\": 500}}, \"payload\": \"x\"",
+        ))?;
+        assert!(
+            err.contains("step 4: payload is not used by Sleep"),
+            "{}",
+            err
+        );
+
+        let err = parse_err(&flow.replace("\"diag*\"", "\"${cfg}\""))?;
+        assert!(
+            err.ends_with("step 6: ${cfg} is not captured by an earlier step"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_recipes_sources_duplicates_and_disabled() -> Result<(), Box<dyn Error>> {
+        let (dir, _guard) = use_dir("foem_recipes_load")?;
+        fs::write(dir.join("a.json"), RECIPE)?;
+        fs::write(dir.join("b.json"), RECIPE)?;
+        fs::write(
+            dir.join("c.json"),
+            RECIPE.replace("Tecno Diag", "Samsung Diag"),
+        )?;
+        fs::write(dir.join("d.json"), "not json")?;
+        fs::write(dir.join("notes.txt"), "ignored")?;
+
+        set_enabled("USB Config Toggle", false)?;
+        let set = load_recipes();
+        assert_eq!(set.recipes.len(), 4);
+        let tecno = set
+            .recipes
+            .iter()
+            .find(|r| r.recipe.name == "Tecno Diag")
+            .ok_or("Tecno Diag not loaded")?;
+        assert_eq!(tecno.source.label(), "a.json");
+        assert_eq!(set.errors.len(), 3, "{:?}", set.errors);
+        assert!(set.errors[0].starts_with("b.json: duplicate recipe name 'Tecno Diag'"));
+        assert!(set.errors[1].contains("already defined in built-in"));
+        assert!(set.errors[2].starts_with("d.json: invalid JSON"));
+        assert!(!set.enabled().any(|r| r.name == "USB Config Toggle"));
+        assert_eq!(set.enabled().count(), 3);
+
+        set_enabled("USB Config Toggle", true)?;
+        assert_eq!(load_recipes().enabled().count(), 4);
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
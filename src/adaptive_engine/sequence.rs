@@ -0,0 +1,428 @@
+/// Ordered, transactional execution of `sequential` recipes.
+///
+/// Every step must pass its markers before the next one runs. A step's
+/// `captures` pull regex groups out of its output into variables that later
+/// payloads reference as `${name}`. When a step fails, the recipe's
+/// `on_failure` steps run best-effort to put the device back the way it was.
+use super::{check_markers, execute_step, ExploitRecipe, ExploitStep, StepKind, RETRY_DELAY};
+
+use regex::Regex;
+use std::collections::BTreeMap;
+
+pub type Variables = BTreeMap<String, String>;
+
+/// Names referenced as `${name}` in `payload`, in order of appearance.
+pub fn referenced_vars(payload: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = payload;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        match after.find('}') {
+            Some(end) => {
+                names.push(&after[..end]);
+                rest = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+pub fn is_valid_var_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Substitute `${name}` references; unknown names are an error. With `quote`
+/// every value is shell-quoted, so captured device output stays one word and
+/// cannot smuggle extra commands into the payload.
+pub fn expand(payload: &str, vars: &Variables, quote: bool) -> Result<String, String> {
+    let mut out = String::with_capacity(payload.len());
+    let mut rest = payload;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            return Err("unterminated ${ in payload".into());
+        };
+        let name = &after[..end];
+        let value = vars
+            .get(name)
+            .ok_or_else(|| format!("variable '{}' is not set", name))?;
+        if quote {
+            let quoted =
+                shlex::try_quote(value).map_err(|e| format!("variable '{}': {}", name, e))?;
+            out.push_str(&quoted);
+        } else {
+            out.push_str(value);
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Store each capture's first group (or whole match) from `out` into `vars`;
+/// nothing is stored unless every capture matched.
+pub fn capture(step: &ExploitStep, out: &str, vars: &mut Variables) -> Result<(), String> {
+    let mut found = Variables::new();
+    for (name, pattern) in &step.captures {
+        let re = Regex::new(pattern).map_err(|e| format!("capture '{}': {}", name, e))?;
+        let caps = re
+            .captures(out)
+            .ok_or_else(|| format!("capture '{}': /{}/ did not match", name, pattern))?;
+        let value = caps
+            .get(1)
+            .or_else(|| caps.get(0))
+            .map_or("", |m| m.as_str());
+        found.insert(name.clone(), value.to_string());
+    }
+    vars.extend(found);
+    Ok(())
+}
+
+/// `step` with variables substituted in its payload and kind fields. Shell
+/// and fastboot payloads are split into words, so their values are quoted.
+pub fn expand_step(step: &ExploitStep, vars: &Variables) -> Result<ExploitStep, String> {
+    let quote = matches!(step.kind, StepKind::AdbShell | StepKind::Fastboot);
+    Ok(ExploitStep {
+        kind: step.kind.map_texts(|text| expand(text, vars, false))?,
+        payload: expand(&step.payload, vars, quote)?,
+        ..step.clone()
+    })
+}
+
+fn kind_label(step: &ExploitStep) -> &'static str {
+    match step.kind {
+        StepKind::AdbShell => "AdbShell",
+        StepKind::Fastboot => "Fastboot",
+        StepKind::AtCommand => "AtCommand",
+        StepKind::RawDiag => "RawDiag",
+        StepKind::Reboot(_) => "Reboot",
+        StepKind::WaitForMode { .. } => "WaitForMode",
+        StepKind::PushFile { .. } => "PushFile",
+        StepKind::Sleep { .. } => "Sleep",
+        StepKind::AssertProp { .. } => "AssertProp",
+    }
+}
+
+/// One-line description of what a step does, for transcripts.
+pub fn describe(step: &ExploitStep) -> String {
+    match &step.kind {
+        StepKind::Reboot(target) => format!("{:?}", target),
+        StepKind::WaitForMode { mode, timeout_ms } => {
+            format!("{:?} within {} ms", mode, timeout_ms)
+        }
+        StepKind::PushFile { local, remote } => format!("{} -> {}", local, remote),
+        StepKind::Sleep { ms } => format!("{} ms", ms),
+        StepKind::AssertProp { name, expected } => format!("{} == {}", name, expected),
+        _ => step.payload.clone(),
+    }
+}
+
+/// Run `step` until its markers pass, at most `retries` times (once when 0).
+fn run_step(
+    serial: &str,
+    step: &ExploitStep,
+    vars: &Variables,
+    diag_port_hint: Option<&str>,
+) -> Result<(String, String), String> {
+    let expanded = expand_step(step, vars)?;
+    // `execute_step` retries transport errors on its own; one call per
+    // attempt here keeps the total at `retries`.
+    let single = ExploitStep {
+        retries: 1,
+        ..expanded.clone()
+    };
+    let attempts = step.retries.max(1);
+    let mut last_error = String::new();
+    for attempt in 1..=attempts {
+        match execute_step(serial, &single, diag_port_hint)
+            .and_then(|out| check_markers(step, &out).map(|()| out))
+        {
+            Ok(out) => return Ok((describe(&expanded), out)),
+            Err(e) => last_error = e,
+        }
+        if attempt < attempts {
+            std::thread::sleep(RETRY_DELAY);
+        }
+    }
+    if attempts > 1 {
+        last_error = format!("{} (after {} attempts)", last_error, attempts);
+    }
+    Err(last_error)
+}
+
+/// Run all steps of `recipe` in order. `Ok` and `Err` both carry the
+/// transcript; on `Err` it ends with the rollback results.
+pub fn run_sequence(
+    serial: &str,
+    recipe: &ExploitRecipe,
+    diag_port_hint: Option<&str>,
+) -> Result<String, String> {
+    let mut vars = Variables::new();
+    let mut transcript = String::new();
+    let total = recipe.steps.len();
+    for (i, step) in recipe.steps.iter().enumerate() {
+        let result = run_step(serial, step, &vars, diag_port_hint).and_then(|(payload, out)| {
+            capture(step, &out, &mut vars)?;
+            Ok((payload, out))
+        });
+        match result {
+            Ok((payload, out)) => {
+                transcript.push_str(&format!(
+                    "Step {}/{} [{}] {}\n",
+                    i + 1,
+                    total,
+                    kind_label(step),
+                    payload
+                ));
+                if !out.trim().is_empty() {
+                    transcript.push_str(&format!("  -> {}\n", out.trim()));
+                }
+            }
+            Err(e) => {
+                transcript.push_str(&format!(
+                    "Step {}/{} [{}] failed: {}\n",
+                    i + 1,
+                    total,
+                    kind_label(step),
+                    e
+                ));
+                rollback(serial, recipe, &vars, diag_port_hint, &mut transcript);
+                return Err(transcript);
+            }
+        }
+    }
+    Ok(transcript)
+}
+
+fn rollback(
+    serial: &str,
+    recipe: &ExploitRecipe,
+    vars: &Variables,
+    diag_port_hint: Option<&str>,
+    transcript: &mut String,
+) {
+    if recipe.on_failure.is_empty() {
+        return;
+    }
+    transcript.push_str("Rollback:\n");
+    for step in &recipe.on_failure {
+        let line = match run_step(serial, step, vars, diag_port_hint) {
+            Ok((payload, _)) => format!("  [{}] {} -- ok\n", kind_label(step), payload),
+            Err(e) => format!(
+                "  [{}] {} -- failed: {}\n",
+                kind_label(step),
+                describe(step),
+                e
+            ),
+        };
+        transcript.push_str(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive_engine::{FuzzGoal, MOCK_STEP_IMPL};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn shell(payload: &str, success: &[&str]) -> ExploitStep {
+        ExploitStep {
+            kind: StepKind::AdbShell,
+            payload: payload.into(),
+            success_markers: success.iter().map(|m| m.to_string()).collect(),
+            failure_markers: vec![],
+            retries: 0,
+            timeout_ms: None,
+            captures: BTreeMap::new(),
+        }
+    }
+
+    fn usb_recipe() -> ExploitRecipe {
+        let mut read = shell("getprop sys.usb.config", &[]);
+        read.captures.insert("old".into(), r"^(\S+)".into());
+        ExploitRecipe {
+            goal: FuzzGoal::EnableDiagPort,
+            name: "USB sequence".into(),
+            preconditions: Default::default(),
+            sequential: true,
+            steps: vec![
+                read,
+                shell("setprop sys.usb.config diag,adb", &[]),
+                shell("getprop sys.usb.config", &["diag"]),
+            ],
+            on_failure: vec![shell("setprop sys.usb.config ${old}", &[])],
+        }
+    }
+
+    /// Mock device whose `sys.usb.config` is changed by setprop when `sticky`.
+    fn mock_device(sticky: bool) -> Rc<RefCell<Vec<String>>> {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let state = RefCell::new("mtp,adb".to_string());
+        let seen = log.clone();
+        MOCK_STEP_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(move |step| {
+                seen.borrow_mut().push(step.payload.clone());
+                if let Some(value) = step.payload.strip_prefix("setprop sys.usb.config ") {
+                    if sticky {
+                        *state.borrow_mut() = value.to_string();
+                    }
+                    return Ok(String::new());
+                }
+                Ok(state.borrow().clone())
+            }));
+        });
+        log
+    }
+
+    struct StepMockGuard;
+    impl Drop for StepMockGuard {
+        fn drop(&mut self) {
+            MOCK_STEP_IMPL.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    #[test]
+    fn test_run_sequence_success() -> Result<(), Box<dyn std::error::Error>> {
+        let _guard = StepMockGuard;
+        let log = mock_device(true);
+        let out = run_sequence("SER", &usb_recipe(), None)?;
+        assert!(out.contains("Step 3/3 [AdbShell] getprop sys.usb.config\n  -> diag,adb"));
+        assert_eq!(log.borrow().len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_sequence_failure_rolls_back_with_captured_value() {
+        let _guard = StepMockGuard;
+        let log = mock_device(false);
+        let out = run_sequence("SER", &usb_recipe(), None).unwrap_err();
+        assert!(out.contains("Step 3/3 [AdbShell] failed: matched no success markers"));
+        assert!(out.contains("Rollback:\n  [AdbShell] setprop sys.usb.config 'mtp,adb' -- ok"));
+        assert_eq!(
+            log.borrow().last().map(String::as_str),
+            Some("setprop sys.usb.config 'mtp,adb'")
+        );
+    }
+
+    #[test]
+    fn test_failed_capture_stops_sequence() {
+        let _guard = StepMockGuard;
+        let log = mock_device(true);
+        let mut recipe = usb_recipe();
+        recipe.steps[0]
+            .captures
+            .insert("serial".into(), "serialno=(\\w+)".into());
+        let out = run_sequence("SER", &recipe, None).unwrap_err();
+        assert!(
+            out.contains("Step 1/3 [AdbShell] failed: capture 'serial'"),
+            "{}",
+            out
+        );
+        // Rollback still runs; `old` was not stored because the step failed.
+        assert!(
+            out.contains("-- failed: variable 'old' is not set"),
+            "{}",
+            out
+        );
+        assert_eq!(log.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_retries_rerun_until_markers_pass() {
+        let _guard = StepMockGuard;
+        let calls = Rc::new(RefCell::new(0));
+        let count = calls.clone();
+        MOCK_STEP_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(move |_| {
+                *count.borrow_mut() += 1;
+                Ok(if *count.borrow() < 3 { "mtp" } else { "diag" }.into())
+            }));
+        });
+        let mut check = shell("getprop sys.usb.config", &["diag"]);
+        check.retries = 3;
+        let recipe = ExploitRecipe {
+            steps: vec![check.clone()],
+            on_failure: vec![],
+            ..usb_recipe()
+        };
+        assert!(run_sequence("SER", &recipe, None).is_ok());
+        assert_eq!(*calls.borrow(), 3);
+
+        *calls.borrow_mut() = 0;
+        check.retries = 2;
+        let recipe = ExploitRecipe {
+            steps: vec![check],
+            ..recipe
+        };
+        let out = run_sequence("SER", &recipe, None).unwrap_err();
+        assert!(out.contains("(after 2 attempts)"), "{}", out);
+        assert_eq!(*calls.borrow(), 2);
+    }
+
+    #[test]
+    fn test_kind_fields_are_expanded() -> Result<(), Box<dyn std::error::Error>> {
+        let _guard = StepMockGuard;
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        MOCK_STEP_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(move |step| {
+                log.borrow_mut().push(step.kind.clone());
+                Ok("diag,adb".into())
+            }));
+        });
+        let mut read = shell("getprop sys.usb.config", &[]);
+        read.captures.insert("cfg".into(), "(.+)".into());
+        let assert = ExploitStep {
+            kind: StepKind::AssertProp {
+                name: "persist.sys.usb.config".into(),
+                expected: "${cfg}".into(),
+            },
+            ..shell("", &[])
+        };
+        let recipe = ExploitRecipe {
+            steps: vec![read, assert],
+            on_failure: vec![],
+            ..usb_recipe()
+        };
+        let out = run_sequence("SER", &recipe, None)?;
+        assert!(
+            out.contains("Step 2/2 [AssertProp] persist.sys.usb.config == diag,adb"),
+            "{}",
+            out
+        );
+        assert_eq!(
+            seen.borrow()[1],
+            StepKind::AssertProp {
+                name: "persist.sys.usb.config".into(),
+                expected: "diag,adb".into(),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_and_references() {
+        let mut vars = Variables::new();
+        vars.insert("cfg".into(), "mtp,adb".into());
+        vars.insert("evil".into(), "x; reboot".into());
+        assert_eq!(
+            expand("setprop sys.usb.config ${cfg}", &vars, true),
+            Ok("setprop sys.usb.config 'mtp,adb'".into())
+        );
+        assert_eq!(
+            expand("setprop a ${evil}", &vars, true),
+            Ok("setprop a 'x; reboot'".into())
+        );
+        assert_eq!(expand("${evil}", &vars, false), Ok("x; reboot".into()));
+        assert_eq!(expand("echo $HOME", &vars, true), Ok("echo $HOME".into()));
+        assert_eq!(
+            expand("echo ${missing}", &vars, true),
+            Err("variable 'missing' is not set".into())
+        );
+        assert_eq!(referenced_vars("a ${x} b ${y_2}"), vec!["x", "y_2"]);
+        assert!(!is_valid_var_name("a-b"));
+    }
+}
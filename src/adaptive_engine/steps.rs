@@ -0,0 +1,211 @@
+/// Implementations of the procedural step kinds: reboot, wait-for-mode, file
+/// push, sleep and property assertion, plus fastboot argument tokenizing.
+use super::autodetect_diag_port;
+use super::preconditions::{glob_match, DeviceMode};
+use crate::exec;
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RebootTarget {
+    System,
+    Bootloader,
+    Recovery,
+    Fastbootd,
+    Edl,
+    Download,
+}
+
+impl RebootTarget {
+    /// Argument for `adb reboot`; `None` means a plain reboot.
+    fn adb_arg(self) -> Option<&'static str> {
+        match self {
+            Self::System => None,
+            Self::Bootloader => Some("bootloader"),
+            Self::Recovery => Some("recovery"),
+            Self::Fastbootd => Some("fastboot"),
+            Self::Edl => Some("edl"),
+            Self::Download => Some("download"),
+        }
+    }
+
+    /// Fastboot arguments, when the bootloader supports this target.
+    fn fastboot_args(self) -> Option<Vec<&'static str>> {
+        match self {
+            Self::System => Some(vec!["reboot"]),
+            Self::Bootloader => Some(vec!["reboot-bootloader"]),
+            Self::Recovery => Some(vec!["reboot", "recovery"]),
+            Self::Fastbootd => Some(vec!["reboot", "fastboot"]),
+            Self::Edl => Some(vec!["oem", "edl"]),
+            Self::Download => None,
+        }
+    }
+}
+
+/// Split a fastboot payload into argv the way a shell would.
+pub fn fastboot_args(payload: &str) -> Result<Vec<String>, String> {
+    match shlex::split(payload) {
+        Some(args) if !args.is_empty() => Ok(args),
+        Some(_) => Err("empty fastboot command".into()),
+        None => Err(format!("cannot tokenize fastboot payload: {}", payload)),
+    }
+}
+
+fn adb_state(serial: &str) -> Option<String> {
+    exec::run_with_timeout(
+        "adb",
+        &["-s", serial, "get-state"],
+        "ADB",
+        Duration::from_secs(5),
+    )
+    .ok()
+}
+
+fn in_fastboot(serial: &str) -> bool {
+    exec::run_with_timeout("fastboot", &["devices"], "Fastboot", Duration::from_secs(5)).is_ok_and(
+        |out| {
+            out.lines()
+                .any(|l| l.split_whitespace().next() == Some(serial))
+        },
+    )
+}
+
+/// Whether `serial` can currently be reached in `mode`.
+pub fn mode_available(serial: &str, mode: DeviceMode, diag_port_hint: Option<&str>) -> bool {
+    match mode {
+        DeviceMode::Adb => adb_state(serial).is_some_and(|s| s == "device"),
+        DeviceMode::Fastboot => in_fastboot(serial),
+        DeviceMode::Diag => diag_port_hint.is_some() || autodetect_diag_port().is_some(),
+    }
+}
+
+/// Reboot through adb when it answers, otherwise through fastboot.
+pub fn reboot(serial: &str, target: RebootTarget, timeout: Duration) -> Result<String, String> {
+    if adb_state(serial).is_some() {
+        let mut args = vec!["-s", serial, "reboot"];
+        args.extend(target.adb_arg());
+        exec::run_with_timeout("adb", &args, "ADB reboot", timeout)?;
+        return Ok(format!("Rebooting to {:?} via adb", target));
+    }
+    if in_fastboot(serial) {
+        let extra = target
+            .fastboot_args()
+            .ok_or_else(|| format!("fastboot cannot reboot to {:?}", target))?;
+        let mut args = vec!["-s", serial];
+        args.extend(extra);
+        exec::run_with_timeout("fastboot", &args, "Fastboot reboot", timeout)?;
+        return Ok(format!("Rebooting to {:?} via fastboot", target));
+    }
+    Err(format!("{} is not reachable over adb or fastboot", serial))
+}
+
+/// Poll once a second until the device shows up in `mode`.
+pub fn wait_for_mode(
+    serial: &str,
+    mode: DeviceMode,
+    timeout: Duration,
+    diag_port_hint: Option<&str>,
+) -> Result<String, String> {
+    let start = Instant::now();
+    loop {
+        if mode_available(serial, mode, diag_port_hint) {
+            return Ok(format!(
+                "Device in {:?} mode after {}s",
+                mode,
+                start.elapsed().as_secs()
+            ));
+        }
+        if start.elapsed() >= timeout {
+            return Err(format!(
+                "Device not in {:?} mode after {}s",
+                mode,
+                timeout.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+pub fn push_file(
+    serial: &str,
+    local: &str,
+    remote: &str,
+    timeout: Duration,
+) -> Result<String, String> {
+    let local = exec::normalize_local_path(local);
+    if !std::path::Path::new(&local).is_file() {
+        return Err(format!("Local file not found: {}", local));
+    }
+    exec::run_with_timeout(
+        "adb",
+        &["-s", serial, "push", &local, remote],
+        "ADB push",
+        timeout,
+    )
+}
+
+/// Compare a property value with `expected`, which may use `*` and `?`.
+pub fn compare_prop(name: &str, actual: &str, expected: &str) -> Result<String, String> {
+    let actual = actual.trim();
+    if glob_match(expected, actual) {
+        Ok(format!("{}={}", name, actual))
+    } else {
+        Err(format!("{} is '{}', expected '{}'", name, actual, expected))
+    }
+}
+
+pub fn assert_prop(
+    serial: &str,
+    name: &str,
+    expected: &str,
+    timeout: Duration,
+) -> Result<String, String> {
+    let actual = exec::run_with_timeout(
+        "adb",
+        &["-s", serial, "shell", "getprop", name],
+        "ADB shell",
+        timeout,
+    )?;
+    compare_prop(name, &actual, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fastboot_args_tokenized() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            fastboot_args("oem  config set 'usb diag'")?,
+            vec!["oem", "config", "set", "usb diag"]
+        );
+        assert!(fastboot_args("flash \"boot").is_err());
+        assert!(fastboot_args("   ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reboot_target_args() {
+        assert_eq!(RebootTarget::System.adb_arg(), None);
+        assert_eq!(RebootTarget::Fastbootd.adb_arg(), Some("fastboot"));
+        assert_eq!(
+            RebootTarget::Bootloader.fastboot_args(),
+            Some(vec!["reboot-bootloader"])
+        );
+        assert_eq!(RebootTarget::Download.fastboot_args(), None);
+    }
+
+    #[test]
+    fn test_compare_prop() {
+        assert_eq!(
+            compare_prop("sys.usb.config", "diag,adb\n", "diag,adb"),
+            Ok("sys.usb.config=diag,adb".into())
+        );
+        assert!(compare_prop("sys.usb.config", "diag,serial_cdev,adb", "diag*").is_ok());
+        assert_eq!(
+            compare_prop("sys.usb.config", "mtp,adb", "diag*"),
+            Err("sys.usb.config is 'mtp,adb', expected 'diag*'".into())
+        );
+    }
+}
@@ -6,7 +6,6 @@ use eframe::egui;
 
 use crate::diagnostics::DeviceDiagnostics;
 use crate::display_version;
-use std::borrow::Cow;
 use crate::features::ai_assistant::{
     self, AiAssistantState, AiSettings, Provider, TelemetrySnapshot,
 };
@@ -14,6 +13,7 @@ use crate::features::{self, Manufacturer};
 use crate::license_text::{COMMUNITY_LINKS, CRYPTO_DONATIONS, FIAT_DONATIONS, LICENSE_TEXT};
 use crate::theme;
 use crate::update_manager::UpdateManager;
+use std::borrow::Cow;
 
 #[derive(Default, PartialEq, Clone, Copy)]
 enum Panel {
@@ -25,6 +25,7 @@ enum Panel {
     Flash,
     Diagnostics,
     Tools,
+    SerialConsole,
     AiAssistant,
     ServerAuth,
     Updates,
@@ -44,11 +45,40 @@ pub struct FOEMApp {
     adb_command: String,
     nck_input: String,
     flash_path: String,
+    edl_memory_idx: usize,
+    edl_lun: String,
+    edl_start_sector: String,
+    edl_sector_count: String,
+    edl_dump_path: String,
+    edl_image_path: String,
+    edl_patch_path: String,
+    /// Storage write waiting for confirmation, with what it will change.
+    edl_pending: Option<(
+        features::edl::firehose::Memory,
+        features::flash::EdlWrite,
+        String,
+    )>,
+    edl_gpt_confirmed: bool,
+    /// EDL sector snapshots of the device the programmer was loaded on.
+    edl_snapshots: Vec<features::vault::VaultEntry>,
+    edl_snapshot_idx: usize,
+    /// Package flash plan waiting for confirmation, with its summary.
+    edl_package_pending: Option<(
+        features::edl::firehose::Memory,
+        features::edl::rawprogram::FlashPlan,
+        String,
+    )>,
+    edl_job: Option<features::flash::EdlJob>,
+    edl_package_dir: String,
+    edl_include: String,
+    edl_exclude: String,
     payload_path: String,
     partition_idx: usize,
     package_filter: String,
     remote_path: String,
     local_path: String,
+    knowledge_bundle_path: String,
+    dry_run_samples_path: String,
     show_full_license: bool,
     ai_settings: AiSettings,
     ai_state: AiAssistantState,
@@ -57,6 +87,34 @@ pub struct FOEMApp {
     server_auth_token: String,
     server_proxy: String,
     service_account_id: String,
+    vault_entries: Vec<features::vault::VaultEntry>,
+    vault_entry_idx: usize,
+    partition_list: Vec<(features::partitions::PartitionInfo, bool)>,
+    partition_root: Option<features::partitions::RootMode>,
+    dump_options: features::partitions::DumpOptions,
+    dump_job: Option<features::partitions::DumpJob>,
+    dump_file_idx: usize,
+    restore_job: Option<std::thread::JoinHandle<String>>,
+    capture_equipment: Vec<bool>,
+    capture_events: bool,
+    capture_job: Option<features::modem_log::CaptureJob>,
+    capture_records: Vec<features::modem_log::DiagRecord>,
+    capture_filter: String,
+    capture_file: String,
+    console_port: String,
+    console_ports: Vec<String>,
+    console_settings: features::serial_console::LineSettings,
+    console_session: Option<features::serial_console::ConsoleSession>,
+    console_entries: Vec<features::serial_console::ConsoleEntry>,
+    console_input: String,
+    console_history: features::serial_console::CommandHistory,
+    console_hex_view: bool,
+    console_send_hex: bool,
+    prop_snapshots: Vec<(std::path::PathBuf, features::prop_snapshot::PropSnapshot)>,
+    snapshot_a_idx: usize,
+    snapshot_b_idx: usize,
+    snapshot_hide_volatile: bool,
+    recipe_set: crate::adaptive_engine::recipes::RecipeSet,
 }
 
 impl FOEMApp {
@@ -82,11 +140,29 @@ impl FOEMApp {
             adb_command: String::new(),
             nck_input: String::new(),
             flash_path: String::new(),
+            edl_memory_idx: 0,
+            edl_lun: "0".into(),
+            edl_start_sector: "0".into(),
+            edl_sector_count: String::new(),
+            edl_dump_path: String::new(),
+            edl_image_path: String::new(),
+            edl_patch_path: String::new(),
+            edl_pending: None,
+            edl_gpt_confirmed: false,
+            edl_snapshots: Vec::new(),
+            edl_snapshot_idx: 0,
+            edl_package_pending: None,
+            edl_job: None,
+            edl_package_dir: String::new(),
+            edl_include: String::new(),
+            edl_exclude: features::edl::rawprogram::DEFAULT_EXCLUDED.join(", "),
             payload_path: String::new(),
             partition_idx: 0,
             package_filter: String::new(),
             remote_path: String::new(),
             local_path: String::new(),
+            knowledge_bundle_path: String::new(),
+            dry_run_samples_path: String::new(),
             show_full_license: false,
             ai_settings: AiSettings::default(),
             ai_state: AiAssistantState::default(),
@@ -95,6 +171,34 @@ impl FOEMApp {
             server_auth_token: String::new(),
             server_proxy: String::new(),
             service_account_id: String::new(),
+            vault_entries: Vec::new(),
+            vault_entry_idx: 0,
+            partition_list: Vec::new(),
+            partition_root: None,
+            dump_options: features::partitions::DumpOptions::default(),
+            dump_job: None,
+            dump_file_idx: 0,
+            restore_job: None,
+            capture_equipment: vec![true; features::modem_log::EQUIPMENT.len()],
+            capture_events: true,
+            capture_job: None,
+            capture_records: Vec::new(),
+            capture_filter: String::new(),
+            capture_file: String::new(),
+            console_port: String::new(),
+            console_ports: Vec::new(),
+            console_settings: features::serial_console::LineSettings::default(),
+            console_session: None,
+            console_entries: Vec::new(),
+            console_input: String::new(),
+            console_history: features::serial_console::CommandHistory::default(),
+            console_hex_view: false,
+            console_send_hex: false,
+            prop_snapshots: features::prop_snapshot::list_snapshots(),
+            snapshot_a_idx: 0,
+            snapshot_b_idx: 0,
+            snapshot_hide_volatile: true,
+            recipe_set: crate::adaptive_engine::recipes::load_recipes(),
         }
     }
 
@@ -122,6 +226,7 @@ impl FOEMApp {
             Panel::Flash => "Flash",
             Panel::Diagnostics => "Diagnostics",
             Panel::Tools => "Tools",
+            Panel::SerialConsole => "Serial Console",
             Panel::AiAssistant => "AI Agent",
             Panel::ServerAuth => "Server Auth",
             Panel::Updates => "Updates",
@@ -139,6 +244,7 @@ impl FOEMApp {
             "flash" => Panel::Flash,
             "diagnostics" => Panel::Diagnostics,
             "tools" => Panel::Tools,
+            "serial" | "console" | "serial console" => Panel::SerialConsole,
             "ai" | "ai agent" | "ai assistant" => Panel::AiAssistant,
             "server" | "server auth" | "api" => Panel::ServerAuth,
             "updates" => Panel::Updates,
@@ -185,6 +291,7 @@ impl eframe::App for FOEMApp {
                     ("Flash", Panel::Flash),
                     ("Diagnostics", Panel::Diagnostics),
                     ("Tools", Panel::Tools),
+                    ("Serial Console", Panel::SerialConsole),
                     ("AI Agent", Panel::AiAssistant),
                     ("Server Auth", Panel::ServerAuth),
                     ("Updates", Panel::Updates),
@@ -246,6 +353,7 @@ impl eframe::App for FOEMApp {
                 Panel::Flash => self.panel_flash(ui),
                 Panel::Diagnostics => self.panel_diagnostics(ui),
                 Panel::Tools => self.panel_tools(ui),
+                Panel::SerialConsole => self.panel_serial_console(ui),
                 Panel::AiAssistant => self.panel_ai_assistant(ui),
                 Panel::ServerAuth => self.panel_server_auth(ui),
                 Panel::Updates => self.panel_updates(ui),
@@ -369,10 +477,76 @@ impl FOEMApp {
             }
         });
 
+        self.device_prop_snapshot_section(ui);
+
         ui.add_space(8.0);
         log_area(ui, &self.log);
     }
 
+    fn device_prop_snapshot_section(&mut self, ui: &mut egui::Ui) {
+        section(ui, "Property Snapshots");
+        ui.horizontal_wrapped(|ui| {
+            if btn(ui, "Save Snapshot") {
+                if let Ok(s) = self.require_device() {
+                    self.log = match features::prop_snapshot::take_snapshot(s) {
+                        Ok((path, snap)) => format!(
+                            "Saved {} properties to {}",
+                            snap.props.len(),
+                            path.display()
+                        ),
+                        Err(e) => format!("Snapshot failed: {}", e),
+                    };
+                    self.prop_snapshots = features::prop_snapshot::list_snapshots();
+                    self.snapshot_a_idx = 0;
+                    self.snapshot_b_idx = 0;
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
+            if btn(ui, "Refresh") {
+                self.prop_snapshots = features::prop_snapshot::list_snapshots();
+                self.log = format!("{} snapshots found.", self.prop_snapshots.len());
+            }
+            ui.checkbox(&mut self.snapshot_hide_volatile, "Hide volatile props");
+        });
+        for (salt, label, idx) in [
+            ("snapshot_a", "A:", &mut self.snapshot_a_idx),
+            ("snapshot_b", "B:", &mut self.snapshot_b_idx),
+        ] {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(
+                    egui::RichText::new(label)
+                        .size(12.0)
+                        .color(theme::SECONDARY),
+                );
+                let selected = self
+                    .prop_snapshots
+                    .get(*idx)
+                    .map(|(_, s)| s.label())
+                    .unwrap_or_else(|| "No snapshots saved".to_string());
+                egui::ComboBox::from_id_salt(salt)
+                    .width(360.0)
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for (i, (_, snap)) in self.prop_snapshots.iter().enumerate() {
+                            ui.selectable_value(idx, i, snap.label());
+                        }
+                    });
+            });
+        }
+        if btn(ui, "Diff A -> B") {
+            self.log = match (
+                self.prop_snapshots.get(self.snapshot_a_idx),
+                self.prop_snapshots.get(self.snapshot_b_idx),
+            ) {
+                (Some((_, a)), Some((_, b))) => {
+                    features::prop_snapshot::render_diff(a, b, self.snapshot_hide_volatile)
+                }
+                _ => "Save at least one snapshot first.".into(),
+            };
+        }
+    }
+
     fn panel_bootloader(&mut self, ui: &mut egui::Ui) {
         heading(ui, "Bootloader");
 
@@ -471,6 +645,8 @@ impl FOEMApp {
             self.repair_efs_nv_section(ui);
             self.repair_samsung_section(ui);
             self.repair_baseband_section(ui);
+            self.repair_adaptive_diag_section(ui, mfr);
+            self.repair_modem_log_section(ui);
 
             ui.add_space(8.0);
             log_area(ui, &self.log);
@@ -488,6 +664,21 @@ impl FOEMApp {
                 }
             }
 
+            if btn(ui, "Read IMEI (Diag)") {
+                self.log = match crate::adaptive_engine::autodetect_diag_port() {
+                    Some(port) => features::repair::read_imei_diag(&port),
+                    None => {
+                        "No diagnostic port detected. Enable diag/AT mode and try again.".into()
+                    }
+                };
+            }
+            if btn(ui, "Identity Check") {
+                if let Ok(s) = self.require_device() {
+                    self.log = features::identity::integrity_report(s, &mfr);
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
             if btn(ui, "Backup IMEI") {
                 if let Ok(s) = self.require_device() {
                     self.log = features::repair::backup_imei(s);
@@ -543,6 +734,13 @@ impl FOEMApp {
                     self.log = "Connect a device first.".into();
                 }
             }
+            if btn(ui, "Deep Check") {
+                if let Ok(s) = self.require_device() {
+                    self.log = features::gms::deep_check(s);
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
             if btn_accent(ui, "Repair GMS") {
                 if let Ok(s) = self.require_device() {
                     self.log = features::repair::repair_gms(s);
@@ -559,27 +757,65 @@ impl FOEMApp {
             if btn(ui, "Backup EFS") {
                 if let Ok(s) = self.require_device() {
                     self.log = features::repair::backup_efs(s);
+                    self.refresh_vault_entries();
                 } else {
                     self.log = "Connect a device first.".into();
                 }
             }
-            if btn(ui, "Restore EFS") {
+            if btn(ui, "Backup NV") {
                 if let Ok(s) = self.require_device() {
-                    self.log = features::repair::restore_efs(s);
+                    self.log = features::repair::backup_nv_data(s);
+                    self.refresh_vault_entries();
                 } else {
                     self.log = "Connect a device first.".into();
                 }
             }
-            if btn(ui, "Backup NV") {
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.label(
+                egui::RichText::new("Vault entry:")
+                    .size(12.0)
+                    .color(theme::SECONDARY),
+            );
+            let selected = self
+                .vault_entries
+                .get(self.vault_entry_idx)
+                .map(|e| e.label())
+                .unwrap_or_else(|| "No backups in vault".to_string());
+            egui::ComboBox::from_id_salt("vault_entry")
+                .width(320.0)
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (i, entry) in self.vault_entries.iter().enumerate() {
+                        ui.selectable_value(&mut self.vault_entry_idx, i, entry.label());
+                    }
+                });
+            if btn(ui, "Refresh Vault") {
+                if self.require_device().is_ok() {
+                    self.refresh_vault_entries();
+                    self.log = format!("{} vault entries found.", self.vault_entries.len());
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
+        });
+        ui.horizontal_wrapped(|ui| {
+            if btn(ui, "Restore EFS") {
                 if let Ok(s) = self.require_device() {
-                    self.log = features::repair::backup_nv_data(s);
+                    self.log = match self.vault_entries.get(self.vault_entry_idx) {
+                        Some(entry) => features::repair::restore_efs(s, &entry.dir),
+                        None => "Select a vault entry first.".into(),
+                    };
                 } else {
                     self.log = "Connect a device first.".into();
                 }
             }
             if btn(ui, "Restore NV") {
                 if let Ok(s) = self.require_device() {
-                    self.log = features::repair::restore_nv_data(s);
+                    self.log = match self.vault_entries.get(self.vault_entry_idx) {
+                        Some(entry) => features::repair::restore_nv_data(s, &entry.dir),
+                        None => "Select a vault entry first.".into(),
+                    };
                 } else {
                     self.log = "Connect a device first.".into();
                 }
@@ -587,6 +823,15 @@ impl FOEMApp {
         });
     }
 
+    fn refresh_vault_entries(&mut self) {
+        self.vault_entries = match self.serial() {
+            Some(s) => features::vault::list_entries(s),
+            None => Vec::new(),
+        };
+        self.vault_entry_idx = 0;
+        self.dump_file_idx = 0;
+    }
+
     fn repair_samsung_section(&mut self, ui: &mut egui::Ui) {
         section(ui, "Samsung Specific");
         ui.horizontal_wrapped(|ui| {
@@ -619,6 +864,13 @@ impl FOEMApp {
                     self.log = "Connect a device first.".into();
                 }
             }
+            if btn(ui, "CSC Inventory") {
+                if let Ok(s) = self.require_device() {
+                    self.log = features::csc::csc_inventory(s);
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
         });
     }
 
@@ -639,9 +891,234 @@ impl FOEMApp {
                     self.log = "Connect a device first.".into();
                 }
             }
+            if btn(ui, "Crash Analysis") {
+                if let Ok(s) = self.require_device() {
+                    self.log = features::modem_crash::analyze_modem_crashes(s);
+                    self.refresh_vault_entries();
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
+            if btn(ui, "Modem Info (DIAG)") {
+                self.log = features::repair::read_modem_info_diag(None);
+            }
         });
     }
 
+    fn repair_adaptive_diag_section(&mut self, ui: &mut egui::Ui, mfr: features::Manufacturer) {
+        use crate::adaptive_engine::{knowledge, recipes};
+
+        section(ui, "Adaptive Diag Enable");
+        ui.horizontal_wrapped(|ui| {
+            if btn(ui, "Enable Diag Port") {
+                if let Ok(s) = self.require_device() {
+                    self.log = features::repair::enable_diag_port(s, &mfr);
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
+            if btn(ui, "Dry Run") {
+                let serial = self.require_device().ok();
+                self.log =
+                    features::repair::dry_run_diag_port(serial, &mfr, &self.dry_run_samples_path);
+            }
+            if btn(ui, "Reload Recipes") {
+                self.recipe_set = recipes::load_recipes();
+                self.log = self.recipe_set.summary();
+            }
+            if btn(ui, "Open Recipes Folder") {
+                let dir = recipes::recipes_dir();
+                self.log = match std::fs::create_dir_all(&dir).and_then(|_| open::that(&dir)) {
+                    Ok(()) => format!("Opened {}", dir.display()),
+                    Err(e) => format!("Cannot open {}: {}", dir.display(), e),
+                };
+            }
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.label(
+                egui::RichText::new("Dry run samples (JSON):")
+                    .size(11.0)
+                    .color(theme::SECONDARY),
+            );
+            ui.add(egui::TextEdit::singleline(&mut self.dry_run_samples_path).desired_width(200.0));
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.label(
+                egui::RichText::new("Knowledge bundle:")
+                    .size(11.0)
+                    .color(theme::SECONDARY),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.knowledge_bundle_path).desired_width(200.0),
+            );
+            let path = std::path::PathBuf::from(crate::exec::normalize_local_path(
+                self.knowledge_bundle_path.trim(),
+            ));
+            if btn(ui, "Export Knowledge") {
+                self.log = if self.knowledge_bundle_path.trim().is_empty() {
+                    "Enter a bundle file path first.".into()
+                } else {
+                    knowledge::export_bundle(&path).unwrap_or_else(|e| e)
+                };
+            }
+            if btn(ui, "Import Knowledge") {
+                self.log = if self.knowledge_bundle_path.trim().is_empty() {
+                    "Enter a bundle file path first.".into()
+                } else {
+                    knowledge::import_bundle(&path).unwrap_or_else(|e| e)
+                };
+            }
+        });
+        let mut toggled = None;
+        for loaded in &mut self.recipe_set.recipes {
+            ui.horizontal_wrapped(|ui| {
+                if ui
+                    .checkbox(&mut loaded.enabled, &loaded.recipe.name)
+                    .changed()
+                {
+                    toggled = Some((loaded.recipe.name.clone(), loaded.enabled));
+                }
+                ui.label(
+                    egui::RichText::new(format!(
+                        "{:?} -- {}",
+                        loaded.recipe.goal,
+                        loaded.source.label()
+                    ))
+                    .size(12.0)
+                    .color(theme::SECONDARY),
+                );
+            });
+        }
+        if let Some((name, enabled)) = toggled {
+            if let Err(e) = recipes::set_enabled(&name, enabled) {
+                self.log = e;
+            }
+        }
+        if !self.recipe_set.errors.is_empty() {
+            ui.label(
+                egui::RichText::new(format!(
+                    "{} recipe file problem(s) -- Reload Recipes for details.",
+                    self.recipe_set.errors.len()
+                ))
+                .size(12.0)
+                .color(theme::SECONDARY),
+            );
+        }
+    }
+
+    fn repair_modem_log_section(&mut self, ui: &mut egui::Ui) {
+        use features::modem_log::{self, CaptureConfig, CaptureJob};
+
+        section(ui, "Modem Log Capture (DIAG)");
+        ui.horizontal_wrapped(|ui| {
+            for (i, (_, name)) in modem_log::EQUIPMENT.iter().enumerate() {
+                ui.checkbox(&mut self.capture_equipment[i], *name);
+            }
+            ui.checkbox(&mut self.capture_events, "Events");
+        });
+        ui.horizontal_wrapped(|ui| {
+            if let Some(job) = &mut self.capture_job {
+                self.capture_records.extend(job.drain());
+                let finished = job.is_finished();
+                if finished || btn_accent(ui, "Stop Capture") {
+                    self.log = job.stop();
+                    self.capture_records.extend(job.drain());
+                    self.capture_file = job.path.to_string_lossy().into_owned();
+                    self.capture_job = None;
+                } else {
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "Capturing to {} ({} records)",
+                            job.path.display(),
+                            self.capture_records.len()
+                        ))
+                        .size(12.0)
+                        .color(theme::SECONDARY),
+                    );
+                    ui.ctx()
+                        .request_repaint_after(std::time::Duration::from_millis(250));
+                }
+            } else if btn_accent(ui, "Start Capture") {
+                let config = CaptureConfig {
+                    equipment: modem_log::EQUIPMENT
+                        .iter()
+                        .zip(&self.capture_equipment)
+                        .filter(|(_, on)| **on)
+                        .map(|((id, _), _)| *id)
+                        .collect(),
+                    events: self.capture_events,
+                };
+                match crate::adaptive_engine::autodetect_diag_port() {
+                    Some(port) => match CaptureJob::start(&port, config) {
+                        Ok(job) => {
+                            self.capture_records.clear();
+                            self.log = format!("Capturing modem logs from {}...", port);
+                            self.capture_job = Some(job);
+                        }
+                        Err(e) => self.log = e,
+                    },
+                    None => {
+                        self.log =
+                            "No diagnostic port detected. Enable diag mode and try again.".into()
+                    }
+                }
+            }
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.label(
+                egui::RichText::new("Capture file:")
+                    .size(12.0)
+                    .color(theme::SECONDARY),
+            );
+            ui.add(egui::TextEdit::singleline(&mut self.capture_file).desired_width(300.0));
+            if btn(ui, "Open Capture") {
+                match modem_log::decode_capture(std::path::Path::new(self.capture_file.trim())) {
+                    Ok(records) => {
+                        self.log = format!("{} records decoded.", records.len());
+                        self.capture_records = records;
+                    }
+                    Err(e) => self.log = e,
+                }
+            }
+            ui.label(
+                egui::RichText::new("Filter:")
+                    .size(12.0)
+                    .color(theme::SECONDARY),
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.capture_filter)
+                    .hint_text("0xB0C0, EVENT, 1605")
+                    .desired_width(140.0),
+            );
+        });
+        if !self.capture_records.is_empty() {
+            let filter = self.capture_filter.as_str();
+            let shown: Vec<_> = self
+                .capture_records
+                .iter()
+                .filter(|r| r.matches(filter))
+                .collect();
+            egui::ScrollArea::vertical()
+                .id_salt("capture_records")
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show_rows(ui, 16.0, shown.len(), |ui, rows| {
+                    for r in &shown[rows] {
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{:<23} {:<12} {:>5} bytes",
+                                r.time_text(),
+                                r.label(),
+                                r.payload_len
+                            ))
+                            .monospace()
+                            .size(11.0),
+                        );
+                    }
+                });
+        }
+    }
+
     fn panel_network(&mut self, ui: &mut egui::Ui) {
         heading(ui, "Network & Security");
 
@@ -765,6 +1242,320 @@ impl FOEMApp {
         });
     }
 
+    /// Firehose storage controls; they act on the programmer loaded above.
+    fn edl_firehose_section(&mut self, ui: &mut egui::Ui) {
+        use features::edl::firehose::{Memory, Power};
+        use features::flash::EdlWrite;
+
+        let memory = Memory::ALL[self.edl_memory_idx];
+        ui.horizontal_wrapped(|ui| {
+            ui.label(
+                egui::RichText::new("Storage:")
+                    .size(12.0)
+                    .color(theme::SECONDARY),
+            );
+            egui::ComboBox::from_id_salt("edl_memory")
+                .width(80.0)
+                .selected_text(memory.name())
+                .show_ui(ui, |ui| {
+                    for (i, m) in Memory::ALL.iter().enumerate() {
+                        ui.selectable_value(&mut self.edl_memory_idx, i, m.name());
+                    }
+                });
+            for (label, value, width) in [
+                ("LUN:", &mut self.edl_lun, 30.0),
+                ("Start sector:", &mut self.edl_start_sector, 90.0),
+                ("Sectors:", &mut self.edl_sector_count, 90.0),
+            ] {
+                ui.label(
+                    egui::RichText::new(label)
+                        .size(12.0)
+                        .color(theme::SECONDARY),
+                );
+                ui.add(egui::TextEdit::singleline(value).desired_width(width));
+            }
+        });
+        for (label, value) in [
+            ("Dump to:", &mut self.edl_dump_path),
+            ("Image:", &mut self.edl_image_path),
+            ("Patch XML:", &mut self.edl_patch_path),
+        ] {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(
+                    egui::RichText::new(label)
+                        .size(12.0)
+                        .color(theme::SECONDARY),
+                );
+                ui.add(egui::TextEdit::singleline(value).desired_width(300.0));
+            });
+        }
+
+        if let Some(job) = &mut self.edl_job {
+            if let Some(report) = job.try_finish() {
+                self.log = report;
+                self.edl_job = None;
+                self.edl_snapshots = features::flash::edl_snapshots();
+                self.edl_snapshot_idx = 0;
+            } else {
+                let p = job.progress();
+                let fraction = match p.total {
+                    0 => 0.0,
+                    total => p.done as f32 / total as f32,
+                };
+                let text = match p.total {
+                    0 => p.stage.clone(),
+                    total => format!(
+                        "{} -- {}",
+                        p.stage,
+                        features::edl::firehose::progress_label(p.done, total)
+                    ),
+                };
+                ui.add(egui::ProgressBar::new(fraction).text(text));
+                ui.ctx()
+                    .request_repaint_after(std::time::Duration::from_millis(200));
+            }
+            return;
+        }
+
+        if let Some((pending_memory, op, summary)) = &self.edl_pending {
+            let touches_gpt = op.touches_gpt(*pending_memory);
+            ui.label(
+                egui::RichText::new(summary)
+                    .size(12.0)
+                    .color(theme::DESTRUCTIVE),
+            );
+            if touches_gpt {
+                ui.checkbox(
+                    &mut self.edl_gpt_confirmed,
+                    egui::RichText::new(
+                        "This rewrites sector 0 or the partition table (GPT); I have checked the target.",
+                    )
+                    .size(12.0)
+                    .color(theme::WARNING),
+                );
+            }
+            let mut confirmed = false;
+            let mut cancelled = false;
+            ui.horizontal_wrapped(|ui| {
+                ui.add_enabled_ui(!touches_gpt || self.edl_gpt_confirmed, |ui| {
+                    confirmed = btn_accent(ui, "Confirm Write");
+                });
+                cancelled = btn(ui, "Cancel");
+            });
+            if cancelled {
+                self.edl_pending = None;
+                self.log = "EDL write cancelled; nothing was written.".into();
+            } else if confirmed {
+                if let Some((memory, op, _)) = self.edl_pending.take() {
+                    let allow_gpt = self.edl_gpt_confirmed;
+                    self.edl_job = Some(features::flash::EdlJob::spawn(move |progress| {
+                        features::flash::edl_write(memory, &op, allow_gpt, progress)
+                    }));
+                    self.log = "EDL write started; backing up first...".into();
+                }
+            }
+            return;
+        }
+
+        let numbers = || -> Result<(u32, u64, u64), String> {
+            let lun = self
+                .edl_lun
+                .trim()
+                .parse()
+                .map_err(|_| "LUN must be a number.")?;
+            let start = self
+                .edl_start_sector
+                .trim()
+                .parse()
+                .map_err(|_| "Start sector must be a number.")?;
+            let count = match self.edl_sector_count.trim() {
+                "" => 0,
+                n => n.parse().map_err(|_| "Sector count must be a number.")?,
+            };
+            Ok((lun, start, count))
+        };
+        let numbers = numbers();
+        let mut clicked = None;
+        ui.horizontal_wrapped(|ui| {
+            for label in [
+                "Storage Info",
+                "Read Sectors",
+                "Write Image",
+                "Erase Sectors",
+                "Apply Patch XML",
+                "Set Boot LUN",
+                "Reset Device",
+                "Reset to EDL",
+            ] {
+                if btn(ui, label) {
+                    clicked = Some(label);
+                }
+            }
+        });
+        if !self.edl_snapshots.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(
+                    egui::RichText::new("Snapshot:")
+                        .size(12.0)
+                        .color(theme::SECONDARY),
+                );
+                let selected = self
+                    .edl_snapshots
+                    .get(self.edl_snapshot_idx)
+                    .map(|e| e.label())
+                    .unwrap_or_default();
+                egui::ComboBox::from_id_salt("edl_snapshot")
+                    .width(300.0)
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for (i, entry) in self.edl_snapshots.iter().enumerate() {
+                            ui.selectable_value(&mut self.edl_snapshot_idx, i, entry.label());
+                        }
+                    });
+                if btn(ui, "Restore Snapshot") {
+                    clicked = Some("Restore Snapshot");
+                }
+            });
+        }
+        let Some(label) = clicked else {
+            return;
+        };
+        let (lun, start, count) = match (label, numbers) {
+            ("Restore Snapshot", _) => (0, 0, 0),
+            (_, Ok(numbers)) => numbers,
+            (_, Err(e)) => {
+                self.log = e.to_string();
+                return;
+            }
+        };
+        let write = match label {
+            "Restore Snapshot" => match self.edl_snapshots.get(self.edl_snapshot_idx) {
+                Some(entry) => Some(EdlWrite::Restore {
+                    dir: entry.dir.clone(),
+                }),
+                None => {
+                    self.log = "Select a snapshot to restore.".into();
+                    return;
+                }
+            },
+            "Write Image" => Some(EdlWrite::Image {
+                lun,
+                start,
+                path: self.edl_image_path.trim().to_string(),
+            }),
+            "Erase Sectors" => Some(EdlWrite::Erase { lun, start, count }),
+            "Apply Patch XML" => Some(EdlWrite::Patch {
+                path: self.edl_patch_path.trim().to_string(),
+            }),
+            _ => None,
+        };
+        if let Some(op) = write {
+            match op.describe(memory) {
+                Ok(summary) => {
+                    self.edl_gpt_confirmed = false;
+                    self.edl_pending = Some((memory, op, summary));
+                    self.log = "Review the EDL write above and confirm it.".into();
+                }
+                Err(e) => self.log = format!("EDL:\n  {}", e),
+            }
+            return;
+        }
+        let dump_path = self.edl_dump_path.clone();
+        self.edl_job = Some(features::flash::EdlJob::spawn(
+            move |progress| match label {
+                "Storage Info" => features::flash::edl_storage_info(memory, lun),
+                "Read Sectors" => features::flash::edl_read_sectors(
+                    memory, lun, start, count, &dump_path, progress,
+                ),
+                "Set Boot LUN" => features::flash::edl_set_boot_lun(memory, lun),
+                "Reset Device" => features::flash::edl_power(memory, Power::Reset),
+                _ => features::flash::edl_power(memory, Power::Edl),
+            },
+        ));
+        self.log = format!("{}...", label);
+    }
+
+    /// Flashing a whole firmware package from its rawprogram/patch files.
+    fn edl_package_section(&mut self, ui: &mut egui::Ui) {
+        for (label, value, width) in [
+            ("Package folder:", &mut self.edl_package_dir, 300.0),
+            ("Only:", &mut self.edl_include, 200.0),
+            ("Skip:", &mut self.edl_exclude, 200.0),
+        ] {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(
+                    egui::RichText::new(label)
+                        .size(12.0)
+                        .color(theme::SECONDARY),
+                );
+                ui.add(egui::TextEdit::singleline(value).desired_width(width));
+            });
+        }
+        // Progress of a running flash is drawn by the Firehose section above.
+        if self.edl_job.is_some() {
+            return;
+        }
+        if let Some((_, _, summary)) = &self.edl_package_pending {
+            egui::ScrollArea::vertical()
+                .id_salt("edl_package_plan")
+                .max_height(180.0)
+                .show(ui, |ui| {
+                    ui.label(
+                        egui::RichText::new(summary)
+                            .monospace()
+                            .size(11.0)
+                            .color(theme::FG),
+                    );
+                });
+            ui.label(
+                egui::RichText::new("Flashing overwrites every selected partition in EDL.")
+                    .size(12.0)
+                    .color(theme::DESTRUCTIVE),
+            );
+            let mut confirmed = false;
+            let mut cancelled = false;
+            ui.horizontal_wrapped(|ui| {
+                confirmed = btn_accent(ui, "Confirm Flash");
+                cancelled = btn(ui, "Cancel");
+            });
+            if cancelled {
+                self.edl_package_pending = None;
+                self.log = "EDL package flash cancelled; nothing was written.".into();
+            } else if confirmed {
+                if let Some((memory, plan, _)) = self.edl_package_pending.take() {
+                    self.edl_job = Some(features::flash::EdlJob::spawn(move |progress| {
+                        features::flash::edl_flash_package(memory, &plan, progress)
+                    }));
+                    self.log = "EDL package flash started; backing up first...".into();
+                }
+            }
+            return;
+        }
+        ui.horizontal_wrapped(|ui| {
+            if btn(ui, "Show Flash Plan") {
+                self.log = features::flash::edl_flash_plan(
+                    &self.edl_package_dir,
+                    &self.edl_include,
+                    &self.edl_exclude,
+                );
+            }
+            if btn_accent(ui, "Flash Package (EDL)") {
+                let memory = features::edl::firehose::Memory::ALL[self.edl_memory_idx];
+                match features::flash::edl_package_preview(
+                    &self.edl_package_dir,
+                    &self.edl_include,
+                    &self.edl_exclude,
+                ) {
+                    Ok((plan, summary)) => {
+                        self.edl_package_pending = Some((memory, plan, summary));
+                        self.log = "Review the flash plan above and confirm it.".into();
+                    }
+                    Err(e) => self.log = format!("EDL Package Flash:\n  {}", e),
+                }
+            }
+        });
+    }
+
     fn panel_flash(&mut self, ui: &mut egui::Ui) {
         heading(ui, "Flash");
         let mfr = *self.manufacturer();
@@ -780,14 +1571,28 @@ impl FOEMApp {
                         self.log = "Connect a device first.".into();
                     }
                 }
-                if btn(ui, "Flash via EDL") {
-                    if let Ok(s) = self.require_device() {
-                        self.log = features::flash::flash_edl(s, &self.flash_path);
-                    } else {
-                        self.log = "Connect a device first.".into();
+                let idle = self.edl_job.is_none();
+                ui.add_enabled_ui(idle, |ui| {
+                    if btn(ui, "Read EDL Info") {
+                        self.edl_job = Some(features::flash::EdlJob::spawn(
+                            features::flash::edl_device_info,
+                        ));
+                        self.log = "Reading EDL info...".into();
+                    }
+                    if btn(ui, "Load Programmer (EDL)") {
+                        let path = self.flash_path.clone();
+                        self.edl_job = Some(features::flash::EdlJob::spawn(move |progress| {
+                            features::flash::flash_edl(&path, progress)
+                        }));
+                        self.log = "Loading Firehose programmer...".into();
                     }
+                });
+                if btn(ui, "Programmer Library") {
+                    self.log = features::flash::programmer_library();
                 }
             });
+            self.edl_firehose_section(ui);
+            self.edl_package_section(ui);
 
             // Fastboot
             section(ui, "Fastboot Flash");
@@ -840,6 +1645,8 @@ impl FOEMApp {
                 }
             });
 
+            self.flash_partition_backup_section(ui);
+
             // Root
             section(ui, "Root (Magisk / KernelSU)");
             ui.horizontal_wrapped(|ui| {
@@ -932,6 +1739,163 @@ impl FOEMApp {
         });
     }
 
+    fn flash_partition_backup_section(&mut self, ui: &mut egui::Ui) {
+        use features::partitions;
+
+        section(ui, "Partition Backup");
+        ui.horizontal_wrapped(|ui| {
+            if btn(ui, "List Partitions") {
+                if let Ok(s) = self.require_device() {
+                    let serial = s.to_string();
+                    match partitions::detect_root(&serial) {
+                        Some(root) => match partitions::list_partitions(&serial, root) {
+                            Ok(list) => {
+                                self.log = format!("{} partitions found.", list.len());
+                                self.partition_list =
+                                    list.into_iter().map(|p| (p, false)).collect();
+                                self.partition_root = Some(root);
+                            }
+                            Err(e) => self.log = e,
+                        },
+                        None => {
+                            self.log =
+                                "Root (su) or a recovery shell is required to read partitions."
+                                    .into()
+                        }
+                    }
+                } else {
+                    self.log = "Connect a device first.".into();
+                }
+            }
+            ui.checkbox(&mut self.dump_options.compress, "Compress (gzip)");
+            ui.checkbox(&mut self.dump_options.resume, "Resume interrupted dump");
+        });
+
+        if !self.partition_list.is_empty() {
+            egui::ScrollArea::vertical()
+                .id_salt("partition_list")
+                .max_height(180.0)
+                .show(ui, |ui| {
+                    for (part, selected) in &mut self.partition_list {
+                        let size = part
+                            .size
+                            .map(partitions::format_size)
+                            .unwrap_or_else(|| "unknown size".into());
+                        ui.checkbox(selected, format!("{} ({})", part.name, size));
+                    }
+                });
+        }
+
+        if let Some(job) = &mut self.dump_job {
+            if let Some(report) = job.try_finish() {
+                self.log = report;
+                self.dump_job = None;
+                self.refresh_vault_entries();
+            } else {
+                let p = job.progress();
+                let fraction = match p.total {
+                    Some(total) if total > 0 => p.done as f32 / total as f32,
+                    _ => 0.0,
+                };
+                ui.add(egui::ProgressBar::new(fraction).text(format!(
+                    "{} ({}/{}) -- {}",
+                    p.partition,
+                    p.index + 1,
+                    p.count,
+                    partitions::format_size(p.done)
+                )));
+                ui.ctx()
+                    .request_repaint_after(std::time::Duration::from_millis(200));
+            }
+        } else if btn_accent(ui, "Dump Selected to Vault") {
+            match (self.require_device(), self.partition_root) {
+                (Ok(s), Some(root)) => {
+                    let selected: Vec<_> = self
+                        .partition_list
+                        .iter()
+                        .filter(|(_, on)| *on)
+                        .map(|(p, _)| p.clone())
+                        .collect();
+                    if selected.is_empty() {
+                        self.log = "Select at least one partition to dump.".into();
+                    } else {
+                        self.dump_job = Some(partitions::DumpJob::spawn(
+                            s,
+                            root,
+                            selected,
+                            self.dump_options,
+                        ));
+                        self.log = "Partition dump started...".into();
+                    }
+                }
+                (Ok(_), None) => self.log = "List partitions first.".into(),
+                (Err(()), _) => self.log = "Connect a device first.".into(),
+            }
+        }
+
+        // (entry dir, partition, display label) for every image in a dump entry.
+        let images: Vec<(std::path::PathBuf, String, String)> = self
+            .vault_entries
+            .iter()
+            .filter(|e| e.manifest.kind == features::vault::BackupKind::PartitionDump)
+            .flat_map(|e| {
+                e.manifest.files.iter().map(move |f| {
+                    (
+                        e.dir.clone(),
+                        f.partition.clone(),
+                        format!("{} -- {}", f.partition, e.label()),
+                    )
+                })
+            })
+            .collect();
+        ui.horizontal_wrapped(|ui| {
+            ui.label(
+                egui::RichText::new("Stored image:")
+                    .size(12.0)
+                    .color(theme::SECONDARY),
+            );
+            let selected = images
+                .get(self.dump_file_idx)
+                .map(|(_, _, label)| label.clone())
+                .unwrap_or_else(|| "(none)".into());
+            egui::ComboBox::from_id_salt("dump_file")
+                .width(320.0)
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (i, (_, _, label)) in images.iter().enumerate() {
+                        ui.selectable_value(&mut self.dump_file_idx, i, label);
+                    }
+                });
+            if btn(ui, "Refresh Vault") {
+                self.refresh_vault_entries();
+            }
+            if self.restore_job.is_some() {
+                ui.spinner();
+                ui.ctx()
+                    .request_repaint_after(std::time::Duration::from_millis(200));
+            } else if btn(ui, "Flash from Vault") {
+                match (self.serial(), images.get(self.dump_file_idx)) {
+                    (Some(s), Some((dir, part, _))) => {
+                        let (s, dir, part) = (s.to_string(), dir.clone(), part.clone());
+                        self.restore_job = Some(std::thread::spawn(move || {
+                            partitions::restore_partition(&s, &dir, &part)
+                        }));
+                        self.log = "Checking the device and rebooting to fastboot...".into();
+                    }
+                    (None, _) => self.log = "Connect a device first.".into(),
+                    (_, None) => self.log = "Select a stored image first.".into(),
+                }
+            }
+        });
+        if self.restore_job.as_ref().is_some_and(|h| h.is_finished()) {
+            if let Some(handle) = self.restore_job.take() {
+                self.log = handle
+                    .join()
+                    .unwrap_or_else(|_| "Partition restore worker panicked.".to_string());
+            }
+        }
+    }
+
     fn panel_diagnostics(&mut self, ui: &mut egui::Ui) {
         heading(ui, "Hardware Diagnostics");
 
@@ -1285,6 +2249,197 @@ impl FOEMApp {
         });
     }
 
+    fn panel_serial_console(&mut self, ui: &mut egui::Ui) {
+        use features::serial_console::{
+            self as console, ConsoleSession, Direction, FlowControl, LineEnding, Parity,
+        };
+
+        heading(ui, "Serial Console");
+
+        if let Some(session) = &self.console_session {
+            self.console_entries.extend(session.drain());
+            ui.ctx()
+                .request_repaint_after(std::time::Duration::from_millis(100));
+        }
+        let open = self.console_session.is_some();
+
+        section(ui, "Port");
+        ui.horizontal_wrapped(|ui| {
+            ui.add_enabled_ui(!open, |ui| {
+                egui::ComboBox::from_id_salt("console_port")
+                    .width(160.0)
+                    .selected_text(if self.console_port.is_empty() {
+                        "(select port)"
+                    } else {
+                        self.console_port.as_str()
+                    })
+                    .show_ui(ui, |ui| {
+                        for name in &self.console_ports {
+                            ui.selectable_value(&mut self.console_port, name.clone(), name);
+                        }
+                    });
+                if btn(ui, "Refresh") {
+                    self.console_ports = console::port_names();
+                }
+                let settings = &mut self.console_settings;
+                egui::ComboBox::from_id_salt("console_baud")
+                    .width(90.0)
+                    .selected_text(settings.baud.to_string())
+                    .show_ui(ui, |ui| {
+                        for baud in console::BAUD_RATES {
+                            ui.selectable_value(&mut settings.baud, *baud, baud.to_string());
+                        }
+                    });
+                egui::ComboBox::from_id_salt("console_parity")
+                    .width(70.0)
+                    .selected_text(format!("Parity: {}", settings.parity.label()))
+                    .show_ui(ui, |ui| {
+                        for p in Parity::ALL {
+                            ui.selectable_value(&mut settings.parity, *p, p.label());
+                        }
+                    });
+                egui::ComboBox::from_id_salt("console_flow")
+                    .width(90.0)
+                    .selected_text(format!("Flow: {}", settings.flow_control.label()))
+                    .show_ui(ui, |ui| {
+                        for f in FlowControl::ALL {
+                            ui.selectable_value(&mut settings.flow_control, *f, f.label());
+                        }
+                    });
+            });
+            egui::ComboBox::from_id_salt("console_eol")
+                .width(70.0)
+                .selected_text(format!(
+                    "EOL: {}",
+                    self.console_settings.line_ending.label()
+                ))
+                .show_ui(ui, |ui| {
+                    for e in LineEnding::ALL {
+                        ui.selectable_value(&mut self.console_settings.line_ending, *e, e.label());
+                    }
+                });
+            if open {
+                if btn(ui, "Close") {
+                    if let Some(mut session) = self.console_session.take() {
+                        session.close();
+                        self.console_entries.extend(session.drain());
+                        self.log = format!("Session saved to {}", session.log_path.display());
+                    }
+                }
+            } else if btn_accent(ui, "Open") {
+                if self.console_port.is_empty() {
+                    self.log = "Select a serial port first.".into();
+                } else {
+                    match ConsoleSession::open(&self.console_port, self.console_settings) {
+                        Ok(session) => {
+                            self.log = format!("Logging session to {}", session.log_path.display());
+                            self.console_entries.clear();
+                            self.console_session = Some(session);
+                        }
+                        Err(e) => self.log = e,
+                    }
+                }
+            }
+        });
+
+        section(ui, "Session");
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut self.console_hex_view, "Hex view");
+            ui.checkbox(&mut self.console_send_hex, "Send as hex");
+            if btn(ui, "Clear") {
+                self.console_entries.clear();
+            }
+        });
+        egui::Frame::none()
+            .fill(theme::CARD_BG)
+            .rounding(theme::ROUNDING)
+            .inner_margin(theme::CARD_PADDING)
+            .show(ui, |ui| {
+                egui::ScrollArea::vertical()
+                    .id_salt("console_output")
+                    .max_height(360.0)
+                    .stick_to_bottom(true)
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        for entry in &self.console_entries {
+                            let color = match entry.direction {
+                                Direction::Tx => theme::ACCENT,
+                                Direction::Rx => theme::FG,
+                                Direction::Urc => theme::WARNING,
+                                Direction::Info => theme::SECONDARY,
+                            };
+                            let text =
+                                if self.console_hex_view && entry.direction != Direction::Info {
+                                    console::hex_dump(&entry.bytes)
+                                } else {
+                                    entry.text()
+                                };
+                            let prefix = match entry.direction {
+                                Direction::Tx => "> ",
+                                Direction::Urc => "* ",
+                                _ => "",
+                            };
+                            ui.label(
+                                egui::RichText::new(format!("{}{}", prefix, text))
+                                    .monospace()
+                                    .size(12.0)
+                                    .color(color),
+                            );
+                        }
+                    });
+            });
+
+        ui.horizontal(|ui| {
+            let input = ui.add(
+                egui::TextEdit::singleline(&mut self.console_input)
+                    .desired_width(400.0)
+                    .font(egui::TextStyle::Monospace)
+                    .hint_text(if self.console_send_hex {
+                        "7E 00 78 F0 7E"
+                    } else {
+                        "AT+CSQ"
+                    }),
+            );
+            if input.has_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::ArrowUp)) {
+                    if let Some(cmd) = self.console_history.previous() {
+                        self.console_input = cmd.to_string();
+                    }
+                } else if ui.input(|i| i.key_pressed(egui::Key::ArrowDown)) {
+                    self.console_input = self
+                        .console_history
+                        .next()
+                        .map(str::to_string)
+                        .unwrap_or_default();
+                }
+            }
+            let submitted = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if btn_accent(ui, "Send") || submitted {
+                if let Some(session) = &mut self.console_session {
+                    let command = self.console_input.clone();
+                    let result = if self.console_send_hex {
+                        session.send_hex(&command)
+                    } else {
+                        session.send_line(&command, self.console_settings.line_ending)
+                    };
+                    match result {
+                        Ok(()) => {
+                            self.console_history.push(&command);
+                            self.console_input.clear();
+                        }
+                        Err(e) => self.log = e,
+                    }
+                    input.request_focus();
+                } else {
+                    self.log = "Open a serial port first.".into();
+                }
+            }
+        });
+
+        ui.add_space(8.0);
+        log_area(ui, &self.log);
+    }
+
     fn panel_ai_assistant(&mut self, ui: &mut egui::Ui) {
         heading(ui, "AI Agent");
 
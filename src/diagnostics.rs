@@ -1,8 +1,9 @@
 /// Device detection and diagnostic utilities via ADB and Fastboot.
 use std::collections::BTreeMap;
-use std::fmt::Write;
 
 use crate::exec::{self, COMMAND_TIMEOUT};
+use crate::features::batch::BatchScript;
+use crate::features::shell_session;
 
 pub struct DeviceDiagnostics {
     device_serial: Option<String>,
@@ -30,6 +31,9 @@ impl DeviceDiagnostics {
                 return res;
             }
         }
+        if let ("adb", ["-s", serial, "shell", command @ ..]) = (program, args) {
+            return shell_session::run(serial, command);
+        }
         exec::run_with_timeout(program, args, "Diagnostics command failed", COMMAND_TIMEOUT)
     }
 
@@ -80,32 +84,25 @@ impl DeviceDiagnostics {
             ("build_fingerprint", "ro.build.fingerprint"),
         ];
 
-        let mut script = String::new();
-        for (_, prop) in props {
-            let _ = writeln!(script, "getprop {} 2>&1; echo B_MARKER_$?;", prop);
-        }
+        let commands: Vec<(&str, String)> = props
+            .iter()
+            .map(|(key, prop)| (*key, format!("getprop {}", prop)))
+            .collect();
+        let batch = BatchScript::from_commands(&commands);
 
-        match Self::run_cmd("adb", &["-s", serial, "shell", "sh", "-c", &script]) {
+        match Self::run_cmd("adb", &["-s", serial, "shell", &batch.script()]) {
             Ok(output) => {
-                let mut parts = output.split("B_MARKER_");
-                let mut prev = parts.next().unwrap_or("").to_string();
-                for (idx, part) in parts.enumerate() {
-                    if idx >= props.len() {
-                        break;
+                for result in batch.parse(&output) {
+                    let key = &result.label;
+                    match result.result() {
+                        Ok(val) if !val.is_empty() => {
+                            info.insert(key.to_string(), val);
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            info.insert(format!("error_{key}"), format!("getprop failed: {}", e));
+                        }
                     }
-                    let (code_str, rest) = match part.find('\n') {
-                        Some(pos) => (&part[..pos], part[pos + 1..].to_string()),
-                        None => (part, String::new()),
-                    };
-                    let val = prev.trim();
-                    let key = props[idx].0;
-                    let code: i32 = code_str.trim().parse().unwrap_or(-1);
-                    if code == 0 && !val.is_empty() {
-                        info.insert(key.to_string(), val.to_string());
-                    } else if code != 0 {
-                        info.insert(format!("error_{key}"), format!("getprop failed: {}", val));
-                    }
-                    prev = rest;
                 }
             }
             Err(e) => {
@@ -169,6 +166,19 @@ mod tests {
         assert!(!DeviceDiagnostics::is_adb_available());
     }
 
+    #[test]
+    fn test_shell_commands_honour_run_mock() {
+        crate::exec::MOCK_RUN_IMPL.with(|mock| {
+            *mock.borrow_mut() = Some(Box::new(|program, args, _| {
+                assert_eq!((program, args), ("adb", &["-s", "SER", "shell", "id"][..]));
+                Ok("uid=2000(shell)".to_string())
+            }));
+        });
+        let result = DeviceDiagnostics::run_cmd("adb", &["-s", "SER", "shell", "id"]);
+        crate::exec::MOCK_RUN_IMPL.with(|mock| *mock.borrow_mut() = None);
+        assert_eq!(result, Ok("uid=2000(shell)".to_string()));
+    }
+
     #[test]
     fn test_diagnostics_new_initialization() {
         let diagnostics = DeviceDiagnostics::new();
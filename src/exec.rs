@@ -1,4 +1,5 @@
-use std::io;
+use std::io::{self, Read};
+use std::path::PathBuf;
 use std::process::{Command, Output, Stdio};
 use std::time::{Duration, Instant};
 
@@ -36,6 +37,17 @@ fn spawn_with_timeout(
     }
 }
 
+/// Append a hint to adb errors that mean the device is not reachable.
+pub fn with_disconnect_hint(mut message: String) -> String {
+    if message.contains("device not found")
+        || message.contains("no devices/emulators found")
+        || message.contains("offline")
+    {
+        message.push_str(" (device disconnected or USB debugging not authorized)");
+    }
+    message
+}
+
 pub fn run_with_timeout(
     program: &str,
     args: &[&str],
@@ -59,13 +71,7 @@ pub fn run_with_timeout(
                 output.status
             );
         }
-        if message.contains("device not found")
-            || message.contains("no devices/emulators found")
-            || message.contains("offline")
-        {
-            message.push_str(" (device disconnected or USB debugging not authorized)");
-        }
-        Err(message)
+        Err(with_disconnect_hint(message))
     };
 
     match attempt(program) {
@@ -88,18 +94,101 @@ pub fn run(program: &str, args: &[&str], error_prefix: &str) -> Result<String, S
 
 #[cfg(test)]
 pub fn run(program: &str, args: &[&str], error_prefix: &str) -> Result<String, String> {
-    let mut mock_result = None;
+    mocked_run(program, args, error_prefix)
+        .unwrap_or_else(|| run_with_timeout(program, args, error_prefix, COMMAND_TIMEOUT))
+}
+
+/// The result of `MOCK_RUN_IMPL` for this call, when a test installed one.
+/// Runners that bypass `run` (such as the persistent shell session) consult
+/// it with the argument list the equivalent `run` call would have used.
+#[cfg(test)]
+pub fn mocked_run(
+    program: &str,
+    args: &[&str],
+    error_prefix: &str,
+) -> Option<Result<String, String>> {
     MOCK_RUN_IMPL.with(|mock| {
-        if let Some(f) = mock.borrow().as_ref() {
-            mock_result = Some(f(program, args, error_prefix));
+        mock.borrow()
+            .as_ref()
+            .map(|f| f(program, args, error_prefix))
+    })
+}
+
+#[cfg(test)]
+type MockStreamFn = Box<dyn Fn(&str, &[&str]) -> Result<Vec<u8>, String>>;
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_STREAM_IMPL: std::cell::RefCell<Option<MockStreamFn>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run a command and hand its raw stdout to `sink` chunk by chunk.
+///
+/// Used for binary transfers (partition dumps) that are too large to buffer
+/// and would be corrupted by the lossy UTF-8 conversion in `run`. There is no
+/// overall timeout because a large dump can legitimately take many minutes.
+pub fn stream_stdout(
+    program: &str,
+    args: &[&str],
+    error_prefix: &str,
+    sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) -> Result<(), String> {
+    #[cfg(test)]
+    {
+        let mocked = MOCK_STREAM_IMPL.with(|mock| mock.borrow().as_ref().map(|f| f(program, args)));
+        if let Some(result) = mocked {
+            let bytes = result?;
+            for chunk in bytes.chunks(4096) {
+                sink(chunk).map_err(|e| format!("{error_prefix}: {}", e))?;
+            }
+            return Ok(());
         }
-    });
+    }
 
-    if let Some(result) = mock_result {
-        result
-    } else {
-        run_with_timeout(program, args, error_prefix, COMMAND_TIMEOUT)
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{error_prefix}: {}", e))?;
+
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| format!("{error_prefix}: stdout not captured"))?;
+    let mut buf = vec![0u8; 256 * 1024];
+    let copy_result = loop {
+        match stdout.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => {
+                if let Err(e) = sink(&buf[..n]) {
+                    break Err(format!("{error_prefix}: {}", e));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(format!("{error_prefix}: {}", e)),
+        }
+    };
+    if copy_result.is_err() {
+        let _ = child.kill();
     }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("{error_prefix}: {}", e))?;
+    copy_result?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(if stderr.is_empty() {
+            format!(
+                "{error_prefix}: command exited with status {}",
+                output.status
+            )
+        } else {
+            stderr
+        });
+    }
+    Ok(())
 }
 
 pub fn run_with_serial(
@@ -127,6 +216,18 @@ pub fn normalize_remote_path(path: &str) -> String {
     path.replace('\\', "/").trim_end_matches('/').to_string()
 }
 
+/// Per-user FOEM data directory (`~/.foem`), falling back to a relative path
+/// when neither `HOME` nor `USERPROFILE` is set.
+pub fn foem_home() -> PathBuf {
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(home).join(".foem");
+    }
+    if let Ok(profile) = std::env::var("USERPROFILE") {
+        return PathBuf::from(profile).join(".foem");
+    }
+    PathBuf::from(".foem")
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -292,5 +393,4 @@ mod tests {
         assert_eq!(normalize_remote_path(" /"), " ");
         assert_eq!(normalize_remote_path("/ "), "/ ");
     }
-
 }
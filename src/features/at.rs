@@ -0,0 +1,405 @@
+/// V.250 AT command response parsing.
+///
+/// A response is complete only when a final result code arrives on a line of
+/// its own. Everything before it is split into the command echo, information
+/// text and intermediate/unsolicited result codes, so a payload such as
+/// `+COPS: 0,0,"BOOK"` can no longer be mistaken for `OK`.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalResult {
+    Ok,
+    Error,
+    /// `+CME ERROR: <n>` or, with verbose errors (`AT+CMEE=2`), `+CME ERROR: <text>`.
+    CmeError {
+        code: Option<u16>,
+        message: String,
+    },
+    /// `+CMS ERROR: <n>` from the SMS command set.
+    CmsError {
+        code: Option<u16>,
+        message: String,
+    },
+    NoCarrier,
+    Busy,
+    NoAnswer,
+    NoDialtone,
+    /// Treated as final: FOEM never switches the port to online data mode.
+    Connect(Option<String>),
+}
+
+impl FinalResult {
+    /// Recognise a final result code occupying a whole line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        match line {
+            "OK" => return Some(Self::Ok),
+            "ERROR" => return Some(Self::Error),
+            "NO CARRIER" => return Some(Self::NoCarrier),
+            "BUSY" => return Some(Self::Busy),
+            "NO ANSWER" => return Some(Self::NoAnswer),
+            "NO DIALTONE" | "NO DIAL TONE" => return Some(Self::NoDialtone),
+            "CONNECT" => return Some(Self::Connect(None)),
+            _ => {}
+        }
+        if let Some(rest) = line.strip_prefix("CONNECT ") {
+            return Some(Self::Connect(Some(rest.trim().to_string())));
+        }
+        if let Some(rest) = line.strip_prefix("+CME ERROR:") {
+            let (code, message) = error_detail(rest, cme_error_text);
+            return Some(Self::CmeError { code, message });
+        }
+        if let Some(rest) = line.strip_prefix("+CMS ERROR:") {
+            let (code, message) = error_detail(rest, cms_error_text);
+            return Some(Self::CmsError { code, message });
+        }
+        None
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Ok | Self::Connect(_))
+    }
+
+    /// Human-readable explanation, e.g. "SIM not inserted (CME error 10)".
+    pub fn description(&self) -> String {
+        match self {
+            Self::Ok => "OK".to_string(),
+            Self::Error => "Command rejected (ERROR)".to_string(),
+            Self::CmeError {
+                code: Some(code),
+                message,
+            } => format!("{} (CME error {})", message, code),
+            Self::CmsError {
+                code: Some(code),
+                message,
+            } => format!("{} (CMS error {})", message, code),
+            Self::CmeError { message, .. } | Self::CmsError { message, .. } => message.clone(),
+            Self::NoCarrier => "No carrier".to_string(),
+            Self::Busy => "Busy".to_string(),
+            Self::NoAnswer => "No answer".to_string(),
+            Self::NoDialtone => "No dial tone".to_string(),
+            Self::Connect(_) => "Connected".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for FinalResult {
+    /// The result code as the modem sends it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "OK"),
+            Self::Error => write!(f, "ERROR"),
+            Self::CmeError { code, message } => match code {
+                Some(code) => write!(f, "+CME ERROR: {}", code),
+                None => write!(f, "+CME ERROR: {}", message),
+            },
+            Self::CmsError { code, message } => match code {
+                Some(code) => write!(f, "+CMS ERROR: {}", code),
+                None => write!(f, "+CMS ERROR: {}", message),
+            },
+            Self::NoCarrier => write!(f, "NO CARRIER"),
+            Self::Busy => write!(f, "BUSY"),
+            Self::NoAnswer => write!(f, "NO ANSWER"),
+            Self::NoDialtone => write!(f, "NO DIALTONE"),
+            Self::Connect(None) => write!(f, "CONNECT"),
+            Self::Connect(Some(text)) => write!(f, "CONNECT {}", text),
+        }
+    }
+}
+
+fn error_detail(rest: &str, table: fn(u16) -> Option<&'static str>) -> (Option<u16>, String) {
+    let rest = rest.trim();
+    match rest.parse::<u16>() {
+        Ok(code) => (
+            Some(code),
+            table(code)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Unknown error {}", code)),
+        ),
+        Err(_) => (None, rest.to_string()),
+    }
+}
+
+/// 3GPP TS 27.007 section 9.2 mobile equipment error codes.
+pub fn cme_error_text(code: u16) -> Option<&'static str> {
+    Some(match code {
+        0 => "Phone failure",
+        1 => "No connection to phone",
+        2 => "Phone-adaptor link reserved",
+        3 => "Operation not allowed",
+        4 => "Operation not supported",
+        5 => "PH-SIM PIN required",
+        6 => "PH-FSIM PIN required",
+        7 => "PH-FSIM PUK required",
+        10 => "SIM not inserted",
+        11 => "SIM PIN required",
+        12 => "SIM PUK required",
+        13 => "SIM failure",
+        14 => "SIM busy",
+        15 => "SIM wrong",
+        16 => "Incorrect password",
+        17 => "SIM PIN2 required",
+        18 => "SIM PUK2 required",
+        20 => "Memory full",
+        21 => "Invalid index",
+        22 => "Not found",
+        23 => "Memory failure",
+        24 => "Text string too long",
+        25 => "Invalid characters in text string",
+        26 => "Dial string too long",
+        27 => "Invalid characters in dial string",
+        30 => "No network service",
+        31 => "Network timeout",
+        32 => "Network not allowed - emergency calls only",
+        40 => "Network personalization PIN required",
+        41 => "Network personalization PUK required",
+        42 => "Network subset personalization PIN required",
+        43 => "Network subset personalization PUK required",
+        44 => "Service provider personalization PIN required",
+        45 => "Service provider personalization PUK required",
+        46 => "Corporate personalization PIN required",
+        47 => "Corporate personalization PUK required",
+        50 => "Incorrect parameters",
+        100 => "Unknown error",
+        _ => return None,
+    })
+}
+
+/// 3GPP TS 27.005 section 3.2.5 message service error codes.
+pub fn cms_error_text(code: u16) -> Option<&'static str> {
+    Some(match code {
+        300 => "ME failure",
+        301 => "SMS service of ME reserved",
+        302 => "Operation not allowed",
+        303 => "Operation not supported",
+        304 => "Invalid PDU mode parameter",
+        305 => "Invalid text mode parameter",
+        310 => "SIM not inserted",
+        311 => "SIM PIN required",
+        312 => "PH-SIM PIN required",
+        313 => "SIM failure",
+        314 => "SIM busy",
+        315 => "SIM wrong",
+        316 => "SIM PUK required",
+        317 => "SIM PIN2 required",
+        318 => "SIM PUK2 required",
+        320 => "Memory failure",
+        321 => "Invalid memory index",
+        322 => "Memory full",
+        330 => "SMSC address unknown",
+        331 => "No network service",
+        332 => "Network timeout",
+        340 => "No +CNMA acknowledgement expected",
+        500 => "Unknown error",
+        _ => return None,
+    })
+}
+
+const UNSOLICITED_PREFIXES: &[&str] = &[
+    "+CREG:", "+CGREG:", "+CEREG:", "+C5GREG:", "RING", "+CRING:", "+CLIP:", "+CMTI:", "+CMT:",
+    "+CDSI:", "+CBM:", "+CUSD:", "+CIEV:", "+CGEV:", "+QIND:", "^",
+];
+
+/// Whether a line looks like an unsolicited result code (URC).
+pub fn is_unsolicited(line: &str) -> bool {
+    UNSOLICITED_PREFIXES.iter().any(|p| line.starts_with(p))
+}
+
+/// The `+NAME` of an extended command or response line (`AT+CGSN=1` -> `+CGSN`).
+fn extended_name(text: &str) -> Option<&str> {
+    let start = text.find(['+', '^', '$'])?;
+    let rest = &text[start..];
+    let end = rest[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .map_or(rest.len(), |i| i + 1);
+    (end > 1).then(|| &rest[..end])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtResponse {
+    /// The command echoed back by the modem (`ATE1`).
+    pub echo: Option<String>,
+    /// Result codes interleaved with the response that belong to something
+    /// else: URCs such as `RING` or `+CREG:` and intermediate codes.
+    pub intermediate: Vec<String>,
+    /// Information text lines, in order.
+    pub information: Vec<String>,
+    pub result: FinalResult,
+}
+
+impl AtResponse {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_success()
+    }
+
+    /// Information text with the command's own `+NAME:` prefix removed,
+    /// one value per line (`+CGMM: "SM-G991B"` -> `"SM-G991B"`).
+    pub fn value(&self) -> String {
+        self.information
+            .iter()
+            .map(|line| match line.split_once(':') {
+                Some((prefix, value)) if extended_name(prefix) == Some(prefix.trim()) => {
+                    value.trim()
+                }
+                _ => line.trim(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Convert a non-success final result into an error message.
+    pub fn into_result(self) -> Result<Self, String> {
+        if self.is_ok() {
+            Ok(self)
+        } else {
+            Err(self.result.description())
+        }
+    }
+}
+
+impl fmt::Display for AtResponse {
+    /// Information lines followed by the final result code, one per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.information {
+            writeln!(f, "{}", line)?;
+        }
+        write!(f, "{}", self.result)
+    }
+}
+
+/// Parse the raw text received after sending `command`. Returns `None` until a
+/// final result code has been received on a complete line.
+pub fn parse_response(command: &str, raw: &str) -> Option<AtResponse> {
+    let complete = match raw.rfind(['\r', '\n']) {
+        Some(i) => &raw[..i],
+        None => return None,
+    };
+    let command = command.trim();
+    let own_name = extended_name(command);
+
+    let mut response = AtResponse {
+        echo: None,
+        intermediate: Vec::new(),
+        information: Vec::new(),
+        result: FinalResult::Ok,
+    };
+    let mut seen_content = false;
+    for line in complete.split(['\r', '\n']).map(str::trim) {
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(result) = FinalResult::parse(line) {
+            response.result = result;
+            return Some(response);
+        }
+        if !seen_content && line.eq_ignore_ascii_case(command) {
+            response.echo = Some(line.to_string());
+        } else if is_unsolicited(line)
+            && (extended_name(line).is_none() || extended_name(line) != own_name)
+        {
+            response.intermediate.push(line.to_string());
+        } else {
+            response.information.push(line.to_string());
+        }
+        seen_content = true;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ok_inside_payload_is_not_final() -> Result<(), Box<dyn std::error::Error>> {
+        let raw = "AT+COPS?\r\r\n+COPS: 0,0,\"BOOK\",7\r\n";
+        assert_eq!(parse_response("AT+COPS?", raw), None);
+
+        let raw = format!("{}\r\nOK\r\n", raw);
+        let resp = parse_response("AT+COPS?", &raw).ok_or("incomplete response")?;
+        assert_eq!(resp.echo.as_deref(), Some("AT+COPS?"));
+        assert_eq!(resp.information, vec!["+COPS: 0,0,\"BOOK\",7"]);
+        assert_eq!(resp.value(), "0,0,\"BOOK\",7");
+        assert!(resp.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_final_line_must_be_complete() {
+        assert_eq!(parse_response("AT", "\r\nOK"), None);
+        assert!(parse_response("AT", "\r\nOK\r").is_some());
+    }
+
+    #[test]
+    fn test_cme_and_cms_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let resp =
+            parse_response("AT+CPIN?", "\r\n+CME ERROR: 10\r\n").ok_or("incomplete response")?;
+        assert_eq!(
+            resp.result,
+            FinalResult::CmeError {
+                code: Some(10),
+                message: "SIM not inserted".into()
+            }
+        );
+        assert_eq!(resp.result.description(), "SIM not inserted (CME error 10)");
+        assert_eq!(resp.result.to_string(), "+CME ERROR: 10");
+        assert_eq!(
+            resp.clone().into_result(),
+            Err("SIM not inserted (CME error 10)".to_string())
+        );
+
+        let verbose =
+            FinalResult::parse("+CME ERROR: SIM PIN required").ok_or("no final result")?;
+        assert_eq!(verbose.description(), "SIM PIN required");
+        let cms = FinalResult::parse("+CMS ERROR: 330").ok_or("no final result")?;
+        assert_eq!(cms.description(), "SMSC address unknown (CMS error 330)");
+        assert_eq!(
+            FinalResult::parse("+CME ERROR: 999")
+                .ok_or("no final result")?
+                .description(),
+            "Unknown error 999 (CME error 999)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_other_final_codes() {
+        assert_eq!(
+            FinalResult::parse("NO CARRIER"),
+            Some(FinalResult::NoCarrier)
+        );
+        assert_eq!(
+            FinalResult::parse("CONNECT 115200"),
+            Some(FinalResult::Connect(Some("115200".into())))
+        );
+        assert_eq!(FinalResult::parse("OKAY"), None);
+        assert_eq!(FinalResult::parse("+CSQ: 20,99"), None);
+    }
+
+    #[test]
+    fn test_urcs_separated_from_information() -> Result<(), Box<dyn std::error::Error>> {
+        let raw = "\r\n+CREG: 1,\"00C3\",\"1A2B\"\r\n+CGSN: \"356938035643809\"\r\nRING\r\nOK\r\n";
+        let resp = parse_response("AT+CGSN=1", raw).ok_or("incomplete response")?;
+        assert_eq!(
+            resp.intermediate,
+            vec!["+CREG: 1,\"00C3\",\"1A2B\"", "RING"]
+        );
+        assert_eq!(resp.value(), "\"356938035643809\"");
+
+        // A solicited +CREG response is information, not a URC.
+        let resp =
+            parse_response("AT+CREG?", "\r\n+CREG: 0,1\r\nOK\r\n").ok_or("incomplete response")?;
+        assert_eq!(resp.information, vec!["+CREG: 0,1"]);
+        assert!(resp.intermediate.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_keeps_colons_in_plain_text() -> Result<(), Box<dyn std::error::Error>> {
+        let resp = parse_response("AT+CGMR", "\r\nM8350_V1.0 Build: 12:30\r\nOK\r\n")
+            .ok_or("incomplete response")?;
+        assert_eq!(resp.value(), "M8350_V1.0 Build: 12:30");
+        assert_eq!(resp.to_string(), "M8350_V1.0 Build: 12:30\nOK");
+        Ok(())
+    }
+}
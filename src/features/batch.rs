@@ -0,0 +1,327 @@
+/// Batched shell execution over a single `adb shell` round trip.
+///
+/// Each command's stdout is framed by marker lines that carry a nonce
+/// generated per script, so command output can never be mistaken for a
+/// delimiter. Stderr is captured separately through a temporary file on the
+/// device, and every command's exit code is preserved.
+use super::adb_shell;
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Per-script marker prefix, unpredictable to the commands being run.
+pub(crate) fn new_nonce() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+    );
+    format!("FOEM{:016x}", hasher.finish())
+}
+
+/// Result of one command in a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub label: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the batch ended before this command reported its status.
+    pub code: Option<i32>,
+}
+
+impl CommandOutput {
+    /// Trimmed stdout on success; otherwise stderr, stdout or the exit code.
+    pub fn result(&self) -> Result<String, String> {
+        match self.code {
+            Some(0) => Ok(self.stdout.trim().to_string()),
+            Some(code) => {
+                let stderr = self.stderr.trim();
+                let stdout = self.stdout.trim();
+                Err(if !stderr.is_empty() {
+                    stderr.to_string()
+                } else if !stdout.is_empty() {
+                    stdout.to_string()
+                } else {
+                    format!("exit code {}", code)
+                })
+            }
+            None => Err("no result (batch output truncated)".to_string()),
+        }
+    }
+}
+
+/// A list of labeled shell commands executed in one `adb shell` call.
+#[derive(Debug, Clone)]
+pub struct BatchScript {
+    nonce: String,
+    commands: Vec<(String, String)>,
+}
+
+impl Default for BatchScript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatchScript {
+    pub fn new() -> Self {
+        Self {
+            nonce: new_nonce(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// Batch from `(label, command)` pairs.
+    pub fn from_commands<L: AsRef<str>, C: AsRef<str>>(commands: &[(L, C)]) -> Self {
+        let mut batch = Self::new();
+        for (label, cmd) in commands {
+            batch.push(label.as_ref(), cmd.as_ref());
+        }
+        batch
+    }
+
+    pub fn push(&mut self, label: &str, command: &str) -> &mut Self {
+        self.commands.push((label.to_string(), command.to_string()));
+        self
+    }
+
+    /// The `sh` script: each command runs in a subshell with stderr sent to
+    /// a scratch file that is printed after the exit-code marker.
+    pub fn script(&self) -> String {
+        let n = &self.nonce;
+        let mut script = String::from(
+            "d=; for t in \"$TMPDIR\" /data/local/tmp /tmp; do \
+             if [ -n \"$t\" ] && [ -d \"$t\" ] && [ -w \"$t\" ]; then d=$t; break; fi; done\n",
+        );
+        for (i, (_, cmd)) in self.commands.iter().enumerate() {
+            script.push_str(&format!(
+                "e=/dev/null; [ -n \"$d\" ] && e=$d/.{n}_{i}\n\
+                 echo '{n} OUT {i}'; (\n{cmd}\n) 2>\"$e\" </dev/null; c=$?; echo\n\
+                 echo \"{n} ERR {i} $c\"; [ \"$e\" != /dev/null ] && cat \"$e\" 2>/dev/null && rm -f \"$e\"; echo\n\
+                 echo '{n} END {i}'\n"
+            ));
+        }
+        script
+    }
+
+    /// Split the combined output of `script()` back into per-command results.
+    pub fn parse(&self, output: &str) -> Vec<CommandOutput> {
+        enum Section {
+            None,
+            Out(usize),
+            Err(usize),
+        }
+        let mut results: Vec<CommandOutput> = self
+            .commands
+            .iter()
+            .map(|(label, _)| CommandOutput {
+                label: label.clone(),
+                stdout: String::new(),
+                stderr: String::new(),
+                code: None,
+            })
+            .collect();
+        let mut stdout: Vec<Vec<&str>> = vec![Vec::new(); results.len()];
+        let mut stderr: Vec<Vec<&str>> = vec![Vec::new(); results.len()];
+
+        let prefix = format!("{} ", self.nonce);
+        let mut section = Section::None;
+        for line in output.lines() {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if let Some(marker) = line.strip_prefix(&prefix) {
+                let mut fields = marker.split(' ');
+                let kind = fields.next().unwrap_or("");
+                let idx = fields
+                    .next()
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .filter(|i| *i < results.len());
+                if let Some(i) = idx {
+                    section = match kind {
+                        "OUT" => Section::Out(i),
+                        "ERR" => {
+                            results[i].code = fields.next().and_then(|c| c.parse().ok());
+                            Section::Err(i)
+                        }
+                        _ => Section::None,
+                    };
+                    continue;
+                }
+            }
+            match section {
+                Section::Out(i) => stdout[i].push(line),
+                Section::Err(i) => stderr[i].push(line),
+                Section::None => {}
+            }
+        }
+
+        // The last line of each section is the one terminated by the script's
+        // own `echo`, so joining with '\n' restores the original text exactly.
+        for (i, result) in results.iter_mut().enumerate() {
+            result.stdout = stdout[i].join("\n");
+            result.stderr = stderr[i].join("\n");
+        }
+        results
+    }
+
+    /// Run the batch on `serial`. `Err` means the adb call itself failed.
+    pub fn run(&self, serial: &str) -> Result<Vec<CommandOutput>, String> {
+        if self.commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        // A single argument reaches the device shell unsplit.
+        let output = adb_shell(serial, &[&self.script()])?;
+        Ok(self.parse(&output))
+    }
+}
+
+/// Run labeled commands in one batch and return `(label, result)` pairs; a
+/// transport failure is reported for every command.
+pub fn run_commands<L: AsRef<str>, C: AsRef<str>>(
+    serial: &str,
+    commands: &[(L, C)],
+) -> Vec<(String, Result<String, String>)> {
+    let batch = BatchScript::from_commands(commands);
+    match batch.run(serial) {
+        Ok(outputs) => outputs
+            .into_iter()
+            .map(|o| {
+                let result = o.result();
+                (o.label, result)
+            })
+            .collect(),
+        Err(e) => commands
+            .iter()
+            .map(|(label, _)| (label.as_ref().to_string(), Err(e.clone())))
+            .collect(),
+    }
+}
+
+/// Build the output a device would print for `script`, for command mocks.
+/// Each reply is `(stdout, stderr, exit code)`; missing replies simulate a
+/// truncated batch.
+#[cfg(test)]
+pub fn mock_reply(script: &str, replies: &[(&str, &str, i32)]) -> Result<String, String> {
+    let nonce = script
+        .lines()
+        .find_map(|l| l.strip_prefix("echo '")?.split_once(" OUT 0'"))
+        .map(|(nonce, _)| nonce)
+        .ok_or_else(|| format!("not a BatchScript: {}", script))?;
+    let mut out = String::new();
+    for (i, (stdout, stderr, code)) in replies.iter().enumerate() {
+        out.push_str(&format!(
+            "{nonce} OUT {i}\n{stdout}\n{nonce} ERR {i} {code}\n{stderr}\n{nonce} END {i}\n"
+        ));
+    }
+    Ok(out)
+}
+
+/// `mock_reply` for commands that only print to stdout.
+#[cfg(test)]
+pub fn mock_stdout(script: &str, replies: &[(&str, i32)]) -> Result<String, String> {
+    let full: Vec<(&str, &str, i32)> = replies.iter().map(|(o, c)| (*o, "", *c)).collect();
+    mock_reply(script, &full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_is_unique() {
+        assert_ne!(BatchScript::new().nonce, BatchScript::new().nonce);
+    }
+
+    #[test]
+    fn test_parse_roundtrip_with_marker_like_output() -> Result<(), Box<dyn std::error::Error>> {
+        let batch = BatchScript::from_commands(&[("a", "x"), ("b", "y"), ("c", "z")]);
+        // Output containing old-style markers and foreign nonces stays intact.
+        let tricky = "B_MARKER_0\nFOEM0000000000000000 END 0\nline";
+        let out = mock_reply(
+            &batch.script(),
+            &[
+                (tricky, "", 0),
+                ("", "permission denied", 1),
+                ("ends with newline\n", "", 0),
+            ],
+        )?;
+        let results = batch.parse(&out);
+        assert_eq!(results[0].stdout, tricky);
+        assert_eq!(results[0].result(), Ok(tricky.to_string()));
+        assert_eq!(results[1].code, Some(1));
+        assert_eq!(results[1].stderr, "permission denied");
+        assert_eq!(results[1].result(), Err("permission denied".to_string()));
+        assert_eq!(results[2].stdout, "ends with newline\n");
+        assert_eq!(results[2].code, Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_truncated_and_crlf() -> Result<(), Box<dyn std::error::Error>> {
+        let batch = BatchScript::from_commands(&[("a", "x"), ("b", "y")]);
+        let out = mock_stdout(&batch.script(), &[("value", 0)])?.replace('\n', "\r\n");
+        let results = batch.parse(&out);
+        assert_eq!(results[0].result(), Ok("value".to_string()));
+        assert_eq!(results[1].code, None);
+        assert!(matches!(results[1].result(), Err(e) if e.contains("truncated")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_falls_back_to_stdout_then_code() -> Result<(), Box<dyn std::error::Error>> {
+        let batch = BatchScript::from_commands(&[("a", "x"), ("b", "y")]);
+        let out = mock_stdout(&batch.script(), &[("partial", 2), ("", 3)])?;
+        let results = batch.parse(&out);
+        assert_eq!(results[0].result(), Err("partial".to_string()));
+        assert_eq!(results[1].result(), Err("exit code 3".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mock_reply_rejects_foreign_script() {
+        assert!(mock_stdout("getprop ro.x", &[("1", 0)]).is_err());
+    }
+
+    #[test]
+    fn test_script_frames_each_command() {
+        let batch = BatchScript::from_commands(&[("a", "getprop ro.x")]);
+        let script = batch.script();
+        assert!(script.contains("(\ngetprop ro.x\n) 2>\"$e\""));
+        assert!(script.contains(&format!("echo \"{} ERR 0 $c\"", batch.nonce)));
+    }
+
+    #[test]
+    fn test_run_commands_transport_error() {
+        crate::exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|_, _, _| Err("device offline".to_string())));
+        });
+        let results = run_commands("SER", &[("a", "x"), ("b", "y")]);
+        crate::exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[1],
+            ("b".to_string(), Err("device offline".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_run_commands_passes_script_as_one_argument() {
+        crate::exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|program, args, _| {
+                assert_eq!(program, "adb");
+                assert_eq!(&args[..3], &["-s", "SER", "shell"]);
+                assert_eq!(args.len(), 4);
+                mock_stdout(args[3], &[("1", 0), ("2", 0)])
+            }));
+        });
+        let results = run_commands("SER", &[("a", "x"), ("b", "y")]);
+        crate::exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+        assert_eq!(results[0].1, Ok("1".to_string()));
+        assert_eq!(results[1].1, Ok("2".to_string()));
+    }
+}
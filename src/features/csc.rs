@@ -0,0 +1,345 @@
+/// Read-only Samsung CSC (Consumer Software Customization) inventory.
+///
+/// Reports the active and original sales codes, every CSC the installed
+/// firmware carries (OMC under `/optics/configs/carriers` or the older
+/// `/system/omc` and `/product/omc`), the OMC version, and the carrier
+/// feature flags of the active CSC. Nothing is written to the device.
+use super::batch;
+
+use std::collections::BTreeMap;
+
+/// Directories holding per-CSC OMC packages, newest layout first.
+const CSC_ROOTS: &[&str] = &[
+    "/optics/configs/carriers",
+    "/product/omc",
+    "/system/omc",
+    "/odm/omc",
+];
+
+const SALES_CODE_PROPS: &[(&str, &str)] = &[
+    ("active", "ro.csc.sales_code"),
+    ("ril", "ril.sales_code"),
+    ("original", "ro.boot.carrierid"),
+    ("omc_path", "persist.sys.omc_path"),
+    ("omcnw_path", "persist.sys.omcnw_path"),
+    ("omc_version", "ro.omc.build.version"),
+    ("country", "ro.csc.country_code"),
+    ("country_iso", "ro.csc.countryiso_code"),
+];
+
+/// Sales codes are three characters of `A-Z0-9` with at least one letter.
+pub fn is_csc_code(name: &str) -> bool {
+    name.len() == 3
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+        && name.bytes().any(|b| b.is_ascii_uppercase())
+}
+
+/// Map `CSC -> directories it was found in` from `ls -1d` output of
+/// `<root>/*` and `<root>/*/*` paths.
+pub fn parse_csc_listing(text: &str) -> BTreeMap<String, Vec<String>> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in text.lines().map(str::trim) {
+        let Some((parent, name)) = path.rsplit_once('/') else {
+            continue;
+        };
+        if !is_csc_code(name) {
+            continue;
+        }
+        let dirs = map.entry(name.to_string()).or_default();
+        if !dirs.iter().any(|d| d == parent) {
+            dirs.push(parent.to_string());
+        }
+    }
+    map
+}
+
+/// Simple `<Tag>value</Tag>` pairs, in document order. Good enough for the
+/// flat OMC configuration files; not a general XML parser.
+pub fn parse_flat_xml(xml: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let Some(close) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..close];
+        rest = &rest[close + 1..];
+        if tag.starts_with(['/', '?', '!']) || tag.ends_with('/') || tag.contains(' ') {
+            continue;
+        }
+        let end_tag = format!("</{}>", tag);
+        let Some(value) = rest.find('<').map(|i| &rest[..i]) else {
+            break;
+        };
+        if rest[value.len()..].starts_with(&end_tag) {
+            pairs.push((tag.to_string(), value.trim().to_string()));
+            rest = &rest[value.len() + end_tag.len()..];
+        }
+    }
+    pairs
+}
+
+/// Carrier feature flags from a plain-text `cscfeature.xml`.
+pub fn parse_csc_features(xml: &str) -> Result<Vec<(String, String)>, String> {
+    if !xml.contains("<FeatureSet") {
+        return Err(if xml.trim().is_empty() {
+            "not found".into()
+        } else {
+            "encoded (newer OMC builds encrypt cscfeature.xml)".into()
+        });
+    }
+    Ok(parse_flat_xml(xml)
+        .into_iter()
+        .filter(|(tag, _)| tag.starts_with("CscFeature_"))
+        .map(|(tag, value)| (tag["CscFeature_".len()..].to_string(), value))
+        .collect())
+}
+
+/// CSC name from an OMC path such as `/optics/configs/carriers/single/XEU/conf`.
+fn csc_from_path(path: &str) -> Option<&str> {
+    path.split('/').find(|p| is_csc_code(p))
+}
+
+/// Read-only CSC inventory report for a Samsung device.
+pub fn csc_inventory(serial: &str) -> String {
+    let mut cmds: Vec<(String, String)> = SALES_CODE_PROPS
+        .iter()
+        .map(|(label, prop)| (label.to_string(), format!("getprop {}", prop)))
+        .collect();
+    let globs: Vec<String> = CSC_ROOTS
+        .iter()
+        .flat_map(|r| [format!("{}/*", r), format!("{}/*/*", r)])
+        .collect();
+    cmds.push((
+        "listing".into(),
+        format!("ls -1d {} 2>/dev/null; true", globs.join(" ")),
+    ));
+    cmds.push(("mps".into(), "cat /efs/imei/mps_code.dat".into()));
+    // The active OMC directory holds SW_Configuration.xml and cscfeature.xml,
+    // either directly or under system/ depending on the OMC generation.
+    cmds.push((
+        "sw_config".into(),
+        "p=$(getprop persist.sys.omc_path); for f in \"$p/SW_Configuration.xml\" \"$p/system/SW_Configuration.xml\" \
+         /system/SW_Configuration.xml; do [ -f \"$f\" ] && cat \"$f\" && break; done; true"
+            .into(),
+    ));
+    cmds.push((
+        "features".into(),
+        "p=$(getprop persist.sys.omc_path); for f in \"$p/cscfeature.xml\" \"$p/system/cscfeature.xml\" \
+         /system/csc/feature.xml; do [ -f \"$f\" ] && cat \"$f\" && break; done; true"
+            .into(),
+    ));
+    let mut values: BTreeMap<String, String> = BTreeMap::new();
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        if let Ok(v) = res {
+            values.insert(label, v.trim().to_string());
+        }
+    }
+    let get = |k: &str| values.get(k).map(String::as_str).unwrap_or("");
+    let or_unknown = |v: &str| {
+        if v.is_empty() {
+            "unknown".to_string()
+        } else {
+            v.to_string()
+        }
+    };
+
+    let active = [get("active"), get("ril"), get("mps")]
+        .into_iter()
+        .find(|v| is_csc_code(v))
+        .or_else(|| csc_from_path(get("omc_path")))
+        .unwrap_or("");
+    let original = get("original");
+    let network = csc_from_path(get("omcnw_path")).unwrap_or("");
+
+    let mut output = String::from("Samsung CSC Inventory:\n");
+    output.push_str(&format!("  Active sales code: {}\n", or_unknown(active)));
+    output.push_str(&format!(
+        "  Original sales code: {}\n",
+        or_unknown(original)
+    ));
+    if !network.is_empty() && network != active {
+        output.push_str(&format!("  Network CSC (SIM-based): {}\n", network));
+    }
+    if !get("mps").is_empty() && get("mps") != active {
+        output.push_str(&format!("  EFS mps_code.dat: {}\n", get("mps")));
+    }
+    if !get("country").is_empty() || !get("country_iso").is_empty() {
+        output.push_str(&format!(
+            "  Country: {} ({})\n",
+            or_unknown(get("country")),
+            or_unknown(get("country_iso"))
+        ));
+    }
+    output.push_str(&format!(
+        "  Active OMC path: {}\n",
+        or_unknown(get("omc_path"))
+    ));
+    output.push_str(&format!(
+        "  OMC version: {}\n",
+        or_unknown(get("omc_version"))
+    ));
+    for (tag, value) in parse_flat_xml(get("sw_config")) {
+        if tag.contains("Version") || tag.ends_with("Code") {
+            output.push_str(&format!("  {}: {}\n", tag, value));
+        }
+    }
+
+    let cscs = parse_csc_listing(get("listing"));
+    output.push_str(&format!(
+        "\n  Supported CSCs in firmware ({}):\n",
+        cscs.len()
+    ));
+    if cscs.is_empty() {
+        output.push_str(
+            "    none found (not a Samsung OMC firmware, or the directories are unreadable)\n",
+        );
+    }
+    let mut by_dir: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (code, dirs) in &cscs {
+        for dir in dirs {
+            by_dir.entry(dir.as_str()).or_default().push(code.as_str());
+        }
+    }
+    for (dir, codes) in &by_dir {
+        output.push_str(&format!("    {}: {}\n", dir, codes.join(" ")));
+    }
+    if !active.is_empty() && !cscs.is_empty() && !cscs.contains_key(active) {
+        output.push_str(&format!(
+            "  WARNING: active CSC {} is not in this firmware's CSC list.\n",
+            active
+        ));
+    }
+    if !original.is_empty() && !cscs.is_empty() && !cscs.contains_key(original) {
+        output.push_str(&format!(
+            "  WARNING: original CSC {} is not in this firmware's CSC list; a matching multi-CSC firmware is needed.\n",
+            original
+        ));
+    }
+
+    output.push_str("\n  Carrier feature flags:\n");
+    match parse_csc_features(get("features")) {
+        Ok(features) if features.is_empty() => output.push_str("    none set\n"),
+        Ok(features) => {
+            for (name, value) in features {
+                output.push_str(&format!("    {} = {}\n", name, value));
+            }
+        }
+        Err(e) => output.push_str(&format!("    cscfeature.xml {}\n", e)),
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_csc_code() {
+        assert!(is_csc_code("XEU"));
+        assert!(is_csc_code("O2U"));
+        assert!(!is_csc_code("123"));
+        assert!(!is_csc_code("xeu"));
+        assert!(!is_csc_code("conf"));
+    }
+
+    #[test]
+    fn test_parse_csc_listing() {
+        let ls = "/optics/configs/carriers/single\n\
+                  /optics/configs/carriers/single/XEU\n\
+                  /optics/configs/carriers/single/BTU\n\
+                  /optics/configs/carriers/multi/EUX\n\
+                  /system/omc/XEU\n\
+                  /system/omc/SW_Configuration.xml\n";
+        let map = parse_csc_listing(ls);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["BTU", "EUX", "XEU"]);
+        assert_eq!(map["XEU"].len(), 2);
+    }
+
+    #[test]
+    fn test_parse_flat_xml() {
+        let xml =
+            "<?xml version=\"1.0\"?>\n<SWConfiguration>\n  <CustomerCSC>XEU</CustomerCSC>\n  \
+                   <CSCVersion>G991BOXM5DWB1</CSCVersion>\n  <Empty/>\n</SWConfiguration>";
+        assert_eq!(
+            parse_flat_xml(xml),
+            vec![
+                ("CustomerCSC".to_string(), "XEU".to_string()),
+                ("CSCVersion".to_string(), "G991BOXM5DWB1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_csc_features() -> Result<(), Box<dyn std::error::Error>> {
+        let xml = "<SamsungMobileFeature><FeatureSet>\n\
+                   <CscFeature_Common_EnableCallRecording>TRUE</CscFeature_Common_EnableCallRecording>\n\
+                   <CscFeature_RIL_ConfigVolte>ON</CscFeature_RIL_ConfigVolte>\n\
+                   </FeatureSet></SamsungMobileFeature>";
+        let features = parse_csc_features(xml)?;
+        assert_eq!(
+            features[0],
+            ("Common_EnableCallRecording".into(), "TRUE".into())
+        );
+        assert_eq!(features.len(), 2);
+        assert!(parse_csc_features("\u{1}\u{2}binary")
+            .unwrap_err()
+            .contains("encoded"));
+        assert_eq!(parse_csc_features("").unwrap_err(), "not found");
+        Ok(())
+    }
+
+    #[test]
+    fn test_csc_inventory_report() {
+        struct MockGuard;
+        impl Drop for MockGuard {
+            fn drop(&mut self) {
+                crate::exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+            }
+        }
+        crate::exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|_, args, _| {
+                let parts = [
+                    "XEU",
+                    "XEU",
+                    "BTU",
+                    "/optics/configs/carriers/single/XEU/conf",
+                    "",
+                    "SAOMC_SM-G991B_OXM_EUX_SS_0011",
+                    "",
+                    "",
+                    "/optics/configs/carriers/single/XEU\n/optics/configs/carriers/single/DBT",
+                    "",
+                    "",
+                    "",
+                ];
+                let replies: Vec<(&str, i32)> = parts.iter().map(|p| (*p, 0)).collect();
+                crate::features::batch::mock_stdout(args.last().ok_or("no script")?, &replies)
+            }));
+        });
+        let _guard = MockGuard;
+
+        let out = csc_inventory("SER1");
+        assert!(out.contains("Active sales code: XEU"), "{}", out);
+        assert!(out.contains("Original sales code: BTU"), "{}", out);
+        assert!(
+            out.contains("OMC version: SAOMC_SM-G991B_OXM_EUX_SS_0011"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("/optics/configs/carriers/single: DBT XEU"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("original CSC BTU is not in this firmware"),
+            "{}",
+            out
+        );
+        assert!(out.contains("cscfeature.xml not found"), "{}", out);
+    }
+}
@@ -0,0 +1,699 @@
+/// Qualcomm DIAG protocol over the async-HDLC serial framing used by the
+/// `HS-USB Diagnostics` interface.
+///
+/// Every request and response is `payload || crc16 (LE)`, with `0x7E` and
+/// `0x7D` escaped as `0x7D, byte ^ 0x20`, and terminated by `0x7E`. The typed
+/// client never writes NV/EFS; apart from queries it only changes the modem's
+/// log and event masks, which do not survive a reboot.
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+const FLAG: u8 = 0x7E;
+const ESCAPE: u8 = 0x7D;
+const ESCAPE_XOR: u8 = 0x20;
+
+pub const CMD_VERSION_INFO: u8 = 0x00;
+pub const CMD_STATUS: u8 = 0x0C;
+pub const CMD_LOG: u8 = 0x10;
+pub const CMD_BAD_CMD: u8 = 0x13;
+pub const CMD_BAD_PARM: u8 = 0x14;
+pub const CMD_BAD_LEN: u8 = 0x15;
+pub const CMD_BAD_MODE: u8 = 0x18;
+pub const CMD_EVENT_REPORT: u8 = 0x60;
+pub const CMD_LOG_CONFIG: u8 = 0x73;
+pub const CMD_EXT_BUILD_ID: u8 = 0x7C;
+pub const CMD_EVENT_MASK_GET: u8 = 0x81;
+
+const LOG_CONFIG_DISABLE: u32 = 0;
+const LOG_CONFIG_RETRIEVE_ID_RANGES: u32 = 1;
+const LOG_CONFIG_SET_MASK: u32 = 3;
+const LOG_CONFIG_GET_MASK: u32 = 4;
+const LOG_EQUIP_COUNT: usize = 16;
+
+/// CRC-16/CCITT as used by HDLC (reflected poly 0x8408, init and xorout 0xFFFF).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Frame a DIAG payload for the wire.
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let crc = crc16(payload).to_le_bytes();
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    for &byte in payload.iter().chain(crc.iter()) {
+        if byte == FLAG || byte == ESCAPE {
+            frame.push(ESCAPE);
+            frame.push(byte ^ ESCAPE_XOR);
+        } else {
+            frame.push(byte);
+        }
+    }
+    frame.push(FLAG);
+    frame
+}
+
+/// Incremental decoder turning a byte stream into CRC-checked payloads.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    escaped: bool,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed received bytes; returns every frame completed by them.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<Vec<u8>, String>> {
+        let mut frames = Vec::new();
+        for &byte in data {
+            match byte {
+                FLAG => {
+                    let raw = std::mem::take(&mut self.buf);
+                    let escaped = std::mem::replace(&mut self.escaped, false);
+                    // Leading flags and back-to-back flags delimit nothing.
+                    if raw.is_empty() {
+                        continue;
+                    }
+                    frames.push(if escaped {
+                        Err("DIAG frame ended inside an escape sequence".to_string())
+                    } else {
+                        check_frame(raw)
+                    });
+                }
+                ESCAPE => self.escaped = true,
+                _ if self.escaped => {
+                    self.escaped = false;
+                    self.buf.push(byte ^ ESCAPE_XOR);
+                }
+                _ => self.buf.push(byte),
+            }
+        }
+        frames
+    }
+}
+
+fn check_frame(mut raw: Vec<u8>) -> Result<Vec<u8>, String> {
+    if raw.len() < 3 {
+        return Err(format!("DIAG frame too short ({} bytes)", raw.len()));
+    }
+    let crc_at = raw.len() - 2;
+    let received = u16::from_le_bytes([raw[crc_at], raw[crc_at + 1]]);
+    raw.truncate(crc_at);
+    let expected = crc16(&raw);
+    if received != expected {
+        return Err(format!(
+            "DIAG frame CRC mismatch (got {:04X}, expected {:04X})",
+            received, expected
+        ));
+    }
+    Ok(raw)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub compile_date: String,
+    pub compile_time: String,
+    pub release_date: String,
+    pub release_time: String,
+    pub version_dir: String,
+    pub station_class_mark: u8,
+    pub cai_revision: u8,
+    pub mobile_model: u8,
+    pub firmware_revision: u16,
+    pub slot_cycle_index: u8,
+    pub hw_version: (u8, u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtBuildId {
+    pub version: u8,
+    pub msm_hw_version: u32,
+    pub mobile_model_id: u32,
+    pub build_id: String,
+    pub model_string: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub demod: u8,
+    pub decode: u8,
+    pub interleave: u8,
+    pub esn: u32,
+    pub rf_mode: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMask {
+    pub equipment_id: u32,
+    pub num_items: u32,
+    pub mask: Vec<u8>,
+}
+
+impl LogMask {
+    pub fn enabled_count(&self) -> u32 {
+        self.mask.iter().map(|b| b.count_ones()).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventMask {
+    pub num_bits: u16,
+    pub mask: Vec<u8>,
+}
+
+impl EventMask {
+    pub fn enabled_count(&self) -> u32 {
+        self.mask.iter().map(|b| b.count_ones()).sum()
+    }
+}
+
+/// Little-endian cursor over a response payload.
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+    what: &'static str,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8], what: &'static str) -> Self {
+        Self { data, pos: 0, what }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + n;
+        let slice = self
+            .data
+            .get(self.pos..end)
+            .ok_or_else(|| format!("{} response truncated at byte {}", self.what, self.pos))?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn text(&mut self, n: usize) -> Result<String, String> {
+        Ok(ascii(self.take(n)?))
+    }
+
+    /// NUL-terminated string; tolerates a missing terminator at the end.
+    fn cstr(&mut self) -> String {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        self.pos += (len + 1).min(rest.len());
+        ascii(&rest[..len])
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos.min(self.data.len())..]
+    }
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+pub fn parse_version_info(resp: &[u8]) -> Result<VersionInfo, String> {
+    let mut f = Fields::new(resp, "Version info");
+    f.u8()?;
+    Ok(VersionInfo {
+        compile_date: f.text(11)?,
+        compile_time: f.text(8)?,
+        release_date: f.text(11)?,
+        release_time: f.text(8)?,
+        version_dir: f.text(8)?,
+        station_class_mark: f.u8()?,
+        cai_revision: f.u8()?,
+        mobile_model: f.u8()?,
+        firmware_revision: f.u16()?,
+        slot_cycle_index: f.u8()?,
+        hw_version: (f.u8()?, f.u8()?),
+    })
+}
+
+pub fn parse_ext_build_id(resp: &[u8]) -> Result<ExtBuildId, String> {
+    let mut f = Fields::new(resp, "Extended build ID");
+    f.u8()?;
+    let version = f.u8()?;
+    f.take(2)?;
+    Ok(ExtBuildId {
+        version,
+        msm_hw_version: f.u32()?,
+        mobile_model_id: f.u32()?,
+        build_id: f.cstr(),
+        model_string: f.cstr(),
+    })
+}
+
+pub fn parse_status(resp: &[u8]) -> Result<Status, String> {
+    let mut f = Fields::new(resp, "Status");
+    f.u8()?;
+    Ok(Status {
+        demod: f.u8()?,
+        decode: f.u8()?,
+        interleave: f.u8()?,
+        esn: f.u32()?,
+        rf_mode: f.u16()?,
+    })
+}
+
+/// Parse the per-equipment last item IDs of a log config "retrieve ID ranges".
+pub fn parse_log_id_ranges(resp: &[u8]) -> Result<Vec<u32>, String> {
+    let mut f = Fields::new(resp, "Log ID ranges");
+    f.take(4)?;
+    f.u32()?;
+    let status = f.u32()?;
+    if status != 0 {
+        return Err(format!("Log ID range query failed with status {}", status));
+    }
+    (0..LOG_EQUIP_COUNT).map(|_| f.u32()).collect()
+}
+
+pub fn parse_log_mask(resp: &[u8]) -> Result<LogMask, String> {
+    let mut f = Fields::new(resp, "Log mask");
+    f.take(4)?;
+    f.u32()?;
+    let status = f.u32()?;
+    if status != 0 {
+        return Err(format!("Log mask query failed with status {}", status));
+    }
+    let equipment_id = f.u32()?;
+    let num_items = f.u32()?;
+    let mask = f.take(num_items.div_ceil(8) as usize)?.to_vec();
+    Ok(LogMask {
+        equipment_id,
+        num_items,
+        mask,
+    })
+}
+
+pub fn parse_event_mask(resp: &[u8]) -> Result<EventMask, String> {
+    let mut f = Fields::new(resp, "Event mask");
+    f.u8()?;
+    let error = f.u8()?;
+    if error != 0 {
+        return Err(format!("Event mask query failed with error {}", error));
+    }
+    f.take(2)?;
+    let num_bits = f.u16()?;
+    let wanted = (num_bits as usize).div_ceil(8);
+    let rest = f.rest();
+    Ok(EventMask {
+        num_bits,
+        mask: rest[..wanted.min(rest.len())].to_vec(),
+    })
+}
+
+fn log_config_status(resp: &[u8]) -> Result<(), String> {
+    let mut f = Fields::new(resp, "Log config");
+    f.take(4)?;
+    f.u32()?;
+    match f.u32()? {
+        0 => Ok(()),
+        status => Err(format!("Log config failed with status {}", status)),
+    }
+}
+
+fn describe_error_response(resp: &[u8]) -> Option<String> {
+    let reason = match *resp.first()? {
+        CMD_BAD_CMD => "command not recognized",
+        CMD_BAD_PARM => "invalid parameters",
+        CMD_BAD_LEN => "invalid length",
+        CMD_BAD_MODE => "not accepted in the current mode",
+        _ => return None,
+    };
+    Some(format!("DIAG rejected the request: {}", reason))
+}
+
+/// Request/response DIAG client over any byte stream (normally a serial port).
+pub struct DiagClient<T: Read + Write> {
+    io: T,
+    decoder: FrameDecoder,
+    timeout: Duration,
+}
+
+impl<T: Read + Write> DiagClient<T> {
+    pub fn new(io: T) -> Self {
+        Self {
+            io,
+            decoder: FrameDecoder::new(),
+            timeout: Duration::from_secs(3),
+        }
+    }
+
+    /// Send one framed request and return the payload of its response.
+    /// Unsolicited log/event packets received in between are skipped.
+    pub fn request(&mut self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let cmd = *payload
+            .first()
+            .ok_or_else(|| "Empty DIAG request".to_string())?;
+        self.io
+            .write_all(&encode_frame(payload))
+            .and_then(|_| self.io.flush())
+            .map_err(|e| format!("Diag write failed: {}", e))?;
+
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = [0u8; 512];
+        let mut last_error = None;
+        while Instant::now() < deadline {
+            let n = match self.io.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("Diag read failed: {}", e)),
+            };
+            for frame in self.decoder.push(&buf[..n]) {
+                match frame {
+                    Ok(resp) if resp.first() == Some(&CMD_LOG) => {}
+                    Ok(resp) if resp.first() == Some(&cmd) => return Ok(resp),
+                    Ok(resp) => {
+                        if let Some(err) = describe_error_response(&resp) {
+                            return Err(err);
+                        }
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            "No DIAG response. Port may not be a Qualcomm diagnostic port.".to_string()
+        }))
+    }
+
+    pub fn version_info(&mut self) -> Result<VersionInfo, String> {
+        parse_version_info(&self.request(&[CMD_VERSION_INFO])?)
+    }
+
+    pub fn ext_build_id(&mut self) -> Result<ExtBuildId, String> {
+        parse_ext_build_id(&self.request(&[CMD_EXT_BUILD_ID])?)
+    }
+
+    pub fn status(&mut self) -> Result<Status, String> {
+        parse_status(&self.request(&[CMD_STATUS])?)
+    }
+
+    /// Last log item ID for each of the 16 equipment IDs (0 = unsupported).
+    pub fn log_id_ranges(&mut self) -> Result<Vec<u32>, String> {
+        let mut req = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        req.extend_from_slice(&LOG_CONFIG_RETRIEVE_ID_RANGES.to_le_bytes());
+        parse_log_id_ranges(&self.request(&req)?)
+    }
+
+    pub fn log_mask(&mut self, equipment_id: u32) -> Result<LogMask, String> {
+        let mut req = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        req.extend_from_slice(&LOG_CONFIG_GET_MASK.to_le_bytes());
+        req.extend_from_slice(&equipment_id.to_le_bytes());
+        parse_log_mask(&self.request(&req)?)
+    }
+
+    /// Enable the first `num_items` log codes of an equipment ID (all of them
+    /// when `num_items` is the last item ID from `log_id_ranges`).
+    pub fn enable_log_items(&mut self, equipment_id: u32, num_items: u32) -> Result<(), String> {
+        let mut req = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        req.extend_from_slice(&LOG_CONFIG_SET_MASK.to_le_bytes());
+        req.extend_from_slice(&equipment_id.to_le_bytes());
+        req.extend_from_slice(&num_items.to_le_bytes());
+        let mut mask = vec![0xFFu8; num_items.div_ceil(8) as usize];
+        if !num_items.is_multiple_of(8) {
+            if let Some(last) = mask.last_mut() {
+                *last = (1u8 << (num_items % 8)) - 1;
+            }
+        }
+        req.extend_from_slice(&mask);
+        log_config_status(&self.request(&req)?)
+    }
+
+    /// Clear every log mask so the modem stops streaming log packets.
+    pub fn disable_logging(&mut self) -> Result<(), String> {
+        let mut req = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        req.extend_from_slice(&LOG_CONFIG_DISABLE.to_le_bytes());
+        log_config_status(&self.request(&req)?)
+    }
+
+    pub fn set_event_reporting(&mut self, enabled: bool) -> Result<(), String> {
+        self.request(&[CMD_EVENT_REPORT, enabled as u8]).map(|_| ())
+    }
+
+    /// Read whatever the port has buffered, hand the raw bytes to `raw`
+    /// (e.g. a QMDL file) and return the complete, CRC-valid frames.
+    pub fn poll(
+        &mut self,
+        raw: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+    ) -> Result<Vec<Vec<u8>>, String> {
+        let mut buf = [0u8; 4096];
+        let n = match self.io.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => 0,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(format!("Diag read failed: {}", e)),
+        };
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        raw(&buf[..n]).map_err(|e| format!("Capture write failed: {}", e))?;
+        Ok(self
+            .decoder
+            .push(&buf[..n])
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect())
+    }
+
+    pub fn event_mask(&mut self) -> Result<EventMask, String> {
+        parse_event_mask(&self.request(&[CMD_EVENT_MASK_GET, 0, 0, 0])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Loopback stream: records writes and serves canned reads.
+    struct FakePort {
+        written: Vec<u8>,
+        replies: VecDeque<Vec<u8>>,
+    }
+
+    impl Read for FakePort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.replies.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl Write for FakePort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client(replies: Vec<Vec<u8>>) -> DiagClient<FakePort> {
+        DiagClient::new(FakePort {
+            written: Vec::new(),
+            replies: replies.into(),
+        })
+    }
+
+    #[test]
+    fn test_crc16_check_value() {
+        // CRC-16/X-25 catalogue check value.
+        assert_eq!(crc16(b"123456789"), 0x906E);
+    }
+
+    #[test]
+    fn test_encode_version_request() {
+        assert_eq!(encode_frame(&[0x00]), vec![0x00, 0x78, 0xF0, 0x7E]);
+        assert_eq!(encode_frame(&[0x0C]), vec![0x0C, 0x14, 0x3A, 0x7E]);
+    }
+
+    #[test]
+    fn test_escaping_roundtrip() {
+        let payload = vec![0x7E, 0x7D, 0x01, 0x5E, 0x7E];
+        let frame = encode_frame(&payload);
+        assert!(!frame[..frame.len() - 1].contains(&FLAG));
+        assert_eq!(&frame[..4], &[0x7D, 0x5E, 0x7D, 0x5D]);
+        assert_eq!(FrameDecoder::new().push(&frame), vec![Ok(payload)]);
+    }
+
+    #[test]
+    fn test_decoder_split_frames_and_bad_crc() {
+        let mut decoder = FrameDecoder::new();
+        let first = encode_frame(&[0x7C, 0x01]);
+        let (a, b) = first.split_at(2);
+        assert!(decoder.push(&[FLAG]).is_empty());
+        assert!(decoder.push(a).is_empty());
+        let mut second = encode_frame(&[0x0C]);
+        second[0] ^= 0xFF;
+        let mut tail = b.to_vec();
+        tail.extend_from_slice(&second);
+        let frames = decoder.push(&tail);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], Ok(vec![0x7C, 0x01]));
+        assert!(frames[1].as_ref().unwrap_err().contains("CRC mismatch"));
+    }
+
+    #[test]
+    fn test_version_info_skips_unsolicited_frames() -> Result<(), Box<dyn std::error::Error>> {
+        let mut resp = vec![CMD_VERSION_INFO];
+        resp.extend_from_slice(b"Jan 01 2024");
+        resp.extend_from_slice(b"12:00:00");
+        resp.extend_from_slice(b"Feb 02 2024");
+        resp.extend_from_slice(b"13:30:00");
+        resp.extend_from_slice(b"MPSS.HI\0");
+        resp.extend_from_slice(&[1, 6, 0x7E, 0x34, 0x12, 2, 3, 0]);
+        let mut wire = encode_frame(&[CMD_LOG, 0x00, 0x01]);
+        wire.extend(encode_frame(&resp));
+        let (a, b) = wire.split_at(10);
+
+        let mut c = client(vec![a.to_vec(), b.to_vec()]);
+        let info = c.version_info()?;
+        assert_eq!(c.io.written, encode_frame(&[CMD_VERSION_INFO]));
+        assert_eq!(info.compile_date, "Jan 01 2024");
+        assert_eq!(info.release_time, "13:30:00");
+        assert_eq!(info.version_dir, "MPSS.HI");
+        assert_eq!(info.mobile_model, 0x7E);
+        assert_eq!(info.firmware_revision, 0x1234);
+        assert_eq!(info.hw_version, (3, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ext_build_id() -> Result<(), Box<dyn std::error::Error>> {
+        let mut resp = vec![CMD_EXT_BUILD_ID, 2, 0, 0];
+        resp.extend_from_slice(&0x0009_10E1u32.to_le_bytes());
+        resp.extend_from_slice(&0x0000_0FFFu32.to_le_bytes());
+        resp.extend_from_slice(b"MPSS.AT.4.0-00123\0SM8350\0");
+        let mut c = client(vec![encode_frame(&resp)]);
+        let id = c.ext_build_id()?;
+        assert_eq!(id.msm_hw_version, 0x0009_10E1);
+        assert_eq!(id.build_id, "MPSS.AT.4.0-00123");
+        assert_eq!(id.model_string, "SM8350");
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_and_error_response() -> Result<(), Box<dyn std::error::Error>> {
+        let mut resp = vec![CMD_STATUS, 1, 2, 3];
+        resp.extend_from_slice(&0xDEAD_BEEFu32.to_le_bytes());
+        resp.extend_from_slice(&5u16.to_le_bytes());
+        let mut c = client(vec![encode_frame(&resp)]);
+        let status = c.status()?;
+        assert_eq!(status.esn, 0xDEAD_BEEF);
+        assert_eq!(status.rf_mode, 5);
+
+        let mut c = client(vec![encode_frame(&[CMD_BAD_CMD, CMD_STATUS])]);
+        assert_eq!(
+            c.status(),
+            Err("DIAG rejected the request: command not recognized".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_and_event_masks() -> Result<(), Box<dyn std::error::Error>> {
+        let mut ranges = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        ranges.extend_from_slice(&1u32.to_le_bytes());
+        ranges.extend_from_slice(&0u32.to_le_bytes());
+        for i in 0..16u32 {
+            ranges.extend_from_slice(&(if i == 1 { 0x0FFFu32 } else { 0 }).to_le_bytes());
+        }
+        let mut mask = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        mask.extend_from_slice(&4u32.to_le_bytes());
+        mask.extend_from_slice(&0u32.to_le_bytes());
+        mask.extend_from_slice(&1u32.to_le_bytes());
+        mask.extend_from_slice(&10u32.to_le_bytes());
+        mask.extend_from_slice(&[0b0000_0101, 0b10]);
+        let mut events = vec![CMD_EVENT_MASK_GET, 0, 0, 0];
+        events.extend_from_slice(&12u16.to_le_bytes());
+        events.extend_from_slice(&[0xFF, 0x01]);
+
+        let mut c = client(vec![
+            encode_frame(&ranges),
+            encode_frame(&mask),
+            encode_frame(&events),
+        ]);
+        let ids = c.log_id_ranges()?;
+        assert_eq!(ids[1], 0x0FFF);
+        let log = c.log_mask(1)?;
+        assert_eq!(log.equipment_id, 1);
+        assert_eq!(log.enabled_count(), 3);
+        let ev = c.event_mask()?;
+        assert_eq!(ev.num_bits, 12);
+        assert_eq!(ev.enabled_count(), 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_enable_log_items_sets_partial_mask() -> Result<(), Box<dyn std::error::Error>> {
+        let mut resp = vec![CMD_LOG_CONFIG, 0, 0, 0];
+        resp.extend_from_slice(&3u32.to_le_bytes());
+        resp.extend_from_slice(&0u32.to_le_bytes());
+        let mut c = client(vec![encode_frame(&resp)]);
+        c.enable_log_items(0x0B, 10)?;
+
+        let mut expected = vec![
+            CMD_LOG_CONFIG,
+            0,
+            0,
+            0,
+            3,
+            0,
+            0,
+            0,
+            0x0B,
+            0,
+            0,
+            0,
+            10,
+            0,
+            0,
+            0,
+        ];
+        expected.extend_from_slice(&[0xFF, 0x03]);
+        assert_eq!(c.io.written, encode_frame(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_response() {
+        let mut c = client(Vec::new());
+        assert!(c.version_info().unwrap_err().contains("No DIAG response"));
+    }
+}
@@ -0,0 +1,366 @@
+/// Vault snapshots of EDL storage taken before Firehose writes.
+///
+/// Writes, erases and GPT patches in EDL bypass Android entirely, so the
+/// sectors they change and both copies of the partition table are read back
+/// into the vault first; a bad write can then be undone by writing the
+/// snapshot back (`flash::EdlWrite::Restore`) in another EDL session.
+use super::firehose::FirehoseClient;
+use super::sahara::DeviceInfo;
+use super::Transport;
+use crate::features::vault::{
+    self, BackupKind, Compression, DeviceIdentity, VaultFile, VaultManifest,
+};
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes of one GPT partition entry array (128 entries of 128 bytes).
+const GPT_ENTRY_BYTES: u64 = 128 * 128;
+
+/// Sectors of the primary GPT: protective MBR, header and entry array.
+/// The backup GPT at the end of the disk is one sector shorter (no MBR).
+pub fn gpt_sectors(sector_size: u64) -> u64 {
+    2 + GPT_ENTRY_BYTES.div_ceil(sector_size.max(1))
+}
+
+/// A named run of sectors on one LUN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorRange {
+    pub name: String,
+    pub lun: u32,
+    pub start: u64,
+    pub count: u64,
+}
+
+impl SectorRange {
+    /// Whether the range overlaps the primary GPT or, when the disk size is
+    /// known, the backup GPT at its end.
+    pub fn touches_gpt(&self, sector_size: u64, disk_sectors: Option<u64>) -> bool {
+        let gpt = gpt_sectors(sector_size);
+        let end = self.start.saturating_add(self.count.max(1));
+        self.start < gpt || disk_sectors.is_some_and(|total| end > total.saturating_sub(gpt - 1))
+    }
+
+    /// `lun0:modemst1:40+8`, stored as the vault file's partition so the
+    /// range can be written back where it was read.
+    fn vault_partition(&self) -> String {
+        format!(
+            "lun{}:{}:{}+{}",
+            self.lun, self.name, self.start, self.count
+        )
+    }
+
+    pub fn from_vault_partition(text: &str) -> Option<Self> {
+        let (lun, rest) = text.strip_prefix("lun")?.split_once(':')?;
+        let (name, span) = rest.rsplit_once(':')?;
+        let (start, count) = span.split_once('+')?;
+        Some(Self {
+            name: name.to_string(),
+            lun: lun.parse().ok()?,
+            start: start.parse().ok()?,
+            count: count.parse().ok()?,
+        })
+    }
+}
+
+/// Vault identity of a device in EDL from what Sahara reported. The chip
+/// serial keys the entry; the HW ID and OEM PK hash take the places of the
+/// fingerprint and build fingerprint, which EDL cannot read.
+pub fn device_identity(info: &DeviceInfo) -> Result<DeviceIdentity, String> {
+    let serial = info
+        .serial
+        .ok_or("Sahara did not report a chip serial, so a backup cannot be tied to this device")?;
+    Ok(DeviceIdentity {
+        serial: format!("edl-{:08x}", serial),
+        model: info.hw_id.map_or_else(String::new, |hw| {
+            format!(
+                "MSM ID 0x{:06X}, OEM ID 0x{:04X}, model 0x{:04X}",
+                hw.msm_id, hw.oem_id, hw.model_id
+            )
+        }),
+        fingerprint: info
+            .hw_id
+            .map_or_else(String::new, |hw| format!("{:016x}", hw.raw)),
+        build_fingerprint: info.pk_hash.clone().unwrap_or_default(),
+    })
+}
+
+/// Size of LUN `lun` in sectors, if the programmer reports it.
+pub fn disk_sectors<T: Transport>(client: &mut FirehoseClient<T>, lun: u32) -> Option<u64> {
+    client
+        .storage_info(lun)
+        .ok()
+        .and_then(|info| info.total_blocks)
+}
+
+/// Read one range into `dir/lun<N>_<name>.bin` and describe it for the manifest.
+fn read_to_vault<T: Transport>(
+    client: &mut FirehoseClient<T>,
+    dir: &Path,
+    range: &SectorRange,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> Result<VaultFile, String> {
+    let file_name = format!("lun{}_{}.bin", range.lun, range.name);
+    let path = dir.join(&file_name);
+    let file =
+        fs::File::create(&path).map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    let stage = format!("Backup LUN {} {}", range.lun, range.name);
+    client
+        .read(
+            range.lun,
+            range.start,
+            range.count,
+            &mut out,
+            &mut |done, total| progress(&stage, done, total),
+        )
+        .map_err(|e| format!("{}: {}", stage, e))?;
+    out.flush()
+        .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    let size = fs::metadata(&path)
+        .map_err(|e| format!("Cannot stat {}: {}", path.display(), e))?
+        .len();
+    Ok(VaultFile {
+        partition: range.vault_partition(),
+        file: file_name,
+        size,
+        sha256: vault::sha256_file(&path)?,
+        compression: Compression::None,
+        raw_sha256: None,
+    })
+}
+
+/// Copy `ranges` and both GPTs of every LUN in `luns` or `ranges` into a new
+/// vault entry for `device`, returning its directory. `note` records why it
+/// was taken.
+pub fn snapshot<T: Transport>(
+    client: &mut FirehoseClient<T>,
+    device: &DeviceIdentity,
+    luns: &[u32],
+    ranges: &[SectorRange],
+    note: &str,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> Result<PathBuf, String> {
+    let mut all_luns: Vec<u32> = luns
+        .iter()
+        .copied()
+        .chain(ranges.iter().map(|r| r.lun))
+        .collect();
+    all_luns.sort_unstable();
+    all_luns.dedup();
+
+    let mut plan = Vec::new();
+    for lun in all_luns {
+        let gpt = gpt_sectors(client.sector_size() as u64);
+        plan.push(SectorRange {
+            name: "gpt_primary".into(),
+            lun,
+            start: 0,
+            count: gpt,
+        });
+        if let Some(total) = disk_sectors(client, lun).filter(|&t| t > 2 * gpt) {
+            plan.push(SectorRange {
+                name: "gpt_backup".into(),
+                lun,
+                start: total - (gpt - 1),
+                count: gpt - 1,
+            });
+        }
+    }
+    for range in ranges.iter().filter(|r| r.count > 0) {
+        if !plan
+            .iter()
+            .any(|p| p.lun == range.lun && p.name == range.name)
+        {
+            plan.push(range.clone());
+        }
+    }
+
+    let dir = vault::new_entry_dir(&device.serial)?;
+    let mut files = Vec::new();
+    for range in &plan {
+        files.push(read_to_vault(client, &dir, range, progress)?);
+    }
+    let manifest = VaultManifest {
+        kind: BackupKind::SectorSnapshot,
+        created_at: vault::now_secs(),
+        foem_version: crate::VERSION.to_string(),
+        device: device.clone(),
+        files,
+        note: Some(note.to_string()),
+    };
+    vault::write_manifest(&dir, &manifest)?;
+    Ok(dir)
+}
+
+/// The ranges of the snapshot in `dir` and the files holding them, at
+/// `sector_size`-byte sectors. With `device`, the snapshot must belong to it
+/// and every file must pass its SHA-256 check, as before writing it back.
+pub fn restore_files(
+    dir: &Path,
+    device: Option<&DeviceIdentity>,
+    sector_size: u64,
+) -> Result<Vec<(SectorRange, PathBuf)>, String> {
+    let entry = vault::load_entry(dir)?;
+    if entry.manifest.kind != BackupKind::SectorSnapshot {
+        return Err(format!(
+            "{} holds a {} backup, not an EDL sector snapshot",
+            dir.display(),
+            entry.manifest.kind.label()
+        ));
+    }
+    if let Some(device) = device {
+        if entry.manifest.device.serial != device.serial {
+            return Err(format!(
+                "The snapshot was taken from {}, but the device in EDL is {}",
+                entry.manifest.device.serial, device.serial
+            ));
+        }
+    }
+    entry
+        .manifest
+        .files
+        .iter()
+        .map(|file| {
+            let range = SectorRange::from_vault_partition(&file.partition).ok_or_else(|| {
+                format!("{}: '{}' is not a sector range", file.file, file.partition)
+            })?;
+            if file.size != range.count * sector_size {
+                return Err(format!(
+                    "{} is {} bytes, not {} sectors of {} bytes; pick the storage type the snapshot was taken with",
+                    file.file, file.size, range.count, sector_size
+                ));
+            }
+            if device.is_some() {
+                vault::verify_file(&entry.dir, file)?;
+            }
+            Ok((range, entry.dir.join(&file.file)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::firehose::sim::SimProgrammer;
+    use super::super::firehose::Memory;
+    use super::super::sahara::HwId;
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_gpt_sectors() {
+        assert_eq!(gpt_sectors(512), 34);
+        assert_eq!(gpt_sectors(4096), 6);
+    }
+
+    #[test]
+    fn test_touches_gpt() {
+        let range = |start, count| SectorRange {
+            name: "x".into(),
+            lun: 0,
+            start,
+            count,
+        };
+        assert!(range(0, 1).touches_gpt(512, None));
+        assert!(range(33, 10).touches_gpt(512, None));
+        assert!(!range(34, 10).touches_gpt(512, None));
+        assert!(!range(100, 10).touches_gpt(512, Some(200)));
+        assert!(range(160, 10).touches_gpt(512, Some(200)));
+        assert!(!range(6, 4).touches_gpt(4096, Some(100)));
+    }
+
+    #[test]
+    fn test_vault_partition_roundtrip() {
+        let range = SectorRange {
+            name: "sectors_40-42".into(),
+            lun: 4,
+            start: 40,
+            count: 2,
+        };
+        assert_eq!(range.vault_partition(), "lun4:sectors_40-42:40+2");
+        assert_eq!(
+            SectorRange::from_vault_partition(&range.vault_partition()),
+            Some(range)
+        );
+        assert_eq!(SectorRange::from_vault_partition("lun0:gpt_primary"), None);
+        assert_eq!(SectorRange::from_vault_partition("modemst1"), None);
+    }
+
+    #[test]
+    fn test_device_identity_needs_a_serial() {
+        let result = device_identity(&DeviceInfo::default());
+        assert!(result.is_err_and(|e| e.contains("did not report a chip serial")));
+    }
+
+    #[test]
+    fn test_snapshot_stores_ranges_and_both_gpts() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join("foem_edl_snapshot_test");
+        let _ = fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+
+        let mut client = FirehoseClient::new(SimProgrammer::new(100), Memory::Emmc);
+        client.configure()?;
+        let range = SectorRange {
+            name: "sectors_40-42".into(),
+            lun: 0,
+            start: 40,
+            count: 2,
+        };
+        let device = device_identity(&DeviceInfo {
+            serial: Some(0x1234_abcd),
+            hw_id: Some(HwId::from_raw(0x0004_60E1_0000_0000)),
+            pk_hash: Some("ab".repeat(32)),
+            ..Default::default()
+        })?;
+        let result = snapshot(
+            &mut client,
+            &device,
+            &[],
+            &[range],
+            "test",
+            &mut |_, _, _| {},
+        );
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+
+        let dir = result?;
+        assert!(
+            dir.starts_with(root.join("edl-1234abcd")),
+            "{}",
+            dir.display()
+        );
+        let entry = vault::load_entry(&dir)?;
+        assert_eq!(entry.manifest.kind, BackupKind::SectorSnapshot);
+        assert_eq!(entry.manifest.device, device);
+        assert_eq!(
+            entry.manifest.device.model,
+            "MSM ID 0x0460E1, OEM ID 0x0000, model 0x0000"
+        );
+        let names: Vec<&str> = entry
+            .manifest
+            .files
+            .iter()
+            .map(|f| f.file.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "lun0_gpt_primary.bin",
+                "lun0_gpt_backup.bin",
+                "lun0_sectors_40-42.bin"
+            ]
+        );
+        for file in &entry.manifest.files {
+            vault::verify_file(&entry.dir, file)?;
+        }
+        let backup = fs::read(entry.dir.join("lun0_gpt_backup.bin"))?;
+        assert_eq!(backup.len(), 33 * 512);
+        assert_eq!(backup[0], 67);
+        let sectors = fs::read(entry.dir.join("lun0_sectors_40-42.bin"))?;
+        assert_eq!(sectors[..512], [40; 512]);
+        assert_eq!(sectors[512..], [41; 512]);
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+}
@@ -0,0 +1,886 @@
+/// Firehose, the XML protocol spoken by a Qualcomm programmer once Sahara
+/// has started it.
+///
+/// The host sends one `<data>` document per command. The device answers
+/// with documents holding `<log value=".."/>` lines and, finally, a
+/// `<response value="ACK|NAK"/>`. Commands that move sector data (`read`,
+/// `program`) switch the pipe to raw mode after their ACK: the data follows
+/// unframed and a second response closes the transfer.
+use super::{parse_xml_elements, Transport, XmlElement};
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+const DOC_END: &[u8] = b"</data>";
+const LOG_HISTORY: usize = 64;
+const DEFAULT_MAX_PAYLOAD: usize = 1024 * 1024;
+
+/// Storage type the programmer should drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Memory {
+    Ufs,
+    Emmc,
+    Nand,
+}
+
+impl Memory {
+    pub const ALL: [Memory; 3] = [Memory::Ufs, Memory::Emmc, Memory::Nand];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Memory::Ufs => "ufs",
+            Memory::Emmc => "emmc",
+            Memory::Nand => "nand",
+        }
+    }
+
+    pub fn sector_size(self) -> usize {
+        match self {
+            Memory::Emmc => 512,
+            Memory::Ufs | Memory::Nand => 4096,
+        }
+    }
+}
+
+/// Targets of the `power` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Power {
+    Reset,
+    Edl,
+}
+
+impl Power {
+    fn value(self) -> &'static str {
+        match self {
+            Power::Reset => "reset",
+            Power::Edl => "reset_to_edl",
+        }
+    }
+}
+
+/// One `<patch>` instruction, as found in Qualcomm patch XML files.
+/// Sector and value fields are kept as strings because they may be
+/// expressions such as `NUM_DISKSECTORS-5.` or `CRC32(2,92)`, which the
+/// programmer evaluates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub lun: u32,
+    pub start_sector: String,
+    pub byte_offset: u64,
+    pub size_in_bytes: u32,
+    pub value: String,
+    pub what: String,
+}
+
+impl Patch {
+    /// Read a `<patch>` element of a patch XML file.
+    pub fn from_element(element: &XmlElement) -> Result<Self, String> {
+        let text = |key: &str| {
+            element
+                .attr(key)
+                .map(str::to_string)
+                .ok_or_else(|| format!("patch is missing {}", key))
+        };
+        let number = |key: &str| {
+            let value = text(key)?;
+            parse_number(&value).ok_or_else(|| format!("patch {} '{}' is not a number", key, value))
+        };
+        Ok(Self {
+            lun: number("physical_partition_number")? as u32,
+            start_sector: text("start_sector")?,
+            byte_offset: number("byte_offset")?,
+            size_in_bytes: number("size_in_bytes")? as u32,
+            value: text("value")?,
+            what: element.attr("what").unwrap_or_default().to_string(),
+        })
+    }
+
+    /// Patches in `filename="DISK"` entries are applied on the device;
+    /// others target image files on the host and do not apply here.
+    pub fn parse_file(text: &str) -> Result<Vec<Self>, String> {
+        parse_xml_elements(text)?
+            .iter()
+            .filter(|e| e.name == "patch" && e.attr("filename") == Some("DISK"))
+            .map(Self::from_element)
+            .collect()
+    }
+}
+
+/// What `getstorageinfo` reported. Programmers differ in what they log, so
+/// every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageInfo {
+    pub mem_type: Option<String>,
+    pub prod_name: Option<String>,
+    pub total_blocks: Option<u64>,
+    pub block_size: Option<u64>,
+    pub num_physical: Option<u64>,
+    pub fw_version: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl StorageInfo {
+    /// Collect the fields from the log lines of a `getstorageinfo` reply:
+    /// newer programmers log a JSON object, older ones plain text.
+    pub fn from_logs(logs: &[String]) -> Self {
+        let mut info = Self::default();
+        for line in logs {
+            let text = line.strip_prefix("INFO:").unwrap_or(line).trim();
+            if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
+                if let Some(si) = json.get("storage_info") {
+                    info.apply_json(si);
+                }
+                continue;
+            }
+            let Some((key, value)) = text.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            let key = key.trim().to_ascii_lowercase();
+            if key.contains("total logical blocks") {
+                info.total_blocks = info.total_blocks.or(parse_number(value));
+            } else if key.contains("block size") {
+                info.block_size = info.block_size.or(parse_number(value));
+            } else if key.contains("physical partitions") {
+                info.num_physical = info.num_physical.or(parse_number(value));
+            }
+        }
+        info
+    }
+
+    fn apply_json(&mut self, si: &serde_json::Value) {
+        let text = |key: &str| {
+            si.get(key).and_then(|v| match v {
+                serde_json::Value::String(s) => Some(s.trim().to_string()),
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+        };
+        let number = |key: &str| text(key).and_then(|v| parse_number(&v));
+        self.mem_type = text("mem_type");
+        self.prod_name = text("prod_name");
+        self.total_blocks = number("total_blocks");
+        self.block_size = number("block_size");
+        self.num_physical = number("num_physical");
+        self.fw_version = text("fw_version");
+        self.serial = text("serial_num");
+    }
+
+    pub fn capacity(&self) -> Option<u64> {
+        self.total_blocks?.checked_mul(self.block_size?)
+    }
+}
+
+impl fmt::Display for StorageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("Type", self.mem_type.clone()),
+            ("Product", self.prod_name.clone()),
+            ("Blocks", self.total_blocks.map(|v| v.to_string())),
+            (
+                "Block size",
+                self.block_size.map(|v| format!("{} bytes", v)),
+            ),
+            (
+                "Capacity",
+                self.capacity()
+                    .map(|v| format!("{:.1} GiB", v as f64 / (1u64 << 30) as f64)),
+            ),
+            ("LUNs", self.num_physical.map(|v| v.to_string())),
+            ("Firmware", self.fw_version.clone()),
+            ("Serial", self.serial.clone()),
+        ];
+        let mut any = false;
+        for (label, value) in fields {
+            if let Some(value) = value {
+                writeln!(f, "  {}: {}", label, value)?;
+                any = true;
+            }
+        }
+        if !any {
+            writeln!(f, "  The programmer reported no storage details.")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decimal or `0x` hexadecimal.
+fn parse_number(text: &str) -> Option<u64> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn command_doc(name: &str, attrs: &[(&str, String)]) -> Vec<u8> {
+    let mut doc = format!("<?xml version=\"1.0\" ?><data><{}", name);
+    for (key, value) in attrs {
+        doc.push_str(&format!(
+            " {}=\"{}\"",
+            key,
+            quick_xml::escape::escape(value.as_str())
+        ));
+    }
+    doc.push_str(" /></data>");
+    doc.into_bytes()
+}
+
+/// The device's answer to one command.
+#[derive(Debug)]
+struct Response {
+    ack: bool,
+    element: XmlElement,
+    logs: Vec<String>,
+}
+
+impl Response {
+    fn rawmode(&self) -> bool {
+        self.element
+            .attr("rawmode")
+            .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+    }
+}
+
+pub struct FirehoseClient<T: Transport> {
+    transport: T,
+    timeout: Duration,
+    /// Bytes received but not yet consumed; raw data can arrive in the
+    /// same read as the response that announced it.
+    pending: Vec<u8>,
+    /// Most recent log lines, for error messages.
+    log: Vec<String>,
+    memory: Memory,
+    sector_size: usize,
+    max_payload: usize,
+}
+
+impl<T: Transport> FirehoseClient<T> {
+    pub fn new(transport: T, memory: Memory) -> Self {
+        Self {
+            transport,
+            timeout: Duration::from_secs(10),
+            pending: Vec::new(),
+            log: Vec::new(),
+            memory,
+            sector_size: memory.sector_size(),
+            max_payload: DEFAULT_MAX_PAYLOAD,
+        }
+    }
+
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    fn fill(&mut self) -> Result<(), String> {
+        let mut buf = vec![0u8; 64 * 1024];
+        let n = self.transport.recv(&mut buf, self.timeout)?;
+        if n == 0 {
+            return Err(format!(
+                "Firehose programmer did not respond within {}s{}",
+                self.timeout.as_secs_f32(),
+                self.last_log_suffix()
+            ));
+        }
+        self.pending.extend_from_slice(&buf[..n]);
+        Ok(())
+    }
+
+    fn next_document(&mut self) -> Result<String, String> {
+        loop {
+            if let Some(pos) = self
+                .pending
+                .windows(DOC_END.len())
+                .position(|w| w == DOC_END)
+            {
+                let doc: Vec<u8> = self.pending.drain(..pos + DOC_END.len()).collect();
+                return Ok(String::from_utf8_lossy(&doc).into_owned());
+            }
+            self.fill()?;
+        }
+    }
+
+    fn push_log(&mut self, line: &str) {
+        if self.log.len() == LOG_HISTORY {
+            self.log.remove(0);
+        }
+        self.log.push(line.to_string());
+    }
+
+    fn last_log_suffix(&self) -> String {
+        match self.log.last() {
+            Some(line) => format!(" (device log: {})", line),
+            None => String::new(),
+        }
+    }
+
+    /// Read documents until a response arrives, collecting the log lines
+    /// sent along the way.
+    fn response(&mut self) -> Result<Response, String> {
+        let mut logs = Vec::new();
+        loop {
+            let doc = self.next_document()?;
+            for element in parse_xml_elements(&doc)? {
+                match element.name.as_str() {
+                    "log" => {
+                        let line = element.attr("value").unwrap_or_default().to_string();
+                        self.push_log(&line);
+                        logs.push(line);
+                    }
+                    "response" => {
+                        let ack = element.attr("value") == Some("ACK");
+                        return Ok(Response { ack, element, logs });
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Send a command and require an ACK.
+    fn command(&mut self, name: &str, attrs: &[(&str, String)]) -> Result<Response, String> {
+        self.transport.send(&command_doc(name, attrs))?;
+        let response = self.response()?;
+        if !response.ack {
+            return Err(self.nak(name));
+        }
+        Ok(response)
+    }
+
+    fn nak(&self, name: &str) -> String {
+        format!(
+            "Firehose {} was rejected (NAK){}",
+            name,
+            self.last_log_suffix()
+        )
+    }
+
+    fn sector_attrs(
+        &self,
+        lun: u32,
+        start_sector: u64,
+        sectors: u64,
+    ) -> Vec<(&'static str, String)> {
+        vec![
+            ("SECTOR_SIZE_IN_BYTES", self.sector_size.to_string()),
+            ("num_partition_sectors", sectors.to_string()),
+            ("physical_partition_number", lun.to_string()),
+            ("start_sector", start_sector.to_string()),
+        ]
+    }
+
+    /// Negotiate the storage type and payload size. A programmer that
+    /// rejects the requested payload size states the size it supports;
+    /// the request is repeated once with that size.
+    pub fn configure(&mut self) -> Result<(), String> {
+        let mut requested = self.max_payload;
+        for _ in 0..2 {
+            let attrs = [
+                ("MemoryName", self.memory.name().to_string()),
+                ("Verbose", "0".to_string()),
+                ("AlwaysValidate", "0".to_string()),
+                ("MaxDigestTableSizeInBytes", "8192".to_string()),
+                ("MaxPayloadSizeToTargetInBytes", requested.to_string()),
+                ("ZLPAwareHost", "1".to_string()),
+                ("SkipStorageInit", "0".to_string()),
+                ("SkipWrite", "0".to_string()),
+            ];
+            self.transport.send(&command_doc("configure", &attrs))?;
+            let response = self.response()?;
+            let supported = response
+                .element
+                .attr("MaxPayloadSizeToTargetInBytes")
+                .and_then(parse_number)
+                .map(|v| v as usize);
+            if response.ack {
+                self.max_payload = supported.unwrap_or(requested).min(requested).max(1);
+                return Ok(());
+            }
+            match supported {
+                Some(size) if size > 0 && size < requested => requested = size,
+                _ => break,
+            }
+        }
+        Err(self.nak("configure"))
+    }
+
+    /// Ask the programmer to describe LUN `lun`. The sector size is taken
+    /// from the answer when it reports one.
+    pub fn storage_info(&mut self, lun: u32) -> Result<StorageInfo, String> {
+        let response = self.command(
+            "getstorageinfo",
+            &[("physical_partition_number", lun.to_string())],
+        )?;
+        let info = StorageInfo::from_logs(&response.logs);
+        if let Some(size) = info.block_size.filter(|s| s.is_power_of_two() && *s >= 512) {
+            self.sector_size = size as usize;
+        }
+        Ok(info)
+    }
+
+    /// Read `sectors` sectors starting at `start_sector` into `out`.
+    /// `progress` receives (bytes done, bytes total).
+    pub fn read(
+        &mut self,
+        lun: u32,
+        start_sector: u64,
+        sectors: u64,
+        out: &mut dyn Write,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<(), String> {
+        let attrs = self.sector_attrs(lun, start_sector, sectors);
+        let response = self.command("read", &attrs)?;
+        if !response.rawmode() {
+            return Err("Firehose read was acknowledged without raw mode".into());
+        }
+        let total = sectors * self.sector_size as u64;
+        let mut done = 0u64;
+        while done < total {
+            if self.pending.is_empty() {
+                self.fill()?;
+            }
+            let take = self.pending.len().min((total - done) as usize);
+            out.write_all(&self.pending[..take])
+                .map_err(|e| format!("Cannot write dump: {}", e))?;
+            self.pending.drain(..take);
+            done += take as u64;
+            progress(done, total);
+        }
+        if !self.response()?.ack {
+            return Err(self.nak("read"));
+        }
+        Ok(())
+    }
+
+    /// Write `len` bytes from `data` starting at `start_sector`, padding the
+    /// last sector with zeros. `label` is reported to the programmer as the
+    /// file name. `progress` receives (bytes done, bytes total).
+    pub fn program(
+        &mut self,
+        lun: u32,
+        start_sector: u64,
+        data: &mut dyn Read,
+        len: u64,
+        label: &str,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<(), String> {
+        let sector = self.sector_size as u64;
+        let sectors = len.div_ceil(sector);
+        let mut attrs = self.sector_attrs(lun, start_sector, sectors);
+        attrs.push(("filename", label.to_string()));
+        let response = self.command("program", &attrs)?;
+        if !response.rawmode() {
+            return Err("Firehose program was acknowledged without raw mode".into());
+        }
+        let total = sectors * sector;
+        let chunk_len = (self.max_payload as u64 / sector).max(1) * sector;
+        let mut chunk = vec![0u8; chunk_len as usize];
+        let mut done = 0u64;
+        while done < total {
+            let size = chunk_len.min(total - done) as usize;
+            let from_source = (len.saturating_sub(done) as usize).min(size);
+            data.read_exact(&mut chunk[..from_source])
+                .map_err(|e| format!("Cannot read {}: {}", label, e))?;
+            chunk[from_source..size].fill(0);
+            self.transport.send(&chunk[..size])?;
+            done += size as u64;
+            progress(done, total);
+        }
+        if !self.response()?.ack {
+            return Err(self.nak("program"));
+        }
+        Ok(())
+    }
+
+    pub fn erase(&mut self, lun: u32, start_sector: u64, sectors: u64) -> Result<(), String> {
+        let attrs = self.sector_attrs(lun, start_sector, sectors);
+        self.command("erase", &attrs).map(|_| ())
+    }
+
+    pub fn patch(&mut self, patch: &Patch) -> Result<(), String> {
+        let attrs = [
+            ("SECTOR_SIZE_IN_BYTES", self.sector_size.to_string()),
+            ("byte_offset", patch.byte_offset.to_string()),
+            ("filename", "DISK".to_string()),
+            ("physical_partition_number", patch.lun.to_string()),
+            ("size_in_bytes", patch.size_in_bytes.to_string()),
+            ("start_sector", patch.start_sector.clone()),
+            ("value", patch.value.clone()),
+            ("what", patch.what.clone()),
+        ];
+        self.command("patch", &attrs).map(|_| ())
+    }
+
+    /// Mark LUN `lun` as the one the device boots from.
+    pub fn set_bootable_drive(&mut self, lun: u32) -> Result<(), String> {
+        self.command("setbootablestoragedrive", &[("value", lun.to_string())])
+            .map(|_| ())
+    }
+
+    pub fn power(&mut self, power: Power) -> Result<(), String> {
+        self.command(
+            "power",
+            &[
+                ("value", power.value().to_string()),
+                ("DelayInSeconds", "1".to_string()),
+            ],
+        )
+        .map(|_| ())
+    }
+}
+
+/// Format Firehose progress as `MiB done / MiB total`.
+pub fn progress_label(done: u64, total: u64) -> String {
+    format!(
+        "{:.1} / {:.1} MiB",
+        done as f64 / (1u64 << 20) as f64,
+        total as f64 / (1u64 << 20) as f64
+    )
+}
+
+/// A simulated programmer for tests that need a device to talk to.
+#[cfg(test)]
+pub mod sim {
+    use super::*;
+
+    /// Simulated programmer with one LUN of `disk`. Commands are answered
+    /// like a device would; `program` data is written to the disk.
+    #[derive(Debug, Default)]
+    pub struct SimProgrammer {
+        pub disk: Vec<u8>,
+        pub sector: usize,
+        pub max_payload: usize,
+        pub outbox: Vec<u8>,
+        /// Raw bytes still expected for a `program` at this offset.
+        pub receiving: Option<(usize, usize)>,
+        pub commands: Vec<XmlElement>,
+        pub packets: Vec<usize>,
+        pub reject: Option<&'static str>,
+    }
+
+    fn reply(inner: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" ?><data>{}</data>",
+            inner
+        )
+    }
+
+    impl SimProgrammer {
+        pub fn new(sectors: usize) -> Self {
+            Self {
+                disk: (0..sectors * 512).map(|i| (i / 512) as u8).collect(),
+                sector: 512,
+                max_payload: 2048,
+                ..Default::default()
+            }
+        }
+
+        fn ack(&mut self, logs: &[&str], extra: &str) {
+            let mut inner: String = logs
+                .iter()
+                .map(|l| format!("<log value=\"{}\" />", l))
+                .collect();
+            inner.push_str(&format!("<response value=\"ACK\" {}/>", extra));
+            self.outbox.extend_from_slice(reply(&inner).as_bytes());
+        }
+
+        fn nak(&mut self, log: &str, extra: &str) {
+            let inner = format!(
+                "<log value=\"{}\" /><response value=\"NAK\" {}/>",
+                log, extra
+            );
+            self.outbox.extend_from_slice(reply(&inner).as_bytes());
+        }
+
+        fn num(cmd: &XmlElement, key: &str) -> Result<usize, String> {
+            cmd.attr(key)
+                .ok_or_else(|| format!("{} without {}", cmd.name, key))?
+                .parse()
+                .map_err(|e| format!("{} {}: {}", cmd.name, key, e))
+        }
+
+        fn range(&self, cmd: &XmlElement) -> Result<(usize, usize), String> {
+            Ok((
+                Self::num(cmd, "start_sector")? * self.sector,
+                Self::num(cmd, "num_partition_sectors")? * self.sector,
+            ))
+        }
+
+        fn handle(&mut self, cmd: XmlElement) -> Result<(), String> {
+            self.commands.push(cmd.clone());
+            if self.reject == Some(cmd.name.as_str()) {
+                self.nak("ERROR: Failed to run command", "");
+                return Ok(());
+            }
+            match cmd.name.as_str() {
+                "configure" => {
+                    let asked = Self::num(&cmd, "MaxPayloadSizeToTargetInBytes")?;
+                    let extra = format!("MaxPayloadSizeToTargetInBytes=\"{}\"", self.max_payload);
+                    if asked > self.max_payload {
+                        self.nak("ERROR: payload too large", &extra);
+                    } else {
+                        self.ack(&["INFO: Storage type set"], &extra);
+                    }
+                }
+                "getstorageinfo" => {
+                    let json = format!(
+                        "INFO: {{&quot;storage_info&quot;: {{&quot;total_blocks&quot;:{}, &quot;block_size&quot;:512, &quot;mem_type&quot;:&quot;eMMC&quot;, &quot;num_physical&quot;:1}}}}",
+                        self.disk.len() / 512
+                    );
+                    self.ack(&[json.as_str()], "");
+                }
+                "read" => {
+                    let (start, len) = self.range(&cmd)?;
+                    self.ack(&[], "rawmode=\"true\"");
+                    let data = self.disk[start..start + len].to_vec();
+                    self.outbox.extend_from_slice(&data);
+                    self.ack(&[], "rawmode=\"false\"");
+                }
+                "program" => {
+                    let (start, len) = self.range(&cmd)?;
+                    self.receiving = Some((start, len));
+                    self.ack(&[], "rawmode=\"true\"");
+                }
+                "erase" => {
+                    let (start, len) = self.range(&cmd)?;
+                    self.disk[start..start + len].fill(0);
+                    self.ack(&[], "");
+                }
+                _ => self.ack(&[], ""),
+            }
+            Ok(())
+        }
+    }
+
+    impl FirehoseClient<SimProgrammer> {
+        /// The simulated disk and the names of the commands received.
+        pub fn sim_state(&self) -> (&[u8], Vec<String>) {
+            let names = self
+                .transport
+                .commands
+                .iter()
+                .map(|c| c.name.clone())
+                .collect();
+            (&self.transport.disk, names)
+        }
+    }
+
+    impl Transport for SimProgrammer {
+        fn send(&mut self, data: &[u8]) -> Result<(), String> {
+            if let Some((offset, left)) = self.receiving {
+                assert!(data.len() <= self.max_payload, "payload over limit");
+                self.packets.push(data.len());
+                self.disk[offset..offset + data.len()].copy_from_slice(data);
+                if data.len() == left {
+                    self.receiving = None;
+                    self.ack(&[], "rawmode=\"false\"");
+                } else {
+                    self.receiving = Some((offset + data.len(), left - data.len()));
+                }
+                return Ok(());
+            }
+            let text = String::from_utf8(data.to_vec()).map_err(|e| e.to_string())?;
+            let cmd = parse_xml_elements(&text)?
+                .into_iter()
+                .find(|e| e.name != "data")
+                .ok_or_else(|| format!("no command in {}", text))?;
+            self.handle(cmd)
+        }
+
+        fn recv(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, String> {
+            // Hand out small pieces so framing across reads is exercised.
+            let n = self.outbox.len().min(buf.len()).min(700);
+            buf[..n].copy_from_slice(&self.outbox[..n]);
+            self.outbox.drain(..n);
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sim::SimProgrammer;
+    use super::*;
+    use std::error::Error;
+
+    fn client(device: SimProgrammer) -> Result<FirehoseClient<SimProgrammer>, String> {
+        let mut client = FirehoseClient::new(device, Memory::Emmc);
+        client.configure()?;
+        Ok(client)
+    }
+
+    /// The error message of `result`, or an error if it succeeded.
+    fn err_of<T: std::fmt::Debug>(result: Result<T, String>) -> Result<String, Box<dyn Error>> {
+        match result {
+            Ok(v) => Err(format!("expected an error, got {:?}", v).into()),
+            Err(e) => Ok(e),
+        }
+    }
+
+    #[test]
+    fn test_configure_retries_with_device_payload_size() -> Result<(), Box<dyn Error>> {
+        let client = client(SimProgrammer::new(8))?;
+        assert_eq!(client.max_payload(), 2048);
+        let sent = &client.transport.commands;
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].attr("MemoryName"), Some("emmc"));
+        assert_eq!(sent[1].attr("MaxPayloadSizeToTargetInBytes"), Some("2048"));
+        assert_eq!(
+            client.log.last().map(String::as_str),
+            Some("INFO: Storage type set")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_info_from_json_log() -> Result<(), Box<dyn Error>> {
+        let mut client = client(SimProgrammer::new(16))?;
+        let info = client.storage_info(0)?;
+        assert_eq!(info.total_blocks, Some(16));
+        assert_eq!(info.block_size, Some(512));
+        assert_eq!(info.mem_type.as_deref(), Some("eMMC"));
+        assert_eq!(info.capacity(), Some(8192));
+        assert!(info.to_string().contains("  LUNs: 1\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_info_from_text_log() {
+        let logs = vec![
+            "INFO: Device Total Logical Blocks: 0x3a3e000".to_string(),
+            "INFO: Device Block Size in Bytes: 0x200".to_string(),
+        ];
+        let info = StorageInfo::from_logs(&logs);
+        assert_eq!(info.total_blocks, Some(0x3a3e000));
+        assert_eq!(info.block_size, Some(512));
+    }
+
+    #[test]
+    fn test_read_streams_raw_data_with_progress() -> Result<(), Box<dyn Error>> {
+        let mut client = client(SimProgrammer::new(16))?;
+        let mut out = Vec::new();
+        let mut seen = Vec::new();
+        client.read(0, 2, 3, &mut out, &mut |done, total| {
+            seen.push((done, total))
+        })?;
+        assert_eq!(out.len(), 1536);
+        assert_eq!((out[0], out[512], out[1535]), (2, 3, 4));
+        assert_eq!(seen.last(), Some(&(1536, 1536)));
+        // The closing response was consumed; the next command still works.
+        client.erase(0, 0, 1)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_program_pads_and_splits_by_max_payload() -> Result<(), Box<dyn Error>> {
+        let mut client = client(SimProgrammer::new(16))?;
+        let image = vec![0xAB; 2600];
+        let mut seen = Vec::new();
+        client.program(
+            0,
+            4,
+            &mut image.as_slice(),
+            2600,
+            "boot.img",
+            &mut |d, t| seen.push((d, t)),
+        )?;
+        let device = &client.transport;
+        assert_eq!(device.packets, vec![2048, 1024]);
+        assert_eq!(seen, vec![(2048, 3072), (3072, 3072)]);
+        assert!(device.disk[2048..2048 + 2600].iter().all(|&b| b == 0xAB));
+        assert!(device.disk[2048 + 2600..2048 + 3072]
+            .iter()
+            .all(|&b| b == 0));
+        assert_eq!(device.disk[2048 + 3072], 10);
+        let cmd = device.commands.last().ok_or("no command sent")?;
+        assert_eq!(cmd.attr("num_partition_sectors"), Some("6"));
+        assert_eq!(cmd.attr("filename"), Some("boot.img"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_erase_patch_boot_drive_and_power() -> Result<(), Box<dyn Error>> {
+        let mut client = client(SimProgrammer::new(8))?;
+        client.erase(0, 1, 2)?;
+        assert!(client.transport.disk[512..1536].iter().all(|&b| b == 0));
+        client.patch(&Patch {
+            lun: 0,
+            start_sector: "NUM_DISKSECTORS-1.".into(),
+            byte_offset: 16,
+            size_in_bytes: 4,
+            value: "CRC32(NUM_DISKSECTORS-1.,92)".into(),
+            what: "Update backup header with CRC of header.".into(),
+        })?;
+        client.set_bootable_drive(1)?;
+        client.power(Power::Reset)?;
+        let names: Vec<&str> = client
+            .transport
+            .commands
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(
+            &names[2..],
+            ["erase", "patch", "setbootablestoragedrive", "power"]
+        );
+        let patch = &client.transport.commands[3];
+        assert_eq!(patch.attr("start_sector"), Some("NUM_DISKSECTORS-1."));
+        assert_eq!(patch.attr("value"), Some("CRC32(NUM_DISKSECTORS-1.,92)"));
+        assert_eq!(client.transport.commands[5].attr("value"), Some("reset"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_patch_file_keeps_disk_patches() -> Result<(), Box<dyn Error>> {
+        let xml = r#"<?xml version="1.0" ?>
+            <patches>
+              <patch SECTOR_SIZE_IN_BYTES="4096" byte_offset="80" filename="gpt_main0.bin"
+                     physical_partition_number="0" size_in_bytes="4" start_sector="1"
+                     value="CRC32(2,4096)" what="Update main header with CRC of partition table." />
+              <patch SECTOR_SIZE_IN_BYTES="4096" byte_offset="0x10" filename="DISK"
+                     physical_partition_number="0" size_in_bytes="4" start_sector="NUM_DISKSECTORS-1."
+                     value="0" what="Zero out header CRC in backup header." />
+            </patches>"#;
+        let patches = Patch::parse_file(xml)?;
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].byte_offset, 16);
+        assert_eq!(patches[0].start_sector, "NUM_DISKSECTORS-1.");
+        let bad = xml.replace("byte_offset=\"0x10\"", "byte_offset=\"x\"");
+        assert_eq!(
+            err_of(Patch::parse_file(&bad))?,
+            "patch byte_offset 'x' is not a number"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nak_reports_device_log() -> Result<(), Box<dyn Error>> {
+        let mut device = SimProgrammer::new(8);
+        device.reject = Some("erase");
+        let mut client = client(device)?;
+        let err = err_of(client.erase(0, 0, 1))?;
+        assert_eq!(
+            err,
+            "Firehose erase was rejected (NAK) (device log: ERROR: Failed to run command)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_silent_programmer_times_out() -> Result<(), Box<dyn Error>> {
+        let mut client = FirehoseClient::new(SimProgrammer::new(8), Memory::Ufs);
+        client.timeout = Duration::from_millis(1);
+        let err = err_of(client.response())?;
+        assert!(
+            err.starts_with("Firehose programmer did not respond"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+}
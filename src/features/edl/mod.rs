@@ -0,0 +1,229 @@
+/// Qualcomm Emergency Download (EDL, USB 05C6:9008) support.
+///
+/// The primary bootloader in EDL speaks Sahara, which identifies the chip
+/// and accepts a signed Firehose programmer; the programmer then reads and
+/// writes storage over the Firehose XML protocol, following the rawprogram
+/// and patch descriptors of a firmware package. Protocol clients run over the
+/// `Transport` trait so they can be driven by a simulated device in tests;
+/// on real hardware the QDLoader 9008 port is opened as a serial port.
+pub mod backup;
+pub mod firehose;
+pub mod programmers;
+pub mod rawprogram;
+pub mod sahara;
+
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
+
+pub const QUALCOMM_VID: u16 = 0x05C6;
+pub const EDL_PID: u16 = 0x9008;
+
+/// Byte pipe to a device in EDL mode.
+pub trait Transport {
+    fn send(&mut self, data: &[u8]) -> Result<(), String>;
+
+    /// Read what is available into `buf`, waiting at most `timeout`.
+    /// `Ok(0)` means nothing arrived in time.
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, String>;
+}
+
+/// Serial port transport for the QDLoader 9008 interface.
+pub struct SerialTransport {
+    port: Box<dyn serialport::SerialPort>,
+}
+
+impl SerialTransport {
+    pub fn open(port_name: &str) -> Result<Self, String> {
+        let port = serialport::new(port_name, 115_200)
+            .timeout(Duration::from_secs(1))
+            .open()
+            .map_err(|e| format!("Cannot open EDL port {}: {}", port_name, e))?;
+        Ok(Self { port })
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send(&mut self, data: &[u8]) -> Result<(), String> {
+        self.port
+            .write_all(data)
+            .and_then(|_| self.port.flush())
+            .map_err(|e| format!("EDL write failed: {}", e))
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, String> {
+        self.port
+            .set_timeout(timeout.max(Duration::from_millis(1)))
+            .map_err(|e| format!("EDL port: {}", e))?;
+        match self.port.read(buf) {
+            Ok(n) => Ok(n),
+            Err(e) if e.kind() == ErrorKind::TimedOut => Ok(0),
+            Err(e) => Err(format!("EDL read failed: {}", e)),
+        }
+    }
+}
+
+/// Read exactly `len` bytes or fail once `timeout` has passed.
+pub fn recv_exact(
+    transport: &mut dyn Transport,
+    len: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>, String> {
+    let deadline = Instant::now() + timeout;
+    let mut data = vec![0u8; len];
+    let mut filled = 0;
+    while filled < len {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(format!(
+                "EDL device timed out after {} of {} bytes",
+                filled, len
+            ));
+        }
+        filled += transport.recv(&mut data[filled..], remaining)?;
+    }
+    Ok(data)
+}
+
+/// One XML element with its unescaped attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlElement {
+    pub name: String,
+    pub attrs: BTreeMap<String, String>,
+}
+
+impl XmlElement {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.get(name).map(String::as_str)
+    }
+}
+
+/// Every element of an XML document in document order, root included.
+/// Firehose messages and Qualcomm flash descriptors are flat lists of
+/// attribute-only elements, so text content is ignored.
+pub fn parse_xml_elements(text: &str) -> Result<Vec<XmlElement>, String> {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(text);
+    let mut elements = Vec::new();
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("XML error at byte {}: {}", reader.error_position(), e))?;
+        let start = match &event {
+            Event::Start(start) | Event::Empty(start) => start,
+            Event::Eof => break,
+            _ => continue,
+        };
+        let mut attrs = BTreeMap::new();
+        for attr in start.attributes() {
+            let attr = attr.map_err(|e| format!("XML attribute error: {}", e))?;
+            let value = attr
+                .unescape_value()
+                .map_err(|e| format!("XML attribute error: {}", e))?;
+            attrs.insert(
+                String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                value.into_owned(),
+            );
+        }
+        elements.push(XmlElement {
+            name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            attrs,
+        });
+    }
+    Ok(elements)
+}
+
+fn is_edl_port(port: &serialport::SerialPortInfo) -> bool {
+    matches!(
+        &port.port_type,
+        serialport::SerialPortType::UsbPort(usb) if usb.vid == QUALCOMM_VID && usb.pid == EDL_PID
+    )
+}
+
+/// Name of the first serial port exposed by a device in EDL mode.
+pub fn find_edl_port() -> Option<String> {
+    serialport::available_ports()
+        .ok()?
+        .into_iter()
+        .find(is_edl_port)
+        .map(|p| p.port_name)
+}
+
+pub fn open_edl_port() -> Result<SerialTransport, String> {
+    let name = find_edl_port().ok_or_else(|| {
+        "No Qualcomm HS-USB QDLoader 9008 device found.\n\
+         Enter EDL mode first, and on Windows install the Qualcomm USB driver."
+            .to_string()
+    })?;
+    SerialTransport::open(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Transport for Chunks {
+        fn send(&mut self, _data: &[u8]) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn recv(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, String> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Ok(0);
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            if n < chunk.len() {
+                self.0.push_front(chunk[n..].to_vec());
+            }
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_recv_exact_joins_chunks_and_times_out() {
+        let mut t = Chunks(vec![vec![1, 2], vec![3, 4, 5]].into());
+        assert_eq!(
+            recv_exact(&mut t, 4, Duration::from_millis(50)),
+            Ok(vec![1, 2, 3, 4])
+        );
+        let err = recv_exact(&mut t, 3, Duration::from_millis(20)).unwrap_err();
+        assert_eq!(err, "EDL device timed out after 1 of 3 bytes");
+    }
+
+    #[test]
+    fn test_parse_xml_elements() -> Result<(), Box<dyn std::error::Error>> {
+        let doc = r#"<?xml version="1.0" ?>
+            <data>
+              <log value="INFO: &quot;hi&quot; &amp; bye" />
+              <response value="ACK" rawmode="false"></response>
+            </data>"#;
+        let elements = parse_xml_elements(doc)?;
+        let names: Vec<&str> = elements.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["data", "log", "response"]);
+        assert_eq!(elements[1].attr("value"), Some("INFO: \"hi\" & bye"));
+        assert_eq!(elements[2].attr("rawmode"), Some("false"));
+        assert!(parse_xml_elements("<data><log value=\"x/></data>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_edl_port() {
+        let usb = |vid, pid| serialport::SerialPortInfo {
+            port_name: "COM9".into(),
+            port_type: serialport::SerialPortType::UsbPort(serialport::UsbPortInfo {
+                vid,
+                pid,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+            }),
+        };
+        assert!(is_edl_port(&usb(0x05C6, 0x9008)));
+        assert!(!is_edl_port(&usb(0x05C6, 0x9091)));
+    }
+}
@@ -0,0 +1,577 @@
+/// Local library of Firehose programmers in `~/.foem/programmers`.
+///
+/// A programmer is a signed ELF. One of its program headers is the hash
+/// segment, which starts with an MBN header and carries the signature and
+/// certificate chain. The chip a programmer is built for is named in the
+/// attestation certificate (`02 009600E100000000 HW_ID` style OU fields) or,
+/// for v6 images, in the signing metadata. The OEM PK hash is the hash of
+/// the root certificate. A device accepts a programmer only when both match
+/// what Sahara reports.
+use super::sahara::DeviceInfo;
+use crate::exec;
+
+use sha2::{Digest, Sha256, Sha384};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PT_NULL: u32 = 0;
+const HASH_SEGMENT: u32 = 2;
+const MAX_PROGRAMMER_SIZE: u64 = 64 * 1024 * 1024;
+
+pub fn programmers_dir() -> PathBuf {
+    exec::foem_home().join("programmers")
+}
+
+/// Identity of one programmer file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Programmer {
+    pub path: PathBuf,
+    pub mbn_version: u32,
+    pub msm_id: Option<u32>,
+    pub oem_id: Option<u16>,
+    pub model_id: Option<u16>,
+    /// Lowercase hex SHA-256 and SHA-384 of the root certificate; empty
+    /// for unsigned images.
+    pub pk_hashes: Vec<String>,
+}
+
+impl Programmer {
+    pub fn parse(path: &Path, data: &[u8]) -> Result<Self, String> {
+        let segment = hash_segment(data)?;
+        let mut programmer = Self {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        programmer.read_mbn(segment)?;
+        Ok(programmer)
+    }
+
+    fn read_mbn(&mut self, segment: &[u8]) -> Result<(), String> {
+        let field = |index: usize| read_u32(segment, index * 4).ok_or("hash segment is truncated");
+        self.mbn_version = field(1)?;
+        let code_size = field(5)? as usize;
+        let sig_size = field(7)? as usize;
+        let cert_size = field(9)? as usize;
+        let (header_len, qti_metadata, metadata) = match self.mbn_version {
+            3 | 5 => (40, 0, 0),
+            6 => (48, field(10)? as usize, field(11)? as usize),
+            v => return Err(format!("unsupported MBN header version {}", v)),
+        };
+        if metadata >= 24 || qti_metadata >= 24 {
+            // OEM metadata follows the QTI metadata; either names the chip.
+            let at = match metadata >= 24 {
+                true => header_len + qti_metadata,
+                false => header_len,
+            };
+            let word = |i: usize| read_u32(segment, at + i * 4);
+            self.msm_id = word(3).filter(|&v| v != 0).map(|v| v & 0x00FF_FFFF);
+            self.oem_id = word(4).map(|v| v as u16);
+            self.model_id = word(5).map(|v| v as u16);
+        }
+        let chain_at = header_len + qti_metadata + metadata + code_size + sig_size;
+        let chain = segment
+            .get(chain_at..chain_at.saturating_add(cert_size).min(segment.len()))
+            .unwrap_or_default();
+        let certs = split_certificates(chain);
+        if let Some(attestation) = certs.first() {
+            self.read_ou_fields(attestation);
+        }
+        if let Some(root) = certs.last() {
+            self.pk_hashes = vec![
+                hex::encode(Sha256::digest(root)),
+                hex::encode(Sha384::digest(root)),
+            ];
+        }
+        Ok(())
+    }
+
+    fn read_ou_fields(&mut self, cert: &[u8]) {
+        let text = String::from_utf8_lossy(cert);
+        for (at, key) in ["HW_ID", "OEM_ID", "MODEL_ID"]
+            .iter()
+            .flat_map(|key| text.match_indices(key))
+        {
+            let Some(value) = ou_value(&text[..at]) else {
+                continue;
+            };
+            match key {
+                "HW_ID" if value != 0 => {
+                    self.msm_id = self.msm_id.or(Some(((value >> 32) & 0x00FF_FFFF) as u32))
+                }
+                "OEM_ID" => self.oem_id = self.oem_id.or(Some(value as u16)),
+                "MODEL_ID" => self.model_id = self.model_id.or(Some(value as u16)),
+                _ => {}
+            }
+        }
+    }
+
+    fn file_name(&self) -> String {
+        self.path.file_name().map_or_else(
+            || self.path.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        )
+    }
+}
+
+/// The value of an OU field such as `01 0000000000000000 HW_ID`, given the
+/// certificate text that precedes the field name.
+fn ou_value(before: &str) -> Option<u64> {
+    let rest = before.strip_suffix(' ')?;
+    let hex_start = rest.len() - rest.bytes().rev().take_while(u8::is_ascii_hexdigit).count();
+    let hex = &rest[hex_start..];
+    let index = rest[..hex_start].strip_suffix(' ')?;
+    let digits = index.get(index.len().checked_sub(2)?..)?;
+    if hex.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+impl fmt::Display for Programmer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (MBN v{}", self.file_name(), self.mbn_version)?;
+        match self.msm_id {
+            Some(msm) => write!(f, ", MSM ID 0x{:06X}", msm)?,
+            None => write!(f, ", chip unknown")?,
+        }
+        if let Some(oem) = self.oem_id.filter(|&o| o != 0) {
+            write!(f, ", OEM ID 0x{:04X}", oem)?;
+        }
+        match self.pk_hashes.first() {
+            Some(hash) => write!(f, ", PK hash {}...)", &hash[..16]),
+            None => write!(f, ", unsigned)"),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    let b = data.get(at..at + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(read_u32(data, at)? as u64 | (read_u32(data, at + 4)? as u64) << 32)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    let b = data.get(at..at + 2)?;
+    Some(u16::from_le_bytes([b[0], b[1]]))
+}
+
+/// The bytes of the hash segment: a `PT_NULL` program header whose OS
+/// segment type (bits 24-26 of `p_flags`) is 2.
+fn hash_segment(data: &[u8]) -> Result<&[u8], String> {
+    if data.get(..4) != Some(b"\x7fELF") {
+        return Err("not an ELF file".into());
+    }
+    if data.get(5) != Some(&1) {
+        return Err("not a little-endian ELF".into());
+    }
+    let is_64 = match data.get(4) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => return Err("unknown ELF class".into()),
+    };
+    let truncated = || "ELF header is truncated".to_string();
+    let (phoff, phentsize, phnum) = match is_64 {
+        false => (
+            read_u32(data, 0x1C).map(u64::from),
+            read_u16(data, 0x2A),
+            read_u16(data, 0x2C),
+        ),
+        true => (
+            read_u64(data, 0x20),
+            read_u16(data, 0x36),
+            read_u16(data, 0x38),
+        ),
+    };
+    let (phoff, phentsize, phnum) = (
+        phoff
+            .filter(|&o| o <= data.len() as u64)
+            .ok_or_else(truncated)? as usize,
+        phentsize.ok_or_else(truncated)? as usize,
+        phnum.ok_or_else(truncated)? as usize,
+    );
+    for i in 0..phnum {
+        let at = phoff + i * phentsize;
+        let (p_type, flags, offset, size) = match is_64 {
+            false => (
+                read_u32(data, at),
+                read_u32(data, at + 24),
+                read_u32(data, at + 4).map(u64::from),
+                read_u32(data, at + 16).map(u64::from),
+            ),
+            true => (
+                read_u32(data, at),
+                read_u32(data, at + 4),
+                read_u64(data, at + 8),
+                read_u64(data, at + 32),
+            ),
+        };
+        let (Some(p_type), Some(flags), Some(offset), Some(size)) = (p_type, flags, offset, size)
+        else {
+            return Err("ELF program headers are truncated".into());
+        };
+        if p_type == PT_NULL && (flags >> 24) & 7 == HASH_SEGMENT {
+            let end = offset.checked_add(size).filter(|&e| e <= data.len() as u64);
+            return match end {
+                Some(end) => Ok(&data[offset as usize..end as usize]),
+                None => Err("hash segment lies outside the file".into()),
+            };
+        }
+    }
+    Err("no hash segment; the image is not signed for Qualcomm secure boot".into())
+}
+
+/// DER certificates laid end to end, stopping at the first byte that does
+/// not start one (the chain is padded with 0xFF).
+fn split_certificates(chain: &[u8]) -> Vec<&[u8]> {
+    let mut certs = Vec::new();
+    let mut rest = chain;
+    while rest.len() >= 2 && rest[0] == 0x30 {
+        let (header, len) = match rest[1] {
+            n if n < 0x80 => (2, n as usize),
+            0x81 if rest.len() >= 3 => (3, rest[2] as usize),
+            0x82 if rest.len() >= 4 => (4, u16::from_be_bytes([rest[2], rest[3]]) as usize),
+            _ => break,
+        };
+        let Some(cert) = rest.get(..header + len) else {
+            break;
+        };
+        certs.push(cert);
+        rest = &rest[header + len..];
+    }
+    certs
+}
+
+/// How well a programmer fits a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Fit {
+    /// Same chip and OEM root key.
+    Exact,
+    /// Same chip; the key could not be compared.
+    ChipOnly,
+}
+
+/// Programmers found in a directory, indexed by chip and root key.
+#[derive(Debug, Default)]
+pub struct ProgrammerLibrary {
+    pub programmers: Vec<Programmer>,
+    /// Files that are not usable programmers, with the reason.
+    pub skipped: Vec<(PathBuf, String)>,
+    by_msm: BTreeMap<u32, Vec<usize>>,
+    by_pk_hash: BTreeMap<String, Vec<usize>>,
+}
+
+impl ProgrammerLibrary {
+    /// Index every `.mbn`, `.elf` and `.bin` file in `dir`. A missing
+    /// directory is an empty library.
+    pub fn load(dir: &Path) -> Self {
+        let mut library = Self::default();
+        let Ok(read) = fs::read_dir(dir) else {
+            return library;
+        };
+        let mut paths: Vec<PathBuf> = read
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.extension().is_some_and(|ext| {
+                    ["mbn", "elf", "bin"]
+                        .contains(&ext.to_string_lossy().to_ascii_lowercase().as_str())
+                })
+            })
+            .collect();
+        paths.sort();
+        for path in paths {
+            let result = match fs::metadata(&path) {
+                Ok(m) if m.len() > MAX_PROGRAMMER_SIZE => Err("too large for a programmer".into()),
+                _ => fs::read(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|data| Programmer::parse(&path, &data)),
+            };
+            match result {
+                Ok(programmer) => library.add(programmer),
+                Err(e) => library.skipped.push((path, e)),
+            }
+        }
+        library
+    }
+
+    fn add(&mut self, programmer: Programmer) {
+        let index = self.programmers.len();
+        if let Some(msm) = programmer.msm_id {
+            self.by_msm.entry(msm).or_default().push(index);
+        }
+        for hash in &programmer.pk_hashes {
+            self.by_pk_hash.entry(hash.clone()).or_default().push(index);
+        }
+        self.programmers.push(programmer);
+    }
+
+    /// Programmers built for the device's chip, best first. Ones signed with
+    /// a different root key are left out: the device would reject them.
+    pub fn suggest(&self, device: &DeviceInfo) -> Vec<(&Programmer, Fit)> {
+        let Some(msm) = device.hw_id.map(|h| h.msm_id) else {
+            return Vec::new();
+        };
+        let device_hash = device.pk_hash.as_deref().map(str::to_ascii_lowercase);
+        let same_key: &[usize] = device_hash
+            .as_ref()
+            .and_then(|h| self.by_pk_hash.get(h))
+            .map_or(&[], Vec::as_slice);
+        let mut found: Vec<(&Programmer, Fit)> = self
+            .by_msm
+            .get(&msm)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .filter_map(|&i| {
+                let programmer = &self.programmers[i];
+                if same_key.contains(&i) {
+                    Some((programmer, Fit::Exact))
+                } else if device_hash.is_none() || programmer.pk_hashes.is_empty() {
+                    Some((programmer, Fit::ChipOnly))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        found.sort_by_key(|(_, fit)| *fit);
+        found
+    }
+
+    /// Suggestions for `device` as text, for the EDL log.
+    pub fn render_suggestions(&self, device: &DeviceInfo, dir: &Path) -> String {
+        let suggestions = self.suggest(device);
+        if suggestions.is_empty() {
+            return format!(
+                "  No matching programmer among {} in {}.\n",
+                self.programmers.len(),
+                dir.display()
+            );
+        }
+        let mut out = format!("  Compatible programmers in {}:\n", dir.display());
+        for (programmer, fit) in suggestions {
+            let note = match fit {
+                Fit::Exact => "chip and OEM key match",
+                Fit::ChipOnly => "chip matches; OEM key not checked",
+            };
+            out.push_str(&format!("    {} -- {}\n", programmer.path.display(), note));
+        }
+        out
+    }
+
+    /// Every indexed programmer and every skipped file.
+    pub fn render(&self, dir: &Path) -> String {
+        let mut out = format!(
+            "Programmer library ({}): {} programmers\n",
+            dir.display(),
+            self.programmers.len()
+        );
+        for programmer in &self.programmers {
+            out.push_str(&format!("  {}\n", programmer));
+        }
+        for (path, reason) in &self.skipped {
+            out.push_str(&format!("  Skipped {}: {}\n", path.display(), reason));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sahara::HwId;
+    use super::*;
+
+    fn der(content: &[u8]) -> Vec<u8> {
+        let mut cert = vec![0x30, 0x82];
+        cert.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        cert.extend_from_slice(content);
+        cert
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// ELF32 with a PT_LOAD and a hash segment holding `segment`.
+    fn elf32(segment: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 52];
+        elf[..6].copy_from_slice(b"\x7fELF\x01\x01");
+        elf[0x1C..0x20].copy_from_slice(&52u32.to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&32u16.to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&2u16.to_le_bytes());
+        let offset = 52 + 2 * 32;
+        elf.extend(words(&[1, 0, 0, 0, 0, 0, 5, 0]));
+        elf.extend(words(&[
+            PT_NULL,
+            offset,
+            0,
+            0,
+            segment.len() as u32,
+            0,
+            0x0220_0000,
+            0,
+        ]));
+        elf.extend_from_slice(segment);
+        elf
+    }
+
+    /// ELF64 with only a hash segment.
+    fn elf64(segment: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3A].copy_from_slice(&1u16.to_le_bytes());
+        let mut ph = words(&[PT_NULL, 0x0220_0000]);
+        ph.extend((64u64 + 56).to_le_bytes());
+        ph.extend([0u8; 16]);
+        ph.extend((segment.len() as u64).to_le_bytes());
+        ph.extend([0u8; 16]);
+        elf.extend(ph);
+        elf.extend_from_slice(segment);
+        elf
+    }
+
+    const ROOT: &[u8] = b"root certificate of the OEM";
+
+    /// MBN v3 hash segment with an attestation and a root certificate.
+    fn signed_v3(hw_id: &str) -> Vec<u8> {
+        let attestation = der(format!(
+            "OU=01 0000000000000000 SW_ID OU=02 {} HW_ID OU=04 0051 OEM_ID OU=06 0017 MODEL_ID",
+            hw_id
+        )
+        .as_bytes());
+        let chain = [attestation, der(ROOT)].concat();
+        let mut segment = words(&[0, 3, 0, 0, 0, 64, 0, 256, 0, chain.len() as u32]);
+        segment.extend([0xAA; 64 + 256]);
+        segment.extend(chain);
+        segment.extend([0xFF; 32]);
+        segment
+    }
+
+    /// What Sahara reports for a device whose root certificate is `der(pk)`.
+    fn device(msm_id: u32, pk: &[u8]) -> DeviceInfo {
+        DeviceInfo {
+            hw_id: Some(HwId::from_raw((msm_id as u64) << 32 | 0x0051_0017)),
+            pk_hash: Some(hex::encode(Sha256::digest(der(pk))).to_ascii_uppercase()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_v3_certificate_fields() -> Result<(), Box<dyn std::error::Error>> {
+        let p = Programmer::parse(
+            Path::new("prog.mbn"),
+            &elf32(&signed_v3("009600E100000000")),
+        )?;
+        assert_eq!(p.mbn_version, 3);
+        assert_eq!(p.msm_id, Some(0x9600E1));
+        assert_eq!(p.oem_id, Some(0x51));
+        assert_eq!(p.model_id, Some(0x17));
+        assert_eq!(p.pk_hashes[0], hex::encode(Sha256::digest(der(ROOT))));
+        assert_eq!(p.pk_hashes[1].len(), 96);
+        assert!(p
+            .to_string()
+            .starts_with("prog.mbn (MBN v3, MSM ID 0x9600E1, OEM ID 0x0051, PK hash"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ou_value() {
+        assert_eq!(ou_value("OU=04 0051 "), Some(0x51));
+        assert_eq!(
+            ou_value("OU=02 009600E100000000 "),
+            Some(0x0096_00E1_0000_0000)
+        );
+        assert_eq!(ou_value("OU=04 0051"), None);
+        assert_eq!(ou_value("OU=4 0051 "), None);
+        assert_eq!(ou_value("OU=04  "), None);
+        assert_eq!(ou_value("é 0051 "), None);
+    }
+
+    #[test]
+    fn test_parse_v6_metadata() -> Result<(), Box<dyn std::error::Error>> {
+        let metadata = words(&[0, 0, 0x3, 0x0017_E0E1, 0x0072, 0x0001, 0, 0]);
+        let mut segment = words(&[0, 6, 0, 0, 0, 32, 0, 0, 0, 0, 0, metadata.len() as u32]);
+        segment.extend(metadata);
+        segment.extend([0u8; 32]);
+        let p = Programmer::parse(Path::new("p.elf"), &elf64(&segment))?;
+        assert_eq!(
+            (p.mbn_version, p.msm_id, p.oem_id, p.model_id),
+            (6, Some(0x17E0E1), Some(0x72), Some(1))
+        );
+        assert!(p.pk_hashes.is_empty());
+        assert!(p.to_string().ends_with(", unsigned)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rejects_non_programmers() {
+        let path = Path::new("x");
+        assert_eq!(
+            Programmer::parse(path, b"MZ..").unwrap_err(),
+            "not an ELF file"
+        );
+        let mut unsigned = elf32(&[]);
+        unsigned[52 + 32 + 24..52 + 32 + 28].copy_from_slice(&0u32.to_le_bytes());
+        assert!(Programmer::parse(path, &unsigned)
+            .unwrap_err()
+            .starts_with("no hash segment"));
+        let mut v4 = signed_v3("0");
+        v4[4] = 4;
+        assert_eq!(
+            Programmer::parse(path, &elf32(&v4)).unwrap_err(),
+            "unsupported MBN header version 4"
+        );
+    }
+
+    #[test]
+    fn test_library_suggests_by_chip_and_key() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_programmer_library");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join("a_8953.mbn"),
+            elf32(&signed_v3("000460E100000000")),
+        )?;
+        fs::write(
+            dir.join("b_sdm660.elf"),
+            elf32(&signed_v3("0006B0E100000000")),
+        )?;
+        let mut other_oem = signed_v3("000460E100000000");
+        let len = other_oem.len();
+        other_oem[len - 40] ^= 1; // a different root certificate
+        fs::write(dir.join("c_8953_other.mbn"), elf32(&other_oem))?;
+        fs::write(dir.join("readme.txt"), "ignored")?;
+        fs::write(dir.join("broken.bin"), "not an elf")?;
+
+        let library = ProgrammerLibrary::load(&dir);
+        assert_eq!(library.programmers.len(), 3);
+        assert_eq!(library.skipped.len(), 1);
+        assert!(library.render(&dir).contains("Skipped"));
+
+        let names = |device: &DeviceInfo| -> Vec<(String, Fit)> {
+            library
+                .suggest(device)
+                .into_iter()
+                .map(|(p, fit)| (p.file_name(), fit))
+                .collect()
+        };
+        assert_eq!(
+            names(&device(0x0460E1, ROOT)),
+            vec![("a_8953.mbn".to_string(), Fit::Exact)]
+        );
+        let mut unknown_key = device(0x0460E1, ROOT);
+        unknown_key.pk_hash = None;
+        assert_eq!(names(&unknown_key).len(), 2);
+        assert!(names(&device(0x0460E1, b"another key")).is_empty());
+        assert!(library
+            .render_suggestions(&device(0x0006B0, ROOT), &dir)
+            .contains("No matching programmer among 3"));
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
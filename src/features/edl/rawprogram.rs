@@ -0,0 +1,657 @@
+/// Flash plans built from the descriptors in Qualcomm firmware packages.
+///
+/// `rawprogram*.xml` lists, per LUN, every partition with its sector range
+/// and the image to write there; `patch*.xml` holds the GPT fix-ups the
+/// programmer applies afterwards. A plan pairs each entry with the image
+/// found on disk so it can be checked before anything is written.
+use super::backup::SectorRange;
+use super::firehose::{FirehoseClient, Patch};
+use super::{parse_xml_elements, Transport, XmlElement};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Partitions left out unless explicitly included: they hold user data and
+/// per-device calibration that a package image would overwrite.
+pub const DEFAULT_EXCLUDED: &[&str] = &["userdata", "persist"];
+
+/// Partitions with per-device calibration and modem EFS. They are copied to
+/// the vault before a package is flashed, whether the plan writes them or not.
+pub const CALIBRATION: &[&str] = &["persist", "modemst1", "modemst2", "fsg", "fsc"];
+
+/// Where a partition starts. Partitions at the end of the disk (the backup
+/// GPT) are given relative to its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartSector {
+    Absolute(u64),
+    FromEnd(u64),
+}
+
+impl StartSector {
+    /// `1024`, or `NUM_DISKSECTORS-5.` (the trailing dot marks decimal).
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().trim_end_matches('.');
+        match text.strip_prefix("NUM_DISKSECTORS") {
+            Some("") => Some(Self::FromEnd(0)),
+            Some(rest) => rest
+                .trim()
+                .strip_prefix('-')?
+                .trim()
+                .parse()
+                .ok()
+                .map(Self::FromEnd),
+            None => text.parse().ok().map(Self::Absolute),
+        }
+    }
+
+    pub fn resolve(self, disk_sectors: u64) -> Option<u64> {
+        match self {
+            Self::Absolute(n) => Some(n),
+            Self::FromEnd(n) => disk_sectors.checked_sub(n),
+        }
+    }
+}
+
+impl std::fmt::Display for StartSector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Absolute(n) => write!(f, "{}", n),
+            Self::FromEnd(n) => write!(f, "end-{}", n),
+        }
+    }
+}
+
+/// One `<program>` entry and the image it refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanEntry {
+    pub lun: u32,
+    pub label: String,
+    /// Empty for partitions the package only reserves.
+    pub filename: String,
+    pub start: StartSector,
+    /// 0 when the partition takes the rest of the disk.
+    pub sectors: u64,
+    pub sector_size: u64,
+    pub file_sector_offset: u64,
+    pub sparse: bool,
+    pub image: PathBuf,
+    /// `None` when the image is missing.
+    pub image_size: Option<u64>,
+    pub included: bool,
+}
+
+impl PlanEntry {
+    fn from_element(element: &XmlElement, dir: &Path) -> Result<Self, String> {
+        let label = element.attr("label").unwrap_or_default().to_string();
+        let number = |key: &str, default: Option<u64>| -> Result<u64, String> {
+            match element.attr(key).map(str::trim) {
+                Some(value) if !value.is_empty() => value
+                    .trim_end_matches('.')
+                    .parse()
+                    .map_err(|_| format!("{}: {} '{}' is not a number", label, key, value)),
+                _ => default.ok_or_else(|| format!("{}: {} is missing", label, key)),
+            }
+        };
+        let start_text = element.attr("start_sector").unwrap_or_default();
+        let start = StartSector::parse(start_text)
+            .ok_or_else(|| format!("{}: start_sector '{}' is not understood", label, start_text))?;
+        let filename = element
+            .attr("filename")
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let image = dir.join(&filename);
+        let image_size = match filename.is_empty() {
+            true => None,
+            false => fs::metadata(&image)
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| m.len()),
+        };
+        Ok(Self {
+            lun: number("physical_partition_number", Some(0))? as u32,
+            start,
+            sectors: number("num_partition_sectors", None)?,
+            sector_size: number("SECTOR_SIZE_IN_BYTES", None)?,
+            file_sector_offset: number("file_sector_offset", Some(0))?,
+            sparse: element
+                .attr("sparse")
+                .is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            included: !filename.is_empty(),
+            label,
+            filename,
+            image,
+            image_size,
+        })
+    }
+
+    /// Bytes of the image that go to the device.
+    pub fn write_len(&self) -> Option<u64> {
+        self.image_size?
+            .checked_sub(self.file_sector_offset * self.sector_size)
+    }
+
+    /// Sectors the entry occupies; for a partition that takes the rest of
+    /// the disk, those its image covers.
+    fn span(&self) -> u64 {
+        match self.sectors {
+            0 => self
+                .write_len()
+                .unwrap_or(0)
+                .div_ceil(self.sector_size)
+                .max(1),
+            n => n,
+        }
+    }
+
+    /// Why this entry cannot be written as it stands.
+    pub fn problem(&self) -> Option<String> {
+        if self.filename.is_empty() {
+            return None;
+        }
+        if self.sparse {
+            return Some("sparse image; convert it with simg2img first".into());
+        }
+        let Some(size) = self.image_size else {
+            return Some(format!("{} not found", self.filename));
+        };
+        let Some(len) = self.write_len() else {
+            return Some(format!(
+                "file_sector_offset {} is past the end of {} ({} bytes)",
+                self.file_sector_offset, self.filename, size
+            ));
+        };
+        let capacity = self.sectors * self.sector_size;
+        if self.sectors > 0 && len > capacity {
+            return Some(format!(
+                "{} is {} bytes but the partition holds {}",
+                self.filename, len, capacity
+            ));
+        }
+        None
+    }
+}
+
+fn is_descriptor(name: &str, prefix: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower.starts_with(prefix) && lower.ends_with(".xml")
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlashPlan {
+    pub entries: Vec<PlanEntry>,
+    pub patches: Vec<Patch>,
+    /// Descriptor files the plan was read from.
+    pub sources: Vec<String>,
+}
+
+impl FlashPlan {
+    /// Read every `rawprogram*.xml` and `patch*.xml` in `dir`. Where a
+    /// package ships both `rawprogramN.xml` and `rawprogram_unsparseN.xml`,
+    /// the unsparse variant is used since sparse images cannot be streamed.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let unsparse: Vec<String> = names
+            .iter()
+            .filter(|n| is_descriptor(n, "rawprogram_unsparse"))
+            .map(|n| n.to_ascii_lowercase().replacen("_unsparse", "", 1))
+            .collect();
+
+        let mut plan = Self::default();
+        for name in &names {
+            let is_program = is_descriptor(name, "rawprogram");
+            let is_patch = is_descriptor(name, "patch");
+            if !(is_program || is_patch) || unsparse.contains(&name.to_ascii_lowercase()) {
+                continue;
+            }
+            let text = fs::read_to_string(dir.join(name))
+                .map_err(|e| format!("Cannot read {}: {}", name, e))?;
+            let result = match is_program {
+                true => plan.add_rawprogram(&text, dir),
+                false => Patch::parse_file(&text).map(|p| plan.patches.extend(p)),
+            };
+            result.map_err(|e| format!("{}: {}", name, e))?;
+            plan.sources.push(name.clone());
+        }
+        if plan.entries.is_empty() {
+            return Err(format!("No rawprogram*.xml found in {}", dir.display()));
+        }
+        Ok(plan)
+    }
+
+    fn add_rawprogram(&mut self, text: &str, dir: &Path) -> Result<(), String> {
+        for element in parse_xml_elements(text)?
+            .iter()
+            .filter(|e| e.name == "program")
+        {
+            self.entries.push(PlanEntry::from_element(element, dir)?);
+        }
+        Ok(())
+    }
+
+    /// Choose the entries to write. With an `include` list only those
+    /// labels are written; labels in `exclude` are skipped unless also
+    /// listed in `include`. Labels compare case-insensitively.
+    pub fn select(&mut self, include: &[String], exclude: &[String]) {
+        let listed =
+            |list: &[String], label: &str| list.iter().any(|l| l.eq_ignore_ascii_case(label));
+        for entry in &mut self.entries {
+            let wanted = include.is_empty() || listed(include, &entry.label);
+            let dropped = listed(exclude, &entry.label) && !listed(include, &entry.label);
+            entry.included = !entry.filename.is_empty() && wanted && !dropped;
+        }
+    }
+
+    pub fn included(&self) -> impl Iterator<Item = &PlanEntry> {
+        self.entries.iter().filter(|e| e.included)
+    }
+
+    /// Everything that must be fixed before the plan can run.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .included()
+            .filter_map(|e| Some(format!("LUN {} {}: {}", e.lun, e.label, e.problem()?)))
+            .collect();
+        if self.included().next().is_none() {
+            problems.push("No partitions are selected.".into());
+        }
+        let mut sizes: Vec<u64> = self.entries.iter().map(|e| e.sector_size).collect();
+        sizes.sort();
+        sizes.dedup();
+        if sizes.len() > 1 {
+            problems.push(format!(
+                "Descriptors disagree on the sector size: {:?}",
+                sizes
+            ));
+        }
+        let mut by_lun: BTreeMap<u32, Vec<(u64, u64, &str)>> = BTreeMap::new();
+        for entry in self.included() {
+            if let StartSector::Absolute(start) = entry.start {
+                by_lun.entry(entry.lun).or_default().push((
+                    start,
+                    start + entry.span(),
+                    &entry.label,
+                ));
+            }
+        }
+        for (lun, mut ranges) in by_lun {
+            ranges.sort();
+            for pair in ranges.windows(2) {
+                if pair[1].0 < pair[0].1 {
+                    problems.push(format!(
+                        "LUN {}: {} and {} overlap at sector {}",
+                        lun, pair[0].2, pair[1].2, pair[1].0
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// One line per entry plus the problems found.
+    pub fn render(&self) -> String {
+        let mut out = format!("Flash plan from {}:\n", self.sources.join(", "));
+        for e in &self.entries {
+            let range = match (e.start, e.sectors) {
+                (start, 0) => format!("{}..end", start),
+                (StartSector::Absolute(s), n) => format!("{}..{}", s, s + n),
+                (start, n) => format!("{} +{}", start, n),
+            };
+            let status = if e.filename.is_empty() {
+                "no image".to_string()
+            } else if !e.included {
+                "excluded".to_string()
+            } else {
+                e.problem().unwrap_or_else(|| "ok".into())
+            };
+            let size = e.write_len().map_or_else(String::new, |n| {
+                format!(" ({:.1} MiB)", n as f64 / (1u64 << 20) as f64)
+            });
+            out.push_str(&format!(
+                "  LUN {} {:<16} {:<22} {}{} -- {}\n",
+                e.lun, e.label, range, e.filename, size, status
+            ));
+        }
+        out.push_str(&format!(
+            "  {} of {} partitions selected, {} patches.\n",
+            self.included().count(),
+            self.entries.len(),
+            self.patches.len()
+        ));
+        match self.problems().as_slice() {
+            [] => out.push_str("Plan is valid.\n"),
+            problems => {
+                out.push_str("Problems:\n");
+                for p in problems {
+                    out.push_str(&format!("  {}\n", p));
+                }
+            }
+        }
+        out
+    }
+
+    /// Sector ranges of the entries labelled `labels`, for backing them up.
+    /// Starts relative to the disk end are resolved with `disk_sectors`;
+    /// entries that run to the end of the disk are left out.
+    pub fn backup_ranges(
+        &self,
+        labels: &[&str],
+        disk_sectors: &mut dyn FnMut(u32) -> Option<u64>,
+    ) -> Vec<SectorRange> {
+        self.entries
+            .iter()
+            .filter(|e| e.sectors > 0 && labels.iter().any(|l| l.eq_ignore_ascii_case(&e.label)))
+            .filter_map(|e| {
+                let start = match e.start {
+                    StartSector::Absolute(start) => start,
+                    relative => relative.resolve(disk_sectors(e.lun)?)?,
+                };
+                Some(SectorRange {
+                    name: e.label.clone(),
+                    lun: e.lun,
+                    start,
+                    count: e.sectors,
+                })
+            })
+            .collect()
+    }
+
+    /// Validate, write every selected image, then apply the patches.
+    /// `progress` receives (label, bytes done, bytes total) per image.
+    pub fn execute<T: Transport>(
+        &self,
+        client: &mut FirehoseClient<T>,
+        progress: &mut dyn FnMut(&str, u64, u64),
+    ) -> Result<String, String> {
+        let problems = self.problems();
+        if !problems.is_empty() {
+            return Err(format!("Plan is not valid:\n  {}", problems.join("\n  ")));
+        }
+        let mut disk_sectors: BTreeMap<u32, u64> = BTreeMap::new();
+        let mut report = String::new();
+        for entry in self.included() {
+            let start = match entry.start {
+                StartSector::Absolute(start) => start,
+                relative => {
+                    let total = match disk_sectors.get(&entry.lun) {
+                        Some(&total) => total,
+                        None => {
+                            let info = client.storage_info(entry.lun)?;
+                            let total = info.total_blocks.ok_or_else(|| {
+                                format!(
+                                    "LUN {}: the programmer did not report the disk size",
+                                    entry.lun
+                                )
+                            })?;
+                            disk_sectors.insert(entry.lun, total);
+                            total
+                        }
+                    };
+                    relative.resolve(total).ok_or_else(|| {
+                        format!("{}: {} is outside the disk", entry.label, relative)
+                    })?
+                }
+            };
+            if client.sector_size() as u64 != entry.sector_size {
+                return Err(format!(
+                    "{}: the package uses {}-byte sectors but the device uses {}; pick the other storage type",
+                    entry.label,
+                    entry.sector_size,
+                    client.sector_size()
+                ));
+            }
+            let len = entry.write_len().unwrap_or(0);
+            let mut file = fs::File::open(&entry.image)
+                .map_err(|e| format!("Cannot open {}: {}", entry.image.display(), e))?;
+            file.seek(SeekFrom::Start(
+                entry.file_sector_offset * entry.sector_size,
+            ))
+            .map_err(|e| format!("Cannot seek {}: {}", entry.image.display(), e))?;
+            client
+                .program(
+                    entry.lun,
+                    start,
+                    &mut file,
+                    len,
+                    &entry.filename,
+                    &mut |done, total| progress(&entry.label, done, total),
+                )
+                .map_err(|e| format!("{}: {}", entry.label, e))?;
+            report.push_str(&format!(
+                "  LUN {} {} <- {} ({} bytes at sector {})\n",
+                entry.lun, entry.label, entry.filename, len, start
+            ));
+        }
+        for patch in &self.patches {
+            client
+                .patch(patch)
+                .map_err(|e| format!("patch '{}': {}", patch.what, e))?;
+        }
+        report.push_str(&format!("  Applied {} patches.\n", self.patches.len()));
+        Ok(report)
+    }
+}
+
+/// Split a comma- or space-separated list of partition labels.
+pub fn split_labels(text: &str) -> Vec<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::firehose::{sim::SimProgrammer, Memory};
+    use super::*;
+
+    fn package(name: &str, files: &[(&str, Vec<u8>)]) -> std::io::Result<PathBuf> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        for (file, data) in files {
+            fs::write(dir.join(file), data)?;
+        }
+        Ok(dir)
+    }
+
+    fn program(label: &str, file: &str, start: &str, sectors: u64) -> String {
+        format!(
+            "<program SECTOR_SIZE_IN_BYTES=\"512\" file_sector_offset=\"0\" filename=\"{}\" \
+             label=\"{}\" num_partition_sectors=\"{}\" physical_partition_number=\"0\" \
+             sparse=\"false\" start_sector=\"{}\" />",
+            file, label, sectors, start
+        )
+    }
+
+    fn rawprogram(programs: &[String]) -> Vec<u8> {
+        format!("<?xml version=\"1.0\" ?><data>{}</data>", programs.concat()).into_bytes()
+    }
+
+    const PATCH: &str = r#"<patches>
+        <patch SECTOR_SIZE_IN_BYTES="512" byte_offset="16" filename="DISK"
+               physical_partition_number="0" size_in_bytes="4"
+               start_sector="NUM_DISKSECTORS-1." value="0" what="Zero backup header CRC." />
+        </patches>"#;
+
+    #[test]
+    fn test_start_sector_parse_and_resolve() {
+        assert_eq!(StartSector::parse("34"), Some(StartSector::Absolute(34)));
+        assert_eq!(
+            StartSector::parse("NUM_DISKSECTORS-33."),
+            Some(StartSector::FromEnd(33))
+        );
+        assert_eq!(StartSector::parse("NUM_DISKSECTORS+1"), None);
+        assert_eq!(StartSector::FromEnd(33).resolve(100), Some(67));
+        assert_eq!(StartSector::FromEnd(200).resolve(100), None);
+    }
+
+    #[test]
+    fn test_plan_checks_images_and_default_exclusions() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = package(
+            "foem_rawprogram_plan",
+            &[
+                (
+                    "rawprogram0.xml",
+                    rawprogram(&[
+                        program("boot", "boot.img", "8", 4),
+                        program("modem", "NON-HLOS.bin", "12", 2),
+                        program("dtbo", "dtbo.img", "14", 2),
+                        program("misc", "", "16", 2),
+                        program("userdata", "userdata.img", "18", 0),
+                    ]),
+                ),
+                ("patch0.xml", PATCH.as_bytes().to_vec()),
+                ("boot.img", vec![1; 2000]),
+                ("NON-HLOS.bin", vec![2; 1500]),
+                ("userdata.img", vec![3; 512]),
+            ],
+        )?;
+        let mut plan = FlashPlan::load(&dir)?;
+        plan.select(&[], &split_labels("userdata, persist"));
+        assert_eq!(plan.sources, vec!["patch0.xml", "rawprogram0.xml"]);
+        assert_eq!(plan.patches.len(), 1);
+        let labels: Vec<&str> = plan.included().map(|e| e.label.as_str()).collect();
+        assert_eq!(labels, vec!["boot", "modem", "dtbo"]);
+        assert_eq!(
+            plan.problems(),
+            vec![
+                "LUN 0 modem: NON-HLOS.bin is 1500 bytes but the partition holds 1024",
+                "LUN 0 dtbo: dtbo.img not found",
+            ]
+        );
+        let text = plan.render();
+        assert!(
+            text.contains("misc") && text.contains("-- no image"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("userdata.img (0.0 MiB) -- excluded"),
+            "{}",
+            text
+        );
+
+        plan.select(
+            &split_labels("boot userdata"),
+            &split_labels("userdata,persist"),
+        );
+        assert!(plan.problems().is_empty(), "{:?}", plan.problems());
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlap_and_unsparse_preference() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = package(
+            "foem_rawprogram_overlap",
+            &[
+                (
+                    "rawprogram0.xml",
+                    b"<data><program sparse=\"true\" /></data>".to_vec(),
+                ),
+                (
+                    "rawprogram_unsparse0.xml",
+                    rawprogram(&[
+                        program("a", "a.img", "10", 4),
+                        program("b", "b.img", "12", 4),
+                    ]),
+                ),
+                ("a.img", vec![0; 512]),
+                ("b.img", vec![0; 512]),
+            ],
+        )?;
+        let plan = FlashPlan::load(&dir)?;
+        assert_eq!(plan.sources, vec!["rawprogram_unsparse0.xml"]);
+        assert_eq!(plan.problems(), vec!["LUN 0: a and b overlap at sector 12"]);
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_ranges_resolve_calibration_partitions() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let dir = package(
+            "foem_rawprogram_backup_ranges",
+            &[(
+                "rawprogram0.xml",
+                rawprogram(&[
+                    program("modemst1", "", "40", 8),
+                    program("boot", "boot.img", "48", 4),
+                    program("fsg", "", "NUM_DISKSECTORS-20.", 8),
+                    program("userdata", "", "100", 0),
+                ]),
+            )],
+        )?;
+        let plan = FlashPlan::load(&dir)?;
+        let _ = fs::remove_dir_all(&dir);
+        let mut asked = Vec::new();
+        let ranges = plan.backup_ranges(&["MODEMST1", "fsg", "userdata"], &mut |lun| {
+            asked.push(lun);
+            Some(200)
+        });
+        let found: Vec<(&str, u64, u64)> = ranges
+            .iter()
+            .map(|r| (r.name.as_str(), r.start, r.count))
+            .collect();
+        assert_eq!(found, [("modemst1", 40, 8), ("fsg", 180, 8)]);
+        assert_eq!(asked, [0]);
+        assert!(plan.backup_ranges(&["fsg"], &mut |_| None).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_writes_images_and_patches() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = package(
+            "foem_rawprogram_execute",
+            &[
+                (
+                    "rawprogram0.xml",
+                    rawprogram(&[
+                        program("boot", "boot.img", "2", 4),
+                        program("gpt_backup", "gpt.bin", "NUM_DISKSECTORS-2.", 2),
+                    ]),
+                ),
+                ("patch0.xml", PATCH.as_bytes().to_vec()),
+                ("boot.img", vec![0xB0; 700]),
+                ("gpt.bin", vec![0x6B; 1024]),
+            ],
+        )?;
+        let plan = FlashPlan::load(&dir)?;
+        let mut client = FirehoseClient::new(SimProgrammer::new(16), Memory::Emmc);
+        client.configure()?;
+        let mut seen = Vec::new();
+        let report = plan.execute(&mut client, &mut |label, done, total| {
+            seen.push((label.to_string(), done, total))
+        })?;
+        assert!(
+            report.contains("LUN 0 gpt_backup <- gpt.bin (1024 bytes at sector 14)"),
+            "{}",
+            report
+        );
+        assert!(report.contains("Applied 1 patches."));
+        assert_eq!(seen.last(), Some(&("gpt_backup".to_string(), 1024, 1024)));
+        let (disk, commands) = client.sim_state();
+        assert!(disk[1024..1724].iter().all(|&b| b == 0xB0));
+        assert!(disk[7 * 512..8 * 512].iter().all(|&b| b == 7));
+        assert!(disk[14 * 512..].iter().all(|&b| b == 0x6B));
+        assert_eq!(commands.last().map(String::as_str), Some("patch"));
+
+        let mut client = FirehoseClient::new(SimProgrammer::new(16), Memory::Ufs);
+        let err = plan.execute(&mut client, &mut |_, _, _| {}).unwrap_err();
+        assert!(
+            err.contains("512-byte sectors but the device uses 4096"),
+            "{}",
+            err
+        );
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
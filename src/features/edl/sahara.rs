@@ -0,0 +1,658 @@
+/// Sahara, the protocol of the Qualcomm primary bootloader in EDL mode.
+///
+/// Every packet starts with a little-endian `(command, length)` header where
+/// `length` includes the header. The device opens with a hello; the host
+/// answers with the mode it wants. Command mode executes read-only queries
+/// (serial number, MSM hardware ID, OEM public key hash, SBL version) and
+/// image transfer mode serves the device's read requests from a programmer
+/// image until the device reports the end of the transfer.
+use super::{recv_exact, Transport};
+
+use std::time::Duration;
+
+const HELLO_REQ: u32 = 0x01;
+const HELLO_RSP: u32 = 0x02;
+const READ_DATA: u32 = 0x03;
+const END_IMAGE_TX: u32 = 0x04;
+const DONE_REQ: u32 = 0x05;
+const DONE_RSP: u32 = 0x06;
+const CMD_READY: u32 = 0x0B;
+const CMD_SWITCH_MODE: u32 = 0x0C;
+const CMD_EXEC: u32 = 0x0D;
+const CMD_EXEC_RSP: u32 = 0x0E;
+const CMD_EXEC_DATA: u32 = 0x0F;
+const READ_DATA_64: u32 = 0x12;
+
+const EXEC_SERIAL_NUM_READ: u32 = 0x01;
+const EXEC_MSM_HW_ID_READ: u32 = 0x02;
+const EXEC_OEM_PK_HASH_READ: u32 = 0x03;
+const EXEC_GET_SOFTWARE_VERSION_SBL: u32 = 0x07;
+
+const HOST_VERSION: u32 = 2;
+const HOST_VERSION_COMPATIBLE: u32 = 1;
+const HELLO_LEN: u32 = 0x30;
+const MAX_PACKET_LEN: u32 = 0x1000;
+/// Command-mode replies (serial, HW ID, PK hash) are a few hundred bytes.
+const MAX_EXEC_LEN: u32 = 0x1000;
+const IMAGE_TX_COMPLETE: u32 = 1;
+
+/// Modes the host can request in its hello response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    ImageTxPending = 0,
+    Command = 3,
+}
+
+/// Name of a Sahara status code as reported in END_IMAGE_TX.
+pub fn status_name(status: u32) -> &'static str {
+    match status {
+        0x00 => "success",
+        0x01 => "invalid command",
+        0x02 => "protocol mismatch",
+        0x03 => "invalid target protocol",
+        0x04 => "invalid host protocol",
+        0x05 => "invalid packet size",
+        0x06 => "unexpected image ID",
+        0x07 => "invalid header size",
+        0x08 => "invalid data size",
+        0x09 => "invalid image type",
+        0x0A => "invalid transmit length",
+        0x0B => "invalid receive length",
+        0x0C => "general transmit/receive error",
+        0x0D => "read data error",
+        0x0E => "unsupported number of program headers",
+        0x0F => "invalid program header size",
+        0x10 => "multiple shared segments",
+        0x11 => "uninitialized program header location",
+        0x12 => "invalid destination address",
+        0x13 => "invalid image header data size",
+        0x14 => "invalid ELF header",
+        0x15 => "unknown host error",
+        0x16 => "receive timeout",
+        0x17 => "transmit timeout",
+        0x18 => "invalid host mode",
+        0x19 => "invalid memory read",
+        0x1A => "invalid data size request",
+        0x1B => "memory debug not supported",
+        0x1C => "invalid mode switch",
+        0x1D => "command execution failure",
+        0x1E => "invalid command parameter",
+        0x1F => "command unsupported",
+        0x20 => "invalid client command",
+        0x21 => "hash table authentication failure (programmer not signed for this device)",
+        0x22 => "hash verification failure",
+        0x23 => "hash table not found",
+        _ => "unknown status",
+    }
+}
+
+/// The chip identification returned by `MSM_HW_ID_READ`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HwId {
+    pub raw: u64,
+    pub msm_id: u32,
+    pub oem_id: u16,
+    pub model_id: u16,
+}
+
+impl HwId {
+    pub fn from_raw(raw: u64) -> Self {
+        Self {
+            raw,
+            msm_id: ((raw >> 32) & 0x00FF_FFFF) as u32,
+            oem_id: (raw >> 16) as u16,
+            model_id: raw as u16,
+        }
+    }
+}
+
+/// What the primary bootloader reports about the device. Queries the
+/// device rejects are left empty and explained in `errors`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub sahara_version: u32,
+    pub serial: Option<u32>,
+    pub hw_id: Option<HwId>,
+    /// Lowercase hex SHA-256 or SHA-384 of the OEM root key.
+    pub pk_hash: Option<String>,
+    pub sbl_version: Option<u32>,
+    pub errors: Vec<String>,
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "  Sahara version: {}", self.sahara_version)?;
+        if let Some(serial) = self.serial {
+            writeln!(f, "  Serial: 0x{:08X}", serial)?;
+        }
+        if let Some(hw) = self.hw_id {
+            writeln!(
+                f,
+                "  HW ID: 0x{:016X} (MSM ID 0x{:06X}, OEM ID 0x{:04X}, model 0x{:04X})",
+                hw.raw, hw.msm_id, hw.oem_id, hw.model_id
+            )?;
+        }
+        if let Some(hash) = &self.pk_hash {
+            writeln!(f, "  OEM PK hash: {}", hash)?;
+        }
+        if let Some(sbl) = self.sbl_version {
+            writeln!(f, "  SBL version: 0x{:08X}", sbl)?;
+        }
+        for e in &self.errors {
+            writeln!(f, "  {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// The PK hash response repeats the hash to fill the buffer; keep one copy.
+fn pk_hash_from(data: &[u8]) -> String {
+    let len = [32, 48]
+        .into_iter()
+        .find(|&n| data.len() >= 2 * n && data[..n] == data[n..2 * n])
+        .unwrap_or(if data.len() >= 48 {
+            48
+        } else {
+            data.len().min(32)
+        });
+    hex::encode(&data[..len])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Packet {
+    command: u32,
+    body: Vec<u8>,
+}
+
+impl Packet {
+    fn u32_at(&self, index: usize) -> Result<u32, String> {
+        let at = index * 4;
+        self.body
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| format!("Sahara packet 0x{:02X} is too short", self.command))
+    }
+
+    fn u64_at(&self, index: usize) -> Result<u64, String> {
+        Ok(self.u32_at(index)? as u64 | (self.u32_at(index + 1)? as u64) << 32)
+    }
+}
+
+fn encode(command: u32, fields: &[u32]) -> Vec<u8> {
+    let len = 8 + 4 * fields.len() as u32;
+    [command, len]
+        .iter()
+        .chain(fields)
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+pub struct SaharaClient<T: Transport> {
+    transport: T,
+    timeout: Duration,
+    version: u32,
+}
+
+impl<T: Transport> SaharaClient<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            timeout: Duration::from_secs(5),
+            version: HOST_VERSION,
+        }
+    }
+
+    /// Hand the transport on, e.g. to Firehose once the programmer runs.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn send(&mut self, command: u32, fields: &[u32]) -> Result<(), String> {
+        self.transport.send(&encode(command, fields))
+    }
+
+    /// `Ok(None)` when the device sent nothing within the timeout.
+    fn try_recv_packet(&mut self) -> Result<Option<Packet>, String> {
+        let mut first = [0u8; 1];
+        if self.transport.recv(&mut first, self.timeout)? == 0 {
+            return Ok(None);
+        }
+        let rest = recv_exact(&mut self.transport, 7, self.timeout)?;
+        let header: Vec<u8> = first.iter().chain(&rest).copied().collect();
+        let command = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if !(8..=MAX_PACKET_LEN).contains(&len) {
+            return Err(format!(
+                "Sahara packet 0x{:02X} has invalid length {}",
+                command, len
+            ));
+        }
+        let body = recv_exact(&mut self.transport, len as usize - 8, self.timeout)?;
+        Ok(Some(Packet { command, body }))
+    }
+
+    fn recv_packet(&mut self) -> Result<Packet, String> {
+        self.try_recv_packet()?.ok_or_else(|| {
+            format!(
+                "EDL device did not respond within {}s",
+                self.timeout.as_secs_f32()
+            )
+        })
+    }
+
+    fn expect(&mut self, command: u32) -> Result<Packet, String> {
+        let packet = self.recv_packet()?;
+        if packet.command == END_IMAGE_TX && command != END_IMAGE_TX {
+            let status = packet.u32_at(1)?;
+            return Err(format!(
+                "Device ended the session: {} (0x{:02X})",
+                status_name(status),
+                status
+            ));
+        }
+        if packet.command != command {
+            return Err(format!(
+                "Expected Sahara packet 0x{:02X}, got 0x{:02X}",
+                command, packet.command
+            ));
+        }
+        Ok(packet)
+    }
+
+    /// Wait for the device's hello and answer it, asking for `mode`. A
+    /// device whose hello was consumed by an earlier session is still
+    /// waiting for the response, so one is sent even if no hello arrives.
+    pub fn hello(&mut self, mode: Mode) -> Result<(), String> {
+        match self.try_recv_packet()? {
+            Some(packet) if packet.command == HELLO_REQ => {
+                let device_version = packet.u32_at(0)?;
+                let compatible = packet.u32_at(1)?;
+                if compatible > HOST_VERSION {
+                    return Err(format!(
+                        "Device needs Sahara version {} or newer; FOEM supports {}",
+                        compatible, HOST_VERSION
+                    ));
+                }
+                self.version = device_version;
+            }
+            Some(packet) => {
+                return Err(format!(
+                    "Expected Sahara hello, got packet 0x{:02X}; power-cycle the device and retry",
+                    packet.command
+                ));
+            }
+            None => {}
+        }
+        let mut fields = [0u32; (HELLO_LEN as usize - 8) / 4];
+        fields[..4].copy_from_slice(&[HOST_VERSION, HOST_VERSION_COMPATIBLE, 0, mode as u32]);
+        self.send(HELLO_RSP, &fields)
+    }
+
+    /// Run one command-mode query and return its data.
+    pub fn exec(&mut self, client_command: u32) -> Result<Vec<u8>, String> {
+        self.send(CMD_EXEC, &[client_command])?;
+        let rsp = self.expect(CMD_EXEC_RSP)?;
+        let (command, len) = (rsp.u32_at(0)?, rsp.u32_at(1)?);
+        if command != client_command {
+            return Err(format!(
+                "Sahara answered command 0x{:02X} instead of 0x{:02X}",
+                command, client_command
+            ));
+        }
+        if len > MAX_EXEC_LEN {
+            return Err(format!(
+                "Sahara announced a {} byte reply to command 0x{:02X}; refusing more than {}",
+                len, client_command, MAX_EXEC_LEN
+            ));
+        }
+        self.send(CMD_EXEC_DATA, &[client_command])?;
+        recv_exact(&mut self.transport, len as usize, self.timeout)
+    }
+
+    /// After `hello(Mode::Command)`: read everything the device reports.
+    pub fn read_info(&mut self) -> Result<DeviceInfo, String> {
+        self.expect(CMD_READY)?;
+        let mut info = DeviceInfo {
+            sahara_version: self.version,
+            ..Default::default()
+        };
+        let queries: [(&str, u32); 4] = [
+            ("Serial", EXEC_SERIAL_NUM_READ),
+            ("HW ID", EXEC_MSM_HW_ID_READ),
+            ("OEM PK hash", EXEC_OEM_PK_HASH_READ),
+            ("SBL version", EXEC_GET_SOFTWARE_VERSION_SBL),
+        ];
+        for (label, command) in queries {
+            let data = match self.exec(command) {
+                Ok(data) => data,
+                // A rejected query ends command mode; re-enter it for the rest.
+                Err(e) if e.starts_with("Device ended the session") => {
+                    info.errors.push(format!("{}: {}", label, e));
+                    self.switch_mode(Mode::Command)?;
+                    self.hello(Mode::Command)?;
+                    self.expect(CMD_READY)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let word = |n: usize| -> Option<[u8; 8]> {
+                let mut buf = [0u8; 8];
+                buf[..n].copy_from_slice(data.get(..n)?);
+                Some(buf)
+            };
+            match command {
+                EXEC_SERIAL_NUM_READ => {
+                    info.serial = word(4).map(|b| u64::from_le_bytes(b) as u32);
+                }
+                EXEC_MSM_HW_ID_READ => {
+                    info.hw_id = word(8).map(|b| HwId::from_raw(u64::from_le_bytes(b)));
+                }
+                EXEC_OEM_PK_HASH_READ if !data.is_empty() => {
+                    info.pk_hash = Some(pk_hash_from(&data));
+                }
+                EXEC_GET_SOFTWARE_VERSION_SBL => {
+                    info.sbl_version = word(4).map(|b| u64::from_le_bytes(b) as u32);
+                }
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    /// In command mode, ask the device to restart Sahara in `mode`; it
+    /// answers with a new hello.
+    pub fn switch_mode(&mut self, mode: Mode) -> Result<(), String> {
+        self.send(CMD_SWITCH_MODE, &[mode as u32])
+    }
+
+    /// After `hello(Mode::ImageTxPending)`: serve the device's reads from
+    /// `image` until it reports the transfer finished. `progress` receives
+    /// the bytes sent so far and the image size.
+    pub fn upload(
+        &mut self,
+        image: &[u8],
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<(), String> {
+        let total = image.len() as u64;
+        let mut sent = 0u64;
+        loop {
+            let packet = self.recv_packet()?;
+            let (offset, len) = match packet.command {
+                READ_DATA => (packet.u32_at(1)? as u64, packet.u32_at(2)? as u64),
+                READ_DATA_64 => (packet.u64_at(2)?, packet.u64_at(4)?),
+                END_IMAGE_TX => {
+                    let status = packet.u32_at(1)?;
+                    if status != 0 {
+                        return Err(format!(
+                            "Programmer rejected: {} (0x{:02X})",
+                            status_name(status),
+                            status
+                        ));
+                    }
+                    break;
+                }
+                other => {
+                    return Err(format!(
+                        "Unexpected Sahara packet 0x{:02X} during image transfer",
+                        other
+                    ));
+                }
+            };
+            let chunk = offset
+                .checked_add(len)
+                .filter(|end| *end <= total)
+                .map(|end| &image[offset as usize..end as usize])
+                .ok_or_else(|| {
+                    format!(
+                        "Device asked for bytes {}..{} of a {} byte image; wrong programmer?",
+                        offset,
+                        offset.saturating_add(len),
+                        total
+                    )
+                })?;
+            self.transport.send(chunk)?;
+            sent += len;
+            progress(sent.min(total), total);
+        }
+        self.send(DONE_REQ, &[])?;
+        let done = self.expect(DONE_RSP)?;
+        if done.u32_at(0)? != IMAGE_TX_COMPLETE {
+            return Err("Device expects more images after the programmer".into());
+        }
+        Ok(())
+    }
+}
+
+/// Identify the device and, given a programmer, upload it. Returns the
+/// device information and the transport, ready for Firehose when a
+/// programmer was loaded.
+pub fn identify_and_load<T: Transport>(
+    transport: T,
+    programmer: Option<&[u8]>,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(DeviceInfo, T), String> {
+    let mut client = SaharaClient::new(transport);
+    client.hello(Mode::Command)?;
+    let info = client.read_info()?;
+    // Go back to image transfer so the device is ready for a programmer,
+    // now or in a later session.
+    client.switch_mode(Mode::ImageTxPending)?;
+    if let Some(image) = programmer {
+        client.hello(Mode::ImageTxPending)?;
+        client.upload(image, progress)?;
+    }
+    Ok((info, client.into_transport()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, VecDeque};
+
+    /// Simulated primary bootloader. Reads are served from `outbox`; every
+    /// host packet is answered the way a device would.
+    #[derive(Debug)]
+    struct SimDevice {
+        outbox: VecDeque<u8>,
+        queries: BTreeMap<u32, Vec<u8>>,
+        /// Read requests (offset, length) issued after an image-mode hello.
+        reads: VecDeque<(u32, u32)>,
+        end_status: u32,
+        expecting: usize,
+        received: Vec<u8>,
+        host_packets: Vec<u32>,
+    }
+
+    impl SimDevice {
+        fn new(queries: &[(u32, &[u8])], reads: &[(u32, u32)]) -> Self {
+            let mut device = Self {
+                outbox: VecDeque::new(),
+                queries: queries.iter().map(|(c, d)| (*c, d.to_vec())).collect(),
+                reads: reads.iter().copied().collect(),
+                end_status: 0,
+                expecting: 0,
+                received: Vec::new(),
+                host_packets: Vec::new(),
+            };
+            device.hello();
+            device
+        }
+
+        fn push(&mut self, command: u32, fields: &[u32]) {
+            self.outbox.extend(encode(command, fields));
+        }
+
+        fn hello(&mut self) {
+            self.push(HELLO_REQ, &[2, 1, 0x400, 0, 0, 0, 0, 0, 0, 0]);
+        }
+
+        fn next_read(&mut self) {
+            match self.reads.pop_front() {
+                Some((offset, len)) => {
+                    self.expecting = len as usize;
+                    self.push(READ_DATA, &[0x0D, offset, len]);
+                }
+                None => self.push(END_IMAGE_TX, &[0x0D, self.end_status]),
+            }
+        }
+    }
+
+    impl Transport for SimDevice {
+        fn send(&mut self, data: &[u8]) -> Result<(), String> {
+            if self.expecting > 0 {
+                assert_eq!(data.len(), self.expecting, "image chunk size");
+                self.received.extend_from_slice(data);
+                self.expecting = 0;
+                self.next_read();
+                return Ok(());
+            }
+            let field = |i: usize| {
+                data.get(i * 4..i * 4 + 4)
+                    .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            };
+            let command = field(0);
+            assert_eq!(field(1) as usize, data.len(), "packet length");
+            self.host_packets.push(command);
+            match command {
+                HELLO_RSP if field(5) == Mode::Command as u32 => self.push(CMD_READY, &[]),
+                HELLO_RSP => self.next_read(),
+                CMD_EXEC => match self.queries.get(&field(2)) {
+                    Some(data) => {
+                        let len = data.len() as u32;
+                        self.push(CMD_EXEC_RSP, &[field(2), len]);
+                    }
+                    None => self.push(END_IMAGE_TX, &[0, 0x1F]),
+                },
+                CMD_EXEC_DATA => {
+                    let data = self.queries[&field(2)].clone();
+                    self.outbox.extend(data);
+                }
+                CMD_SWITCH_MODE => self.hello(),
+                DONE_REQ => self.push(DONE_RSP, &[IMAGE_TX_COMPLETE]),
+                other => panic!("unexpected host packet 0x{:02X}", other),
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize, String> {
+            let n = buf.len().min(self.outbox.len());
+            for (slot, byte) in buf.iter_mut().zip(self.outbox.drain(..n)) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    const HWID: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0xE1, 0x60, 0x04, 0x00];
+
+    fn full_queries() -> Vec<(u32, &'static [u8])> {
+        vec![
+            (EXEC_SERIAL_NUM_READ, &[0x78, 0x56, 0x34, 0x12]),
+            (EXEC_MSM_HW_ID_READ, &HWID),
+            (EXEC_OEM_PK_HASH_READ, &[0xAB; 64]),
+            (EXEC_GET_SOFTWARE_VERSION_SBL, &[2, 0, 0, 0]),
+        ]
+    }
+
+    #[test]
+    fn test_identify_reads_device_info() -> Result<(), Box<dyn std::error::Error>> {
+        let device = SimDevice::new(&full_queries(), &[]);
+        let (info, device) = identify_and_load(device, None, &mut |_, _| {})?;
+        assert_eq!(info.serial, Some(0x1234_5678));
+        let hw = info.hw_id.ok_or("no HW ID")?;
+        assert_eq!((hw.msm_id, hw.oem_id, hw.model_id), (0x0460E1, 0, 0));
+        assert_eq!(info.pk_hash, Some("ab".repeat(32)));
+        assert_eq!(info.sbl_version, Some(2));
+        assert!(info.errors.is_empty());
+        assert!(info.to_string().contains("MSM ID 0x0460E1"));
+        // The device was left waiting for a programmer.
+        assert_eq!(device.host_packets.last(), Some(&CMD_SWITCH_MODE));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_query_is_reported_and_skipped() -> Result<(), Box<dyn std::error::Error>> {
+        let mut queries = full_queries();
+        queries.retain(|(c, _)| *c != EXEC_MSM_HW_ID_READ);
+        let device = SimDevice::new(&queries, &[]);
+        let (info, _) = identify_and_load(device, None, &mut |_, _| {})?;
+        assert_eq!(info.hw_id, None);
+        assert_eq!(
+            info.errors,
+            vec!["HW ID: Device ended the session: command unsupported (0x1F)"]
+        );
+        assert_eq!(info.sbl_version, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_query_reply_is_refused() {
+        let oversized = vec![0u8; MAX_EXEC_LEN as usize + 1];
+        let mut queries = full_queries();
+        queries[0] = (EXEC_SERIAL_NUM_READ, &oversized);
+        let device = SimDevice::new(&queries, &[]);
+        let result = identify_and_load(device, None, &mut |_, _| {});
+        assert_eq!(
+            result.err().as_deref(),
+            Some("Sahara announced a 4097 byte reply to command 0x01; refusing more than 4096")
+        );
+    }
+
+    #[test]
+    fn test_upload_serves_read_requests() -> Result<(), Box<dyn std::error::Error>> {
+        let image: Vec<u8> = (0..=255).collect();
+        let device = SimDevice::new(&full_queries(), &[(0, 52), (128, 100), (52, 76)]);
+        let mut seen = Vec::new();
+        let (_, device) = identify_and_load(device, Some(&image), &mut |sent, total| {
+            seen.push((sent, total))
+        })?;
+        let mut expected = image[..52].to_vec();
+        expected.extend_from_slice(&image[128..228]);
+        expected.extend_from_slice(&image[52..128]);
+        assert_eq!(device.received, expected);
+        assert_eq!(seen, vec![(52, 256), (152, 256), (228, 256)]);
+        assert_eq!(device.host_packets.last(), Some(&DONE_REQ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_upload_errors() {
+        let image = vec![0u8; 64];
+        let device = SimDevice::new(&full_queries(), &[(32, 64)]);
+        let err = identify_and_load(device, Some(&image), &mut |_, _| {}).unwrap_err();
+        assert_eq!(
+            err,
+            "Device asked for bytes 32..96 of a 64 byte image; wrong programmer?"
+        );
+
+        let mut device = SimDevice::new(&full_queries(), &[(0, 64)]);
+        device.end_status = 0x21;
+        let err = identify_and_load(device, Some(&image), &mut |_, _| {}).unwrap_err();
+        assert!(
+            err.starts_with("Programmer rejected: hash table authentication failure"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_hello_without_device_hello_still_responds() -> Result<(), Box<dyn std::error::Error>> {
+        let mut device = SimDevice::new(&full_queries(), &[]);
+        device.outbox.clear();
+        let mut client = SaharaClient::new(device);
+        client.timeout = Duration::from_millis(20);
+        client.hello(Mode::Command)?;
+        assert_eq!(client.read_info()?.serial, Some(0x1234_5678));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pk_hash_lengths() {
+        assert_eq!(pk_hash_from(&[1; 64]), "01".repeat(32));
+        let mut sha384 = vec![2u8; 48];
+        sha384[0] = 9;
+        sha384.extend_from_slice(&sha384.clone());
+        assert_eq!(pk_hash_from(&sha384).len(), 96);
+        assert_eq!(pk_hash_from(&[3; 32]), "03".repeat(32));
+    }
+}
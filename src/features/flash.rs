@@ -2,9 +2,18 @@
 ///
 /// Supports Qualcomm EDL (9008), MediaTek BROM/SP Flash,
 /// Samsung Download/Odin mode, and standard Fastboot flashing.
-use super::{adb, adb_shell, fastboot, Manufacturer};
+use super::edl::backup::{self, SectorRange};
+use super::edl::firehose::{self, FirehoseClient, Memory, Power};
+use super::edl::programmers::{self, ProgrammerLibrary};
+use super::edl::rawprogram::{self, FlashPlan};
+use super::edl::{self, sahara};
+use super::{adb, adb_shell, fastboot, vault, Manufacturer};
 use crate::exec::normalize_local_path;
 
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+
 // -- EDL (Emergency Download) Mode --
 
 /// Reboot device into EDL mode (Qualcomm 9008).
@@ -24,22 +33,709 @@ pub fn enter_edl_mode(serial: &str) -> String {
         .to_string()
 }
 
-/// Flash via EDL using a firehose programmer.
-pub fn flash_edl(_serial: &str, programmer_path: &str) -> String {
+/// Read the chip identity of a device in EDL mode over Sahara and suggest
+/// programmers from the local library.
+/// `progress` receives (stage, done, total); identification has no byte count.
+pub fn edl_device_info(progress: &mut dyn FnMut(&str, u64, u64)) -> String {
+    progress("Sahara handshake", 0, 0);
+    let result =
+        edl::open_edl_port().and_then(|port| sahara::identify_and_load(port, None, &mut |_, _| {}));
+    match result {
+        Ok((info, _)) => {
+            remember_sahara_device(&info);
+            let dir = programmers::programmers_dir();
+            let library = ProgrammerLibrary::load(&dir);
+            format!(
+                "EDL Device:\n{}{}",
+                info,
+                library.render_suggestions(&info, &dir)
+            )
+        }
+        Err(e) => format!("EDL Device:\n  {}", e),
+    }
+}
+
+/// The programmers in `~/.foem/programmers` with the chip and key each is
+/// signed for.
+pub fn programmer_library() -> String {
+    let dir = programmers::programmers_dir();
+    ProgrammerLibrary::load(&dir).render(&dir)
+}
+
+/// Identify the device in EDL mode and upload a Firehose programmer.
+/// `progress` receives (stage, bytes uploaded, programmer size).
+pub fn flash_edl(programmer_path: &str, progress: &mut dyn FnMut(&str, u64, u64)) -> String {
     if programmer_path.is_empty() {
-        return "EDL Flash:\n  Firehose programmer (.mbn/.elf) path is required.\n  \
-                These are chipset-specific files (e.g., prog_emmc_firehose_8953.mbn)."
-            .to_string();
+        return format!(
+            "EDL Flash:\n  Firehose programmer (.mbn/.elf) path is required.\n  \
+             These are chipset-specific files (e.g., prog_emmc_firehose_8953.mbn).\n  \
+             Put programmers in {} and use Read EDL Info to find one that fits.",
+            programmers::programmers_dir().display()
+        );
+    }
+    let path = normalize_local_path(programmer_path);
+    let image = match std::fs::read(&path) {
+        Ok(image) => image,
+        Err(e) => return format!("EDL Flash:\n  Cannot read programmer {}: {}", path, e),
+    };
+    let mut uploaded = 0;
+    progress("Sahara handshake", 0, image.len() as u64);
+    let result = edl::open_edl_port().and_then(|port| {
+        sahara::identify_and_load(port, Some(&image), &mut |sent, total| {
+            uploaded = sent;
+            progress("Upload programmer", sent, total)
+        })
+    });
+    match result {
+        Ok((info, _)) => {
+            remember_sahara_device(&info);
+            format!(
+                "EDL Flash:\n{}  Programmer: {}\n  Uploaded {} bytes; Firehose is starting.",
+                info, path, uploaded
+            )
+        }
+        Err(e) => format!(
+            "EDL Flash:\n  Programmer: {}\n  Failed after {} bytes: {}",
+            path, uploaded, e
+        ),
+    }
+}
+
+/// What Sahara last reported about the device in EDL. Firehose cannot be
+/// asked for the chip serial, so backups taken during Firehose writes are
+/// keyed on the identity read before the programmer was loaded.
+fn sahara_device() -> &'static Mutex<Option<sahara::DeviceInfo>> {
+    static DEVICE: OnceLock<Mutex<Option<sahara::DeviceInfo>>> = OnceLock::new();
+    DEVICE.get_or_init(Default::default)
+}
+
+fn remember_sahara_device(info: &sahara::DeviceInfo) {
+    *sahara_device().lock().unwrap_or_else(|e| e.into_inner()) = Some(info.clone());
+}
+
+/// Vault identity of the device the Firehose programmer was loaded on.
+fn edl_device_identity() -> Result<vault::DeviceIdentity, String> {
+    let info = sahara_device()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or(
+            "The device identity is unknown, so nothing can be backed up. \
+             Load the programmer from FOEM (Load Programmer) so its serial and HW ID are read first.",
+        )?;
+    backup::device_identity(&info)
+}
+
+/// Open the EDL port and configure the Firehose programmer running on it.
+fn firehose_session(memory: Memory) -> Result<FirehoseClient<edl::SerialTransport>, String> {
+    let mut client = FirehoseClient::new(edl::open_edl_port()?, memory);
+    client
+        .configure()
+        .map_err(|e| format!("{}\n  Is a Firehose programmer running? Load one first.", e))?;
+    Ok(client)
+}
+
+/// Storage details of LUN `lun` as reported by the Firehose programmer.
+pub fn edl_storage_info(memory: Memory, lun: u32) -> String {
+    let result = firehose_session(memory).and_then(|mut client| {
+        let info = client.storage_info(lun)?;
+        Ok((info, client.sector_size(), client.max_payload()))
+    });
+    match result {
+        Ok((info, sector, payload)) => format!(
+            "EDL Storage (LUN {}):\n{}  Sector size: {} bytes\n  Max payload: {} bytes",
+            lun, info, sector, payload
+        ),
+        Err(e) => format!("EDL Storage:\n  {}", e),
+    }
+}
+
+/// Dump `count` sectors from `start` on LUN `lun` to `dump_path`.
+/// `progress` receives (stage, bytes done, bytes total).
+pub fn edl_read_sectors(
+    memory: Memory,
+    lun: u32,
+    start: u64,
+    count: u64,
+    dump_path: &str,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> String {
+    if dump_path.is_empty() || count == 0 {
+        return "EDL Read:\n  A dump path and a sector count are required.".to_string();
+    }
+    let path = normalize_local_path(dump_path);
+    let mut last = (0, 0);
+    let result = firehose_session(memory).and_then(|mut client| {
+        let mut file =
+            std::fs::File::create(&path).map_err(|e| format!("Cannot create {}: {}", path, e))?;
+        client.read(lun, start, count, &mut file, &mut |done, total| {
+            last = (done, total);
+            progress("Read", done, total)
+        })
+    });
+    match result {
+        Ok(()) => format!(
+            "EDL Read:\n  LUN {} sectors {}..{} -> {}\n  {}",
+            lun,
+            start,
+            start + count,
+            path,
+            firehose::progress_label(last.0, last.1)
+        ),
+        Err(e) => format!(
+            "EDL Read:\n  Failed at {}: {}",
+            firehose::progress_label(last.0, last.1),
+            e
+        ),
+    }
+}
+
+/// A Firehose operation that changes storage. The UI shows `describe` and
+/// waits for confirmation before handing it to `edl_write`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EdlWrite {
+    /// Write the image at `path` starting at sector `start`.
+    Image {
+        lun: u32,
+        start: u64,
+        path: String,
+    },
+    Erase {
+        lun: u32,
+        start: u64,
+        count: u64,
+    },
+    /// Apply the on-device (`filename="DISK"`) entries of a patch XML file,
+    /// which fix up the GPT headers after partitions have been written.
+    Patch {
+        path: String,
+    },
+    /// Write an EDL sector snapshot from the vault back where it was read.
+    Restore {
+        dir: std::path::PathBuf,
+    },
+}
+
+impl EdlWrite {
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Image { .. } => "EDL Write",
+            Self::Erase { .. } => "EDL Erase",
+            Self::Patch { .. } => "EDL Patch",
+            Self::Restore { .. } => "EDL Restore",
+        }
+    }
+
+    /// Sectors the operation changes with `sector_size`-byte sectors.
+    /// Patches only change the GPT and have no ranges of their own.
+    fn ranges(&self, sector_size: u64) -> Result<Vec<SectorRange>, String> {
+        let (lun, start, count) = match self {
+            Self::Image { lun, start, path } => {
+                if path.is_empty() {
+                    return Err("Image path is required.".into());
+                }
+                let path = normalize_local_path(path);
+                let len = std::fs::metadata(&path)
+                    .map_err(|e| format!("Cannot read {}: {}", path, e))?
+                    .len();
+                if len == 0 {
+                    return Err(format!("{} is empty.", path));
+                }
+                (*lun, *start, len.div_ceil(sector_size))
+            }
+            Self::Erase { lun, start, count } => {
+                if *count == 0 {
+                    return Err("A sector count is required.".into());
+                }
+                (*lun, *start, *count)
+            }
+            Self::Patch { .. } => return Ok(Vec::new()),
+            Self::Restore { dir } => {
+                return Ok(backup::restore_files(dir, None, sector_size)?
+                    .into_iter()
+                    .map(|(range, _)| range)
+                    .collect())
+            }
+        };
+        let end = start
+            .checked_add(count)
+            .ok_or("The sector range does not fit on any disk.")?;
+        Ok(vec![SectorRange {
+            name: format!("sectors_{}-{}", start, end),
+            lun,
+            start,
+            count,
+        }])
+    }
+
+    fn patches(&self) -> Result<Vec<firehose::Patch>, String> {
+        let Self::Patch { path } = self else {
+            return Ok(Vec::new());
+        };
+        if path.is_empty() {
+            return Err("Patch XML path is required.".into());
+        }
+        let path = normalize_local_path(path);
+        std::fs::read_to_string(&path)
+            .map_err(|e| format!("Cannot read {}: {}", path, e))
+            .and_then(|text| firehose::Patch::parse_file(&text))
+    }
+
+    /// What the operation will change, for the confirmation prompt.
+    pub fn describe(&self, memory: Memory) -> Result<String, String> {
+        let sector_size = memory.sector_size() as u64;
+        let ranges = self.ranges(sector_size)?;
+        let target = match (self, ranges.as_slice()) {
+            (Self::Image { path, .. }, [r]) => format!(
+                "Write {} to {} LUN {} sectors {}..{} ({} bytes).",
+                normalize_local_path(path),
+                memory.name(),
+                r.lun,
+                r.start,
+                r.start + r.count,
+                r.count * sector_size
+            ),
+            (Self::Erase { .. }, [r]) => format!(
+                "Erase {} LUN {} sectors {}..{}.",
+                memory.name(),
+                r.lun,
+                r.start,
+                r.start + r.count
+            ),
+            (Self::Patch { path }, _) => format!(
+                "Apply {} GPT patches from {}.",
+                self.patches()?.len(),
+                normalize_local_path(path)
+            ),
+            (Self::Restore { dir }, ranges) => {
+                let entry = vault::load_entry(dir)?;
+                let mut text = format!(
+                    "Write back the EDL snapshot {} of {} to {}:",
+                    entry.label(),
+                    entry.manifest.device.serial,
+                    memory.name()
+                );
+                for r in ranges {
+                    text.push_str(&format!(
+                        "\n  LUN {} {} sectors {}..{}",
+                        r.lun,
+                        r.name,
+                        r.start,
+                        r.start + r.count
+                    ));
+                }
+                text
+            }
+            _ => return Err("Nothing to write.".into()),
+        };
+        Ok(format!(
+            "{}\nThe affected sectors and the GPT are copied to the vault first.",
+            target
+        ))
+    }
+
+    /// Whether the operation may change sector 0 or the primary GPT.
+    /// The backup GPT is checked again once the disk size is known.
+    pub fn touches_gpt(&self, memory: Memory) -> bool {
+        let sector_size = memory.sector_size() as u64;
+        matches!(self, Self::Patch { .. })
+            || self
+                .ranges(sector_size)
+                .is_ok_and(|ranges| ranges.iter().any(|r| r.touches_gpt(sector_size, None)))
+    }
+}
+
+/// Snapshot what `op` will change into the vault, then run it.
+/// GPT sectors are refused unless `allow_gpt` is set.
+fn run_write<T: edl::Transport>(
+    client: &mut FirehoseClient<T>,
+    device: &vault::DeviceIdentity,
+    op: &EdlWrite,
+    allow_gpt: bool,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> Result<String, String> {
+    let patches = op.patches()?;
+    let mut luns: Vec<u32> = patches
+        .iter()
+        .map(|p| p.lun)
+        .chain(
+            op.ranges(client.sector_size() as u64)?
+                .iter()
+                .map(|r| r.lun),
+        )
+        .collect();
+    luns.sort_unstable();
+    luns.dedup();
+    // Asking for the disk size can correct the sector size, so the ranges
+    // are worked out again afterwards.
+    let disks: BTreeMap<u32, u64> = luns
+        .iter()
+        .filter_map(|&lun| Some((lun, backup::disk_sectors(client, lun)?)))
+        .collect();
+    let sector_size = client.sector_size() as u64;
+    let ranges = op.ranges(sector_size)?;
+    for range in &ranges {
+        if let Some(&total) = disks.get(&range.lun) {
+            if range.start + range.count > total {
+                return Err(format!(
+                    "Sectors {}..{} run past the end of LUN {} ({} sectors); nothing was written.",
+                    range.start,
+                    range.start + range.count,
+                    range.lun,
+                    total
+                ));
+            }
+        }
+    }
+    let touches_gpt = matches!(op, EdlWrite::Patch { .. })
+        || ranges
+            .iter()
+            .any(|r| r.touches_gpt(sector_size, disks.get(&r.lun).copied()));
+    if touches_gpt && !allow_gpt {
+        return Err(
+            "This changes sector 0 or the GPT; nothing was written. Confirm the GPT write to proceed."
+                .into(),
+        );
+    }
+    let restore = match op {
+        EdlWrite::Restore { dir } => backup::restore_files(dir, Some(device), sector_size)?,
+        _ => Vec::new(),
+    };
+
+    let note = match (op, ranges.as_slice()) {
+        (EdlWrite::Restore { dir }, _) => {
+            format!("Before {} of {}", op.title(), dir.display())
+        }
+        (_, [r]) => format!(
+            "Before {} of LUN {} sectors {}..{}",
+            op.title(),
+            r.lun,
+            r.start,
+            r.start + r.count
+        ),
+        _ => format!("Before {} of the GPT on LUNs {:?}", op.title(), luns),
+    };
+    let dir = backup::snapshot(client, device, &luns, &ranges, &note, progress)
+        .map_err(|e| format!("Backup failed; nothing was written: {}", e))?;
+    let done = match op {
+        EdlWrite::Image { lun, start, path } => {
+            let path = normalize_local_path(path);
+            let label = std::path::Path::new(&path)
+                .file_name()
+                .map_or_else(|| path.clone(), |n| n.to_string_lossy().into_owned());
+            let mut last = (0, 0);
+            std::fs::File::open(&path)
+                .and_then(|f| Ok((f.metadata()?.len(), f)))
+                .map_err(|e| format!("Cannot read {}: {}", path, e))
+                .and_then(|(len, mut file)| {
+                    client.program(*lun, *start, &mut file, len, &label, &mut |done, total| {
+                        last = (done, total);
+                        progress("Write", done, total)
+                    })
+                })
+                .map(|()| {
+                    format!(
+                        "{} -> LUN {} sector {}\n  {}",
+                        path,
+                        lun,
+                        start,
+                        firehose::progress_label(last.0, last.1)
+                    )
+                })
+                .map_err(|e| {
+                    format!(
+                        "Failed at {}: {}",
+                        firehose::progress_label(last.0, last.1),
+                        e
+                    )
+                })
+        }
+        EdlWrite::Erase { lun, start, count } => client
+            .erase(*lun, *start, *count)
+            .map(|()| format!("LUN {} sectors {}..{} erased.", lun, start, start + count)),
+        EdlWrite::Patch { .. } => {
+            let mut applied = 0;
+            patches
+                .iter()
+                .try_for_each(|patch| -> Result<(), String> {
+                    client.patch(patch)?;
+                    applied += 1;
+                    progress("Patch", applied, patches.len() as u64);
+                    Ok(())
+                })
+                .map(|()| format!("Applied {} patches.", applied))
+                .map_err(|e| {
+                    format!(
+                        "Applied {} of {} patches, then: {}",
+                        applied,
+                        patches.len(),
+                        e
+                    )
+                })
+        }
+        EdlWrite::Restore { .. } => {
+            let mut written = 0;
+            restore
+                .iter()
+                .try_for_each(|(range, path)| -> Result<(), String> {
+                    let mut file = std::fs::File::open(path)
+                        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+                    let stage = format!("Restore LUN {} {}", range.lun, range.name);
+                    client
+                        .program(
+                            range.lun,
+                            range.start,
+                            &mut file,
+                            range.count * sector_size,
+                            &range.name,
+                            &mut |done, total| progress(&stage, done, total),
+                        )
+                        .map_err(|e| format!("{}: {}", stage, e))?;
+                    written += 1;
+                    Ok(())
+                })
+                .map(|()| format!("Wrote back {} ranges.", written))
+                .map_err(|e| {
+                    format!(
+                        "Wrote back {} of {} ranges, then: {}",
+                        written,
+                        restore.len(),
+                        e
+                    )
+                })
+        }
+    };
+    match done {
+        Ok(report) => Ok(format!("  {}\n  Backup: {}", report, dir.display())),
+        Err(e) => Err(format!("{}\n  Backup: {}", e, dir.display())),
+    }
+}
+
+/// EDL sector snapshots of the device the programmer was loaded on, newest first.
+pub fn edl_snapshots() -> Vec<vault::VaultEntry> {
+    match edl_device_identity() {
+        Ok(device) => vault::list_entries(&device.serial)
+            .into_iter()
+            .filter(|e| e.manifest.kind == vault::BackupKind::SectorSnapshot)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Run a confirmed `EdlWrite` on the device: back up, then write.
+pub fn edl_write(
+    memory: Memory,
+    op: &EdlWrite,
+    allow_gpt: bool,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> String {
+    let result = edl_device_identity().and_then(|device| {
+        let mut client = firehose_session(memory)?;
+        run_write(&mut client, &device, op, allow_gpt, progress)
+    });
+    match result {
+        Ok(report) => format!("{}:\n{}", op.title(), report),
+        Err(e) => format!("{}:\n  {}", op.title(), e),
+    }
+}
+
+/// Load the rawprogram/patch descriptors of the package in `package_dir`
+/// and select partitions from comma-separated label lists.
+fn load_flash_plan(package_dir: &str, include: &str, exclude: &str) -> Result<FlashPlan, String> {
+    if package_dir.is_empty() {
+        return Err("Firmware package folder (with rawprogram*.xml) is required.".into());
+    }
+    let dir = normalize_local_path(package_dir);
+    let mut plan = FlashPlan::load(std::path::Path::new(&dir))?;
+    plan.select(
+        &rawprogram::split_labels(include),
+        &rawprogram::split_labels(exclude),
+    );
+    Ok(plan)
+}
+
+/// Show what flashing the package would write, and whether it can.
+pub fn edl_flash_plan(package_dir: &str, include: &str, exclude: &str) -> String {
+    match load_flash_plan(package_dir, include, exclude) {
+        Ok(plan) => plan.render(),
+        Err(e) => format!("Flash plan:\n  {}", e),
+    }
+}
+
+/// Load the package for confirmation: the plan and a summary of what it
+/// will write and back up, or why it cannot run.
+pub fn edl_package_preview(
+    package_dir: &str,
+    include: &str,
+    exclude: &str,
+) -> Result<(FlashPlan, String), String> {
+    let plan = load_flash_plan(package_dir, include, exclude)?;
+    if !plan.problems().is_empty() {
+        return Err(format!("Nothing will be written.\n{}", plan.render()));
+    }
+    let calibration: Vec<&str> = plan
+        .entries
+        .iter()
+        .filter(|e| {
+            rawprogram::CALIBRATION
+                .iter()
+                .any(|l| l.eq_ignore_ascii_case(&e.label))
+        })
+        .map(|e| e.label.as_str())
+        .collect();
+    let backed_up = match calibration.as_slice() {
+        [] => "The GPT is".to_string(),
+        labels => format!("The GPT and {} are", labels.join(", ")),
+    };
+    let summary = format!(
+        "{}{} copied to the vault before anything is written.",
+        plan.render(),
+        backed_up
+    );
+    Ok((plan, summary))
+}
+
+/// Back up the GPT and calibration partitions, then write `plan`.
+fn run_package<T: edl::Transport>(
+    client: &mut FirehoseClient<T>,
+    device: &vault::DeviceIdentity,
+    plan: &FlashPlan,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> Result<String, String> {
+    let mut luns: Vec<u32> = plan
+        .included()
+        .map(|e| e.lun)
+        .chain(plan.patches.iter().map(|p| p.lun))
+        .collect();
+    luns.sort_unstable();
+    luns.dedup();
+    // Asking for the disk size can correct the sector size; the backup reads
+    // the package's sector counts, so they must agree before it starts.
+    let disks: BTreeMap<u32, u64> = luns
+        .iter()
+        .filter_map(|&lun| Some((lun, backup::disk_sectors(client, lun)?)))
+        .collect();
+    if let Some(entry) = plan
+        .entries
+        .iter()
+        .find(|e| e.sector_size != client.sector_size() as u64)
+    {
+        return Err(format!(
+            "{}: the package uses {}-byte sectors but the device uses {}; pick the other storage type. Nothing was written.",
+            entry.label,
+            entry.sector_size,
+            client.sector_size()
+        ));
+    }
+    let ranges = plan.backup_ranges(rawprogram::CALIBRATION, &mut |lun| match disks.get(&lun) {
+        Some(&total) => Some(total),
+        None => backup::disk_sectors(client, lun),
+    });
+    let note = format!("Before EDL package flash from {}", plan.sources.join(", "));
+    let dir = backup::snapshot(client, device, &luns, &ranges, &note, progress)
+        .map_err(|e| format!("Backup failed; nothing was written: {}", e))?;
+    match plan.execute(client, progress) {
+        Ok(report) => Ok(format!("{}  Backup: {}", report, dir.display())),
+        Err(e) => Err(format!("{}\n  Backup: {}", e, dir.display())),
+    }
+}
+
+/// Write a confirmed flash plan through Firehose.
+/// `progress` receives (stage, bytes done, bytes total).
+pub fn edl_flash_package(
+    memory: Memory,
+    plan: &FlashPlan,
+    progress: &mut dyn FnMut(&str, u64, u64),
+) -> String {
+    let mut stage = String::new();
+    let result = edl_device_identity().and_then(|device| {
+        let mut client = firehose_session(memory)?;
+        run_package(&mut client, &device, plan, &mut |label, done, total| {
+            if stage != label {
+                stage = label.to_string();
+            }
+            progress(label, done, total)
+        })
+    });
+    match result {
+        Ok(report) => format!("EDL Package Flash:\n{}", report),
+        Err(e) if stage.is_empty() => format!("EDL Package Flash:\n  {}", e),
+        Err(e) => format!("EDL Package Flash:\n  Stopped during {}: {}", stage, e),
+    }
+}
+
+/// Make LUN `lun` the boot drive (UFS devices boot from LUN 1 or 2).
+pub fn edl_set_boot_lun(memory: Memory, lun: u32) -> String {
+    match firehose_session(memory).and_then(|mut client| client.set_bootable_drive(lun)) {
+        Ok(()) => format!("EDL Boot LUN:\n  LUN {} is now bootable.", lun),
+        Err(e) => format!("EDL Boot LUN:\n  {}", e),
+    }
+}
+
+/// Leave EDL by resetting, or restart into EDL with a fresh Sahara session.
+pub fn edl_power(memory: Memory, power: Power) -> String {
+    match firehose_session(memory).and_then(|mut client| client.power(power)) {
+        Ok(()) => format!("EDL Power:\n  {:?} requested.", power),
+        Err(e) => format!("EDL Power:\n  {}", e),
+    }
+}
+
+/// Firehose progress shared with the UI.
+#[derive(Debug, Clone, Default)]
+pub struct EdlProgress {
+    pub stage: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+/// An EDL operation running on a background thread so the UI can show progress.
+pub struct EdlJob {
+    progress: Arc<Mutex<EdlProgress>>,
+    handle: Option<JoinHandle<String>>,
+}
+
+impl EdlJob {
+    /// Run `work`, which reports (stage, bytes done, bytes total) through
+    /// its argument and returns the log text.
+    pub fn spawn<F>(work: F) -> Self
+    where
+        F: FnOnce(&mut dyn FnMut(&str, u64, u64)) -> String + Send + 'static,
+    {
+        let progress = Arc::new(Mutex::new(EdlProgress::default()));
+        let shared = Arc::clone(&progress);
+        let handle = std::thread::spawn(move || {
+            work(&mut |stage, done, total| {
+                if let Ok(mut guard) = shared.lock() {
+                    if guard.stage != stage {
+                        guard.stage = stage.to_string();
+                    }
+                    guard.done = done;
+                    guard.total = total;
+                }
+            })
+        });
+        Self {
+            progress,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn progress(&self) -> EdlProgress {
+        self.progress.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+
+    /// The operation's log text once the worker has finished.
+    pub fn try_finish(&mut self) -> Option<String> {
+        if !self.handle.as_ref()?.is_finished() {
+            return None;
+        }
+        let handle = self.handle.take()?;
+        Some(
+            handle
+                .join()
+                .unwrap_or_else(|_| "EDL worker panicked.".to_string()),
+        )
     }
-    format!(
-        "EDL Flash:\n\
-         Programmer: {}\n\
-         Protocol: Sahara/Firehose (Qualcomm)\n\
-         Note: Device must be in EDL mode (Qualcomm HS-USB 9008).\n\
-         This operation uses low-level Qualcomm protocols to flash partitions.\n\
-         Ensure the correct programmer file for your chipset is selected.",
-        programmer_path
-    )
 }
 
 // -- Fastboot Flash --
@@ -318,7 +1014,12 @@ pub fn install_kernelsu(serial: &str, path: &str) -> String {
 #[cfg(test)]
 mod tests {
     use crate::exec::MOCK_RUN_IMPL;
-    use crate::features::flash::{erase_partition, reboot_to};
+    use crate::features::edl::firehose::{sim::SimProgrammer, FirehoseClient, Memory};
+    use crate::features::flash::{
+        edl_package_preview, erase_partition, reboot_to, run_package, run_write, EdlJob, EdlWrite,
+    };
+    use crate::features::vault;
+    use std::error::Error;
 
     #[test]
     fn test_reboot_to_system_success() {
@@ -497,4 +1198,281 @@ mod tests {
             *mock.borrow_mut() = None;
         });
     }
+
+    fn device() -> vault::DeviceIdentity {
+        vault::DeviceIdentity {
+            serial: "edl-1234abcd".into(),
+            model: String::new(),
+            fingerprint: String::new(),
+            build_fingerprint: String::new(),
+        }
+    }
+
+    fn sim_client() -> Result<FirehoseClient<SimProgrammer>, String> {
+        let mut client = FirehoseClient::new(SimProgrammer::new(100), Memory::Emmc);
+        client.configure()?;
+        Ok(client)
+    }
+
+    fn image(name: &str, len: usize) -> Result<String, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, vec![0xAA; len])?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn test_edl_write_refuses_gpt_without_confirmation() -> Result<(), Box<dyn Error>> {
+        let path = image("foem_edl_gpt_refused.img", 512)?;
+        let mut client = sim_client()?;
+        let op = EdlWrite::Image {
+            lun: 0,
+            start: 0,
+            path: path.clone(),
+        };
+        assert!(op.touches_gpt(Memory::Emmc));
+        let result = run_write(&mut client, &device(), &op, false, &mut |_, _, _| {});
+        assert!(result.is_err_and(|e| e.contains("nothing was written")));
+        let (disk, commands) = client.sim_state();
+        assert_eq!(disk[..512], [0; 512]);
+        assert!(!commands.iter().any(|c| c == "program" || c == "read"));
+
+        let patch = EdlWrite::Patch {
+            path: "patch0.xml".into(),
+        };
+        assert!(patch.touches_gpt(Memory::Emmc));
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_edl_write_backs_up_before_writing() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join("foem_edl_write_backup_test");
+        let _ = std::fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let path = image("foem_edl_backup.img", 700)?;
+        let mut client = sim_client()?;
+        let op = EdlWrite::Image {
+            lun: 0,
+            start: 40,
+            path: path.clone(),
+        };
+        assert!(!op.touches_gpt(Memory::Emmc));
+        let mut stages = Vec::new();
+        let result = run_write(&mut client, &device(), &op, false, &mut |stage, _, _| {
+            if stages.last() != Some(&stage.to_string()) {
+                stages.push(stage.to_string());
+            }
+        });
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+
+        let report = result?;
+        assert_eq!(stages.last().map(String::as_str), Some("Write"));
+        let (disk, commands) = client.sim_state();
+        assert!(disk[40 * 512..40 * 512 + 700].iter().all(|&b| b == 0xAA));
+        let first_read = commands.iter().position(|c| c == "read");
+        let program = commands.iter().position(|c| c == "program");
+        assert!(first_read < program, "{:?}", commands);
+
+        let dir = report
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("Backup: "))
+            .ok_or("no backup in report")?;
+        let entry = vault::load_entry(std::path::Path::new(dir))?;
+        assert_eq!(entry.manifest.kind, vault::BackupKind::SectorSnapshot);
+        let saved = std::fs::read(entry.dir.join("lun0_sectors_40-42.bin"))?;
+        assert_eq!(saved[..512], [40; 512]);
+        assert_eq!(saved[512..], [41; 512]);
+
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_edl_erase_past_end_is_refused() -> Result<(), Box<dyn Error>> {
+        let mut client = sim_client()?;
+        let op = EdlWrite::Erase {
+            lun: 0,
+            start: 90,
+            count: 20,
+        };
+        let result = run_write(&mut client, &device(), &op, true, &mut |_, _, _| {});
+        assert!(result.is_err_and(|e| e.contains("run past the end of LUN 0")));
+        let (_, commands) = client.sim_state();
+        assert!(!commands.iter().any(|c| c == "erase"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_edl_job_reports_result() {
+        let mut job = EdlJob::spawn(|progress| {
+            progress("Read", 512, 1024);
+            "EDL Read:\n  done".to_string()
+        });
+        let report = loop {
+            if let Some(report) = job.try_finish() {
+                break report;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        };
+        assert_eq!(report, "EDL Read:\n  done");
+        let progress = job.progress();
+        assert_eq!((progress.stage.as_str(), progress.done), ("Read", 512));
+    }
+
+    fn package_dir(name: &str, boot_image: bool) -> Result<std::path::PathBuf, Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let entry = |label: &str, file: &str, start: u64, sectors: u64| {
+            format!(
+                "<program SECTOR_SIZE_IN_BYTES=\"512\" file_sector_offset=\"0\" filename=\"{}\" \
+                 label=\"{}\" num_partition_sectors=\"{}\" physical_partition_number=\"0\" \
+                 sparse=\"false\" start_sector=\"{}\" />",
+                file, label, sectors, start
+            )
+        };
+        std::fs::write(
+            dir.join("rawprogram0.xml"),
+            format!(
+                "<?xml version=\"1.0\" ?><data>{}{}</data>",
+                entry("modemst1", "", 40, 4),
+                entry("boot", "boot.img", 50, 2)
+            ),
+        )?;
+        if boot_image {
+            std::fs::write(dir.join("boot.img"), vec![0xB0; 1024])?;
+        }
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_edl_package_preview_refuses_invalid_plan() -> Result<(), Box<dyn Error>> {
+        let dir = package_dir("foem_edl_preview_invalid", false)?;
+        let result = edl_package_preview(&dir.to_string_lossy(), "", "");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(result.is_err_and(|e| e.starts_with("Nothing will be written.")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_edl_package_flash_backs_up_calibration_first() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join("foem_edl_package_backup_test");
+        let _ = std::fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let dir = package_dir("foem_edl_package_backup", true)?;
+        let preview = edl_package_preview(&dir.to_string_lossy(), "", "");
+        let mut client = sim_client()?;
+        let result = preview
+            .map_err(|e| e.to_string())
+            .and_then(|(plan, summary)| {
+                assert!(summary.ends_with(
+                    "The GPT and modemst1 are copied to the vault before anything is written."
+                ));
+                run_package(&mut client, &device(), &plan, &mut |_, _, _| {})
+            });
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let report = result?;
+        let (disk, commands) = client.sim_state();
+        assert!(disk[50 * 512..52 * 512].iter().all(|&b| b == 0xB0));
+        let first_read = commands.iter().position(|c| c == "read");
+        let program = commands.iter().position(|c| c == "program");
+        assert!(first_read < program, "{:?}", commands);
+
+        let backup = report
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("Backup: "))
+            .ok_or("no backup in report")?;
+        let entry = vault::load_entry(std::path::Path::new(backup))?;
+        let files: Vec<&str> = entry
+            .manifest
+            .files
+            .iter()
+            .map(|f| f.file.as_str())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "lun0_gpt_primary.bin",
+                "lun0_gpt_backup.bin",
+                "lun0_modemst1.bin"
+            ]
+        );
+        let saved = std::fs::read(entry.dir.join("lun0_modemst1.bin"))?;
+        assert_eq!(saved[..512], [40; 512]);
+
+        let _ = std::fs::remove_dir_all(&root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_edl_package_sector_size_is_checked_before_backup() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join("foem_edl_package_sector_test");
+        let _ = std::fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let dir = package_dir("foem_edl_package_sector", true)?;
+        let xml = std::fs::read_to_string(dir.join("rawprogram0.xml"))?;
+        std::fs::write(
+            dir.join("rawprogram0.xml"),
+            xml.replace(
+                "SECTOR_SIZE_IN_BYTES=\"512\"",
+                "SECTOR_SIZE_IN_BYTES=\"4096\"",
+            ),
+        )?;
+        let preview = edl_package_preview(&dir.to_string_lossy(), "", "");
+        let mut client = sim_client()?;
+        let result = preview
+            .map_err(|e| e.to_string())
+            .and_then(|(plan, _)| run_package(&mut client, &device(), &plan, &mut |_, _, _| {}));
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(result.is_err_and(|e| e.contains("uses 4096-byte sectors but the device uses 512")));
+        let (_, commands) = client.sim_state();
+        assert!(!commands.iter().any(|c| c == "read" || c == "program"));
+        assert!(!root.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_edl_restore_writes_a_snapshot_back() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join("foem_edl_restore_test");
+        let _ = std::fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let path = image("foem_edl_restore.img", 1024)?;
+        let mut client = sim_client()?;
+        let write = EdlWrite::Image {
+            lun: 0,
+            start: 40,
+            path: path.clone(),
+        };
+        let result = run_write(&mut client, &device(), &write, false, &mut |_, _, _| {}).and_then(
+            |report| {
+                let dir = report
+                    .lines()
+                    .find_map(|l| l.trim().strip_prefix("Backup: "))
+                    .ok_or("no backup in report")?;
+                let restore = EdlWrite::Restore { dir: dir.into() };
+                assert!(restore.touches_gpt(Memory::Emmc));
+                let other = vault::DeviceIdentity {
+                    serial: "edl-00000001".into(),
+                    ..device()
+                };
+                let refused = run_write(&mut client, &other, &restore, true, &mut |_, _, _| {});
+                assert!(refused.is_err_and(|e| e.contains("taken from edl-1234abcd")));
+                run_write(&mut client, &device(), &restore, true, &mut |_, _, _| {})
+            },
+        );
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_file(path);
+
+        assert!(result?.contains("Wrote back 3 ranges."));
+        let (disk, _) = client.sim_state();
+        assert_eq!(disk[40 * 512..41 * 512], [40; 512]);
+        assert_eq!(disk[41 * 512..42 * 512], [41; 512]);
+        Ok(())
+    }
 }
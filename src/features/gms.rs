@@ -0,0 +1,953 @@
+/// Deep GMS health check.
+///
+/// For Play services, Services Framework, Play Store and Setup Wizard this
+/// reports version, signing certificate (read from the APK Signature Scheme
+/// v2/v3 block on the device), enabled state, disabled components, denied
+/// permissions, missing privileged-permission allowlist entries and storage
+/// use. Every problem comes with a concrete fix. Read-only.
+use super::batch;
+use crate::exec;
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+const CHECKED_PACKAGES: &[(&str, &str)] = &[
+    ("com.google.android.gms", "Google Play services"),
+    ("com.google.android.gsf", "Google Services Framework"),
+    ("com.android.vending", "Google Play Store"),
+    ("com.google.android.setupwizard", "Setup Wizard"),
+];
+
+/// Packages that must carry the Google release key; anything else is a
+/// re-signed or patched build. Setup Wizard is OEM-signed on some ROMs.
+const GOOGLE_SIGNED_ONLY: &[&str] = &[
+    "com.google.android.gms",
+    "com.google.android.gsf",
+    "com.android.vending",
+];
+
+/// SHA-256 of known Google signing certificates.
+const GOOGLE_SIGNERS: &[(&str, &str)] = &[(
+    "F0FD6C5B410F25CB25C3B53346C8972FAE30F8EE7411DF910480AD6B2D60DB83",
+    "Google release key",
+)];
+
+const SIG_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const SCHEME_V2_ID: u32 = 0x7109_871a;
+const SCHEME_V3_ID: u32 = 0xf053_68c0;
+const SCHEME_V31_ID: u32 = 0x1b93_ad61;
+const EOCD_SIG: u32 = 0x0605_4b50;
+/// EOCD record (22 bytes) plus the largest possible ZIP comment.
+const EOCD_SEARCH_LEN: usize = 22 + 0xffff;
+/// Bytes read in front of the central directory on the first attempt.
+const SIG_BLOCK_WINDOW: u64 = 64 * 1024;
+const SIG_BLOCK_MAX: u64 = 16 * 1024 * 1024;
+
+const PRIVAPP_GLOBS: &[&str] = &[
+    "/system/etc/permissions/privapp-permissions*.xml",
+    "/product/etc/permissions/privapp-permissions*.xml",
+    "/system_ext/etc/permissions/privapp-permissions*.xml",
+];
+
+// -- APK Signing Block --
+
+fn read_u32(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(buf: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(buf.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Central directory offset from the tail of a ZIP file.
+pub fn central_directory_offset(tail: &[u8]) -> Result<u64, String> {
+    if tail.len() < 22 {
+        return Err("file too short for a ZIP end-of-central-directory record".into());
+    }
+    for start in (0..=tail.len() - 22).rev() {
+        if read_u32(tail, start) != Some(EOCD_SIG) {
+            continue;
+        }
+        let comment_len = u16::from_le_bytes([tail[start + 20], tail[start + 21]]) as usize;
+        if start + 22 + comment_len == tail.len() {
+            return read_u32(tail, start + 16)
+                .map(u64::from)
+                .ok_or_else(|| "truncated EOCD record".to_string());
+        }
+    }
+    Err("ZIP end-of-central-directory record not found".into())
+}
+
+/// Total length of the APK Signing Block that ends at the end of `window`
+/// (the bytes immediately before the central directory).
+pub fn signing_block_len(window: &[u8]) -> Result<u64, String> {
+    if window.len() < 32 || &window[window.len() - 16..] != SIG_BLOCK_MAGIC {
+        return Err("no APK Signing Block (v1/JAR-signed only)".into());
+    }
+    let size = read_u64(window, window.len() - 24).ok_or("truncated signing block footer")?;
+    let total = size.checked_add(8).ok_or("invalid signing block size")?;
+    if !(32..=SIG_BLOCK_MAX).contains(&total) {
+        return Err(format!("implausible signing block size {}", total));
+    }
+    Ok(total)
+}
+
+/// One signer certificate found in the signing block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignerCert {
+    pub scheme: &'static str,
+    pub sha256: String,
+}
+
+/// Split a sequence of u32-length-prefixed values.
+fn length_prefixed(mut buf: &[u8]) -> Result<Vec<&[u8]>, String> {
+    let mut items = Vec::new();
+    while !buf.is_empty() {
+        let len = read_u32(buf, 0).ok_or("truncated length prefix")? as usize;
+        let item = buf
+            .get(4..4 + len)
+            .ok_or("length prefix exceeds signing block")?;
+        items.push(item);
+        buf = &buf[4 + len..];
+    }
+    Ok(items)
+}
+
+fn length_prefixed_field(buf: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let len = read_u32(buf, 0).ok_or("truncated length prefix")? as usize;
+    let field = buf.get(4..4 + len).ok_or("length prefix exceeds signer")?;
+    Ok((field, &buf[4 + len..]))
+}
+
+/// Signer certificates from a v2/v3 scheme value. Both schemes share the
+/// `signers -> signer -> signed data -> (digests, certificates, ...)` layout.
+fn scheme_certificates(scheme: &'static str, value: &[u8]) -> Result<Vec<SignerCert>, String> {
+    let (signers, _) = length_prefixed_field(value)?;
+    let mut certs = Vec::new();
+    for signer in length_prefixed(signers)? {
+        let (signed_data, _) = length_prefixed_field(signer)?;
+        let (_digests, rest) = length_prefixed_field(signed_data)?;
+        let (certificates, _) = length_prefixed_field(rest)?;
+        if let Some(cert) = length_prefixed(certificates)?.first() {
+            certs.push(SignerCert {
+                scheme,
+                sha256: hex::encode_upper(Sha256::digest(cert)),
+            });
+        }
+    }
+    Ok(certs)
+}
+
+/// Parse a complete APK Signing Block (size field through magic).
+pub fn parse_signing_block(block: &[u8]) -> Result<Vec<SignerCert>, String> {
+    let total = signing_block_len(block)? as usize;
+    if block.len() != total {
+        return Err("signing block length mismatch".into());
+    }
+    let mut pairs = &block[8..block.len() - 24];
+    let mut certs = Vec::new();
+    while !pairs.is_empty() {
+        let len = read_u64(pairs, 0).ok_or("truncated signing block pair")? as usize;
+        let pair = pairs
+            .get(8..8 + len)
+            .ok_or("signing block pair exceeds block")?;
+        let id = read_u32(pair, 0).ok_or("truncated signing block pair id")?;
+        let scheme = match id {
+            SCHEME_V2_ID => Some("v2"),
+            SCHEME_V3_ID => Some("v3"),
+            SCHEME_V31_ID => Some("v3.1"),
+            _ => None,
+        };
+        if let Some(scheme) = scheme {
+            certs.extend(scheme_certificates(scheme, &pair[4..])?);
+        }
+        pairs = &pairs[8 + len..];
+    }
+    if certs.is_empty() {
+        return Err("signing block has no v2/v3 signer".into());
+    }
+    Ok(certs)
+}
+
+fn parse_od_hex(text: &str) -> Result<Vec<u8>, String> {
+    let compact: String = text.split_whitespace().collect();
+    hex::decode(compact).map_err(|e| format!("bad od output: {}", e))
+}
+
+// -- dumpsys parsing --
+
+/// The parts of `dumpsys package <pkg>` the health check needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageDump {
+    pub version_name: String,
+    pub version_code: String,
+    pub system: bool,
+    pub privileged: bool,
+    /// COMPONENT_ENABLED_STATE_* for user 0 (0 default, 1 enabled, 2+ disabled).
+    pub enabled_state: u8,
+    pub requested: Vec<String>,
+    pub install_granted: BTreeSet<String>,
+    pub runtime_granted: BTreeSet<String>,
+    pub runtime_denied: Vec<String>,
+    pub disabled_components: Vec<String>,
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+pub fn parse_package_dump(text: &str) -> PackageDump {
+    let mut dump = PackageDump::default();
+    let mut section: Option<(String, usize)> = None;
+    let mut seen_package = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        // Only the active package entry; the hidden system copy repeats fields.
+        if trimmed.starts_with("Package [") {
+            if seen_package {
+                break;
+            }
+            seen_package = true;
+        }
+        if trimmed.starts_with("Hidden system packages:") {
+            break;
+        }
+        let indent = indent_of(line);
+        if section.as_ref().is_some_and(|(_, at)| indent <= *at) {
+            section = None;
+        }
+        if let Some((name, _)) = &section {
+            let perm = trimmed.split(':').next().unwrap_or(trimmed).to_string();
+            let granted = trimmed.contains("granted=true");
+            match name.as_str() {
+                "requested permissions" => dump.requested.push(perm),
+                "install permissions" if granted => {
+                    dump.install_granted.insert(perm);
+                }
+                "runtime permissions" if granted => {
+                    dump.runtime_granted.insert(perm);
+                }
+                "runtime permissions" => dump.runtime_denied.push(perm),
+                "disabledComponents" => dump.disabled_components.push(perm),
+                _ => {}
+            }
+            continue;
+        }
+        if let Some(name) = trimmed.strip_suffix(':') {
+            if matches!(
+                name,
+                "requested permissions"
+                    | "install permissions"
+                    | "runtime permissions"
+                    | "disabledComponents"
+                    | "enabledComponents"
+            ) {
+                section = Some((name.to_string(), indent));
+                continue;
+            }
+        }
+        if let Some(v) = trimmed.strip_prefix("versionName=") {
+            dump.version_name = v.to_string();
+        } else if let Some(v) = trimmed.strip_prefix("versionCode=") {
+            dump.version_code = v.split_whitespace().next().unwrap_or("").to_string();
+        } else if let Some(v) = trimmed.strip_prefix("pkgFlags=") {
+            dump.system = v.split_whitespace().any(|f| f == "SYSTEM");
+        } else if let Some(v) = trimmed.strip_prefix("privateFlags=") {
+            dump.privileged = v.split_whitespace().any(|f| f == "PRIVILEGED");
+        } else if trimmed.starts_with("User 0:") {
+            dump.enabled_state = trimmed
+                .split_whitespace()
+                .find_map(|t| t.strip_prefix("enabled="))
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+        }
+    }
+    dump
+}
+
+/// `package -> allowlisted permissions` from privapp-permissions XML files.
+pub fn parse_privapp_allowlist(xml: &str) -> BTreeMap<String, BTreeSet<String>> {
+    fn attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+        let key = format!("{}=\"", name);
+        let start = tag.find(&key)? + key.len();
+        let len = tag[start..].find('"')?;
+        Some(&tag[start..start + len])
+    }
+
+    let mut map: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for tag in xml.split('<').map(str::trim) {
+        if tag.starts_with("privapp-permissions") {
+            current = attr(tag, "package").map(str::to_string);
+            if let Some(pkg) = &current {
+                map.entry(pkg.clone()).or_default();
+            }
+        } else if tag.starts_with("/privapp-permissions") {
+            current = None;
+        } else if tag.starts_with("permission ") {
+            if let (Some(pkg), Some(perm)) = (&current, attr(tag, "name")) {
+                map.entry(pkg.clone()).or_default().insert(perm.to_string());
+            }
+        }
+    }
+    map
+}
+
+/// `package -> (code+apk bytes, data bytes, cache bytes)` from `dumpsys diskstats`.
+pub fn parse_diskstats(text: &str) -> BTreeMap<String, (u64, u64, u64)> {
+    fn list<'a>(text: &'a str, key: &str) -> Vec<&'a str> {
+        text.lines()
+            .find_map(|l| l.trim().strip_prefix(key))
+            .map(|v| {
+                v.trim()
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(|s| s.trim().trim_matches('"'))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    let num = |v: Option<&&str>| v.and_then(|s| s.parse().ok()).unwrap_or(0);
+
+    let names = list(text, "Package Names:");
+    let apps = list(text, "App Sizes:");
+    let data = list(text, "App Data Sizes:");
+    let cache = list(text, "Cache Sizes:");
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            (
+                name.to_string(),
+                (num(apps.get(i)), num(data.get(i)), num(cache.get(i))),
+            )
+        })
+        .collect()
+}
+
+// -- Report --
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<String>,
+}
+
+fn ok(message: String) -> Finding {
+    Finding {
+        severity: Severity::Ok,
+        message,
+        fix: None,
+    }
+}
+
+fn problem(severity: Severity, message: String, fix: String) -> Finding {
+    Finding {
+        severity,
+        message,
+        fix: Some(fix),
+    }
+}
+
+fn trusted_signers() -> Vec<(String, String)> {
+    let mut signers: Vec<(String, String)> = GOOGLE_SIGNERS
+        .iter()
+        .map(|(d, n)| (d.to_string(), n.to_string()))
+        .collect();
+    // One "<sha256> [label]" per line for OEM-signed Setup Wizard builds.
+    if let Ok(text) = std::fs::read_to_string(exec::foem_home().join("trusted_signers.txt")) {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (digest, label) = line.split_once(' ').unwrap_or((line, "user-trusted"));
+            signers.push((
+                digest.replace(':', "").to_ascii_uppercase(),
+                label.trim().to_string(),
+            ));
+        }
+    }
+    signers
+}
+
+/// Inputs gathered from the device for one package.
+#[derive(Debug, Clone)]
+pub struct PackageState {
+    pub dump: Option<PackageDump>,
+    pub signers: Result<Vec<SignerCert>, String>,
+    pub storage: Option<(u64, u64, u64)>,
+}
+
+/// Evaluate one package; `allowlist` is `None` when the XML files were unreadable.
+pub fn check_package(
+    package: &str,
+    state: &PackageState,
+    allowlist: Option<&BTreeMap<String, BTreeSet<String>>>,
+    enforce_privapp: bool,
+    trusted: &[(String, String)],
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let Some(dump) = &state.dump else {
+        findings.push(problem(
+            Severity::Fail,
+            "Not installed".into(),
+            "Flash the GApps/GMS package matching this Android version and CPU ABI.".into(),
+        ));
+        return findings;
+    };
+
+    findings.push(ok(format!(
+        "Version {} ({}){}",
+        if dump.version_name.is_empty() {
+            "?"
+        } else {
+            &dump.version_name
+        },
+        if dump.version_code.is_empty() {
+            "?"
+        } else {
+            &dump.version_code
+        },
+        if dump.privileged {
+            ", privileged system app"
+        } else if dump.system {
+            ", system app"
+        } else {
+            ", user app"
+        }
+    )));
+
+    if dump.enabled_state >= 2 {
+        findings.push(problem(
+            Severity::Fail,
+            format!("Package is disabled (state {})", dump.enabled_state),
+            format!("adb shell pm enable {}", package),
+        ));
+    }
+
+    if GOOGLE_SIGNED_ONLY.contains(&package) && !dump.privileged {
+        findings.push(problem(
+            Severity::Warn,
+            "Not installed as a privileged app".into(),
+            format!(
+                "Reinstall {} into /system/priv-app (or /product/priv-app) with its privapp-permissions XML.",
+                package
+            ),
+        ));
+    }
+
+    match &state.signers {
+        Ok(signers) => {
+            for cert in signers {
+                match trusted.iter().find(|(d, _)| *d == cert.sha256) {
+                    Some((_, name)) => findings.push(ok(format!(
+                        "Signer ({}) {}: {}",
+                        cert.scheme, name, cert.sha256
+                    ))),
+                    None => {
+                        let severity = if GOOGLE_SIGNED_ONLY.contains(&package) {
+                            Severity::Fail
+                        } else {
+                            Severity::Warn
+                        };
+                        findings.push(problem(
+                            severity,
+                            format!("Unknown signer ({}): {}", cert.scheme, cert.sha256),
+                            if severity == Severity::Fail {
+                                "Re-signed/tampered build: replace it with an official Google-signed APK; Play Integrity and account sync will keep failing otherwise.".into()
+                            } else {
+                                "If this is the OEM's own Setup Wizard, add the digest to ~/.foem/trusted_signers.txt.".into()
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        Err(e) => findings.push(problem(
+            Severity::Warn,
+            format!("Signer not verified: {}", e),
+            "Pull the APK (adb pull <pm path>) and check it with apksigner verify --print-certs."
+                .into(),
+        )),
+    }
+
+    if !dump.disabled_components.is_empty() {
+        let shown: Vec<&str> = dump
+            .disabled_components
+            .iter()
+            .take(5)
+            .map(String::as_str)
+            .collect();
+        findings.push(problem(
+            Severity::Warn,
+            format!(
+                "{} disabled component(s): {}{}",
+                dump.disabled_components.len(),
+                shown.join(", "),
+                if dump.disabled_components.len() > shown.len() {
+                    ", ..."
+                } else {
+                    ""
+                }
+            ),
+            format!(
+                "adb shell pm enable {}/{} (repeat per component)",
+                package, dump.disabled_components[0]
+            ),
+        ));
+    }
+
+    if !dump.runtime_denied.is_empty() {
+        findings.push(problem(
+            Severity::Warn,
+            format!(
+                "{} runtime permission(s) denied: {}",
+                dump.runtime_denied.len(),
+                dump.runtime_denied.join(", ")
+            ),
+            format!(
+                "adb shell pm grant {} {} (repeat per permission)",
+                package, dump.runtime_denied[0]
+            ),
+        ));
+    }
+
+    if dump.privileged {
+        if let Some(allowlist) = allowlist {
+            let listed = allowlist.get(package);
+            let missing: Vec<&str> = dump
+                .requested
+                .iter()
+                .filter(|p| p.starts_with("android.permission."))
+                .filter(|p| !dump.install_granted.contains(*p))
+                .filter(|p| !dump.runtime_granted.contains(*p) && !dump.runtime_denied.contains(*p))
+                .filter(|p| !listed.is_some_and(|set| set.contains(*p)))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                findings.push(problem(
+                    if enforce_privapp { Severity::Fail } else { Severity::Warn },
+                    format!(
+                        "{} privileged permission(s) not granted and not allowlisted: {}",
+                        missing.len(),
+                        missing.join(", ")
+                    ),
+                    format!(
+                        "Add <permission name=\"...\"/> entries under <privapp-permissions package=\"{}\"> in /system/etc/permissions/privapp-permissions-google.xml and reboot.",
+                        package
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let Some((code, data, cache)) = state.storage {
+        let usage = format!(
+            "Storage: app {}, data {}, cache {}",
+            super::partitions::format_size(code),
+            super::partitions::format_size(data),
+            super::partitions::format_size(cache)
+        );
+        if cache > 512 * 1024 * 1024 {
+            findings.push(problem(
+                Severity::Warn,
+                format!("{} (oversized cache)", usage),
+                format!(
+                    "adb shell pm trim-caches 999G, or clear the cache of {} in Settings.",
+                    package
+                ),
+            ));
+        } else {
+            findings.push(ok(usage));
+        }
+    }
+    findings
+}
+
+/// Read v2/v3 signer certificates for each `(package, apk path)` with
+/// `tail`/`od` on the device, using at most three batched round trips.
+fn read_signers(
+    serial: &str,
+    apks: &[(&str, String)],
+) -> BTreeMap<String, Result<Vec<SignerCert>, String>> {
+    let mut results = BTreeMap::new();
+    let quote = |p: &str| {
+        shlex::try_quote(p)
+            .map(|q| q.into_owned())
+            .unwrap_or_default()
+    };
+
+    let tail_cmds: Vec<(String, String)> = apks
+        .iter()
+        .map(|(pkg, path)| {
+            (
+                pkg.to_string(),
+                format!(
+                    "tail -c {} {} | od -An -v -tx1",
+                    EOCD_SEARCH_LEN,
+                    quote(path)
+                ),
+            )
+        })
+        .collect();
+    let mut offsets = BTreeMap::new();
+    for (pkg, res) in batch::run_commands(serial, &tail_cmds) {
+        match res
+            .and_then(|t| parse_od_hex(&t))
+            .and_then(|t| central_directory_offset(&t))
+        {
+            Ok(offset) => {
+                offsets.insert(pkg, offset);
+            }
+            Err(e) => {
+                results.insert(pkg, Err(e));
+            }
+        }
+    }
+
+    let read_range = |path: &str, end: u64, len: u64| {
+        format!(
+            "tail -c +{} {} | head -c {} | od -An -v -tx1",
+            end - len + 1,
+            quote(path),
+            len
+        )
+    };
+    let mut pending = Vec::new();
+    let mut window_cmds = Vec::new();
+    for (pkg, path) in apks {
+        if let Some(&offset) = offsets.get(*pkg) {
+            window_cmds.push((
+                pkg.to_string(),
+                read_range(path, offset, offset.min(SIG_BLOCK_WINDOW)),
+            ));
+        }
+    }
+    for (pkg, res) in batch::run_commands(serial, &window_cmds) {
+        let window = match res.and_then(|t| parse_od_hex(&t)) {
+            Ok(w) => w,
+            Err(e) => {
+                results.insert(pkg, Err(e));
+                continue;
+            }
+        };
+        match signing_block_len(&window) {
+            Ok(total) if total as usize <= window.len() => {
+                let block = &window[window.len() - total as usize..];
+                results.insert(pkg, parse_signing_block(block));
+            }
+            Ok(total) => pending.push((pkg, total)),
+            Err(e) => {
+                results.insert(pkg, Err(e));
+            }
+        }
+    }
+
+    let full_cmds: Vec<(String, String)> = pending
+        .iter()
+        .filter_map(|(pkg, total)| {
+            let (_, path) = apks.iter().find(|(p, _)| p == pkg)?;
+            let offset = *offsets.get(pkg)?;
+            (*total <= offset).then(|| (pkg.clone(), read_range(path, offset, *total)))
+        })
+        .collect();
+    for (pkg, res) in batch::run_commands(serial, &full_cmds) {
+        results.insert(
+            pkg,
+            res.and_then(|t| parse_od_hex(&t))
+                .and_then(|b| parse_signing_block(&b)),
+        );
+    }
+    for (pkg, _) in pending {
+        results
+            .entry(pkg)
+            .or_insert_with(|| Err("signing block extends past start of file".into()));
+    }
+    results
+}
+
+/// Run the deep GMS health check and render the report.
+pub fn deep_check(serial: &str) -> String {
+    let mut cmds: Vec<(String, String)> = vec![
+        (
+            "enforce".into(),
+            "getprop ro.control_privapp_permissions".into(),
+        ),
+        ("diskstats".into(), "dumpsys diskstats".into()),
+        (
+            "privapp".into(),
+            format!("cat {} 2>/dev/null || true", PRIVAPP_GLOBS.join(" ")),
+        ),
+    ];
+    for (pkg, _) in CHECKED_PACKAGES {
+        cmds.push((format!("path:{}", pkg), format!("pm path {}", pkg)));
+        cmds.push((format!("dump:{}", pkg), format!("dumpsys package {}", pkg)));
+    }
+
+    let mut enforce = false;
+    let mut storage = BTreeMap::new();
+    let mut allowlist = None;
+    let mut paths = Vec::new();
+    let mut dumps = BTreeMap::new();
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        let Ok(out) = res else {
+            continue;
+        };
+        match label.as_str() {
+            "enforce" => enforce = out.trim() == "enforce",
+            "diskstats" => storage = parse_diskstats(&out),
+            "privapp" if !out.trim().is_empty() => allowlist = Some(parse_privapp_allowlist(&out)),
+            _ => {
+                if let Some(pkg) = label.strip_prefix("path:") {
+                    let base = out
+                        .lines()
+                        .filter_map(|l| l.trim().strip_prefix("package:"))
+                        .find(|p| p.ends_with("/base.apk"))
+                        .or_else(|| out.lines().find_map(|l| l.trim().strip_prefix("package:")));
+                    if let (Some(path), Some((pkg, _))) =
+                        (base, CHECKED_PACKAGES.iter().find(|(p, _)| *p == pkg))
+                    {
+                        paths.push((*pkg, path.to_string()));
+                    }
+                } else if let Some(pkg) = label.strip_prefix("dump:") {
+                    if out.contains(&format!("Package [{}]", pkg)) {
+                        dumps.insert(pkg.to_string(), parse_package_dump(&out));
+                    }
+                }
+            }
+        }
+    }
+    let mut signers = read_signers(serial, &paths);
+    let trusted = trusted_signers();
+
+    let mut output = String::from("GMS Deep Check:\n");
+    if allowlist.is_none() {
+        output.push_str("  privapp-permissions XML unreadable: allowlist check skipped.\n");
+    }
+    let mut worst = Severity::Ok;
+    for (pkg, name) in CHECKED_PACKAGES {
+        let state = PackageState {
+            dump: dumps.remove(*pkg),
+            signers: signers
+                .remove(*pkg)
+                .unwrap_or_else(|| Err("APK path not available".into())),
+            storage: storage.get(*pkg).copied(),
+        };
+        output.push_str(&format!("\n  {} ({}):\n", name, pkg));
+        for f in check_package(pkg, &state, allowlist.as_ref(), enforce, &trusted) {
+            worst = worst.max(f.severity);
+            let tag = match f.severity {
+                Severity::Ok => "OK  ",
+                Severity::Warn => "WARN",
+                Severity::Fail => "FAIL",
+            };
+            output.push_str(&format!("    [{}] {}\n", tag, f.message));
+            if let Some(fix) = f.fix {
+                output.push_str(&format!("           fix: {}\n", fix));
+            }
+        }
+    }
+    output.push_str(match worst {
+        Severity::Ok => "\n  Result: GMS looks healthy.\n",
+        Severity::Warn => "\n  Result: GMS usable, review the warnings.\n",
+        Severity::Fail => "\n  Result: GMS is broken or tampered -- apply the fixes above.\n",
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lp(data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_le_bytes().to_vec();
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// A signing block with one v2 signer carrying `cert`.
+    fn signing_block(cert: &[u8]) -> Vec<u8> {
+        let mut signed_data = lp(&[]);
+        signed_data.extend(lp(&lp(cert)));
+        let signer = lp(&lp(&signed_data));
+        let value = lp(&signer);
+
+        let mut pair = SCHEME_V2_ID.to_le_bytes().to_vec();
+        pair.extend(value);
+        let mut pairs = (pair.len() as u64).to_le_bytes().to_vec();
+        pairs.extend(pair);
+
+        let size = (pairs.len() + 24) as u64;
+        let mut block = size.to_le_bytes().to_vec();
+        block.extend(pairs);
+        block.extend(size.to_le_bytes());
+        block.extend_from_slice(SIG_BLOCK_MAGIC);
+        block
+    }
+
+    #[test]
+    fn test_signing_block_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let block = signing_block(b"fake-der");
+        let mut window = vec![0u8; 100];
+        window.extend(&block);
+        assert_eq!(signing_block_len(&window)? as usize, block.len());
+
+        let certs = parse_signing_block(&block)?;
+        assert_eq!(certs.len(), 1);
+        assert_eq!(certs[0].scheme, "v2");
+        assert_eq!(
+            certs[0].sha256,
+            hex::encode_upper(Sha256::digest(b"fake-der"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_signing_block() {
+        assert!(signing_block_len(&[0u8; 64]).unwrap_err().contains("v1"));
+    }
+
+    #[test]
+    fn test_central_directory_offset() -> Result<(), Box<dyn std::error::Error>> {
+        let mut tail = vec![0xAAu8; 10];
+        tail.extend(EOCD_SIG.to_le_bytes());
+        tail.extend([0u8; 8]);
+        tail.extend(1234u32.to_le_bytes()); // cd size
+        tail.extend(5678u32.to_le_bytes()); // cd offset
+        tail.extend(3u16.to_le_bytes());
+        tail.extend(b"abc");
+        assert_eq!(central_directory_offset(&tail)?, 5678);
+        assert!(central_directory_offset(&tail[..tail.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_od_hex() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(parse_od_hex(" 41 50\n 4b\n")?, b"APK");
+        Ok(())
+    }
+
+    const DUMP: &str = "\
+Packages:
+  Package [com.google.android.gms] (abc):
+    versionCode=243530038 minSdk=31 targetSdk=34
+    versionName=24.35.30 (190400-668537210)
+    pkgFlags=[ SYSTEM HAS_CODE ALLOW_CLEAR_USER_DATA ]
+    privateFlags=[ PRIVATE_FLAG_ACTIVITIES_RESIZE PRIVILEGED PRODUCT ]
+    requested permissions:
+      android.permission.INTERNET
+      android.permission.READ_PRIVILEGED_PHONE_STATE
+      android.permission.MANAGE_USB
+      android.permission.CAMERA
+    install permissions:
+      android.permission.INTERNET: granted=true
+      android.permission.MANAGE_USB: granted=true
+    User 0: ceDataInode=1 installed=true hidden=false suspended=false stopped=false notLaunched=false enabled=0 instant=false
+      runtime permissions:
+        android.permission.CAMERA: granted=false, flags=[ USER_SET ]
+      disabledComponents:
+        com.google.android.gms.chimera.GmsIntentOperationService
+Hidden system packages:
+  Package [com.google.android.gms] (def):
+    versionName=1.0
+";
+
+    #[test]
+    fn test_parse_package_dump() {
+        let dump = parse_package_dump(DUMP);
+        assert_eq!(dump.version_name, "24.35.30 (190400-668537210)");
+        assert_eq!(dump.version_code, "243530038");
+        assert!(dump.system && dump.privileged);
+        assert_eq!(dump.enabled_state, 0);
+        assert_eq!(dump.requested.len(), 4);
+        assert!(dump
+            .install_granted
+            .contains("android.permission.MANAGE_USB"));
+        assert_eq!(dump.runtime_denied, vec!["android.permission.CAMERA"]);
+        assert_eq!(
+            dump.disabled_components,
+            vec!["com.google.android.gms.chimera.GmsIntentOperationService"]
+        );
+    }
+
+    #[test]
+    fn test_parse_privapp_allowlist() {
+        let xml = r#"<permissions>
+            <privapp-permissions package="com.google.android.gms">
+                <permission name="android.permission.MANAGE_USB"/>
+                <deny-permission name="android.permission.FOO"/>
+            </privapp-permissions>
+        </permissions>"#;
+        let map = parse_privapp_allowlist(xml);
+        let set = &map["com.google.android.gms"];
+        assert!(set.contains("android.permission.MANAGE_USB"));
+        assert!(!set.contains("android.permission.FOO"));
+    }
+
+    #[test]
+    fn test_parse_diskstats() {
+        let text = "Package Names: [\"com.android.vending\",\"com.google.android.gms\"]\n\
+                    App Sizes: [100,200]\nApp Data Sizes: [10,20]\nCache Sizes: [1,2]\n";
+        let map = parse_diskstats(text);
+        assert_eq!(map["com.google.android.gms"], (200, 20, 2));
+    }
+
+    #[test]
+    fn test_check_package_findings() {
+        let trusted = vec![(GOOGLE_SIGNERS[0].0.to_string(), "Google".to_string())];
+        let state = PackageState {
+            dump: Some(parse_package_dump(DUMP)),
+            signers: Ok(vec![SignerCert {
+                scheme: "v3",
+                sha256: "00".repeat(32),
+            }]),
+            storage: Some((1, 2, 3)),
+        };
+        let findings = check_package(
+            "com.google.android.gms",
+            &state,
+            Some(&BTreeMap::new()),
+            true,
+            &trusted,
+        );
+        let fails: Vec<&Finding> = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Fail)
+            .collect();
+        assert_eq!(fails.len(), 2, "{:?}", findings);
+        assert!(fails[0].message.starts_with("Unknown signer"));
+        assert!(fails[1].message.contains("READ_PRIVILEGED_PHONE_STATE"));
+        assert!(!fails[1].message.contains("MANAGE_USB"));
+        assert!(findings
+            .iter()
+            .any(|f| f.fix.as_deref() == Some("adb shell pm grant com.google.android.gms android.permission.CAMERA (repeat per permission)")));
+        assert!(findings
+            .iter()
+            .all(|f| f.severity == Severity::Ok || f.fix.is_some()));
+    }
+
+    #[test]
+    fn test_missing_package() {
+        let state = PackageState {
+            dump: None,
+            signers: Err("n/a".into()),
+            storage: None,
+        };
+        let findings = check_package("com.android.vending", &state, None, false, &[]);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Fail);
+    }
+}
@@ -3,6 +3,7 @@
 /// Battery, screen, sensors, camera, audio, connectivity,
 /// biometrics, USB, vibration, and general hardware tests.
 use super::adb_shell;
+use super::batch::BatchScript;
 use std::fmt::Write;
 
 /// Run all available hardware tests.
@@ -52,41 +53,21 @@ pub fn run_all(serial: &str) -> String {
         "getprop gsm.defaultpdpcontext.active 2>/dev/null",
     ];
 
-    let capacity: usize = commands.iter().map(|c| c.len() + 24).sum();
-    let mut script = String::with_capacity(capacity);
-    for cmd in &commands {
-        script.push_str(cmd);
-        script.push_str("; echo B_MARKER_FOEM_$?\n");
-    }
-
-    match adb_shell(serial, &["sh", "-c", &script]) {
-        Ok(res) => {
-            let mut parts = Vec::new();
-            let mut current_part = String::new();
-            let mut current_status = 0;
-
-            for line in res.lines() {
-                if let Some(stripped) = line.strip_prefix("B_MARKER_FOEM_") {
-                    if let Ok(status) = stripped.parse::<i32>() {
-                        parts.push((current_part.trim_end().to_string(), status));
-                        current_part.clear();
-                        current_status = status;
-                        continue;
-                    }
-                }
-                current_part.push_str(line);
-                current_part.push('\n');
-            }
+    let batch = BatchScript::from_commands(
+        &commands
+            .iter()
+            .enumerate()
+            .map(|(i, cmd)| (i.to_string(), *cmd))
+            .collect::<Vec<_>>(),
+    );
 
-            // If the output ended without a marker (shouldn't happen), add the last part
-            if !current_part.is_empty() {
-                parts.push((current_part.trim_end().to_string(), current_status));
-            }
-
-            // Ensure we have enough parts
-            while parts.len() < commands.len() {
-                parts.push((String::new(), 1)); // Default to failure
-            }
+    match batch.run(serial) {
+        Ok(results) => {
+            // A truncated batch reports the missing commands as failed.
+            let parts: Vec<(String, i32)> = results
+                .iter()
+                .map(|r| (r.stdout.trim_end().to_string(), r.code.unwrap_or(1)))
+                .collect();
 
             append_battery_report(&mut output, &parts[0], &parts[1]);
             append_sensor_report(&mut output, &parts[2]);
@@ -401,37 +382,16 @@ pub fn battery_stats(serial: &str) -> String {
 /// Test display by launching display test activities.
 pub fn test_display(serial: &str) -> String {
     let mut output = String::from("Display Test:\n");
-    let script = "wm size 2>&1; echo B_MARKER_$?; \
-                  wm density 2>&1; echo B_MARKER_$?; \
-                  dumpsys display 2>&1; echo B_MARKER_$?; \
-                  getevent -lp 2>&1; echo B_MARKER_$?;";
-    match adb_shell(serial, &["sh", "-c", script]) {
-        Ok(res) => {
-            let mut results = Vec::new();
-            let mut remaining = res.as_str();
-            for _ in 0..4 {
-                if let Some(pos) = remaining.find("B_MARKER_") {
-                    let out = remaining[..pos].trim_end().to_string();
-                    remaining = &remaining[pos + "B_MARKER_".len()..];
-                    let status: String = remaining
-                        .chars()
-                        .take_while(|c| c.is_ascii_digit())
-                        .collect();
-                    remaining = &remaining[status.len()..];
-                    if remaining.starts_with("\r\n") {
-                        remaining = &remaining[2..];
-                    } else if remaining.starts_with('\n') {
-                        remaining = &remaining[1..];
-                    }
-                    if status == "0" {
-                        results.push(Ok(out));
-                    } else {
-                        results.push(Err(()));
-                    }
-                } else {
-                    results.push(Err(()));
-                }
-            }
+    let batch = BatchScript::from_commands(&[
+        ("size", "wm size"),
+        ("density", "wm density"),
+        ("display", "dumpsys display"),
+        ("input", "getevent -lp"),
+    ]);
+    match batch.run(serial) {
+        Ok(outputs) => {
+            let results: Vec<Result<String, ()>> =
+                outputs.iter().map(|o| o.result().map_err(|_| ())).collect();
 
             // 1. wm size
             match results.first().unwrap_or(&Err(())) {
@@ -0,0 +1,636 @@
+/// Read-only IMEI/identity integrity check.
+///
+/// Collects IMEIs per SIM slot from `service call iphonesubinfo`, radio
+/// properties and `AT+CGSN` on the diag port, then cross-checks them: Luhn
+/// check digit, null/zero values, TAC against the shop's offline TAC table,
+/// and agreement between sources and slots. Nothing is written to the device.
+use super::at::AtResponse;
+use super::batch;
+use super::repair::{open_diag_port, send_at_command};
+use super::{adb_shell, Manufacturer};
+use crate::adaptive_engine::autodetect_diag_port;
+use crate::exec;
+
+use std::collections::BTreeMap;
+
+/// Known placeholder IMEIs left behind by a wiped or corrupted EFS/NV.
+const NULL_IMEIS: &[&str] = &["000000000000000", "004999010640000", "123456789012347"];
+
+/// Reporting Body Identifier: the first two digits of the TAC.
+fn reporting_body(tac: &str) -> Option<&'static str> {
+    Some(match tac.get(..2)? {
+        "00" => "Test IMEI",
+        "01" => "PTCRB (USA)",
+        "35" | "44" | "98" => "BABT (UK)",
+        "86" => "TAF (China)",
+        "91" => "MSAI (India)",
+        "99" => "GHA (multi-RAT)",
+        _ => return None,
+    })
+}
+
+/// Luhn check over all 15 digits of an IMEI.
+pub fn luhn_valid(imei: &str) -> bool {
+    if imei.len() != 15 || !imei.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let sum: u32 = imei
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, b)| {
+            let d = (b - b'0') as u32;
+            if i % 2 == 1 {
+                let dd = d * 2;
+                dd / 10 + dd % 10
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TacEntry {
+    pub brand: String,
+    pub models: Vec<String>,
+}
+
+/// TAC -> model lookup read from `~/.foem/tac.csv`.
+///
+/// One `tac,brand,model[;model...]` row per allocation, with the first 8
+/// IMEI digits and the `ro.product.model` values they belong to; `#` starts
+/// a comment. The GSMA TAC database is licensed and cannot be redistributed,
+/// so FOEM ships no rows and shops maintain their own table.
+#[derive(Debug, Clone, Default)]
+pub struct TacTable {
+    entries: BTreeMap<String, TacEntry>,
+}
+
+impl TacTable {
+    pub fn load() -> Self {
+        let mut table = Self::default();
+        if let Ok(text) = std::fs::read_to_string(exec::foem_home().join("tac.csv")) {
+            table.extend_from_csv(&text);
+        }
+        table
+    }
+
+    pub fn extend_from_csv(&mut self, text: &str) {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ',').map(str::trim);
+            let (Some(tac), Some(brand), Some(models)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if tac.len() != 8 || !tac.bytes().all(|b| b.is_ascii_digit()) {
+                continue;
+            }
+            self.entries.insert(
+                tac.to_string(),
+                TacEntry {
+                    brand: brand.to_string(),
+                    models: models
+                        .split(';')
+                        .map(str::trim)
+                        .filter(|m| !m.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                },
+            );
+        }
+    }
+
+    pub fn get(&self, tac: &str) -> Option<&TacEntry> {
+        self.entries.get(tac)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn normalize_model(model: &str) -> String {
+    model
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// One IMEI value as reported by one source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reading {
+    pub source: String,
+    pub slot: u8,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Severity {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Ok => "OK  ",
+            Self::Warn => "WARN",
+            Self::Fail => "FAIL",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+fn finding(severity: Severity, message: String) -> Finding {
+    Finding { severity, message }
+}
+
+/// Extract the UTF-16 string from `service call` Parcel output, e.g.
+/// `0x00000000: 00000000 0000000f 00350033 '........3.5.'`.
+/// The first word is the exception status; a non-zero one (a denied
+/// caller's `SecurityException`) carries a message, not a value, so it
+/// yields an empty string.
+pub fn parse_parcel_string(output: &str) -> String {
+    let body = output
+        .split_once("Parcel(")
+        .map_or(output, |(_, body)| body)
+        .trim_start();
+    let body = match body.split_once(':') {
+        Some((offset, rest)) if offset.starts_with("0x") => rest,
+        _ => body,
+    };
+    let status = body.split_whitespace().next().unwrap_or_default();
+    if u32::from_str_radix(status, 16) != Ok(0) {
+        return String::new();
+    }
+    output
+        .lines()
+        .filter_map(|line| {
+            let start = line.find('\'')?;
+            let end = line.rfind('\'')?;
+            (end > start).then(|| &line[start + 1..end])
+        })
+        .flat_map(str::chars)
+        .filter(|c| c.is_ascii_digit())
+        .collect()
+}
+
+fn digits(value: &str) -> String {
+    value.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+const PROP_SOURCES: &[(&str, u8)] = &[
+    ("persist.radio.imei", 1),
+    ("persist.radio.imei1", 1),
+    ("persist.radio.imei2", 2),
+    ("ro.ril.oem.imei", 1),
+    ("ro.ril.oem.imei1", 1),
+    ("ro.ril.oem.imei2", 2),
+];
+
+/// SIM slots queried through `iphonesubinfo`.
+const SLOTS: [u8; 2] = [1, 2];
+
+/// `IPhoneSubInfo.getDeviceIdForPhone` for a 1-based `slot`. Android 11
+/// inserted `getDeviceIdWithFeature` before it, which shifts the transaction
+/// code; its new feature id argument is left off and reads as null.
+fn device_id_call(sdk: u32, slot: u8) -> String {
+    let code = if sdk >= 30 { 4 } else { 3 };
+    format!(
+        "service call iphonesubinfo {} i32 {} s16 com.android.shell",
+        code,
+        slot - 1
+    )
+}
+
+/// Gather IMEI readings and the device model over adb.
+fn collect_device_readings(serial: &str) -> (Vec<Reading>, String) {
+    let mut model = String::new();
+    let mut sdk = 0;
+    for (label, result) in batch::run_commands(
+        serial,
+        &[
+            ("model", "getprop ro.product.model"),
+            ("sdk", "getprop ro.build.version.sdk"),
+        ],
+    ) {
+        let value = result.unwrap_or_default();
+        match label.as_str() {
+            "model" => model = value.trim().to_string(),
+            _ => sdk = value.trim().parse().unwrap_or(0),
+        }
+    }
+
+    let mut cmds: Vec<(String, String)> = SLOTS
+        .iter()
+        .map(|slot| {
+            (
+                format!("iphonesubinfo {}", slot),
+                device_id_call(sdk, *slot),
+            )
+        })
+        .collect();
+    cmds.push(("ril.gsm.imei".into(), "getprop ril.gsm.imei".into()));
+    for (prop, _) in PROP_SOURCES {
+        cmds.push((prop.to_string(), format!("getprop {}", prop)));
+    }
+
+    let mut readings = Vec::new();
+    for (label, result) in batch::run_commands(serial, &cmds) {
+        let Ok(value) = result else {
+            continue;
+        };
+        if let Some(slot) = label
+            .strip_prefix("iphonesubinfo ")
+            .and_then(|s| s.parse().ok())
+        {
+            let imei = parse_parcel_string(&value);
+            if !imei.is_empty() {
+                readings.push(Reading {
+                    source: "service call iphonesubinfo".into(),
+                    slot,
+                    value: imei,
+                });
+            }
+            continue;
+        }
+        match label.as_str() {
+            // Some RILs publish both slots as "imei1,imei2".
+            "ril.gsm.imei" => {
+                for (i, part) in value.split(',').enumerate().take(2) {
+                    let imei = digits(part);
+                    if !imei.is_empty() {
+                        readings.push(Reading {
+                            source: "getprop ril.gsm.imei".into(),
+                            slot: i as u8 + 1,
+                            value: imei,
+                        });
+                    }
+                }
+            }
+            prop => {
+                let imei = digits(&value);
+                let slot = PROP_SOURCES
+                    .iter()
+                    .find(|(p, _)| *p == prop)
+                    .map_or(1, |(_, s)| *s);
+                if !imei.is_empty() {
+                    readings.push(Reading {
+                        source: format!("getprop {}", prop),
+                        slot,
+                        value: imei,
+                    });
+                }
+            }
+        }
+    }
+    (readings, model)
+}
+
+/// IMEI of the AT channel's SIM slot via `AT+CGSN`, when a diag port exists.
+fn collect_at_reading(port_name: &str) -> Result<Reading, String> {
+    let mut port = open_diag_port(port_name)?;
+    let resp = send_at_command(&mut port, "AT+CGSN").and_then(AtResponse::into_result)?;
+    Ok(Reading {
+        source: format!("AT+CGSN ({})", port_name),
+        slot: 1,
+        value: digits(&resp.value()),
+    })
+}
+
+/// Evaluate readings against each other and the TAC table.
+pub fn check_readings(readings: &[Reading], model: &str, tacs: &TacTable) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if readings.is_empty() {
+        findings.push(finding(
+            Severity::Fail,
+            "No IMEI could be read from any source (NV/EFS may be corrupted or root is missing)."
+                .into(),
+        ));
+        return findings;
+    }
+
+    for r in readings {
+        let what = format!("Slot {} via {}: {}", r.slot, r.source, r.value);
+        if NULL_IMEIS.contains(&r.value.as_str()) || r.value.bytes().all(|b| b == b'0') {
+            findings.push(finding(
+                Severity::Fail,
+                format!("{} is a null/placeholder IMEI", what),
+            ));
+            continue;
+        }
+        if !luhn_valid(&r.value) {
+            findings.push(finding(
+                Severity::Fail,
+                format!("{} fails the Luhn check or is not 15 digits", what),
+            ));
+            continue;
+        }
+        let tac = &r.value[..8];
+        let body = reporting_body(tac).unwrap_or("unknown reporting body");
+        match tacs.get(tac) {
+            Some(entry) => {
+                let device = normalize_model(model);
+                let known = entry.models.iter().any(|m| normalize_model(m) == device);
+                if model.is_empty() || known {
+                    findings.push(finding(
+                        Severity::Ok,
+                        format!(
+                            "{} -- TAC {} is {} {}",
+                            what,
+                            tac,
+                            entry.brand,
+                            entry.models.join("/")
+                        ),
+                    ));
+                } else {
+                    findings.push(finding(
+                        Severity::Fail,
+                        format!(
+                            "{} -- TAC {} belongs to {} {}, but the device reports {}",
+                            what,
+                            tac,
+                            entry.brand,
+                            entry.models.join("/"),
+                            model
+                        ),
+                    ));
+                }
+            }
+            None => findings.push(finding(
+                Severity::Ok,
+                format!(
+                    "{} -- valid ({}; TAC {} not in offline table)",
+                    what, body, tac
+                ),
+            )),
+        }
+    }
+
+    if tacs.is_empty() {
+        findings.push(finding(
+            Severity::Warn,
+            "Offline TAC table is empty: TAC-to-model check skipped \
+             (add rows to ~/.foem/tac.csv)."
+                .into(),
+        ));
+    }
+
+    let mut by_slot: BTreeMap<u8, Vec<&Reading>> = BTreeMap::new();
+    for r in readings {
+        by_slot.entry(r.slot).or_default().push(r);
+    }
+    for (slot, list) in &by_slot {
+        let first = &list[0].value;
+        if list.iter().any(|r| &r.value != first) {
+            let detail: Vec<String> = list
+                .iter()
+                .map(|r| format!("{} = {}", r.source, r.value))
+                .collect();
+            findings.push(finding(
+                Severity::Fail,
+                format!("Slot {} sources disagree: {}", slot, detail.join(", ")),
+            ));
+        }
+    }
+    if let (Some(s1), Some(s2)) = (by_slot.get(&1), by_slot.get(&2)) {
+        if s1.iter().any(|a| s2.iter().any(|b| a.value == b.value)) {
+            findings.push(finding(
+                Severity::Warn,
+                "Slot 1 and slot 2 report the same IMEI (cloned or mis-restored NV)".into(),
+            ));
+        }
+    }
+    findings
+}
+
+/// Build the intake integrity report for a connected device.
+pub fn integrity_report(serial: &str, manufacturer: &Manufacturer) -> String {
+    let (mut readings, model) = collect_device_readings(serial);
+    let mut output = format!(
+        "Identity Integrity Report ({} {}):\n",
+        manufacturer.name(),
+        if model.is_empty() {
+            "unknown model"
+        } else {
+            &model
+        }
+    );
+
+    match autodetect_diag_port() {
+        Some(port) => match collect_at_reading(&port) {
+            Ok(r) if !r.value.is_empty() => readings.push(r),
+            Ok(_) => output.push_str("  AT+CGSN returned no IMEI.\n"),
+            Err(e) => output.push_str(&format!("  AT+CGSN unavailable: {}\n", e)),
+        },
+        None => output.push_str("  No diag port: AT+CGSN skipped.\n"),
+    }
+    if adb_shell(serial, &["id", "-u"]).is_ok_and(|uid| uid.trim() != "0") && readings.is_empty() {
+        output.push_str("  Note: Android 10+ hides IMEIs from unprivileged shells.\n");
+    }
+
+    output.push_str("\n  Readings:\n");
+    for r in &readings {
+        output.push_str(&format!(
+            "    slot {} | {} | {}\n",
+            r.slot, r.source, r.value
+        ));
+    }
+
+    let findings = check_readings(&readings, &model, &TacTable::load());
+    let worst = findings
+        .iter()
+        .map(|f| f.severity)
+        .max()
+        .unwrap_or(Severity::Ok);
+    output.push_str("\n  Checks:\n");
+    for f in &findings {
+        output.push_str(&format!("    [{}] {}\n", f.severity.label(), f.message));
+    }
+    output.push_str(match worst {
+        Severity::Ok => "\n  Result: identity looks consistent.\n",
+        Severity::Warn => "\n  Result: review the warnings before starting repairs.\n",
+        Severity::Fail => "\n  Result: identity problems found -- suspect corrupted EFS/NV.\n",
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(source: &str, slot: u8, value: &str) -> Reading {
+        Reading {
+            source: source.into(),
+            slot,
+            value: value.into(),
+        }
+    }
+
+    fn table() -> TacTable {
+        let mut t = TacTable::default();
+        t.extend_from_csv("# comment\n49015420,Example,Model A; MODEL-A2\nbad,row\n");
+        t
+    }
+
+    #[test]
+    fn test_luhn() {
+        assert!(luhn_valid("490154203237518"));
+        assert!(!luhn_valid("490154203237519"));
+        assert!(!luhn_valid("49015420323751"));
+        assert!(!luhn_valid("49015420323751a"));
+    }
+
+    #[test]
+    fn test_parse_parcel_string() {
+        let out = "Result: Parcel(\n  0x00000000: 00000000 0000000f 00390034 00310030 '........4.9.0.1.'\n  \
+                   0x00000010: 00340035 00300032 00320033 00370033 '5.4.2.0.3.2.3.7.'\n  \
+                   0x00000020: 00310035 00000038                   '5.1.8...        ')";
+        assert_eq!(parse_parcel_string(out), "490154203237518");
+    }
+
+    #[test]
+    fn test_parse_parcel_string_ignores_exceptions() {
+        let denied = "Result: Parcel(\n  0x00000000: ffffffff 00000044 00650052 00750071 '....D...R.e.q.u.'\n  \
+                      0x00000010: 00720069 00730065 00730020 00650068 'i.r.e.s. .s.h.e.'\n  \
+                      0x00000020: 006c006c 00750020 00650073 00200072 'l.l. .u.s.e.r. .'\n  \
+                      0x00000030: 00300032 00300030 00000020          '2.0.0.0. ...    ')";
+        assert_eq!(parse_parcel_string(denied), "");
+        assert_eq!(
+            parse_parcel_string("Result: Parcel(fffffffc 00000000 '........')"),
+            ""
+        );
+        assert_eq!(parse_parcel_string("service: not found"), "");
+    }
+
+    #[test]
+    fn test_tac_csv_rows() {
+        let mut t = TacTable::default();
+        t.extend_from_csv(
+            "# tac,brand,model[;model...]\n\
+             35332509, Samsung , SM-G991B;SM-G991U; \n\
+             86891104,Xiaomi,M2101K9AG\n\
+             3533250,Samsung,SM-short\n\
+             8689110X,Xiaomi,bad digits\n\
+             86891104,Xiaomi,21081111RG\n",
+        );
+        assert_eq!(
+            t.get("35332509"),
+            Some(&TacEntry {
+                brand: "Samsung".into(),
+                models: vec!["SM-G991B".into(), "SM-G991U".into()],
+            })
+        );
+        // A later row for the same TAC replaces the earlier one.
+        assert_eq!(
+            t.get("86891104").map(|e| e.models.clone()),
+            Some(vec!["21081111RG".to_string()])
+        );
+        assert!(t.get("3533250").is_none());
+        assert!(t.get("8689110X").is_none());
+
+        let readings = vec![reading("getprop", 1, "353325090000005")];
+        let findings = check_readings(&readings, "SM-G991U", &t);
+        assert!(findings.iter().all(|f| f.severity == Severity::Ok));
+        let findings = check_readings(&readings, "M2101K9AG", &t);
+        assert!(findings.iter().any(|f| f.severity == Severity::Fail));
+    }
+
+    #[test]
+    fn test_empty_table_is_reported() {
+        let readings = vec![reading("getprop", 1, "490154203237518")];
+        let findings = check_readings(&readings, "Model A", &TacTable::default());
+        assert!(findings
+            .iter()
+            .any(|f| f.severity == Severity::Warn && f.message.contains("TAC table is empty")));
+
+        let findings = check_readings(&readings, "Model A", &table());
+        assert!(findings.iter().all(|f| f.severity == Severity::Ok));
+    }
+
+    #[test]
+    fn test_device_id_call_per_slot() {
+        assert_eq!(
+            device_id_call(29, 2),
+            "service call iphonesubinfo 3 i32 1 s16 com.android.shell"
+        );
+        assert_eq!(
+            device_id_call(34, 1),
+            "service call iphonesubinfo 4 i32 0 s16 com.android.shell"
+        );
+    }
+
+    #[test]
+    fn test_consistent_identity() {
+        let readings = vec![
+            reading("service call", 1, "490154203237518"),
+            reading("getprop", 1, "490154203237518"),
+        ];
+        let findings = check_readings(&readings, "model-a2", &table());
+        assert!(
+            findings.iter().all(|f| f.severity == Severity::Ok),
+            "{:?}",
+            findings
+        );
+    }
+
+    #[test]
+    fn test_model_mismatch_only_for_known_tac() {
+        let readings = vec![reading("getprop", 1, "490154203237518")];
+        let findings = check_readings(&readings, "Other Phone", &table());
+        assert_eq!(findings[0].severity, Severity::Fail);
+        assert!(findings[0].message.contains("belongs to Example"));
+
+        let findings = check_readings(&readings, "Other Phone", &TacTable::default());
+        assert_eq!(findings[0].severity, Severity::Ok);
+        assert!(findings[0].message.contains("not in offline table"));
+    }
+
+    #[test]
+    fn test_null_bad_luhn_and_disagreement() {
+        let readings = vec![
+            reading("service call", 1, "490154203237518"),
+            reading("AT+CGSN", 1, "000000000000000"),
+            reading("getprop imei2", 2, "490154203237519"),
+        ];
+        let findings = check_readings(&readings, "", &TacTable::default());
+        let fails: Vec<&str> = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Fail)
+            .map(|f| f.message.as_str())
+            .collect();
+        assert_eq!(fails.len(), 3, "{:?}", fails);
+        assert!(fails[0].contains("null/placeholder"));
+        assert!(fails[1].contains("Luhn"));
+        assert!(fails[2].starts_with("Slot 1 sources disagree"));
+    }
+
+    #[test]
+    fn test_duplicate_slots_warn() {
+        let readings = vec![
+            reading("a", 1, "490154203237518"),
+            reading("b", 2, "490154203237518"),
+        ];
+        let findings = check_readings(&readings, "", &TacTable::default());
+        assert_eq!(findings.last().map(|f| f.severity), Some(Severity::Warn));
+    }
+
+    #[test]
+    fn test_no_readings() {
+        let findings = check_readings(&[], "", &TacTable::default());
+        assert_eq!(findings[0].severity, Severity::Fail);
+    }
+}
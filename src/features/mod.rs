@@ -1,11 +1,25 @@
 pub mod ai_assistant;
+pub mod at;
+pub mod batch;
 /// Feature modules for FOEM.
 pub mod bootloader;
+pub mod csc;
+pub mod diag;
+pub mod edl;
 pub mod flash;
+pub mod gms;
 pub mod hardware_test;
+pub mod identity;
+pub mod modem_crash;
+pub mod modem_log;
 pub mod network;
+pub mod partitions;
+pub mod prop_snapshot;
 pub mod repair;
+pub mod serial_console;
+pub mod shell_session;
 pub mod tools;
+pub mod vault;
 
 use crate::exec;
 
@@ -114,11 +128,10 @@ pub fn fastboot(serial: &str, args: &[&str]) -> Result<String, String> {
     exec::run_with_serial("fastboot", serial, args, "Failed to execute Fastboot")
 }
 
-/// Shared helper: run an ADB shell command.
+/// Shared helper: run an ADB shell command through the device's persistent
+/// shell session.
 pub fn adb_shell(serial: &str, args: &[&str]) -> Result<String, String> {
-    let mut full_args = vec!["shell"];
-    full_args.extend_from_slice(args);
-    adb(serial, &full_args)
+    shell_session::run(serial, args)
 }
 
 #[cfg(test)]
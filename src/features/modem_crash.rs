@@ -0,0 +1,622 @@
+/// Modem / subsystem crash collector and analyzer.
+///
+/// Gathers subsystem restart (SSR) events and crash reasons from the kernel
+/// log, the radio log buffer and the `msm_subsys`/`remoteproc` sysfs nodes,
+/// checks for ramdumps, and turns them into a timeline with a probable cause
+/// per crash. The raw evidence is stored as a vault entry for the device.
+use super::batch;
+use super::vault::{self, BackupKind, VaultFile, VaultManifest};
+
+use std::collections::BTreeMap;
+use std::fs;
+
+const RADIO_LOG_LINES: u32 = 5000;
+
+const RAMDUMP_DIRS: &[&str] = &[
+    "/data/vendor/ramdump",
+    "/data/vendor/ssrdump",
+    "/data/ramdump",
+    "/sdcard/ramdump",
+];
+
+/// Probable cause category of a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cause {
+    FirmwareMismatch,
+    NvCorruption,
+    RfHardware,
+    Unknown,
+}
+
+impl Cause {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::FirmwareMismatch => "firmware mismatch",
+            Self::NvCorruption => "NV/EFS corruption",
+            Self::RfHardware => "RF hardware",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    fn advice(&self) -> &'static str {
+        match self {
+            Self::FirmwareMismatch => {
+                "Flash modem/NON-HLOS, modem config (MCFG) and the matching system build together."
+            }
+            Self::NvCorruption => "Restore EFS/NV from the vault, or rebuild NV with the OEM tool.",
+            Self::RfHardware => {
+                "Check RF calibration, the transceiver/PA area and antenna contacts; reflashing will not help."
+            }
+            Self::Unknown => "Capture a modem log (DIAG) during the next crash for more detail.",
+        }
+    }
+}
+
+/// Classify a crash reason string. Keywords match whole words of the reason
+/// split on non-alphanumerics, so `nv` matches `mcfg_nv.c` but not `env`.
+/// RF is checked first because RF NV items (`rfnv`) would otherwise count as
+/// generic NV corruption.
+pub fn classify(reason: &str) -> Cause {
+    const RF: &[&str] = &[
+        "rfnv",
+        "rfc",
+        "rfm",
+        "rffe",
+        "rfcal",
+        "rfic",
+        "txagc",
+        "tuner",
+        "antenna",
+        "transceiver",
+    ];
+    /// RF part families, matched only when followed by a part number
+    /// (`wtr3925`, `qpa4360`).
+    const RF_PARTS: &[&str] = &["wtr", "qpa", "qet", "sdr"];
+    const FIRMWARE: &[&str] = &[
+        "mcfg",
+        "mbn",
+        "mismatch",
+        "version",
+        "auth",
+        "signature",
+        "invalid elf",
+        "failed to load",
+        "hwio",
+        "sw id",
+    ];
+    const NV: &[&str] = &["efs", "nv", "nvim", "sfs", "imei", "qmi nv"];
+
+    let lower = reason.to_ascii_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let padded = format!(" {} ", words.join(" "));
+    let has = |keys: &[&str]| keys.iter().any(|k| padded.contains(&format!(" {} ", k)));
+    let is_part = |word: &&str| {
+        RF_PARTS.iter().any(|family| {
+            word.strip_prefix(family)
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        })
+    };
+
+    if has(RF) || words.iter().any(is_part) {
+        Cause::RfHardware
+    } else if has(FIRMWARE) {
+        Cause::FirmwareMismatch
+    } else if has(NV) {
+        Cause::NvCorruption
+    } else {
+        Cause::Unknown
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// Crash with a reason string reported by the subsystem.
+    Crash,
+    /// Watchdog bite or fatal error without a reason.
+    Watchdog,
+    /// Restart sequence started by the kernel.
+    Restart,
+    /// RIL reported the radio unavailable or a modem reset.
+    RilError,
+    Ramdump,
+}
+
+impl EventKind {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Crash => "CRASH",
+            Self::Watchdog => "WDOG",
+            Self::Restart => "SSR",
+            Self::RilError => "RIL",
+            Self::Ramdump => "DUMP",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashEvent {
+    /// Seconds since the Unix epoch; kernel times are approximate.
+    pub epoch: f64,
+    pub source: &'static str,
+    pub kind: EventKind,
+    pub subsystem: String,
+    pub detail: String,
+    pub cause: Option<Cause>,
+}
+
+/// Split `[  123.456789] message` into uptime seconds and message.
+fn kernel_line(line: &str) -> Option<(f64, &str)> {
+    let rest = line.trim_start().strip_prefix('[')?;
+    let (ts, msg) = rest.split_once(']')?;
+    Some((ts.trim().parse().ok()?, msg.trim()))
+}
+
+/// Subsystem name from messages like `... requested for modem, restart_level`.
+fn subsystem_after<'a>(msg: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = &msg[msg.find(marker)? + marker.len()..];
+    let name = rest.trim_start().split([',', ' ', '.', ':']).next()?;
+    (!name.is_empty()).then_some(name)
+}
+
+/// Parse kernel log lines; `boot_epoch` converts uptime to wall time.
+pub fn parse_kernel_log(text: &str, boot_epoch: f64) -> Vec<CrashEvent> {
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let Some((uptime, msg)) = kernel_line(line) else {
+            continue;
+        };
+        let lower = msg.to_ascii_lowercase();
+        let event = |kind, subsystem: &str, detail: &str, cause| CrashEvent {
+            epoch: boot_epoch + uptime,
+            source: "kernel",
+            kind,
+            subsystem: subsystem.to_string(),
+            detail: detail.to_string(),
+            cause,
+        };
+
+        if let Some(idx) = lower.find("subsystem failure reason:") {
+            // "modem subsystem failure reason: mcfg_nv.c:245:MCFG ...".
+            let subsystem = msg[..idx].split_whitespace().last().unwrap_or("?");
+            let reason = msg[idx + "subsystem failure reason:".len()..]
+                .trim()
+                .trim_end_matches('.');
+            events.push(event(
+                EventKind::Crash,
+                subsystem,
+                reason,
+                Some(classify(reason)),
+            ));
+        } else if let Some(idx) = lower.find("fatal error received:") {
+            // remoteproc (qcom_q6v5): "... 4080000.remoteproc: fatal error received: <reason>".
+            let reason = msg[idx + "fatal error received:".len()..].trim();
+            events.push(event(
+                EventKind::Crash,
+                "mpss",
+                reason,
+                Some(classify(reason)),
+            ));
+        } else if lower.contains("restart sequence requested for") {
+            let subsystem = subsystem_after(msg, "requested for").unwrap_or("?");
+            events.push(event(EventKind::Restart, subsystem, msg, None));
+        } else if lower.contains("crash detected in") {
+            let subsystem = subsystem_after(msg, "crash detected in").unwrap_or("?");
+            events.push(event(EventKind::Restart, subsystem, msg, None));
+        } else if lower.contains("watchdog bite") || lower.contains("err_fatal") {
+            let subsystem = if lower.contains("modem") {
+                "modem"
+            } else {
+                "?"
+            };
+            events.push(event(EventKind::Watchdog, subsystem, msg, None));
+        } else if lower.contains("ramdump")
+            && (lower.contains("collect") || lower.contains("saved"))
+        {
+            events.push(event(EventKind::Ramdump, "?", msg, None));
+        }
+    }
+    events
+}
+
+/// Parse `logcat -v epoch` radio buffer lines for RIL error patterns.
+pub fn parse_radio_log(text: &str) -> Vec<CrashEvent> {
+    const PATTERNS: &[(&str, &str)] = &[
+        ("radio_not_available", "radio not available"),
+        ("modem_restart", "modem restart"),
+        ("modem restart", "modem restart"),
+        ("ssr", "modem SSR reported to RIL"),
+        ("modem_err", "modem error"),
+        ("qmi service not ready", "QMI service down"),
+        ("qmi_err_", "QMI error"),
+    ];
+    let mut events = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(epoch) = fields.next().and_then(|t| t.parse::<f64>().ok()) else {
+            continue;
+        };
+        let lower = line.to_ascii_lowercase();
+        let Some((_, what)) = PATTERNS.iter().find(|(p, _)| {
+            if *p == "ssr" {
+                lower
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .any(|w| w == "ssr")
+            } else {
+                lower.contains(p)
+            }
+        }) else {
+            continue;
+        };
+        let msg = line.split_once(": ").map_or(line, |(_, m)| m).trim();
+        events.push(CrashEvent {
+            epoch,
+            source: "radio",
+            kind: EventKind::RilError,
+            subsystem: "ril".into(),
+            detail: format!("{}: {}", what, msg),
+            cause: None,
+        });
+    }
+    events
+}
+
+/// `name|state|restart_level|crash_count` rows from the sysfs listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubsystemState {
+    pub name: String,
+    pub state: String,
+    pub restart_level: String,
+    pub crash_count: Option<u32>,
+}
+
+pub fn parse_subsystems(text: &str) -> Vec<SubsystemState> {
+    text.lines()
+        .filter_map(|line| {
+            let mut f = line.trim().split('|').map(str::trim);
+            let name = f.next().filter(|n| !n.is_empty())?;
+            Some(SubsystemState {
+                name: name.to_string(),
+                state: f.next().unwrap_or("").to_string(),
+                restart_level: f.next().unwrap_or("").to_string(),
+                crash_count: f.next().and_then(|c| c.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+/// Count of files listed by `ls` per ramdump directory.
+pub fn parse_ramdumps(text: &str) -> Vec<(String, usize)> {
+    let mut dirs = Vec::new();
+    let mut current: Option<(String, usize)> = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(dir) = line.strip_suffix(':') {
+            dirs.extend(current.take());
+            current = Some((dir.to_string(), 0));
+        } else if !line.is_empty() && !line.starts_with("total ") {
+            if let Some((_, n)) = current.as_mut() {
+                *n += 1;
+            }
+        }
+    }
+    dirs.extend(current);
+    dirs.retain(|(_, n)| *n > 0);
+    dirs
+}
+
+/// Crashes per cause, most frequent first; unknown causes sort last.
+fn cause_summary(events: &[CrashEvent]) -> Vec<(Cause, usize)> {
+    let mut counts: BTreeMap<Cause, usize> = BTreeMap::new();
+    for cause in events.iter().filter_map(|e| e.cause) {
+        *counts.entry(cause).or_default() += 1;
+    }
+    let mut list: Vec<(Cause, usize)> = counts.into_iter().collect();
+    list.sort_by_key(|(cause, n)| (*cause == Cause::Unknown, std::cmp::Reverse(*n)));
+    list
+}
+
+/// Render the timeline and cause summary.
+pub fn render_report(
+    events: &[CrashEvent],
+    subsystems: &[SubsystemState],
+    ramdumps: &[(String, usize)],
+) -> String {
+    let mut output = String::from("Modem Crash Analysis:\n");
+
+    if !subsystems.is_empty() {
+        output.push_str("\n  Subsystems:\n");
+        for s in subsystems {
+            output.push_str(&format!(
+                "    {:<10} state={:<8} restart_level={}{}\n",
+                s.name,
+                s.state,
+                if s.restart_level.is_empty() {
+                    "?"
+                } else {
+                    &s.restart_level
+                },
+                s.crash_count
+                    .map(|n| format!(" crashes={}", n))
+                    .unwrap_or_default()
+            ));
+        }
+    }
+
+    output.push_str("\n  Ramdumps:\n");
+    if ramdumps.is_empty() {
+        output.push_str("    none found\n");
+    }
+    for (dir, n) in ramdumps {
+        output.push_str(&format!("    {} -- {} file(s)\n", dir, n));
+    }
+
+    output.push_str("\n  Timeline (UTC, kernel times approximate):\n");
+    if events.is_empty() {
+        output.push_str("    no SSR, crash or RIL error events found\n");
+    }
+    for e in events {
+        output.push_str(&format!(
+            "    {} [{:<5}] {:<6} {:<8} {}{}\n",
+            vault::utc_timestamp(e.epoch.max(0.0) as u64),
+            e.kind.label(),
+            e.source,
+            e.subsystem,
+            e.detail,
+            e.cause
+                .map(|c| format!("  => {}", c.label()))
+                .unwrap_or_default()
+        ));
+    }
+
+    let causes = cause_summary(events);
+    output.push_str("\n  Probable cause:\n");
+    match causes.first() {
+        Some((cause, _)) => {
+            for (c, n) in &causes {
+                output.push_str(&format!("    {} -- {} crash(es)\n", c.label(), n));
+            }
+            output.push_str(&format!("    Suggested action: {}\n", cause.advice()));
+        }
+        None if events.iter().any(|e| e.kind != EventKind::RilError) => {
+            output.push_str("    Restarts without a reported reason (watchdog/fatal error).\n");
+            output.push_str(&format!(
+                "    Suggested action: {}\n",
+                Cause::Unknown.advice()
+            ));
+        }
+        None => output.push_str("    No modem crashes recorded since boot.\n"),
+    }
+    output
+}
+
+/// Write the raw evidence and the report as a vault entry for `serial`.
+fn save_evidence(serial: &str, evidence: &[(&str, &str)], report: &str) -> Result<String, String> {
+    let dir = vault::new_entry_dir(serial)?;
+    let mut files = Vec::new();
+    for (name, text) in evidence.iter().chain([&("report", report)]) {
+        let file = format!("{}.txt", name);
+        let path = dir.join(&file);
+        fs::write(&path, text).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        files.push(VaultFile {
+            partition: name.to_string(),
+            file,
+            size: text.len() as u64,
+            sha256: vault::sha256_file(&path)?,
+            compression: vault::Compression::None,
+            raw_sha256: None,
+        });
+    }
+    let manifest = VaultManifest {
+        kind: BackupKind::CrashEvidence,
+        created_at: vault::now_secs(),
+        foem_version: crate::VERSION.to_string(),
+        device: vault::device_identity(serial),
+        files,
+        note: Some("Modem crash analysis evidence".into()),
+    };
+    vault::write_manifest(&dir, &manifest)?;
+    Ok(dir.display().to_string())
+}
+
+/// Collect crash evidence from the device, analyze it and save it to the vault.
+pub fn analyze_modem_crashes(serial: &str) -> String {
+    let subsys_script = "for d in /sys/bus/msm_subsys/devices/*; do [ -e \"$d/name\" ] && \
+        echo \"$(cat $d/name)|$(cat $d/state 2>/dev/null)|$(cat $d/restart_level 2>/dev/null)|$(cat $d/crash_count 2>/dev/null)\"; done; \
+        for d in /sys/class/remoteproc/remoteproc*; do [ -e \"$d/name\" ] && \
+        echo \"$(cat $d/name)|$(cat $d/state 2>/dev/null)|$(cat $d/recovery 2>/dev/null)|\"; done; true";
+    let ramdump_script = format!("ls -l {} 2>/dev/null; true", RAMDUMP_DIRS.join(" "));
+    let radio_cmd = format!("logcat -b radio -d -v epoch -t {}", RADIO_LOG_LINES);
+    let cmds: Vec<(&str, &str)> = vec![
+        ("clock", "cat /proc/uptime; date +%s"),
+        ("dmesg", "dmesg 2>/dev/null || su -c dmesg"),
+        ("radio", radio_cmd.as_str()),
+        ("subsys", subsys_script),
+        ("ramdumps", ramdump_script.as_str()),
+    ];
+
+    let mut raw: BTreeMap<String, String> = BTreeMap::new();
+    let mut notes = Vec::new();
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        match res {
+            Ok(out) => {
+                raw.insert(label, out);
+            }
+            Err(e) => notes.push(format!("  {} unavailable: {}\n", label, e)),
+        }
+    }
+    let text = |key: &str| raw.get(key).map(String::as_str).unwrap_or("");
+
+    let mut clock = text("clock").split_whitespace();
+    let uptime: f64 = clock.next().and_then(|v| v.parse().ok()).unwrap_or(0.0);
+    let now: f64 = clock.nth(1).and_then(|v| v.parse().ok()).unwrap_or(0.0);
+    let boot_epoch = (now - uptime).max(0.0);
+
+    let mut events = parse_kernel_log(text("dmesg"), boot_epoch);
+    events.extend(parse_radio_log(text("radio")));
+    events.sort_by(|a, b| a.epoch.total_cmp(&b.epoch));
+    let subsystems = parse_subsystems(text("subsys"));
+    let ramdumps = parse_ramdumps(text("ramdumps"));
+
+    let mut output = render_report(&events, &subsystems, &ramdumps);
+    for note in &notes {
+        output.push_str(note);
+    }
+    let evidence: Vec<(&str, &str)> = ["dmesg", "radio", "subsys", "ramdumps"]
+        .iter()
+        .filter_map(|k| raw.get(*k).map(|v| (*k, v.as_str())))
+        .collect();
+    match save_evidence(serial, &evidence, &output) {
+        Ok(dir) => output.push_str(&format!("\n  Evidence saved: {}\n", dir)),
+        Err(e) => output.push_str(&format!("\n  Evidence not saved: {}\n", e)),
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockGuard;
+
+    impl Drop for MockGuard {
+        fn drop(&mut self) {
+            crate::exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+            vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    const DMESG: &str = "\
+[   12.000000] random: crng init done
+[  100.500000] subsys-restart: __subsystem_restart_dev(): Restart sequence requested for modem, restart_level = RELATED.
+[  100.400000] subsys-pil-tz 4080000.qcom,mss: modem subsystem failure reason: mcfg_nv.c:245:MCFG version mismatch.
+[  200.000000] qcom-q6v5-mss 4080000.remoteproc: fatal error received: rfc_common.c:1120:RFC init failed
+[  300.000000] subsys-pil-tz 4080000.qcom,mss: modem subsystem failure reason: efs_esp.c:88:EFS sync failed.
+";
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify("mcfg_nv.c:245:MCFG version mismatch"),
+            Cause::FirmwareMismatch
+        );
+        assert_eq!(classify("rfnv_items.c:10:bad item"), Cause::RfHardware);
+        assert_eq!(
+            classify("efs_esp.c:88:EFS sync failed"),
+            Cause::NvCorruption
+        );
+        assert_eq!(classify("dog.c:1:timeout"), Cause::Unknown);
+        assert_eq!(classify("wtr3925_init: no ack"), Cause::RfHardware);
+        // Keywords inside longer identifiers are not evidence.
+        assert_eq!(
+            classify("ipa_heap_alloc.c:30:perf_env assert"),
+            Cause::Unknown
+        );
+        assert_eq!(classify("compile_time.c:3:assert"), Cause::Unknown);
+        assert_eq!(classify("wtrace.c:7:overflow"), Cause::Unknown);
+    }
+
+    #[test]
+    fn test_parse_kernel_log() {
+        let events = parse_kernel_log(DMESG, 1_000.0);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].kind, EventKind::Restart);
+        assert_eq!(events[0].subsystem, "modem");
+        assert_eq!(events[0].epoch, 1_100.5);
+        assert_eq!(events[1].kind, EventKind::Crash);
+        assert_eq!(events[1].subsystem, "modem");
+        assert_eq!(events[1].detail, "mcfg_nv.c:245:MCFG version mismatch");
+        assert_eq!(events[1].cause, Some(Cause::FirmwareMismatch));
+        assert_eq!(events[2].cause, Some(Cause::RfHardware));
+        assert_eq!(events[3].cause, Some(Cause::NvCorruption));
+    }
+
+    #[test]
+    fn test_parse_radio_log() {
+        let log = "1700000000.123  1234  1250 E RILJ    : [UNSL]< RADIO_NOT_AVAILABLE\n\
+                   1700000001.000  1234  1250 D RILJ    : getSignalStrength ok\n\
+                   1700000002.000  1234  1250 E qcrild  : SSR detected for modem\n\
+                   --------- beginning of radio\n";
+        let events = parse_radio_log(log);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].epoch, 1_700_000_000.123);
+        assert!(events[0].detail.starts_with("radio not available"));
+        assert!(events[1].detail.starts_with("modem SSR"));
+    }
+
+    #[test]
+    fn test_parse_subsystems_and_ramdumps() {
+        let subs = parse_subsystems("modem|ONLINE|RELATED|3\nadsp|ONLINE||\n\n");
+        assert_eq!(subs.len(), 2);
+        assert_eq!(subs[0].crash_count, Some(3));
+        assert_eq!(subs[1].crash_count, None);
+
+        let ls = "/data/vendor/ramdump:\ntotal 8\n-rw------- 1 root root 4096 2024-01-01 00:00 modem_0.elf\n\n\
+                  /data/vendor/ssrdump:\ntotal 0\n";
+        assert_eq!(
+            parse_ramdumps(ls),
+            vec![("/data/vendor/ramdump".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_report_probable_cause() {
+        let mut events = parse_kernel_log(DMESG, 0.0);
+        events.push(events[1].clone());
+        let report = render_report(&events, &[], &[]);
+        assert!(report.contains("firmware mismatch -- 2 crash(es)"));
+        assert!(report.contains("Suggested action: Flash modem"));
+        assert!(report.contains("none found"));
+    }
+
+    #[test]
+    fn test_analyze_saves_evidence() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_modem_crash_evidence");
+        let _ = fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        crate::exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|_, args, _| {
+                let script = args.last().copied().unwrap_or("");
+                if script.contains("/proc/uptime") {
+                    crate::features::batch::mock_stdout(
+                        script,
+                        &[
+                            ("1000.0 900.0\n1700000000", 0),
+                            (DMESG, 0),
+                            ("", 0),
+                            ("modem|ONLINE|RELATED|2", 0),
+                            ("", 0),
+                        ],
+                    )
+                } else {
+                    Ok(String::new())
+                }
+            }));
+        });
+        let _guard = MockGuard;
+
+        let out = analyze_modem_crashes("SER1");
+        assert!(out.contains("crashes=2"), "{}", out);
+        assert!(out.contains("Evidence saved"), "{}", out);
+
+        let entries = vault::list_entries("SER1");
+        assert_eq!(entries.len(), 1);
+        let manifest = &entries[0].manifest;
+        assert_eq!(manifest.kind, BackupKind::CrashEvidence);
+        let names: Vec<&str> = manifest
+            .files
+            .iter()
+            .map(|f| f.partition.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["dmesg", "radio", "subsys", "ramdumps", "report"]
+        );
+        for f in &manifest.files {
+            vault::verify_file(&entries[0].dir, f)?;
+        }
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+}
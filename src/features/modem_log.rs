@@ -0,0 +1,410 @@
+/// Modem log capture over the Qualcomm DIAG port.
+///
+/// Log and event masks are configured through `diag::DiagClient`, the raw
+/// HDLC stream is written unchanged to a `.qmdl` file on the host (readable by
+/// QCAT, QCSuper and friends), and each packet is decoded into a short record
+/// for the in-app view.
+use super::diag::{self, DiagClient, FrameDecoder};
+use super::repair::open_diag_port;
+use super::vault;
+use crate::exec;
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Log equipment IDs worth capturing for "no service" troubleshooting.
+pub const EQUIPMENT: &[(u32, &str)] = &[
+    (1, "CDMA / 1x"),
+    (4, "WCDMA"),
+    (5, "GSM"),
+    (7, "UMTS NAS"),
+    (0x0B, "LTE / NR"),
+];
+
+/// Seconds between the Unix epoch and the GPS epoch (1980-01-06) DIAG counts from.
+const GPS_EPOCH_OFFSET_SECS: u64 = 315_964_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Log,
+    Event,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagRecord {
+    pub kind: RecordKind,
+    /// Log code or event ID.
+    pub code: u16,
+    /// Unix time in milliseconds, when the packet carried a usable timestamp.
+    pub timestamp_ms: Option<u64>,
+    pub payload_len: usize,
+}
+
+impl DiagRecord {
+    pub fn label(&self) -> String {
+        match self.kind {
+            RecordKind::Log => format!("LOG 0x{:04X}", self.code),
+            RecordKind::Event => format!("EVENT {}", self.code),
+        }
+    }
+
+    pub fn time_text(&self) -> String {
+        match self.timestamp_ms {
+            Some(ms) => format!("{}.{:03}", vault::utc_timestamp(ms / 1000), ms % 1000),
+            None => "-".to_string(),
+        }
+    }
+
+    /// Case-insensitive match against the label, e.g. "0xB0C0", "event" or "1605".
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.trim();
+        filter.is_empty()
+            || self
+                .label()
+                .to_ascii_lowercase()
+                .contains(&filter.to_ascii_lowercase())
+    }
+}
+
+/// Convert a DIAG timestamp (upper 48 bits in 1.25 ms units since the GPS
+/// epoch) into Unix milliseconds.
+fn diag_time_to_unix_ms(ts: u64) -> Option<u64> {
+    if ts == 0 {
+        return None;
+    }
+    Some((ts >> 16) * 5 / 4 + GPS_EPOCH_OFFSET_SECS * 1000)
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Decode one DIAG packet into zero or more records. Responses to commands and
+/// unknown packet types decode to nothing.
+pub fn decode_packet(packet: &[u8]) -> Vec<DiagRecord> {
+    match packet.first() {
+        Some(&diag::CMD_LOG) => decode_log(packet).into_iter().collect(),
+        Some(&diag::CMD_EVENT_REPORT) => decode_events(packet),
+        _ => Vec::new(),
+    }
+}
+
+/// `cmd, more, len:u16, log_len:u16, code:u16, ts:u64, payload`
+fn decode_log(packet: &[u8]) -> Option<DiagRecord> {
+    let log_len = le_u16(packet, 4)? as usize;
+    Some(DiagRecord {
+        kind: RecordKind::Log,
+        code: le_u16(packet, 6)?,
+        timestamp_ms: diag_time_to_unix_ms(le_u64(packet, 8)?),
+        payload_len: log_len.saturating_sub(12),
+    })
+}
+
+/// `cmd, len:u16, events...` where each event is
+/// `id:u16 (bits 0-11 id, 13-14 payload size, 15 truncated ts), ts, payload`.
+fn decode_events(packet: &[u8]) -> Vec<DiagRecord> {
+    let mut records = Vec::new();
+    let end = le_u16(packet, 1).map_or(0, |len| (3 + len as usize).min(packet.len()));
+    let mut pos = 3;
+    let mut last_time = None;
+    while pos + 2 <= end {
+        let Some(word) = le_u16(packet, pos) else {
+            break;
+        };
+        pos += 2;
+        if word & 0x8000 != 0 {
+            // Truncated timestamps only carry bits 16..31; reuse the last full one.
+            pos += 2;
+        } else {
+            let Some(ts) = le_u64(packet, pos) else {
+                break;
+            };
+            last_time = diag_time_to_unix_ms(ts);
+            pos += 8;
+        }
+        let payload_len = match (word >> 13) & 0x3 {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            _ => {
+                let Some(&len) = packet.get(pos) else {
+                    break;
+                };
+                pos += 1;
+                len as usize
+            }
+        };
+        pos += payload_len;
+        if pos > end {
+            break;
+        }
+        records.push(DiagRecord {
+            kind: RecordKind::Event,
+            code: word & 0x0FFF,
+            timestamp_ms: last_time,
+            payload_len,
+        });
+    }
+    records
+}
+
+/// Decode a saved `.qmdl` capture.
+pub fn decode_capture(path: &Path) -> Result<Vec<DiagRecord>, String> {
+    let data =
+        fs::read(path).map_err(|e| format!("Cannot read capture {}: {}", path.display(), e))?;
+    Ok(FrameDecoder::new()
+        .push(&data)
+        .into_iter()
+        .filter_map(Result::ok)
+        .flat_map(|packet| decode_packet(&packet))
+        .collect())
+}
+
+pub fn captures_dir() -> PathBuf {
+    exec::foem_home().join("captures")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CaptureConfig {
+    /// Log equipment IDs to enable in full (see `EQUIPMENT`).
+    pub equipment: Vec<u32>,
+    pub events: bool,
+}
+
+/// A running capture on a background thread.
+pub struct CaptureJob {
+    pub path: PathBuf,
+    pending: Arc<Mutex<Vec<DiagRecord>>>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<String>>,
+}
+
+impl CaptureJob {
+    pub fn start(port_name: &str, config: CaptureConfig) -> Result<Self, String> {
+        if config.equipment.is_empty() && !config.events {
+            return Err("Select at least one log group or events to capture.".to_string());
+        }
+        let mut port = open_diag_port(port_name)?;
+        // Short reads keep the stop flag responsive.
+        port.set_timeout(Duration::from_millis(200))
+            .map_err(|e| format!("Cannot configure {}: {}", port_name, e))?;
+
+        let dir = captures_dir();
+        fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        let path = dir.join(format!("{}.qmdl", vault::utc_timestamp(vault::now_secs())));
+        let file = fs::File::create(&path)
+            .map_err(|e| format!("Cannot create {}: {}", path.display(), e))?;
+
+        let pending = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (worker_pending, worker_stop) = (Arc::clone(&pending), Arc::clone(&stop));
+        let worker_path = path.clone();
+        let handle = std::thread::spawn(move || {
+            run_capture(
+                DiagClient::new(port),
+                BufWriter::new(file),
+                &worker_path,
+                &config,
+                &worker_pending,
+                &worker_stop,
+            )
+        });
+        Ok(Self {
+            path,
+            pending,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Records decoded since the last call.
+    pub fn drain(&self) -> Vec<DiagRecord> {
+        self.pending
+            .lock()
+            .map(|mut p| std::mem::take(&mut *p))
+            .unwrap_or_default()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|h| h.is_finished())
+    }
+
+    /// Stop capturing, restore the masks and return the capture summary.
+    pub fn stop(&mut self) -> String {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| "Capture worker panicked.".to_string()),
+            None => String::new(),
+        }
+    }
+}
+
+fn run_capture<T: std::io::Read + std::io::Write>(
+    mut client: DiagClient<T>,
+    mut file: impl Write,
+    path: &Path,
+    config: &CaptureConfig,
+    pending: &Mutex<Vec<DiagRecord>>,
+    stop: &AtomicBool,
+) -> String {
+    let mut output = String::from("Modem log capture:\n");
+    if !config.equipment.is_empty() {
+        match client.log_id_ranges() {
+            Ok(ranges) => {
+                for &equip in &config.equipment {
+                    let name = EQUIPMENT
+                        .iter()
+                        .find(|(id, _)| *id == equip)
+                        .map_or("unknown", |(_, n)| n);
+                    let result = match ranges.get(equip as usize) {
+                        Some(&last) if last > 0 => client.enable_log_items(equip, last),
+                        _ => Err("not supported by this modem".to_string()),
+                    };
+                    match result {
+                        Ok(()) => output.push_str(&format!("  Logging {} enabled\n", name)),
+                        Err(e) => output.push_str(&format!("  Logging {}: {}\n", name, e)),
+                    }
+                }
+            }
+            Err(e) => output.push_str(&format!("  Log masks unavailable: {}\n", e)),
+        }
+    }
+    if config.events {
+        match client.set_event_reporting(true) {
+            Ok(()) => output.push_str("  Event reporting enabled\n"),
+            Err(e) => output.push_str(&format!("  Event reporting: {}\n", e)),
+        }
+    }
+
+    let (mut logs, mut events) = (0usize, 0usize);
+    while !stop.load(Ordering::Relaxed) {
+        let packets = match client.poll(&mut |raw| file.write_all(raw)) {
+            Ok(p) => p,
+            Err(e) => {
+                output.push_str(&format!("  Capture stopped: {}\n", e));
+                break;
+            }
+        };
+        let records: Vec<DiagRecord> = packets.iter().flat_map(|p| decode_packet(p)).collect();
+        if records.is_empty() {
+            continue;
+        }
+        for r in &records {
+            match r.kind {
+                RecordKind::Log => logs += 1,
+                RecordKind::Event => events += 1,
+            }
+        }
+        if let Ok(mut p) = pending.lock() {
+            p.extend(records);
+        }
+    }
+
+    // Leave the modem as we found it; failures here are not fatal.
+    if !config.equipment.is_empty() {
+        let _ = client.disable_logging();
+    }
+    if config.events {
+        let _ = client.set_event_reporting(false);
+    }
+    if let Err(e) = file.flush() {
+        output.push_str(&format!("  Capture write failed: {}\n", e));
+    }
+    output.push_str(&format!(
+        "  {} log packets, {} events saved to {}\n",
+        logs,
+        events,
+        path.display()
+    ));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::diag::encode_frame;
+
+    fn log_packet(code: u16, ts: u64) -> Vec<u8> {
+        let mut p = vec![diag::CMD_LOG, 0];
+        p.extend_from_slice(&16u16.to_le_bytes());
+        p.extend_from_slice(&16u16.to_le_bytes());
+        p.extend_from_slice(&code.to_le_bytes());
+        p.extend_from_slice(&ts.to_le_bytes());
+        p.extend_from_slice(&[0xAA; 4]);
+        p
+    }
+
+    #[test]
+    fn test_decode_log_packet() {
+        // 1000 units of 1.25 ms after the GPS epoch.
+        let records = decode_packet(&log_packet(0xB0C0, 1000 << 16));
+        assert_eq!(
+            records,
+            vec![DiagRecord {
+                kind: RecordKind::Log,
+                code: 0xB0C0,
+                timestamp_ms: Some(GPS_EPOCH_OFFSET_SECS * 1000 + 1250),
+                payload_len: 4,
+            }]
+        );
+        assert_eq!(records[0].label(), "LOG 0xB0C0");
+        assert_eq!(records[0].time_text(), "19800106-000001.250");
+    }
+
+    #[test]
+    fn test_decode_event_packet() {
+        let mut events = Vec::new();
+        // Event 1605, full timestamp, no payload.
+        events.extend_from_slice(&1605u16.to_le_bytes());
+        events.extend_from_slice(&(8u64 << 16).to_le_bytes());
+        // Event 300, truncated timestamp, 2-byte payload.
+        events.extend_from_slice(&(300u16 | 0x8000 | (2 << 13)).to_le_bytes());
+        events.extend_from_slice(&[0x01, 0x00, 0xBE, 0xEF]);
+        // Event 7, truncated timestamp, length-prefixed payload.
+        events.extend_from_slice(&(7u16 | 0x8000 | (3 << 13)).to_le_bytes());
+        events.extend_from_slice(&[0x02, 0x00, 3, 1, 2, 3]);
+        let mut packet = vec![diag::CMD_EVENT_REPORT];
+        packet.extend_from_slice(&(events.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&events);
+
+        let records = decode_packet(&packet);
+        let summary: Vec<(u16, usize)> = records.iter().map(|r| (r.code, r.payload_len)).collect();
+        assert_eq!(summary, vec![(1605, 0), (300, 2), (7, 3)]);
+        assert_eq!(records[1].timestamp_ms, records[0].timestamp_ms);
+        assert!(records[0].matches("event 16"));
+        assert!(!records[0].matches("LOG"));
+    }
+
+    #[test]
+    fn test_decode_ignores_command_responses() {
+        assert!(decode_packet(&[diag::CMD_VERSION_INFO, 1, 2]).is_empty());
+        assert!(decode_packet(&[diag::CMD_LOG, 0]).is_empty());
+    }
+
+    #[test]
+    fn test_decode_capture_file() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join("foem_modem_log_capture.qmdl");
+        let mut data = encode_frame(&log_packet(0x412F, 1 << 16));
+        data.extend(encode_frame(&[diag::CMD_STATUS, 0, 0]));
+        data.extend(encode_frame(&log_packet(0xB193, 2 << 16)));
+        fs::write(&path, &data)?;
+
+        let records = decode_capture(&path)?;
+        let codes: Vec<u16> = records.iter().map(|r| r.code).collect();
+        assert_eq!(codes, vec![0x412F, 0xB193]);
+
+        let _ = fs::remove_file(&path);
+        Ok(())
+    }
+}
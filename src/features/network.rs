@@ -7,60 +7,24 @@ use std::fmt::Write;
 /// Knox enrollment bypass (Samsung)
 /// Google account removal
 use super::adb_shell;
+use super::batch::{self, BatchScript};
 
 // -- FRP (Factory Reset Protection) Bypass --
 /// Execute multiple ADB shell commands in a single batched process to prevent N+1 overhead.
-/// Note: This executes via `sh -c` and delimits output with `B_MARKER_<exit_code>`.
+/// Arguments are shell-quoted; see `batch::BatchScript` for the output framing.
 fn batch_adb_shell_commands<F>(serial: &str, cmds: &[&[&str]], mut handle_result: F)
 where
     F: FnMut(usize, &[&str], Result<String, String>),
 {
-    if cmds.is_empty() {
-        return;
+    let mut batch = BatchScript::new();
+    for (i, cmd) in cmds.iter().enumerate() {
+        let quoted = shlex::try_join(cmd.iter().copied()).unwrap_or_default();
+        batch.push(&i.to_string(), &quoted);
     }
-    let mut script = String::new();
-    for cmd in cmds {
-        let quoted_args: Vec<String> = cmd
-            .iter()
-            .map(|arg| {
-                if arg.is_empty() {
-                    "''".to_string()
-                } else {
-                    format!("'{}'", arg.replace('\'', "'\\''"))
-                }
-            })
-            .collect();
-        let cmd_str = quoted_args.join(" ");
-        script.push_str(&format!("{} 2>&1; echo B_MARKER_$?;\n", cmd_str));
-    }
-    match adb_shell(serial, &["sh", "-c", &script]) {
-        Ok(res) => {
-            let mut parts = res.split("B_MARKER_");
-            let mut prev_output = parts.next().unwrap_or("");
-            for (i, part) in parts.enumerate() {
-                if i >= cmds.len() {
-                    break;
-                }
-                let (code_str, next_out) = match part.find('\n') {
-                    Some(idx) => {
-                        let (c, rest) = part.split_at(idx);
-                        (c, &rest[1..]) // Skip the newline
-                    }
-                    None => (part, ""),
-                };
-                let code: i32 = code_str.trim().parse().unwrap_or(-1);
-                let out = prev_output.trim().to_string();
-                if code == 0 {
-                    handle_result(i, cmds[i], Ok(out));
-                } else {
-                    let err = if out.is_empty() {
-                        format!("exit code {}", code)
-                    } else {
-                        out
-                    };
-                    handle_result(i, cmds[i], Err(err));
-                }
-                prev_output = next_out;
+    match batch.run(serial) {
+        Ok(results) => {
+            for (i, res) in results.iter().enumerate() {
+                handle_result(i, cmds[i], res.result());
             }
         }
         Err(e) => {
@@ -245,27 +209,19 @@ pub fn check_carrier_lock(serial: &str) -> String {
         ("Network Type", "gsm.network.type"),
         ("Phone Type", "gsm.current.phone-type"),
     ];
-    let mut script = String::new();
-    for (_, prop) in &props {
-        let _ = write!(script, "getprop {}; echo B_MARKER; ", prop);
-    }
+    let cmds: Vec<(&str, String)> = props
+        .iter()
+        .map(|(label, prop)| (*label, format!("getprop {}", prop)))
+        .collect();
 
     let mut output = String::from("Carrier/SIM Status:\n");
-    match adb_shell(serial, &["sh", "-c", &script]) {
-        Ok(res) => {
-            let mut parts = res.split("B_MARKER");
-            for (label, _) in &props {
-                let val = parts.next().unwrap_or("").trim();
-                if !val.is_empty() {
-                    output.push_str(&format!("  {}: {}\n", label, val));
-                } else {
-                    output.push_str(&format!("  {}: --\n", label));
-                }
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        match res {
+            Ok(val) if !val.is_empty() => {
+                let _ = writeln!(output, "  {}: {}", label, val);
             }
-        }
-        Err(_) => {
-            for (label, _) in &props {
-                output.push_str(&format!("  {}: --\n", label));
+            _ => {
+                let _ = writeln!(output, "  {}: --", label);
             }
         }
     }
@@ -507,7 +463,14 @@ mod tests {
             *mock.borrow_mut() = Some(Box::new(|program, args, _error_prefix| {
                 if program == "adb" && args.len() > 3 && args[2] == "shell" {
                     let long_string = "A".repeat(130);
-                    return Ok(format!("Row: 0 name=user_setup_complete, value=1\nB_MARKER_0\npackage:com.google.android.setupwizard\nB_MARKER_0\n{}\nB_MARKER_0\n", long_string));
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[
+                            ("Row: 0 name=user_setup_complete, value=1", 0),
+                            ("package:com.google.android.setupwizard", 0),
+                            (&long_string, 0),
+                        ],
+                    );
                 }
                 Ok("".to_string())
             }));
@@ -535,7 +498,14 @@ mod tests {
         MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _error_prefix| {
                 if program == "adb" && args.len() > 3 && args[2] == "shell" {
-                    return Ok("Permission denied\nB_MARKER_1\nSuccess 2\nB_MARKER_0\nSuccess 3\nB_MARKER_0\n".to_string());
+                    return batch::mock_reply(
+                        args.last().ok_or("no script")?,
+                        &[
+                            ("", "Permission denied", 1),
+                            ("Success 2", "", 0),
+                            ("Success 3", "", 0),
+                        ],
+                    );
                 }
                 Ok("".to_string())
             }));
@@ -574,13 +544,15 @@ mod tests {
         assert!(output.contains("Google account: error (adb connection failed)"));
     }
 
-
     #[test]
     fn test_bypass_frp_adb_bypass() {
         MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _error_prefix| {
                 if program == "adb" && args.len() > 3 && args[2] == "shell" {
-                    return Ok("B_MARKER_0\nB_MARKER_0\nB_MARKER_0\n".to_string());
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("", 0), ("", 0), ("", 0)],
+                    );
                 }
                 Ok("".to_string())
             }));
@@ -601,7 +573,10 @@ mod tests {
         MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _error_prefix| {
                 if program == "adb" && args.len() > 3 && args[2] == "shell" {
-                    return Ok("B_MARKER_0\nB_MARKER_0\nB_MARKER_0\n".to_string());
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("", 0), ("", 0), ("", 0)],
+                    );
                 }
                 Ok("".to_string())
             }));
@@ -622,7 +597,10 @@ mod tests {
         MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _error_prefix| {
                 if program == "adb" && args.len() > 3 && args[2] == "shell" {
-                    return Ok("B_MARKER_0\nB_MARKER_0\nB_MARKER_0\n".to_string());
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("", 0), ("", 0), ("", 0)],
+                    );
                 }
                 Ok("".to_string())
             }));
@@ -644,7 +622,10 @@ mod tests {
         MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _error_prefix| {
                 if program == "adb" && args.len() > 3 && args[2] == "shell" {
-                    return Ok("B_MARKER_0\nB_MARKER_0\nB_MARKER_0\n".to_string());
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("", 0), ("", 0), ("", 0)],
+                    );
                 }
                 Ok("".to_string())
             }));
@@ -0,0 +1,757 @@
+/// Partition browser and selective partition dumps to the host vault.
+///
+/// Partitions are enumerated from `/dev/block/by-name` with root (Magisk `su`)
+/// or from a recovery shell, streamed to the host over `adb exec-out dd`, and
+/// stored in the same manifest-driven vault layout as EFS/NV backups so any of
+/// them can later be written back with `flash::flash_partition`.
+use super::vault::{self, BackupKind, Compression, VaultFile, VaultManifest};
+use super::{adb, adb_shell, fastboot, flash};
+use crate::exec;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Transfer block size; resumed dumps restart on a multiple of it.
+const DUMP_BLOCK: u64 = 1024 * 1024;
+
+/// How shell commands gain access to block devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootMode {
+    /// The shell already runs as uid 0 (recovery, `adb root`).
+    Native,
+    /// Commands must be wrapped in `su -c`.
+    Su,
+}
+
+impl RootMode {
+    pub fn wrap(&self, cmd: &str) -> String {
+        match self {
+            Self::Native => cmd.to_string(),
+            Self::Su => format!(
+                "su -c {}",
+                shlex::try_quote(cmd).unwrap_or_else(|_| cmd.into())
+            ),
+        }
+    }
+}
+
+/// Find a way to run commands as root, preferring a native root shell.
+pub fn detect_root(serial: &str) -> Option<RootMode> {
+    if adb_shell(serial, &["id", "-u"]).is_ok_and(|o| o.trim() == "0") {
+        return Some(RootMode::Native);
+    }
+    if adb_shell(serial, &["su", "-c", "id -u"]).is_ok_and(|o| o.trim() == "0") {
+        return Some(RootMode::Su);
+    }
+    None
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    pub name: String,
+    pub path: String,
+    pub size: Option<u64>,
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Enumerate named partitions and their sizes.
+pub fn list_partitions(serial: &str, root: RootMode) -> Result<Vec<PartitionInfo>, String> {
+    let script = "d=/dev/block/by-name; [ -d $d ] || d=/dev/block/bootdevice/by-name; \
+                  for f in $d/*; do echo \"$f $(blockdev --getsize64 $f 2>/dev/null)\"; done";
+    let out = adb_shell(serial, &[&root.wrap(script)])?;
+    let partitions = parse_partition_listing(&out);
+    if partitions.is_empty() {
+        return Err("No partitions found under /dev/block/by-name.".to_string());
+    }
+    Ok(partitions)
+}
+
+fn parse_partition_listing(out: &str) -> Vec<PartitionInfo> {
+    let mut partitions: Vec<PartitionInfo> = out
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = fields.next()?;
+            let name = path
+                .rsplit('/')
+                .next()
+                .filter(|n| !n.is_empty() && *n != "*")?;
+            Some(PartitionInfo {
+                name: name.to_string(),
+                path: path.to_string(),
+                size: fields.next().and_then(|s| s.parse().ok()),
+            })
+        })
+        .collect();
+    partitions.sort_by(|a, b| a.name.cmp(&b.name));
+    partitions
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DumpOptions {
+    pub compress: bool,
+    /// Continue the most recent interrupted dump instead of starting over.
+    pub resume: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DumpProgress {
+    pub partition: String,
+    pub index: usize,
+    pub count: usize,
+    pub done: u64,
+    pub total: Option<u64>,
+}
+
+/// Stream one partition into `dir`, continuing a `.part` file left behind by
+/// an interrupted run when `resume` is set. A partition the interrupted run
+/// already finished is kept instead of being read again.
+fn dump_partition(
+    serial: &str,
+    root: RootMode,
+    part: &PartitionInfo,
+    dir: &Path,
+    options: DumpOptions,
+    progress: &mut dyn FnMut(u64),
+) -> Result<VaultFile, String> {
+    let raw_path = dir.join(format!("{}.img", part.name));
+    let part_path = dir.join(format!("{}.img.part", part.name));
+
+    if options.resume {
+        if let Some((file, raw_size)) = finished_gzip(part, dir).filter(|_| options.compress) {
+            progress(raw_size);
+            return Ok(file);
+        }
+        // The raw image only appears once every byte has been received.
+        if let Ok(meta) = fs::metadata(&raw_path) {
+            if part.size.is_none_or(|size| meta.len() == size) {
+                progress(meta.len());
+                return store_image(part, dir, &raw_path, meta.len(), options.compress);
+            }
+        }
+    }
+
+    let mut done = if options.resume {
+        fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0)
+    } else {
+        0
+    };
+    done -= done % DUMP_BLOCK;
+
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&part_path)
+        .map_err(|e| format!("Cannot open {}: {}", part_path.display(), e))?;
+    file.set_len(done)
+        .map_err(|e| format!("Cannot truncate {}: {}", part_path.display(), e))?;
+    let mut file = io::BufWriter::new(
+        OpenOptions::new()
+            .append(true)
+            .open(&part_path)
+            .map_err(|e| format!("Cannot open {}: {}", part_path.display(), e))?,
+    );
+    progress(done);
+
+    if part.size.is_none_or(|size| done < size) {
+        let cmd = root.wrap(&format!(
+            "dd if={} bs={} skip={} 2>/dev/null",
+            part.path,
+            DUMP_BLOCK,
+            done / DUMP_BLOCK
+        ));
+        exec::stream_stdout(
+            "adb",
+            &["-s", serial, "exec-out", &cmd],
+            "Partition dump failed",
+            &mut |chunk| {
+                file.write_all(chunk)?;
+                done += chunk.len() as u64;
+                progress(done);
+                Ok(())
+            },
+        )?;
+    }
+    file.flush()
+        .map_err(|e| format!("Cannot write {}: {}", part_path.display(), e))?;
+    drop(file);
+
+    if let Some(size) = part.size {
+        if done != size {
+            return Err(format!(
+                "{}: received {} of {} bytes. Run the dump again with resume enabled.",
+                part.name, done, size
+            ));
+        }
+    }
+    fs::rename(&part_path, &raw_path)
+        .map_err(|e| format!("Cannot finalize {}: {}", raw_path.display(), e))?;
+    store_image(part, dir, &raw_path, done, options.compress)
+}
+
+/// Hash a complete raw image and compress it when asked.
+fn store_image(
+    part: &PartitionInfo,
+    dir: &Path,
+    raw_path: &Path,
+    size: u64,
+    compress: bool,
+) -> Result<VaultFile, String> {
+    let raw_sha256 = vault::sha256_file(raw_path)?;
+
+    if !compress {
+        return Ok(VaultFile {
+            partition: part.name.clone(),
+            file: format!("{}.img", part.name),
+            size,
+            sha256: raw_sha256,
+            compression: Compression::None,
+            raw_sha256: None,
+        });
+    }
+
+    let gz_name = format!("{}.img.gz", part.name);
+    let gz_path = dir.join(&gz_name);
+    gzip_file(raw_path, &gz_path)?;
+    let _ = fs::remove_file(raw_path);
+    Ok(VaultFile {
+        partition: part.name.clone(),
+        file: gz_name,
+        size: fs::metadata(&gz_path)
+            .map_err(|e| format!("Cannot stat {}: {}", gz_path.display(), e))?
+            .len(),
+        sha256: vault::sha256_file(&gz_path)?,
+        compression: Compression::Gzip,
+        raw_sha256: Some(raw_sha256),
+    })
+}
+
+/// The compressed image of an earlier run and its raw size, if it
+/// decompresses cleanly to the full partition size.
+fn finished_gzip(part: &PartitionInfo, dir: &Path) -> Option<(VaultFile, u64)> {
+    let file = format!("{}.img.gz", part.name);
+    let path = dir.join(&file);
+    let input = fs::File::open(&path).ok()?;
+    let mut hasher = Sha256::new();
+    let raw_size = io::copy(&mut GzDecoder::new(input), &mut hasher).ok()?;
+    if part.size.is_some_and(|size| size != raw_size) {
+        return None;
+    }
+    let stored = VaultFile {
+        partition: part.name.clone(),
+        size: fs::metadata(&path).ok()?.len(),
+        sha256: vault::sha256_file(&path).ok()?,
+        file,
+        compression: Compression::Gzip,
+        raw_sha256: Some(hex::encode(hasher.finalize())),
+    };
+    Some((stored, raw_size))
+}
+
+fn gzip_file(src: &Path, dst: &Path) -> Result<(), String> {
+    let mut input =
+        fs::File::open(src).map_err(|e| format!("Cannot open {}: {}", src.display(), e))?;
+    let output =
+        fs::File::create(dst).map_err(|e| format!("Cannot create {}: {}", dst.display(), e))?;
+    let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+    io::copy(&mut input, &mut encoder)
+        .and_then(|_| encoder.finish().map(|_| ()))
+        .map_err(|e| format!("Compression of {} failed: {}", src.display(), e))
+}
+
+fn gunzip_file(src: &Path, dst: &Path) -> Result<(), String> {
+    let input = fs::File::open(src).map_err(|e| format!("Cannot open {}: {}", src.display(), e))?;
+    let mut output =
+        fs::File::create(dst).map_err(|e| format!("Cannot create {}: {}", dst.display(), e))?;
+    io::copy(&mut GzDecoder::new(input), &mut output)
+        .map(|_| ())
+        .map_err(|e| format!("Decompression of {} failed: {}", src.display(), e))
+}
+
+/// Dump the selected partitions into one vault entry.
+pub fn dump_partitions(
+    serial: &str,
+    root: RootMode,
+    selected: &[PartitionInfo],
+    options: DumpOptions,
+    progress: &mut dyn FnMut(&DumpProgress),
+) -> String {
+    if selected.is_empty() {
+        return "Select at least one partition to dump.".to_string();
+    }
+    let dir = match options
+        .resume
+        .then(|| vault::latest_incomplete_dir(serial))
+        .flatten()
+    {
+        Some(dir) => dir,
+        None => match vault::new_entry_dir(serial) {
+            Ok(dir) => dir,
+            Err(e) => return format!("Partition dump failed: {}", e),
+        },
+    };
+
+    let mut output = format!("Partition dump to {}:\n", dir.display());
+    let mut files = Vec::new();
+    let mut failed = false;
+    for (index, part) in selected.iter().enumerate() {
+        let mut state = DumpProgress {
+            partition: part.name.clone(),
+            index,
+            count: selected.len(),
+            done: 0,
+            total: part.size,
+        };
+        let result = dump_partition(serial, root, part, &dir, options, &mut |done| {
+            state.done = done;
+            progress(&state);
+        });
+        match result {
+            Ok(f) => {
+                output.push_str(&format!(
+                    "  {} -- {} stored ({}, sha256 {})\n",
+                    part.name,
+                    format_size(f.size),
+                    f.file,
+                    f.raw_sha256.as_deref().unwrap_or(&f.sha256)
+                ));
+                files.push(f);
+            }
+            Err(e) => {
+                failed = true;
+                output.push_str(&format!("  {} -- failed: {}\n", part.name, e));
+            }
+        }
+    }
+
+    if failed {
+        // Leaving the directory without a manifest marks it as resumable.
+        output.push_str("  Dump incomplete. Enable resume and run it again to continue.\n");
+        return output;
+    }
+    let manifest = VaultManifest {
+        kind: BackupKind::PartitionDump,
+        created_at: vault::now_secs(),
+        foem_version: crate::VERSION.to_string(),
+        device: vault::device_identity(serial),
+        files,
+        note: None,
+    };
+    match vault::write_manifest(&dir, &manifest) {
+        Ok(()) => output.push_str(&format!("  Vault entry: {}\n", dir.display())),
+        Err(e) => output.push_str(&format!("  Vault error: {}\n", e)),
+    }
+    output
+}
+
+/// A partition dump running on a background thread so the UI can show progress.
+pub struct DumpJob {
+    progress: Arc<Mutex<DumpProgress>>,
+    handle: Option<JoinHandle<String>>,
+}
+
+impl DumpJob {
+    pub fn spawn(
+        serial: &str,
+        root: RootMode,
+        selected: Vec<PartitionInfo>,
+        options: DumpOptions,
+    ) -> Self {
+        let progress = Arc::new(Mutex::new(DumpProgress::default()));
+        let shared = Arc::clone(&progress);
+        let serial = serial.to_string();
+        let handle = std::thread::spawn(move || {
+            dump_partitions(&serial, root, &selected, options, &mut |p| {
+                if let Ok(mut guard) = shared.lock() {
+                    *guard = p.clone();
+                }
+            })
+        });
+        Self {
+            progress,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn progress(&self) -> DumpProgress {
+        self.progress.lock().map(|p| p.clone()).unwrap_or_default()
+    }
+
+    /// The dump report once the worker has finished.
+    pub fn try_finish(&mut self) -> Option<String> {
+        if !self.handle.as_ref()?.is_finished() {
+            return None;
+        }
+        let handle = self.handle.take()?;
+        Some(
+            handle
+                .join()
+                .unwrap_or_else(|_| "Partition dump worker panicked.".to_string()),
+        )
+    }
+}
+
+/// A temp path no other restore, in this or another FOEM process, uses.
+fn unique_temp_image(partition: &str) -> PathBuf {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    std::env::temp_dir().join(format!(
+        "foem_restore_{}_{}_{}.img",
+        partition,
+        std::process::id(),
+        nanos
+    ))
+}
+
+/// Flash a partition image stored in the vault back via fastboot.
+///
+/// The device must be booted into Android: its identity is read over adb
+/// and checked against the vault entry, since an image from another handset
+/// would overwrite this one's modem or calibration data. Only then is it
+/// rebooted to the bootloader for the flash.
+pub fn restore_partition(serial: &str, entry_dir: &Path, partition: &str) -> String {
+    let entry = match vault::load_entry(entry_dir) {
+        Ok(e) => e,
+        Err(e) => return format!("Partition restore failed: {}", e),
+    };
+    let current = vault::device_identity(serial);
+    if current.model.is_empty() {
+        return "Partition restore blocked: the device identity cannot be read over adb.\n\
+                Boot the device into Android so the vault entry can be checked against it."
+            .to_string();
+    }
+    let problems = vault::restore_problems(&entry, &current);
+    if !problems.is_empty() {
+        return format!(
+            "Partition restore blocked:\n  - {}",
+            problems.join("\n  - ")
+        );
+    }
+    let mut notes: String = vault::restore_warnings(&entry, &current)
+        .iter()
+        .map(|w| format!("Note: {}\n", w))
+        .collect();
+    let file = match entry
+        .manifest
+        .files
+        .iter()
+        .find(|f| f.partition == partition)
+    {
+        Some(f) => f,
+        None => return format!("Vault entry has no image for {}.", partition),
+    };
+    if let Err(e) = vault::verify_file(&entry.dir, file) {
+        return format!("Partition restore blocked: {}", e);
+    }
+
+    let stored = entry.dir.join(&file.file);
+    let image: PathBuf = match file.compression {
+        Compression::None => stored,
+        Compression::Gzip => {
+            let tmp = unique_temp_image(partition);
+            if let Err(e) = gunzip_file(&stored, &tmp) {
+                return format!("Partition restore failed: {}", e);
+            }
+            let raw_ok = match (&file.raw_sha256, vault::sha256_file(&tmp)) {
+                (Some(expected), Ok(actual)) => expected.eq_ignore_ascii_case(&actual),
+                _ => false,
+            };
+            if !raw_ok {
+                let _ = fs::remove_file(&tmp);
+                return format!(
+                    "Partition restore blocked: decompressed {} failed its SHA-256 check",
+                    partition
+                );
+            }
+            tmp
+        }
+    };
+
+    let result = to_fastboot(serial)
+        .map(|()| flash::flash_partition(serial, partition, &image.to_string_lossy()));
+    if file.compression != Compression::None {
+        let _ = fs::remove_file(&image);
+    }
+    match result {
+        Ok(out) => {
+            notes.push_str(&out);
+            notes
+        }
+        Err(e) => format!("{}Partition restore failed: {}", notes, e),
+    }
+}
+
+/// How long the bootloader may take to show up on fastboot after a reboot.
+const FASTBOOT_WAIT: Duration = Duration::from_secs(90);
+
+/// Reboot `serial` to the bootloader and wait until fastboot answers.
+fn to_fastboot(serial: &str) -> Result<(), String> {
+    adb(serial, &["reboot", "bootloader"])?;
+    let start = Instant::now();
+    while fastboot(serial, &["getvar", "product"]).is_err() {
+        if start.elapsed() >= FASTBOOT_WAIT {
+            return Err(format!(
+                "{} did not reach fastboot within {}s",
+                serial,
+                FASTBOOT_WAIT.as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::batch;
+
+    struct MockGuard;
+    impl Drop for MockGuard {
+        fn drop(&mut self) {
+            crate::exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+            crate::exec::MOCK_STREAM_IMPL.with(|m| *m.borrow_mut() = None);
+            vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    /// A device `SER1` reporting `model` over adb whose bootloader accepts a
+    /// flash of the dumped `persist` image.
+    fn mock_handset(model: &'static str) {
+        crate::exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(move |program, args, _| {
+                let cmd = args.join(" ");
+                if cmd.contains("getprop ro.product.model") {
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[(model, 0), ("14", 0), ("kona", 0), ("fp", 0)],
+                    );
+                }
+                match (program, args.get(2).copied()) {
+                    ("fastboot", Some("flash")) => {
+                        assert_eq!(model, "Pixel", "only the original handset is flashed");
+                        assert_eq!(args[3], "persist");
+                        let flashed = fs::read(args[4]).map_err(|e| e.to_string())?;
+                        assert_eq!(flashed.len(), DUMP_BLOCK as usize + 10);
+                        Ok("OKAY".to_string())
+                    }
+                    _ => Ok(String::new()),
+                }
+            }));
+        });
+    }
+
+    #[test]
+    fn test_root_mode_wrap() {
+        assert_eq!(RootMode::Native.wrap("dd if=/x"), "dd if=/x");
+        assert_eq!(RootMode::Su.wrap("dd if=/x"), "su -c 'dd if=/x'");
+    }
+
+    #[test]
+    fn test_parse_partition_listing() {
+        let out = "/dev/block/by-name/modemst1 2097152\n\
+                   /dev/block/by-name/boot_a 100663296\n\
+                   /dev/block/by-name/misc \n\
+                   /dev/block/by-name/* \n";
+        let parts = parse_partition_listing(out);
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].name, "boot_a");
+        assert_eq!(parts[0].size, Some(100_663_296));
+        assert_eq!(parts[1].name, "misc");
+        assert_eq!(parts[1].size, None);
+        assert_eq!(parts[2].path, "/dev/block/by-name/modemst1");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2 * 1024 * 1024), "2.0 MiB");
+    }
+
+    #[test]
+    fn test_dump_compress_and_restore_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_partitions_roundtrip");
+        let _ = fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = MockGuard;
+
+        let image: Vec<u8> = (0..DUMP_BLOCK as usize + 10)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let streamed = image.clone();
+        crate::exec::MOCK_STREAM_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(move |program, args| {
+                assert_eq!(program, "adb");
+                assert_eq!(args[2], "exec-out");
+                assert_eq!(
+                    args[3],
+                    "su -c 'dd if=/dev/block/by-name/persist bs=1048576 skip=0 2>/dev/null'"
+                );
+                Ok(streamed.clone())
+            }));
+        });
+        mock_handset("Pixel");
+
+        let part = PartitionInfo {
+            name: "persist".into(),
+            path: "/dev/block/by-name/persist".into(),
+            size: Some(image.len() as u64),
+        };
+        let mut last = DumpProgress::default();
+        let output = dump_partitions(
+            "SER1",
+            RootMode::Su,
+            &[part],
+            DumpOptions {
+                compress: true,
+                resume: false,
+            },
+            &mut |p| last = p.clone(),
+        );
+        assert!(output.contains("persist -- "), "{}", output);
+        assert_eq!(last.done, image.len() as u64);
+
+        let entries = vault::list_entries("SER1");
+        assert_eq!(entries.len(), 1);
+        let file = &entries[0].manifest.files[0];
+        assert_eq!(file.compression, Compression::Gzip);
+        assert_eq!(file.file, "persist.img.gz");
+
+        let result = restore_partition("SER1", &entries[0].dir, "persist");
+        assert_eq!(result, "Flash persist result:\nOKAY");
+
+        // The same entry must not go onto a different handset.
+        mock_handset("Other");
+        let result = restore_partition("SER1", &entries[0].dir, "persist");
+        assert!(
+            result.starts_with("Partition restore blocked:"),
+            "{}",
+            result
+        );
+        assert!(result.contains("connected device is Other"), "{}", result);
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_resumes_from_partial_file() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_partitions_resume");
+        let _ = fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = MockGuard;
+
+        let total = 2 * DUMP_BLOCK + 5;
+        let dir = vault::new_entry_dir("SER1")?;
+        // One full block plus a torn tail from the interrupted run.
+        fs::write(
+            dir.join("modem.img.part"),
+            vec![7u8; DUMP_BLOCK as usize + 100],
+        )?;
+
+        crate::exec::MOCK_STREAM_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(move |_, args| {
+                assert!(args[3].contains("skip=1 "), "{}", args[3]);
+                Ok(vec![7u8; (total - DUMP_BLOCK) as usize])
+            }));
+        });
+        crate::exec::MOCK_RUN_IMPL
+            .with(|m| *m.borrow_mut() = Some(Box::new(|_, _, _| Ok(String::new()))));
+
+        let part = PartitionInfo {
+            name: "modem".into(),
+            path: "/dev/block/by-name/modem".into(),
+            size: Some(total),
+        };
+        let output = dump_partitions(
+            "SER1",
+            RootMode::Native,
+            &[part],
+            DumpOptions {
+                compress: false,
+                resume: true,
+            },
+            &mut |_| {},
+        );
+        assert!(output.contains(&dir.display().to_string()), "{}", output);
+        assert_eq!(fs::metadata(dir.join("modem.img"))?.len(), total);
+        assert!(vault::load_entry(&dir).is_ok());
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_keeps_finished_partitions() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_partitions_resume_finished");
+        let _ = fs::remove_dir_all(&root);
+        vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = MockGuard;
+
+        let dir = vault::new_entry_dir("SER1")?;
+        // `boot` finished before the interruption; `dtbo` was cut off while
+        // being compressed and is read again.
+        fs::write(dir.join("boot.raw"), vec![3u8; 300])?;
+        gzip_file(&dir.join("boot.raw"), &dir.join("boot.img.gz"))?;
+        fs::remove_file(dir.join("boot.raw"))?;
+        fs::write(dir.join("dtbo.img.gz"), b"\x1f\x8b\x08")?;
+
+        crate::exec::MOCK_STREAM_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|_, args| {
+                assert!(args[3].contains("by-name/dtbo "), "{}", args[3]);
+                Ok(vec![9u8; 200])
+            }));
+        });
+        crate::exec::MOCK_RUN_IMPL
+            .with(|m| *m.borrow_mut() = Some(Box::new(|_, _, _| Ok(String::new()))));
+
+        let parts = [("boot", 300), ("dtbo", 200)].map(|(name, size)| PartitionInfo {
+            name: name.into(),
+            path: format!("/dev/block/by-name/{}", name),
+            size: Some(size),
+        });
+        let output = dump_partitions(
+            "SER1",
+            RootMode::Native,
+            &parts,
+            DumpOptions {
+                compress: true,
+                resume: true,
+            },
+            &mut |_| {},
+        );
+        assert!(!output.contains("failed"), "{}", output);
+
+        let entry = vault::load_entry(&dir)?;
+        assert_eq!(entry.manifest.files.len(), 2);
+        for file in &entry.manifest.files {
+            vault::verify_file(&dir, file)?;
+        }
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+}
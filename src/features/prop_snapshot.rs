@@ -0,0 +1,389 @@
+/// Full `getprop` snapshots and diffs.
+///
+/// Snapshots are stored as JSON in `~/.foem/snapshots/<serial>/<timestamp>.json`.
+/// Any two can be compared: the same device before and after a flash, or a
+/// known-good unit against a faulty one of the same model. Volatile
+/// properties (service states, boot timings, SIM/network state, per-unit
+/// serials) are filtered out by default so real configuration drift stands out.
+use super::adb_shell;
+use super::vault::{now_secs, sanitize_serial, utc_timestamp};
+use crate::exec;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Properties that change at runtime or differ per unit and SIM.
+const VOLATILE_PREFIXES: &[&str] = &[
+    "init.svc.",
+    "init.svc_debug_pid.",
+    "ro.boottime.",
+    "sys.usb.",
+    "vendor.usb.",
+    "persist.sys.usb.",
+    "gsm.operator.",
+    "gsm.sim.",
+    "net.",
+    "dhcp.",
+    "wifi.",
+    "vendor.wifi.",
+    "debug.tracing.",
+    "sys.sysctl.",
+    "sys.rescue_boot_count",
+    "persist.sys.boot.reason",
+];
+
+const VOLATILE_KEYS: &[&str] = &[
+    "ro.serialno",
+    "ro.boot.serialno",
+    "ril.serialnumber",
+    "ro.boot.bootreason",
+    "sys.boot.reason",
+    "sys.boot.reason.last",
+    "ro.runtime.firstboot",
+    "persist.sys.timezone",
+    "gsm.nitz.time",
+    "gsm.network.type",
+    "sys.boot_completed",
+    "dev.bootcomplete",
+    "sys.oem_unlock_allowed",
+    "ro.boot.cpuid",
+    "ro.boot.wifimacaddr",
+    "ro.boot.btmacaddr",
+    "persist.vendor.radio.nitz_sav_time",
+];
+
+pub fn is_volatile(key: &str) -> bool {
+    VOLATILE_KEYS.contains(&key) || VOLATILE_PREFIXES.iter().any(|p| key.starts_with(p))
+}
+
+/// Coarse grouping so radio and display drift is listed first.
+pub fn category(key: &str) -> &'static str {
+    const RADIO: &[&str] = &[
+        "ril",
+        "radio",
+        "telephony",
+        "gsm.",
+        "modem",
+        "ims",
+        "volte",
+        "vowifi",
+        "baseband",
+        "nv.",
+    ];
+    const DISPLAY: &[&str] = &[
+        "display",
+        "lcd",
+        "density",
+        "surfaceflinger",
+        "sf.",
+        "hwui",
+        "panel",
+        "graphics",
+        "hwc",
+        "egl",
+        "opengles",
+        "brightness",
+    ];
+    let lower = key.to_ascii_lowercase();
+    if RADIO.iter().any(|p| lower.contains(p)) {
+        "Radio"
+    } else if DISPLAY.iter().any(|p| lower.contains(p)) {
+        "Display"
+    } else {
+        "Other"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PropSnapshot {
+    pub serial: String,
+    pub model: String,
+    /// Seconds since the Unix epoch.
+    pub taken_at: u64,
+    pub props: BTreeMap<String, String>,
+}
+
+impl PropSnapshot {
+    /// Label used by the snapshot pickers.
+    pub fn label(&self) -> String {
+        format!(
+            "{} {} -- {} ({} props)",
+            self.model,
+            self.serial,
+            utc_timestamp(self.taken_at),
+            self.props.len()
+        )
+    }
+}
+
+/// Parse `getprop` output (`[key]: [value]`). Values may span lines.
+pub fn parse_getprop(text: &str) -> BTreeMap<String, String> {
+    let mut props = BTreeMap::new();
+    let mut last: Option<String> = None;
+    for line in text.lines() {
+        let parsed = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once("]: ["))
+            .map(|(k, v)| (k, v.strip_suffix(']')));
+        match parsed {
+            Some((key, value)) => {
+                props.insert(
+                    key.to_string(),
+                    value.unwrap_or_else(|| &line[key.len() + 5..]).to_string(),
+                );
+                last = Some(key.to_string());
+            }
+            None => {
+                if let Some(value) = last.as_ref().and_then(|k| props.get_mut(k)) {
+                    value.push('\n');
+                    value.push_str(line.strip_suffix(']').unwrap_or(line));
+                }
+            }
+        }
+    }
+    props
+}
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_SNAPSHOT_ROOT: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+pub fn snapshots_root() -> PathBuf {
+    #[cfg(test)]
+    {
+        if let Some(path) = MOCK_SNAPSHOT_ROOT.with(|m| m.borrow().clone()) {
+            return path;
+        }
+    }
+    exec::foem_home().join("snapshots")
+}
+
+/// Take a full `getprop` snapshot of `serial` and save it.
+pub fn take_snapshot(serial: &str) -> Result<(PathBuf, PropSnapshot), String> {
+    let out = adb_shell(serial, &["getprop"])?;
+    let props = parse_getprop(&out);
+    if props.is_empty() {
+        return Err("getprop returned no properties.".to_string());
+    }
+    let snapshot = PropSnapshot {
+        serial: serial.to_string(),
+        model: props.get("ro.product.model").cloned().unwrap_or_default(),
+        taken_at: now_secs(),
+        props,
+    };
+    let dir = snapshots_root().join(sanitize_serial(serial));
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Cannot create snapshot directory {}: {}", dir.display(), e))?;
+    let base = utc_timestamp(snapshot.taken_at);
+    let mut path = dir.join(format!("{}.json", base));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.json", base, n));
+        n += 1;
+    }
+    let json = serde_json::to_string_pretty(&snapshot)
+        .map_err(|e| format!("Cannot serialize snapshot: {}", e))?;
+    fs::write(&path, json).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    Ok((path, snapshot))
+}
+
+pub fn load_snapshot(path: &Path) -> Result<PropSnapshot, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read snapshot {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))
+}
+
+/// All saved snapshots of every device, newest first.
+pub fn list_snapshots() -> Vec<(PathBuf, PropSnapshot)> {
+    let mut list: Vec<(PathBuf, PropSnapshot)> = fs::read_dir(snapshots_root())
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .flat_map(|device| fs::read_dir(device.path()).into_iter().flatten())
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|x| x == "json"))
+        .filter_map(|p| load_snapshot(&p).ok().map(|s| (p, s)))
+        .collect();
+    list.sort_by(|a, b| b.1.taken_at.cmp(&a.1.taken_at).then_with(|| b.0.cmp(&a.0)));
+    list
+}
+
+/// One differing property; `None` means absent on that side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropDiff {
+    pub key: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+pub fn diff(left: &PropSnapshot, right: &PropSnapshot, hide_volatile: bool) -> Vec<PropDiff> {
+    let mut keys: Vec<&String> = left.props.keys().chain(right.props.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|k| !hide_volatile || !is_volatile(k))
+        .filter_map(|k| {
+            let (l, r) = (left.props.get(k), right.props.get(k));
+            (l != r).then(|| PropDiff {
+                key: k.clone(),
+                left: l.cloned(),
+                right: r.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Render a diff grouped by category, radio and display first.
+pub fn render_diff(left: &PropSnapshot, right: &PropSnapshot, hide_volatile: bool) -> String {
+    let diffs = diff(left, right, hide_volatile);
+    let mut output = format!(
+        "Property Diff:\n  A: {}\n  B: {}\n",
+        left.label(),
+        right.label()
+    );
+    if left.model != right.model {
+        output.push_str("  WARNING: different models; expect many unrelated differences.\n");
+    }
+    if hide_volatile {
+        output.push_str("  Volatile properties hidden.\n");
+    }
+    if diffs.is_empty() {
+        output.push_str("\n  No differences.\n");
+        return output;
+    }
+
+    for group in ["Radio", "Display", "Other"] {
+        let in_group: Vec<&PropDiff> = diffs.iter().filter(|d| category(&d.key) == group).collect();
+        if in_group.is_empty() {
+            continue;
+        }
+        output.push_str(&format!("\n  {} ({}):\n", group, in_group.len()));
+        for d in in_group {
+            match (&d.left, &d.right) {
+                (Some(l), Some(r)) => {
+                    output.push_str(&format!("    ~ {}: [{}] -> [{}]\n", d.key, l, r))
+                }
+                (Some(l), None) => {
+                    output.push_str(&format!("    - {}: [{}] (only in A)\n", d.key, l))
+                }
+                (None, Some(r)) => {
+                    output.push_str(&format!("    + {}: [{}] (only in B)\n", d.key, r))
+                }
+                (None, None) => {}
+            }
+        }
+    }
+    output.push_str(&format!("\n  {} differing properties.\n", diffs.len()));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(serial: &str, model: &str, props: &[(&str, &str)]) -> PropSnapshot {
+        PropSnapshot {
+            serial: serial.into(),
+            model: model.into(),
+            taken_at: 1_700_000_000,
+            props: props
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_getprop() {
+        let out = "[ro.product.model]: [SM-G991B]\n[persist.radio.multisim.config]: [dsds]\n\
+                   [ro.build.description]: [line one\nline two]\n[empty]: []\n";
+        let props = parse_getprop(out);
+        assert_eq!(props["ro.product.model"], "SM-G991B");
+        assert_eq!(props["ro.build.description"], "line one\nline two");
+        assert_eq!(props["empty"], "");
+        assert_eq!(props.len(), 4);
+    }
+
+    #[test]
+    fn test_volatile_and_category() {
+        assert!(is_volatile("init.svc.adbd"));
+        assert!(is_volatile("ro.serialno"));
+        assert!(!is_volatile("persist.radio.multisim.config"));
+        assert_eq!(category("persist.vendor.radio.enable_volte"), "Radio");
+        assert_eq!(category("ro.sf.lcd_density"), "Display");
+        assert_eq!(category("ro.build.id"), "Other");
+    }
+
+    #[test]
+    fn test_diff_filters_volatile() {
+        let a = snapshot(
+            "A1",
+            "Pixel 7",
+            &[
+                ("init.svc.adbd", "running"),
+                ("ro.sf.lcd_density", "420"),
+                ("ril.ecclist", "112"),
+            ],
+        );
+        let b = snapshot(
+            "B1",
+            "Pixel 7",
+            &[
+                ("init.svc.adbd", "stopped"),
+                ("ro.sf.lcd_density", "480"),
+                ("persist.radio.x", "1"),
+            ],
+        );
+        let d = diff(&a, &b, true);
+        let keys: Vec<&str> = d.iter().map(|d| d.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec!["persist.radio.x", "ril.ecclist", "ro.sf.lcd_density"]
+        );
+        assert_eq!(diff(&a, &b, false).len(), 4);
+
+        let text = render_diff(&a, &b, true);
+        assert!(text.contains("Radio (2)"), "{}", text);
+        assert!(text.contains("~ ro.sf.lcd_density: [420] -> [480]"));
+        assert!(text.contains("- ril.ecclist: [112] (only in A)"));
+        assert!(!text.contains("different models"));
+    }
+
+    #[test]
+    fn test_take_and_list_snapshots() -> Result<(), Box<dyn std::error::Error>> {
+        struct Guard;
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                crate::exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+                MOCK_SNAPSHOT_ROOT.with(|m| *m.borrow_mut() = None);
+            }
+        }
+        let root = std::env::temp_dir().join("foem_prop_snapshots");
+        let _ = fs::remove_dir_all(&root);
+        MOCK_SNAPSHOT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        crate::exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|_, _, _| {
+                Ok("[ro.product.model]: [Pixel 7]\n[ro.build.id]: [TQ3A]\n".to_string())
+            }));
+        });
+        let _guard = Guard;
+
+        let (first, snap) = take_snapshot("192.168.1.5:5555")?;
+        let (second, _) = take_snapshot("192.168.1.5:5555")?;
+        assert_ne!(first, second);
+        assert_eq!(snap.model, "Pixel 7");
+        assert!(first
+            .parent()
+            .is_some_and(|p| p.ends_with("192.168.1.5_5555")));
+
+        let list = list_snapshots();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].1, snap);
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+}
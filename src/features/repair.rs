@@ -2,13 +2,25 @@
 ///
 /// These operations interact with critical device partitions and data.
 /// Manufacturer-specific methods are used where applicable.
+use super::at::{self, AtResponse};
+use super::batch;
+use super::serial_console::LineSettings;
+use super::vault::{self, BackupKind, VaultFile, VaultManifest};
 use super::{adb, adb_shell, Manufacturer};
-use crate::adaptive_engine::{autodetect_diag_port, execute_goal, fingerprint, FuzzGoal};
+use crate::adaptive_engine::dry_run::{dry_run, load_samples, Samples};
+use crate::adaptive_engine::preconditions::{DeviceContext, DeviceMode};
+use crate::adaptive_engine::{autodetect_diag_port, execute_goal, FuzzGoal};
+use crate::exec::normalize_local_path;
 
 use std::fmt::Write;
 use std::io::{Read, Write as IoWrite};
+use std::path::Path;
 use std::time::Duration;
 
+/// Staging area on the device. Backups only live here until they are pulled
+/// into the host vault, because a reset or the repair itself can wipe it.
+const DEVICE_STAGING: &str = "/data/local/tmp/FOEM";
+
 #[cfg(not(test))]
 fn available_ports_mockable() -> Result<Vec<serialport::SerialPortInfo>, serialport::Error> {
     serialport::available_ports()
@@ -33,59 +45,8 @@ fn available_ports_mockable() -> Result<Vec<serialport::SerialPortInfo>, serialp
     })
 }
 
-
 // -- IMEI Management --
 
-/// Execute a batch of adb shell commands in one `sh -c` to avoid N+1 overhead.
-fn batch_shell(
-    serial: &str,
-    labeled_cmds: &[(&str, &str)],
-) -> Vec<(String, Result<String, String>)> {
-    if labeled_cmds.is_empty() {
-        return Vec::new();
-    }
-    let mut script = String::new();
-    for (_label, cmd) in labeled_cmds {
-        script.push_str(cmd);
-        script.push_str("; echo B_MARKER_$?;\n");
-    }
-    let mut results = Vec::with_capacity(labeled_cmds.len());
-    match adb_shell(serial, &["sh", "-c", &script]) {
-        Ok(output) => {
-            let mut parts = output.split("B_MARKER_");
-            let mut prev = parts.next().unwrap_or("").to_string();
-            for (idx, part) in parts.enumerate() {
-                if idx >= labeled_cmds.len() {
-                    break;
-                }
-                let (code_str, rest) = match part.find('\n') {
-                    Some(pos) => (&part[..pos], part[pos + 1..].to_string()),
-                    None => (part, String::new()),
-                };
-                let val = prev.trim().to_string();
-                let code: i32 = code_str.trim().parse().unwrap_or(-1);
-                if code == 0 {
-                    results.push((labeled_cmds[idx].0.to_string(), Ok(val)));
-                } else {
-                    let err = if val.is_empty() {
-                        format!("exit code {}", code)
-                    } else {
-                        val
-                    };
-                    results.push((labeled_cmds[idx].0.to_string(), Err(err)));
-                }
-                prev = rest;
-            }
-        }
-        Err(e) => {
-            for (label, _) in labeled_cmds {
-                results.push((label.to_string(), Err(e.clone())));
-            }
-        }
-    }
-    results
-}
-
 /// Read current IMEI(s) from the device using batched shell commands to avoid N+1 overhead.
 pub fn read_imei(serial: &str) -> String {
     let methods: &[(&str, &str)] = &[
@@ -94,7 +55,7 @@ pub fn read_imei(serial: &str) -> String {
         ("dumpsys", "dumpsys iphonesubinfo"),
     ];
     let mut output = String::from("IMEI Information:\n");
-    for (label, res) in batch_shell(serial, methods) {
+    for (label, res) in batch::run_commands(serial, methods) {
         match res {
             Ok(val) if !val.is_empty() => output.push_str(&format!("  {} -- {}\n", label, val)),
             Ok(_) => output.push_str(&format!("  {} -- empty response\n", label)),
@@ -202,53 +163,18 @@ Check the device screen for the MTB/Modem Test interface.
     output
 }
 
-/// Backup IMEI data (EFS-based) to device storage.
+/// Backup IMEI data (EFS-based) to the host vault.
 pub fn backup_imei(serial: &str) -> String {
-    let backup_path = "/data/local/tmp/FOEM/imei_backup";
-    let _ = adb_shell(serial, &["mkdir", "-p", backup_path]);
     let partitions = ["efs", "modemst1", "modemst2", "fsg", "fsc"];
     let mut output = String::from("IMEI/EFS Backup:\n");
-
-    let mut cmd = String::new();
-    for (i, part) in partitions.iter().enumerate() {
-        let src = format!("/dev/block/bootdevice/by-name/{}", part);
-        let dst = format!("{}/{}.img", backup_path, part);
-        cmd.push_str(&format!(
-            "if dd if={} of={} 2>/dev/null; then echo OK; else echo FAIL; fi",
-            src, dst
-        ));
-        if i < partitions.len() - 1 {
-            cmd.push_str("; echo B_MARKER; ");
-        }
-    }
-
-    match adb_shell(serial, &["sh", "-c", &cmd]) {
-        Ok(res) => {
-            let parts: Vec<&str> = res.split("B_MARKER").collect();
-            for (i, part) in partitions.iter().enumerate() {
-                let out = parts.get(i).copied().unwrap_or("").trim();
-                if out.contains("OK") {
-                    let _ = std::fmt::Write::write_fmt(
-                        &mut output,
-                        format_args!("  {} -- backed up\n", part),
-                    );
-                } else {
-                    let _ = std::fmt::Write::write_fmt(
-                        &mut output,
-                        format_args!("  {} -- not found or access denied\n", part),
-                    );
-                }
-            }
-        }
-        Err(_) => {
-            for part in &partitions {
-                let _ = std::fmt::Write::write_fmt(
-                    &mut output,
-                    format_args!("  {} -- not found or access denied\n", part),
-                );
-            }
-        }
-    }
+    backup_partitions_to_vault(
+        serial,
+        BackupKind::Imei,
+        "imei_backup",
+        &partitions,
+        None,
+        &mut output,
+    );
     output
 }
 
@@ -282,6 +208,9 @@ pub fn write_imei(_serial: &str, imei: &str, manufacturer: &Manufacturer) -> Str
                     at_cmd,
                     resp
                 ));
+                if !resp.is_ok() {
+                    output.push_str(&format!("  Error: {}\n", resp.result.description()));
+                }
             }
             Err(e) => {
                 output.push_str(&format!(
@@ -350,14 +279,13 @@ fn build_imei_write_commands(manufacturer: &Manufacturer, imeis: &[String]) -> V
 
 // -- Diagnostic Serial Port Communication --
 
-/// Send an AT command to a serial port and read the response.
+/// Send an AT command and read until a final result code arrives on its own line.
 pub fn send_at_command(
     port: &mut Box<dyn serialport::SerialPort>,
     command: &str,
-) -> Result<String, String> {
+) -> Result<AtResponse, String> {
     // Clear any stale data from the serial buffer before sending the command
-    let mut discard = [0u8; 1024];
-    let _ = port.read(&mut discard);
+    let _ = port.clear(serialport::ClearBuffer::Input);
 
     let cmd = format!("{}\r\n", command);
     port.write_all(cmd.as_bytes())
@@ -365,9 +293,6 @@ pub fn send_at_command(
     port.flush()
         .map_err(|e| format!("Failed to flush port: {}", e))?;
 
-    // Allow device time to process the command and prepare the response
-    std::thread::sleep(Duration::from_millis(200));
-
     let mut response = Vec::new();
     let mut buf = [0u8; 256];
     let deadline = std::time::Instant::now() + Duration::from_secs(3);
@@ -377,16 +302,13 @@ pub fn send_at_command(
             Ok(0) => break,
             Ok(n) => {
                 response.extend_from_slice(&buf[..n]);
-                let text = String::from_utf8_lossy(&response);
-                if text.contains("OK") || text.contains("ERROR") {
-                    break;
-                }
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                if !response.is_empty() {
-                    break;
+                if let Some(parsed) =
+                    at::parse_response(command, &String::from_utf8_lossy(&response))
+                {
+                    return Ok(parsed);
                 }
             }
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
             Err(e) => return Err(format!("Read error: {}", e)),
         }
         if std::time::Instant::now() >= deadline {
@@ -397,28 +319,10 @@ pub fn send_at_command(
     if response.is_empty() {
         return Err("No response from device. Port may not be a diagnostic port.".to_string());
     }
-
-    Ok(String::from_utf8_lossy(&response).to_string())
-}
-
-/// Parse the meaningful value from an AT command response.
-fn parse_at_value(response: &str, command_echo: &str) -> String {
-    let mut value_lines = Vec::new();
-    for line in response.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed == "OK" || trimmed == "ERROR" {
-            continue;
-        }
-        if trimmed.starts_with(command_echo) {
-            continue;
-        }
-        if let Some((_prefix, val)) = trimmed.split_once(':') {
-            value_lines.push(val.trim().to_string());
-        } else {
-            value_lines.push(trimmed.to_string());
-        }
-    }
-    value_lines.join("\n")
+    Err(format!(
+        "No final result code received. Partial response: {}",
+        response.escape_ascii()
+    ))
 }
 
 /// List available serial/diagnostic ports on the system.
@@ -457,12 +361,20 @@ pub fn list_diag_ports() -> String {
 }
 
 pub fn open_diag_port(port_name: &str) -> Result<Box<dyn serialport::SerialPort>, String> {
-    let port_result = serialport::new(port_name, 115200)
+    open_serial_port(port_name, &LineSettings::default())
+}
+
+/// Open a serial port with explicit line settings (8 data bits, 1 stop bit).
+pub fn open_serial_port(
+    port_name: &str,
+    settings: &LineSettings,
+) -> Result<Box<dyn serialport::SerialPort>, String> {
+    let port_result = serialport::new(port_name, settings.baud)
         .timeout(Duration::from_secs(3))
         .data_bits(serialport::DataBits::Eight)
-        .parity(serialport::Parity::None)
+        .parity(settings.parity.to_serialport())
         .stop_bits(serialport::StopBits::One)
-        .flow_control(serialport::FlowControl::None)
+        .flow_control(settings.flow_control.to_serialport())
         .open();
 
     match port_result {
@@ -492,29 +404,105 @@ pub fn open_diag_port(port_name: &str) -> Result<Box<dyn serialport::SerialPort>
     }
 }
 
-/// Send raw diagnostic bytes over an open port. Returns error on write/flush failure.
+/// Send one DIAG command (HDLC-framed) over an open port and return the
+/// decoded response payload.
 pub fn send_diag_bytes(
     port: &mut Box<dyn serialport::SerialPort>,
     bytes: &[u8],
-) -> Result<(), String> {
-    port.write_all(bytes)
-        .map_err(|e| format!("Diag write failed: {}", e))?;
-    port.flush()
-        .map_err(|e| format!("Diag flush failed: {}", e))?;
-    Ok(())
+) -> Result<Vec<u8>, String> {
+    super::diag::DiagClient::new(port).request(bytes)
+}
+
+/// Read modem build and mask information over the Qualcomm DIAG protocol.
+pub fn read_modem_info_diag(port_name: Option<&str>) -> String {
+    let autodetected = autodetect_diag_port();
+    let port_name = match port_name.or(autodetected.as_deref()) {
+        Some(p) => p.to_string(),
+        None => {
+            return "No diagnostic port detected. Enable diag mode and try again.".to_string();
+        }
+    };
+    let port = match open_diag_port(&port_name) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    let mut client = super::diag::DiagClient::new(port);
+    let mut output = format!("DIAG Modem Info ({}):\n", port_name);
+
+    match client.version_info() {
+        Ok(v) => output.push_str(&format!(
+            "  Version dir: {}\n  Compiled: {} {}\n  Released: {} {}\n  \
+             Mobile model: {}, firmware rev {}, HW {}.{}\n",
+            v.version_dir,
+            v.compile_date,
+            v.compile_time,
+            v.release_date,
+            v.release_time,
+            v.mobile_model,
+            v.firmware_revision,
+            v.hw_version.0,
+            v.hw_version.1
+        )),
+        Err(e) => {
+            output.push_str(&format!("  Version info: {}\n", e));
+            return output;
+        }
+    }
+    match client.ext_build_id() {
+        Ok(b) => output.push_str(&format!(
+            "  Build ID: {}\n  Model: {}\n  MSM HW version: 0x{:08X}\n",
+            b.build_id, b.model_string, b.msm_hw_version
+        )),
+        Err(e) => output.push_str(&format!("  Build ID: {}\n", e)),
+    }
+    match client.status() {
+        Ok(st) => output.push_str(&format!(
+            "  ESN: 0x{:08X}\n  RF mode: {}\n",
+            st.esn, st.rf_mode
+        )),
+        Err(e) => output.push_str(&format!("  Status: {}\n", e)),
+    }
+    match client.log_id_ranges() {
+        Ok(ranges) => {
+            output.push_str("  Log masks:\n");
+            for (equip, last) in ranges.iter().enumerate().filter(|(_, l)| **l > 0) {
+                match client.log_mask(equip as u32) {
+                    Ok(m) => output.push_str(&format!(
+                        "    equip {:>2}: {}/{} items enabled\n",
+                        equip,
+                        m.enabled_count(),
+                        m.num_items
+                    )),
+                    Err(e) => output.push_str(&format!(
+                        "    equip {:>2}: last item 0x{:X}, mask unavailable ({})\n",
+                        equip, last, e
+                    )),
+                }
+            }
+        }
+        Err(e) => output.push_str(&format!("  Log masks: {}\n", e)),
+    }
+    match client.event_mask() {
+        Ok(m) => output.push_str(&format!(
+            "  Event mask: {}/{} events enabled\n",
+            m.enabled_count(),
+            m.num_bits
+        )),
+        Err(e) => output.push_str(&format!("  Event mask: {}\n", e)),
+    }
+    output
 }
 
 fn query_device_identity(port: &mut Box<dyn serialport::SerialPort>, output: &mut String) {
     match send_at_command(port, "AT") {
+        Ok(resp) if resp.is_ok() => output.push_str("  Port alive: OK\n"),
         Ok(resp) => {
-            if !resp.contains("OK") {
-                output.push_str(
-                    "  Port did not respond with OK to AT command.\n\
-                     Device may not be in AT command / diagnostic mode.\n",
-                );
-                return;
-            }
-            output.push_str("  Port alive: OK\n");
+            output.push_str(&format!(
+                "  Port answered AT with {}.\n\
+                 Device may not be in AT command / diagnostic mode.\n",
+                resp.result
+            ));
+            return;
         }
         Err(e) => {
             output.push_str(&format!(
@@ -528,45 +516,36 @@ fn query_device_identity(port: &mut Box<dyn serialport::SerialPort>, output: &mu
         }
     }
 
-    if let Ok(resp) = send_at_command(port, "AT+CGMI") {
-        let val = parse_at_value(&resp, "AT+CGMI");
-        if !val.is_empty() {
-            output.push_str(&format!("  Manufacturer: {}\n", val));
-        }
-    }
-
-    if let Ok(resp) = send_at_command(port, "AT+CGMM") {
-        let val = parse_at_value(&resp, "AT+CGMM");
-        if !val.is_empty() {
-            output.push_str(&format!("  Model: {}\n", val));
-        }
-    }
-
-    if let Ok(resp) = send_at_command(port, "AT+CGMR") {
-        let val = parse_at_value(&resp, "AT+CGMR");
-        if !val.is_empty() {
-            output.push_str(&format!("  Revision: {}\n", val));
+    for (command, label) in [
+        ("AT+CGMI", "Manufacturer"),
+        ("AT+CGMM", "Model"),
+        ("AT+CGMR", "Revision"),
+    ] {
+        if let Ok(resp) = send_at_command(port, command).and_then(AtResponse::into_result) {
+            let val = resp.value();
+            if !val.is_empty() {
+                output.push_str(&format!("  {}: {}\n", label, val));
+            }
         }
     }
 
     match send_at_command(port, "AT+CGSN") {
+        Ok(resp) if !resp.is_ok() => {
+            output.push_str(&format!(
+                "  IMEI (AT+CGSN): {}\n",
+                resp.result.description()
+            ));
+        }
         Ok(resp) => {
-            if resp.contains("ERROR") {
-                output.push_str(
-                    "  IMEI (AT+CGSN): ERROR -- command not supported or device not ready.\n",
-                );
+            let imei = resp.value();
+            if imei.is_empty() {
+                output.push_str("  IMEI (AT+CGSN): no response data.\n");
             } else {
-                let imei = parse_at_value(&resp, "AT+CGSN");
-                if imei.is_empty() {
-                    output.push_str("  IMEI (AT+CGSN): no response data.\n");
+                let clean: String = imei.chars().filter(|c| c.is_ascii_digit()).collect();
+                if clean.len() == 15 {
+                    output.push_str(&format!("  IMEI 1: {}\n", clean));
                 } else {
-                    let clean: String =
-                        imei.trim().chars().filter(|c| c.is_ascii_digit()).collect();
-                    if clean.len() == 15 {
-                        output.push_str(&format!("  IMEI 1: {}\n", clean));
-                    } else {
-                        output.push_str(&format!("  IMEI (raw): {}\n", imei));
-                    }
+                    output.push_str(&format!("  IMEI (raw): {}\n", imei));
                 }
             }
         }
@@ -575,19 +554,14 @@ fn query_device_identity(port: &mut Box<dyn serialport::SerialPort>, output: &mu
         }
     }
 
-    if let Ok(resp) = send_at_command(port, "AT+CGSN=1") {
-        if !resp.contains("ERROR") {
-            let imei2 = parse_at_value(&resp, "AT+CGSN");
-            if !imei2.is_empty() {
-                let clean: String = imei2
-                    .trim()
-                    .chars()
-                    .filter(|c| c.is_ascii_digit())
-                    .collect();
-                if clean.len() == 15 {
-                    output.push_str(&format!("  IMEI 2: {}\n", clean));
-                }
-            }
+    if let Ok(resp) = send_at_command(port, "AT+CGSN=1").and_then(AtResponse::into_result) {
+        let clean: String = resp
+            .value()
+            .chars()
+            .filter(|c| c.is_ascii_digit())
+            .collect();
+        if clean.len() == 15 {
+            output.push_str(&format!("  IMEI 2: {}\n", clean));
         }
     }
 }
@@ -615,16 +589,6 @@ pub fn read_imei_diag(port_name: &str) -> String {
     output
 }
 
-fn get_fingerprint(serial: &str) -> String {
-    let script = "getprop ro.product.model; echo SEP; getprop ro.build.version.release; echo SEP; getprop ro.board.platform";
-    let out = adb_shell(serial, &["sh", "-c", script]).unwrap_or_default();
-    let mut parts = out.split("SEP");
-    let model = parts.next().unwrap_or("").trim();
-    let release = parts.next().unwrap_or("").trim();
-    let platform = parts.next().unwrap_or("").trim();
-    fingerprint(model, release, platform)
-}
-
 // -- GMS (Google Mobile Services) Repair --
 
 const GMS_PACKAGES: &[&str] = &[
@@ -639,32 +603,21 @@ const GMS_PACKAGES: &[&str] = &[
 /// Check GMS package status.
 pub fn check_gms(serial: &str) -> String {
     let mut output = String::from("GMS Package Status:\n");
-    let mut cmd = String::new();
-    for (i, pkg) in GMS_PACKAGES.iter().enumerate() {
-        cmd.push_str(&format!("pm list packages {}", pkg));
-        if i < GMS_PACKAGES.len() - 1 {
-            cmd.push_str("; echo B_MARKER; ");
-        }
-    }
-
-    match adb_shell(serial, &["sh", "-c", &cmd]) {
-        Ok(res) => {
-            let parts: Vec<&str> = res.split("B_MARKER").collect();
-            for (i, pkg) in GMS_PACKAGES.iter().enumerate() {
-                let out = parts.get(i).copied().unwrap_or("");
-                let installed = out.contains(pkg);
-                output.push_str(&format!(
-                    "  {} -- {}\n",
-                    pkg,
-                    if installed { "installed" } else { "MISSING" }
-                ));
-            }
-        }
-        Err(_) => {
-            for pkg in GMS_PACKAGES {
-                output.push_str(&format!("  {} -- MISSING\n", pkg));
-            }
-        }
+    let cmds: Vec<(&str, String)> = GMS_PACKAGES
+        .iter()
+        .map(|pkg| (*pkg, format!("pm list packages {}", pkg)))
+        .collect();
+    for (pkg, res) in batch::run_commands(serial, &cmds) {
+        // `pm list packages` matches substrings, so look for the exact line.
+        let installed = res.is_ok_and(|out| {
+            out.lines()
+                .any(|l| l.trim().strip_prefix("package:") == Some(pkg.as_str()))
+        });
+        output.push_str(&format!(
+            "  {} -- {}\n",
+            pkg,
+            if installed { "installed" } else { "MISSING" }
+        ));
     }
     output
 }
@@ -672,23 +625,28 @@ pub fn check_gms(serial: &str) -> String {
 /// Clear GMS caches and force restart.
 pub fn repair_gms(serial: &str) -> String {
     let mut output = String::from("GMS Repair:\n");
-    let mut cmd = String::new();
-    for (i, pkg) in GMS_PACKAGES.iter().enumerate() {
-        cmd.push_str(&format!("pm clear {}", pkg));
-        if i < GMS_PACKAGES.len() - 1 {
-            cmd.push_str("; echo B_MARKER; ");
-        }
-    }
-
-    // Batch force-stop and broadcast into the same shell command
-    cmd.push_str("; am force-stop com.google.android.gms; am broadcast -a android.intent.action.BOOT_COMPLETED");
+    let mut cmds: Vec<(&str, String)> = GMS_PACKAGES
+        .iter()
+        .map(|pkg| (*pkg, format!("pm clear {}", pkg)))
+        .collect();
+    // Batch force-stop and broadcast into the same shell call
+    cmds.push((
+        "restart",
+        "am force-stop com.google.android.gms; am broadcast -a android.intent.action.BOOT_COMPLETED"
+            .to_string(),
+    ));
 
-    let _ = adb_shell(serial, &["sh", "-c", &cmd]);
-    for pkg in GMS_PACKAGES {
-        output.push_str(&format!("  Cleared cache: {}\n", pkg));
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        match (label.as_str(), res) {
+            ("restart", Ok(_)) => output.push_str("  Force-stopped GMS and sent boot broadcast.\n"),
+            ("restart", Err(e)) => {
+                output.push_str(&format!("  Force-stop/boot broadcast failed: {}\n", e))
+            }
+            (pkg, Ok(_)) => output.push_str(&format!("  Cleared cache: {}\n", pkg)),
+            (pkg, Err(e)) => output.push_str(&format!("  Clear failed: {} ({})\n", pkg, e)),
+        }
     }
 
-    output.push_str("  Force-stopped GMS and sent boot broadcast.\n");
     output.push_str("  Reboot recommended for full effect.\n");
     output
 }
@@ -701,127 +659,366 @@ pub fn install_gms_package(serial: &str, apk_path: &str) -> String {
     }
 }
 
-// -- EFS Backup and Restore --
+// -- Host Backup Vault --
 
-/// Backup EFS partition.
-pub fn backup_efs(serial: &str) -> String {
-    let backup_dir = "/data/local/tmp/FOEM/efs_backup";
-    let _ = adb_shell(serial, &["mkdir", "-p", backup_dir]);
-    match adb_shell(serial, &["ls", "/efs/"]) {
-        Ok(listing) => {
-            let _ = adb_shell(
-                serial,
-                &[
-                    "tar",
-                    "-czf",
-                    &format!("{}/efs.tar.gz", backup_dir),
-                    "/efs/",
-                ],
-            );
-            format!(
-                "EFS backup attempt:\n  Contents: {}\n  Saved to: {}/efs.tar.gz",
-                listing, backup_dir
+/// Dump block partitions into the device staging area, pull every image that
+/// was read into a new vault entry and record them in its manifest.
+///
+/// Returns how many partitions made it into the vault.
+fn backup_partitions_to_vault(
+    serial: &str,
+    kind: BackupKind,
+    staging_name: &str,
+    partitions: &[&str],
+    note: Option<String>,
+    output: &mut String,
+) -> usize {
+    let staging = format!("{}/{}", DEVICE_STAGING, staging_name);
+    let _ = adb_shell(serial, &["mkdir", "-p", &staging]);
+
+    let cmds: Vec<(&str, String)> = partitions
+        .iter()
+        .map(|part| {
+            (
+                *part,
+                format!(
+                    "dd if=/dev/block/bootdevice/by-name/{} of={}/{}.img",
+                    part, staging, part
+                ),
             )
+        })
+        .collect();
+
+    let mut dumped = Vec::new();
+    for ((_, res), part) in batch::run_commands(serial, &cmds)
+        .into_iter()
+        .zip(partitions)
+    {
+        if res.is_ok() {
+            dumped.push(*part);
         }
-        Err(_) => "EFS partition not accessible. Root may be required.".to_string(),
     }
+    for part in partitions.iter().filter(|p| !dumped.contains(p)) {
+        output.push_str(&format!(
+            "  {} -- not found or access denied (root required)\n",
+            part
+        ));
+    }
+
+    let remotes: Vec<(String, &str)> = dumped
+        .iter()
+        .map(|part| (format!("{}/{}.img", staging, part), *part))
+        .collect();
+    let saved = store_in_vault(serial, kind, &remotes, note, output);
+    let _ = adb_shell(serial, &["rm", "-rf", &staging]);
+    saved
 }
 
-/// Restore EFS partition from backup.
-pub fn restore_efs(serial: &str) -> String {
-    let backup_path = "/data/local/tmp/FOEM/efs_backup/efs.tar.gz";
-    match adb_shell(serial, &["ls", backup_path]) {
-        Ok(_) => {
-            let _ = adb_shell(serial, &["tar", "-xzf", backup_path, "-C", "/"]);
-            format!(
-                "EFS restore attempted from {}.\nReboot required.",
-                backup_path
+/// Pull the given `(remote path, partition)` pairs into a new vault entry.
+///
+/// Returns the number of files recorded in the written manifest.
+fn store_in_vault(
+    serial: &str,
+    kind: BackupKind,
+    remotes: &[(String, &str)],
+    note: Option<String>,
+    output: &mut String,
+) -> usize {
+    if remotes.is_empty() {
+        output.push_str("  Nothing was saved to the vault.\n");
+        return 0;
+    }
+    let dir = match vault::new_entry_dir(serial) {
+        Ok(d) => d,
+        Err(e) => {
+            output.push_str(&format!("  Vault error: {}\n", e));
+            return 0;
+        }
+    };
+
+    let mut files: Vec<VaultFile> = Vec::new();
+    for (remote, part) in remotes {
+        match vault::pull_file(serial, &dir, remote, part) {
+            Ok(f) => {
+                output.push_str(&format!(
+                    "  {} -- saved ({} bytes, sha256 {})\n",
+                    part, f.size, f.sha256
+                ));
+                files.push(f);
+            }
+            Err(e) => output.push_str(&format!("  {} -- pull to host failed: {}\n", part, e)),
+        }
+    }
+    if files.is_empty() {
+        let _ = std::fs::remove_dir_all(&dir);
+        output.push_str("  Nothing was saved to the vault.\n");
+        return 0;
+    }
+
+    let saved = files.len();
+    let manifest = VaultManifest {
+        kind,
+        created_at: vault::now_secs(),
+        foem_version: crate::VERSION.to_string(),
+        device: vault::device_identity(serial),
+        files,
+        note,
+    };
+    match vault::write_manifest(&dir, &manifest) {
+        Ok(()) => {
+            output.push_str(&format!("  Vault entry: {}\n", dir.display()));
+            saved
+        }
+        Err(e) => {
+            output.push_str(&format!("  Vault error: {}\n", e));
+            0
+        }
+    }
+}
+
+/// Refuse restores onto the wrong device or from damaged files.
+fn restore_blocked(serial: &str, entry: &vault::VaultEntry, output: &mut String) -> bool {
+    let current = vault::device_identity(serial);
+    for w in vault::restore_warnings(entry, &current) {
+        output.push_str(&format!("  Note: {}\n", w));
+    }
+    let problems = vault::restore_problems(entry, &current);
+    if problems.is_empty() {
+        return false;
+    }
+    output.push_str("  Restore blocked:\n");
+    for p in &problems {
+        output.push_str(&format!("    - {}\n", p));
+    }
+    true
+}
+
+/// Size in bytes of each named block partition, as reported by `blockdev`.
+fn partition_sizes(serial: &str, partitions: &[&str]) -> Vec<Option<u64>> {
+    let cmds: Vec<(&str, String)> = partitions
+        .iter()
+        .map(|part| {
+            (
+                *part,
+                format!(
+                    "blockdev --getsize64 /dev/block/bootdevice/by-name/{}",
+                    part
+                ),
             )
+        })
+        .collect();
+    batch::run_commands(serial, &cmds)
+        .into_iter()
+        .map(|(_, res)| res.ok().and_then(|v| v.parse().ok()))
+        .collect()
+}
+
+// -- EFS Backup and Restore --
+
+/// Archive `/efs` on the device and store the archive in a new vault entry.
+fn efs_to_vault(serial: &str, note: Option<String>, output: &mut String) -> usize {
+    let staging = format!("{}/efs_backup", DEVICE_STAGING);
+    let archive = format!("{}/efs.tar.gz", staging);
+    let _ = adb_shell(serial, &["mkdir", "-p", &staging]);
+    let listing = match adb_shell(serial, &["ls", "/efs/"]) {
+        Ok(listing) => listing,
+        Err(_) => {
+            output.push_str("  EFS partition not accessible. Root may be required.\n");
+            return 0;
         }
-        Err(_) => "No EFS backup found. Run backup first.".to_string(),
+    };
+    let _ = adb_shell(serial, &["tar", "-czf", &archive, "/efs/"]);
+
+    output.push_str(&format!("  Contents: {}\n", listing));
+    let saved = store_in_vault(serial, BackupKind::Efs, &[(archive, "efs")], note, output);
+    let _ = adb_shell(serial, &["rm", "-rf", &staging]);
+    saved
+}
+
+/// Backup the EFS partition to the host vault.
+pub fn backup_efs(serial: &str) -> String {
+    let mut output = String::from("EFS backup:\n");
+    efs_to_vault(serial, None, &mut output);
+    output
+}
+
+/// Restore the EFS partition from a vault entry.
+///
+/// The entry must come from this device and pass its checksums, and the
+/// current `/efs` is snapshotted into the vault before anything is written.
+pub fn restore_efs(serial: &str, entry_dir: &Path) -> String {
+    let entry = match vault::load_entry(entry_dir) {
+        Ok(e) => e,
+        Err(e) => return format!("No usable EFS backup: {}", e),
+    };
+    if entry.manifest.kind != BackupKind::Efs {
+        return format!(
+            "Vault entry is of type {}, not an EFS archive.",
+            entry.manifest.kind.label()
+        );
     }
+    let file = match entry.manifest.files.first() {
+        Some(f) => f,
+        None => return "Vault entry contains no EFS archive.".to_string(),
+    };
+
+    let mut output = String::from("EFS Restore:\n");
+    if restore_blocked(serial, &entry, &mut output) {
+        return output;
+    }
+
+    output.push_str("  Pre-restore snapshot:\n");
+    let note = format!(
+        "Automatic snapshot before restoring {}",
+        entry.dir.display()
+    );
+    if efs_to_vault(serial, Some(note), &mut output) == 0 {
+        output.push_str("  Restore aborted: the current EFS could not be snapshotted.\n");
+        return output;
+    }
+
+    let staging = format!("{}/efs_restore", DEVICE_STAGING);
+    let remote = format!("{}/{}", staging, file.file);
+    let local = entry.dir.join(&file.file);
+    let _ = adb_shell(serial, &["mkdir", "-p", &staging]);
+    if let Err(e) = adb(serial, &["push", &local.to_string_lossy(), &remote]) {
+        output.push_str(&format!(
+            "  Restore failed: could not push archive: {}\n",
+            e
+        ));
+        return output;
+    }
+    let _ = adb_shell(serial, &["tar", "-xzf", &remote, "-C", "/"]);
+    let _ = adb_shell(serial, &["rm", "-rf", &staging]);
+    output.push_str(&format!(
+        "  EFS restore attempted from {}.\n  Reboot required.\n",
+        entry.dir.display()
+    ));
+    output
 }
 
 // -- NV Data (Non-Volatile) --
 
-/// Backup NV data partitions (modemst1, modemst2, fsg).
+/// Backup NV data partitions (modemst1, modemst2, fsg, fsc) to the host vault.
 pub fn backup_nv_data(serial: &str) -> String {
-    let backup_dir = "/data/local/tmp/FOEM/nv_backup";
-    let _ = adb_shell(serial, &["mkdir", "-p", backup_dir]);
     let partitions = ["modemst1", "modemst2", "fsg", "fsc"];
     let mut output = String::from("NV Data Backup:\n");
+    backup_partitions_to_vault(
+        serial,
+        BackupKind::NvData,
+        "nv_backup",
+        &partitions,
+        None,
+        &mut output,
+    );
+    output
+}
 
-    let mut cmd = String::new();
-    for (i, part) in partitions.iter().enumerate() {
-        let src = format!("/dev/block/bootdevice/by-name/{}", part);
-        let dst = format!("{}/{}.img", backup_dir, part);
-        cmd.push_str(&format!(
-            "if dd if={} of={} 2>/dev/null; then echo OK; else echo FAIL; fi",
-            src, dst
-        ));
-        if i < partitions.len() - 1 {
-            cmd.push_str("; echo B_MARKER; ");
-        }
+/// Restore NV data partitions from a vault entry.
+///
+/// Nothing is written unless the entry was taken from this device, every
+/// image passes its checksum and matches its partition size, and the current
+/// partitions have been snapshotted into the vault.
+pub fn restore_nv_data(serial: &str, entry_dir: &Path) -> String {
+    let entry = match vault::load_entry(entry_dir) {
+        Ok(e) => e,
+        Err(e) => return format!("No usable NV backup: {}", e),
+    };
+    if !matches!(entry.manifest.kind, BackupKind::NvData | BackupKind::Imei) {
+        return format!(
+            "Vault entry is of type {}, not NV data.",
+            entry.manifest.kind.label()
+        );
     }
 
-    match adb_shell(serial, &["sh", "-c", &cmd]) {
-        Ok(res) => {
-            let parts: Vec<&str> = res.split("B_MARKER").collect();
-            for (i, part) in partitions.iter().enumerate() {
-                let out = parts.get(i).copied().unwrap_or("").trim();
-                if out.contains("OK") {
-                    output.push_str(&format!("  {} -- saved\n", part));
-                } else {
-                    output.push_str(&format!("  {} -- failed (root required)\n", part));
-                }
-            }
+    let mut output = String::from("NV Data Restore:\n");
+    if restore_blocked(serial, &entry, &mut output) {
+        return output;
+    }
+
+    let partitions: Vec<&str> = entry
+        .manifest
+        .files
+        .iter()
+        .map(|f| f.partition.as_str())
+        .collect();
+    let sizes = partition_sizes(serial, &partitions);
+    let mut size_problems = Vec::new();
+    for (file, size) in entry.manifest.files.iter().zip(&sizes) {
+        match size {
+            Some(size) if *size == file.size => {}
+            Some(size) => size_problems.push(format!(
+                "{} image is {} bytes, partition is {} bytes",
+                file.partition, file.size, size
+            )),
+            None => size_problems.push(format!(
+                "{}: partition size unavailable (root required)",
+                file.partition
+            )),
         }
-        Err(_) => {
-            for part in &partitions {
-                output.push_str(&format!("  {} -- failed (root required)\n", part));
-            }
+    }
+    if !size_problems.is_empty() {
+        output.push_str("  Restore blocked:\n");
+        for p in &size_problems {
+            output.push_str(&format!("    - {}\n", p));
         }
+        return output;
     }
-    output
-}
 
-/// Restore NV data partitions from backup.
-pub fn restore_nv_data(serial: &str) -> String {
-    let backup_dir = "/data/local/tmp/FOEM/nv_backup";
-    let partitions = ["modemst1", "modemst2", "fsg", "fsc"];
-    let mut output = String::from("NV Data Restore:\n");
+    output.push_str("  Pre-restore snapshot:\n");
+    let note = format!(
+        "Automatic snapshot before restoring {}",
+        entry.dir.display()
+    );
+    let snapshot = backup_partitions_to_vault(
+        serial,
+        BackupKind::NvData,
+        "nv_snapshot",
+        &partitions,
+        Some(note),
+        &mut output,
+    );
+    if snapshot != partitions.len() {
+        output.push_str("  Restore aborted: the current partitions could not be snapshotted.\n");
+        return output;
+    }
 
-    let mut cmd = String::new();
-    for (i, part) in partitions.iter().enumerate() {
-        let src = format!("{}/{}.img", backup_dir, part);
-        let dst = format!("/dev/block/bootdevice/by-name/{}", part);
-        cmd.push_str(&format!(
-            "if dd if={} of={} 2>/dev/null; then echo OK; else echo FAIL; fi",
-            src, dst
-        ));
-        if i < partitions.len() - 1 {
-            cmd.push_str("; echo B_MARKER; ");
+    let staging = format!("{}/nv_restore", DEVICE_STAGING);
+    let _ = adb_shell(serial, &["mkdir", "-p", &staging]);
+    for file in &entry.manifest.files {
+        let local = entry.dir.join(&file.file);
+        let remote = format!("{}/{}", staging, file.file);
+        if let Err(e) = adb(serial, &["push", &local.to_string_lossy(), &remote]) {
+            let _ = adb_shell(serial, &["rm", "-rf", &staging]);
+            output.push_str(&format!(
+                "  Restore aborted: could not push {}: {}\n",
+                file.file, e
+            ));
+            return output;
         }
     }
 
-    match adb_shell(serial, &["sh", "-c", &cmd]) {
-        Ok(res) => {
-            let parts: Vec<&str> = res.split("B_MARKER").collect();
-            for (i, part) in partitions.iter().enumerate() {
-                let out = parts.get(i).copied().unwrap_or("").trim();
-                if out.contains("OK") {
-                    output.push_str(&format!("  {} -- restored\n", part));
-                } else {
-                    output.push_str(&format!("  {} -- failed\n", part));
-                }
-            }
-        }
-        Err(_) => {
-            for part in &partitions {
-                output.push_str(&format!("  {} -- failed\n", part));
-            }
+    let cmds: Vec<(&str, String)> = entry
+        .manifest
+        .files
+        .iter()
+        .map(|file| {
+            (
+                file.partition.as_str(),
+                format!(
+                    "dd if={}/{} of=/dev/block/bootdevice/by-name/{}",
+                    staging, file.file, file.partition
+                ),
+            )
+        })
+        .collect();
+    for (partition, res) in batch::run_commands(serial, &cmds) {
+        match res {
+            Ok(_) => output.push_str(&format!("  {} -- restored\n", partition)),
+            Err(e) => output.push_str(&format!("  {} -- failed: {}\n", partition, e)),
         }
     }
+    let _ = adb_shell(serial, &["rm", "-rf", &staging]);
     output.push_str("  Reboot required.\n");
     output
 }
@@ -885,27 +1082,19 @@ pub fn check_baseband(serial: &str) -> String {
         ("Radio", "gsm.current.phone-type"),
     ];
 
-    let mut script = String::new();
-    for (_, prop) in &props {
-        let _ = write!(script, "getprop {}; echo B_MARKER; ", prop);
-    }
+    let cmds: Vec<(&str, String)> = props
+        .iter()
+        .map(|(label, prop)| (*label, format!("getprop {}", prop)))
+        .collect();
 
     let mut output = String::from("Baseband/Modem Info:\n");
-    match adb_shell(serial, &["sh", "-c", script.as_str()]) {
-        Ok(res) => {
-            let mut parts = res.split("B_MARKER");
-            for (label, _) in &props {
-                let val = parts.next().unwrap_or("").trim();
-                if !val.is_empty() {
-                    let _ = writeln!(output, "  {}: {}", label, val);
-                } else {
-                    let _ = writeln!(output, "  {}: not available", label);
-                }
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        match res {
+            Ok(val) if !val.is_empty() => {
+                let _ = writeln!(output, "  {}: {}", label, val);
             }
-        }
-        Err(_) => {
-            for (label, _) in &props {
-                output.push_str(&format!("  {}: not available\n", label));
+            _ => {
+                let _ = writeln!(output, "  {}: not available", label);
             }
         }
     }
@@ -945,28 +1134,14 @@ pub fn read_build_props(serial: &str) -> String {
     ];
     let mut output = String::from("Build Properties:\n");
 
-    let mut cmd = String::new();
-    for (i, (_, prop)) in props.iter().enumerate() {
-        cmd.push_str(&format!("getprop {}", prop));
-        if i < props.len() - 1 {
-            cmd.push_str("; echo B_MARKER; ");
-        }
-    }
-
-    match adb_shell(serial, &["sh", "-c", &cmd]) {
-        Ok(res) => {
-            let parts: Vec<&str> = res.split("B_MARKER").map(|s| s.trim()).collect();
-            for (i, (label, _)) in props.iter().enumerate() {
-                let val = parts.get(i).copied().unwrap_or("").trim();
-                let display_val = if val.is_empty() { "--" } else { val };
-                output.push_str(&format!("  {}: {}\n", label, display_val));
-            }
-        }
-        Err(_) => {
-            for (label, _) in &props {
-                output.push_str(&format!("  {}: --\n", label));
-            }
-        }
+    let cmds: Vec<(&str, String)> = props
+        .iter()
+        .map(|(label, prop)| (*label, format!("getprop {}", prop)))
+        .collect();
+    for (label, res) in batch::run_commands(serial, &cmds) {
+        let val = res.unwrap_or_default();
+        let display_val = if val.is_empty() { "--" } else { val.as_str() };
+        output.push_str(&format!("  {}: {}\n", label, display_val));
     }
     output
 }
@@ -974,15 +1149,58 @@ pub fn read_build_props(serial: &str) -> String {
 // -- Adaptive Diag Enable via heuristic engine --
 
 /// Enable diagnostic port using adaptive heuristic engine with self-learning.
-pub fn enable_diag_port(serial: &str, manufacturer: &Manufacturer) -> String {
-    let fp = get_fingerprint(serial);
+fn diag_fingerprint(device: &mut DeviceContext, manufacturer: &Manufacturer) -> String {
+    if device.manufacturer.is_empty() {
+        device.manufacturer = manufacturer.name().to_string();
+    }
     // Use manufacturer hint as part of fingerprint to increase specificity
-    let fp = format!("{}|{}", fp, manufacturer.name());
-    execute_goal(serial, FuzzGoal::EnableDiagPort, &fp, None)
+    format!("{}|{}", device.fingerprint(), manufacturer.name())
+}
+
+pub fn enable_diag_port(serial: &str, manufacturer: &Manufacturer) -> String {
+    let mut device = DeviceContext::query(serial, None);
+    let fp = diag_fingerprint(&mut device, manufacturer);
+    execute_goal(serial, FuzzGoal::EnableDiagPort, &fp, &device, None)
+}
+
+/// Show what `enable_diag_port` would do, checking markers against the
+/// samples file at `samples_path` (optional). Without a device, every mode
+/// is assumed reachable so recipes can be checked offline.
+pub fn dry_run_diag_port(
+    serial: Option<&str>,
+    manufacturer: &Manufacturer,
+    samples_path: &str,
+) -> String {
+    let samples = if samples_path.trim().is_empty() {
+        Samples::new()
+    } else {
+        match load_samples(Path::new(&normalize_local_path(samples_path.trim()))) {
+            Ok(samples) => samples,
+            Err(e) => return e,
+        }
+    };
+    let mut device = match serial {
+        Some(serial) => DeviceContext::query(serial, None),
+        None => DeviceContext {
+            modes: vec![DeviceMode::Adb, DeviceMode::Fastboot, DeviceMode::Diag],
+            ..Default::default()
+        },
+    };
+    let fp = diag_fingerprint(&mut device, manufacturer);
+    dry_run(
+        serial.unwrap_or("<serial>"),
+        FuzzGoal::EnableDiagPort,
+        &fp,
+        &device,
+        None,
+        &samples,
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::features::batch;
+
     #[test]
     fn read_imei_success() {
         super::MOCK_AVAILABLE_PORTS.with(|mock| {
@@ -1009,9 +1227,10 @@ mod tests {
                         return Ok("Starting: Intent { action=android.intent.action.DIAL ... }"
                             .to_string());
                     }
-                    if cmd.contains("sh -c") {
-                        return Ok(
-                            "123456789012345\nB_MARKER_0\nB_MARKER_0\nB_MARKER_0\n".to_string()
+                    if cmd.contains("iphonesubinfo") {
+                        return batch::mock_stdout(
+                            args.last().ok_or("no script")?,
+                            &[("123456789012345", 0), ("", 0), ("", 0)],
                         );
                     }
                 }
@@ -1065,8 +1284,11 @@ mod tests {
                     if cmd.contains("shell am start -a android.intent.action.DIAL") {
                         return Err("error".to_string());
                     }
-                    if cmd.contains("sh -c") {
-                        return Ok("B_MARKER_0\nB_MARKER_0\nB_MARKER_0\n".to_string());
+                    if cmd.contains("iphonesubinfo") {
+                        return batch::mock_stdout(
+                            args.last().ok_or("no script")?,
+                            &[("", 0), ("", 0), ("", 0)],
+                        );
                     }
                 }
                 Ok("".to_string())
@@ -1093,7 +1315,7 @@ mod tests {
                     if cmd.contains("shell am start -a android.intent.action.DIAL") {
                         return Err("error".to_string());
                     }
-                    if cmd.contains("sh -c") {
+                    if cmd.contains("iphonesubinfo") {
                         return Err("device offline".to_string());
                     }
                 }
@@ -1114,22 +1336,24 @@ mod tests {
     use super::{build_imei_write_commands, parse_imei_input};
     use crate::features::Manufacturer;
 
-
     #[test]
     fn check_gms_all_installed() {
         crate::exec::MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _| {
                 if program == "adb" {
                     let cmd = args.join(" ");
-                    if cmd.contains("sh -c") && cmd.contains("pm list packages") {
-                        let mut res = String::new();
-                        res.push_str("package:com.google.android.gms\nB_MARKER");
-                        res.push_str("package:com.google.android.gsf\nB_MARKER");
-                        res.push_str("package:com.android.vending\nB_MARKER");
-                        res.push_str("package:com.google.android.apps.setup\nB_MARKER");
-                        res.push_str("package:com.google.android.setupwizard\nB_MARKER");
-                        res.push_str("package:com.google.android.apps.restore");
-                        return Ok(res);
+                    if cmd.contains("pm list packages") {
+                        return batch::mock_stdout(
+                            args.last().ok_or("no script")?,
+                            &[
+                                ("package:com.google.android.gms", 0),
+                                ("package:com.google.android.gsf", 0),
+                                ("package:com.android.vending", 0),
+                                ("package:com.google.android.apps.setup", 0),
+                                ("package:com.google.android.setupwizard", 0),
+                                ("package:com.google.android.apps.restore", 0),
+                            ],
+                        );
                     }
                 }
                 Ok("".to_string())
@@ -1155,15 +1379,18 @@ mod tests {
             *mock.borrow_mut() = Some(Box::new(|program, args, _| {
                 if program == "adb" {
                     let cmd = args.join(" ");
-                    if cmd.contains("sh -c") && cmd.contains("pm list packages") {
-                        let mut res = String::new();
-                        res.push_str("package:com.google.android.gms\nB_MARKER");
-                        res.push_str("\nB_MARKER"); // gsf missing
-                        res.push_str("package:com.android.vending\nB_MARKER");
-                        res.push_str("\nB_MARKER"); // setup missing
-                        res.push_str("package:com.google.android.setupwizard\nB_MARKER");
-                        res.push_str("\n"); // restore missing
-                        return Ok(res);
+                    if cmd.contains("pm list packages") {
+                        return batch::mock_stdout(
+                            args.last().ok_or("no script")?,
+                            &[
+                                ("package:com.google.android.gms", 0),
+                                ("", 0), // gsf missing
+                                ("package:com.android.vending", 0),
+                                ("", 0), // setup missing
+                                ("package:com.google.android.setupwizard", 0),
+                                ("", 0), // restore missing
+                            ],
+                        );
                     }
                 }
                 Ok("".to_string())
@@ -1251,7 +1478,6 @@ mod tests {
         assert!(commands[1].contains("AT+EGMR=1,10"));
     }
 
-
     #[test]
     fn test_open_xiaomi_mtb() {
         crate::exec::MOCK_RUN_IMPL.with(|mock| {
@@ -1274,56 +1500,306 @@ mod tests {
         });
     }
 
-    #[test]
-    fn test_restore_efs_success() {
+    struct VaultMockGuard;
+    impl Drop for VaultMockGuard {
+        fn drop(&mut self) {
+            crate::exec::MOCK_RUN_IMPL.with(|mock| {
+                *mock.borrow_mut() = None;
+            });
+            crate::features::vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    fn write_entry(
+        dir: &std::path::Path,
+        kind: crate::features::vault::BackupKind,
+        files: &[(&str, &str)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use crate::features::vault::{self, DeviceIdentity, VaultFile, VaultManifest};
+        std::fs::create_dir_all(dir)?;
+        let mut entries = Vec::new();
+        for (partition, file) in files {
+            std::fs::write(dir.join(file), b"data")?;
+            entries.push(VaultFile {
+                partition: partition.to_string(),
+                file: file.to_string(),
+                size: 4,
+                sha256: vault::sha256_file(&dir.join(file))?,
+                compression: vault::Compression::None,
+                raw_sha256: None,
+            });
+        }
+        vault::write_manifest(
+            dir,
+            &VaultManifest {
+                kind,
+                created_at: 1,
+                foem_version: crate::VERSION.to_string(),
+                device: DeviceIdentity {
+                    serial: "serial123".into(),
+                    model: "Model".into(),
+                    fingerprint: "Model|14|kona".into(),
+                    build_fingerprint: "fp".into(),
+                },
+                files: entries,
+                note: None,
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Mocked device `serial123` matching the entries written by `write_entry`.
+    fn mock_rooted_device(partition_size: &'static str) {
         crate::exec::MOCK_RUN_IMPL.with(|mock| {
-            *mock.borrow_mut() = Some(Box::new(|program, args, _| {
-                if program == "adb" {
-                    let cmd = args.join(" ");
-                    if cmd.contains("shell ls /sdcard/FOEM/efs_backup/efs.tar.gz") {
-                        return Ok("/sdcard/FOEM/efs_backup/efs.tar.gz".to_string());
-                    }
-                    if cmd.contains("shell tar -xzf /sdcard/FOEM/efs_backup/efs.tar.gz -C /") {
-                        return Ok("".to_string());
-                    }
+            *mock.borrow_mut() = Some(Box::new(move |program, args, _| {
+                assert_eq!(program, "adb");
+                let cmd = args.join(" ");
+                if cmd.contains("getprop ro.product.model") {
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("Model", 0), ("14", 0), ("kona", 0), ("fp", 0)],
+                    );
+                }
+                if cmd.contains("blockdev --getsize64") {
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[(partition_size, 0), (partition_size, 0)],
+                    );
+                }
+                if cmd.contains("dd if=") {
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("", 0), ("", 0)],
+                    );
+                }
+                if args[2] == "pull" {
+                    std::fs::write(args[4], b"snap").map_err(|e| e.to_string())?;
+                    return Ok("1 file pulled".to_string());
                 }
                 Ok("".to_string())
             }));
         });
+    }
 
-        let output = super::restore_efs("serial123");
-        assert_eq!(
-            output,
-            "EFS restore attempted from /sdcard/FOEM/efs_backup/efs.tar.gz.\nReboot required."
-        );
+    #[test]
+    fn test_backup_nv_data_pulls_into_vault() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_repair_nv_backup_vault");
+        let _ = std::fs::remove_dir_all(&root);
+        crate::features::vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = VaultMockGuard;
 
         crate::exec::MOCK_RUN_IMPL.with(|mock| {
-            *mock.borrow_mut() = None;
+            *mock.borrow_mut() = Some(Box::new(|program, args, _| {
+                assert_eq!(program, "adb");
+                let cmd = args.join(" ");
+                if cmd.contains("dd if=") {
+                    return batch::mock_reply(
+                        args.last().ok_or("no script")?,
+                        &[
+                            ("", "", 0),
+                            ("", "", 0),
+                            ("", "No such file", 1),
+                            ("", "No such file", 1),
+                        ],
+                    );
+                }
+                if args[2] == "pull" {
+                    std::fs::write(args[4], b"nv").map_err(|e| e.to_string())?;
+                    return Ok("1 file pulled".to_string());
+                }
+                if cmd.contains("getprop ro.product.model") {
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("Model", 0), ("14", 0), ("kona", 0), ("fp", 0)],
+                    );
+                }
+                Ok("".to_string())
+            }));
         });
+
+        let output = super::backup_nv_data("serial123");
+        assert!(output.contains("modemst1 -- saved (2 bytes"));
+        assert!(output.contains("modemst2 -- saved"));
+        assert!(output.contains("fsg -- not found or access denied"));
+
+        let entries = crate::features::vault::list_entries("serial123");
+        assert_eq!(entries.len(), 1);
+        let manifest = &entries[0].manifest;
+        assert_eq!(manifest.kind, crate::features::vault::BackupKind::NvData);
+        assert_eq!(manifest.device.fingerprint, "Model|14|kona");
+        assert_eq!(manifest.files.len(), 2);
+        assert!(entries[0].dir.join("modemst1.img").exists());
+
+        let _ = std::fs::remove_dir_all(&root);
+        Ok(())
     }
 
     #[test]
-    fn test_restore_efs_not_found() {
+    fn test_restore_efs_success() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_repair_restore_efs_vault");
+        let dir = root.join("entry");
+        let _ = std::fs::remove_dir_all(&root);
+        write_entry(
+            &dir,
+            crate::features::vault::BackupKind::Efs,
+            &[("efs", "efs.tar.gz")],
+        )?;
+        crate::features::vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = VaultMockGuard;
+
         crate::exec::MOCK_RUN_IMPL.with(|mock| {
             *mock.borrow_mut() = Some(Box::new(|program, args, _| {
                 if program == "adb" {
                     let cmd = args.join(" ");
-                    if cmd.contains("shell ls /sdcard/FOEM/efs_backup/efs.tar.gz") {
-                        return Err("No such file or directory".to_string());
+                    if cmd.contains("getprop ro.product.model") {
+                        return batch::mock_stdout(
+                            args.last().ok_or("no script")?,
+                            &[("Model", 0), ("14", 0), ("kona", 0), ("fp", 0)],
+                        );
+                    }
+                    if args[2] == "pull" {
+                        std::fs::write(args[4], b"snap").map_err(|e| e.to_string())?;
+                    }
+                    if args[2] == "push" {
+                        assert!(args[3].ends_with("efs.tar.gz"));
+                        assert_eq!(args[4], "/data/local/tmp/FOEM/efs_restore/efs.tar.gz");
+                    }
+                    if cmd.contains("shell tar -xzf") {
+                        assert!(cmd.contains(
+                            "shell tar -xzf /data/local/tmp/FOEM/efs_restore/efs.tar.gz -C /"
+                        ));
                     }
                 }
                 Ok("".to_string())
             }));
         });
 
-        let output = super::restore_efs("serial123");
-        assert_eq!(
-            output,
-            "No EFS backup found. Run backup first."
-        );
+        let output = super::restore_efs("serial123", &dir);
+        assert!(output.contains("Pre-restore snapshot:"));
+        assert!(output.contains(&format!(
+            "EFS restore attempted from {}.\n  Reboot required.",
+            dir.display()
+        )));
+
+        let snapshots = crate::features::vault::list_entries("serial123");
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0]
+            .manifest
+            .note
+            .as_deref()
+            .is_some_and(|n| n.starts_with("Automatic snapshot before restoring")));
+
+        let _ = std::fs::remove_dir_all(&root);
+        Ok(())
+    }
 
+    #[test]
+    fn test_restore_efs_not_found() {
+        let _guard = VaultMockGuard;
         crate::exec::MOCK_RUN_IMPL.with(|mock| {
-            *mock.borrow_mut() = None;
+            *mock.borrow_mut() = Some(Box::new(|_, _, _| Ok("".to_string())));
         });
+
+        let dir = std::env::temp_dir().join("foem_repair_restore_efs_missing");
+        let output = super::restore_efs("serial123", &dir);
+        assert!(output.starts_with("No usable EFS backup:"));
+    }
+
+    #[test]
+    fn test_restore_nv_data_rejects_efs_entry() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_repair_restore_nv_wrong_kind");
+        let _ = std::fs::remove_dir_all(&dir);
+        write_entry(
+            &dir,
+            crate::features::vault::BackupKind::Efs,
+            &[("efs", "efs.tar.gz")],
+        )?;
+
+        let output = super::restore_nv_data("serial123", &dir);
+        assert_eq!(output, "Vault entry is of type EFS, not NV data.");
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_nv_data_blocks_foreign_device() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_repair_restore_nv_foreign");
+        let _ = std::fs::remove_dir_all(&dir);
+        write_entry(
+            &dir,
+            crate::features::vault::BackupKind::NvData,
+            &[("fsg", "fsg.img")],
+        )?;
+        let _guard = VaultMockGuard;
+
+        crate::exec::MOCK_RUN_IMPL.with(|mock| {
+            *mock.borrow_mut() = Some(Box::new(|_, args, _| {
+                let cmd = args.join(" ");
+                assert!(!cmd.contains("dd if="), "nothing may be written");
+                if cmd.contains("getprop ro.product.model") {
+                    return batch::mock_stdout(
+                        args.last().ok_or("no script")?,
+                        &[("Other", 0), ("14", 0), ("mt6789", 0), ("fp", 0)],
+                    );
+                }
+                Ok("".to_string())
+            }));
+        });
+
+        let output = super::restore_nv_data("other_serial", &dir);
+        assert!(output.contains("Restore blocked:"));
+        assert!(output.contains("Backup was taken from serial serial123"));
+        assert!(output.contains("connected device is Other (mt6789)"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_nv_data_blocks_size_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_repair_restore_nv_size");
+        let _ = std::fs::remove_dir_all(&dir);
+        write_entry(
+            &dir,
+            crate::features::vault::BackupKind::NvData,
+            &[("modemst1", "modemst1.img"), ("modemst2", "modemst2.img")],
+        )?;
+        let _guard = VaultMockGuard;
+        mock_rooted_device("2097152");
+
+        let output = super::restore_nv_data("serial123", &dir);
+        assert!(output.contains("modemst1 image is 4 bytes, partition is 2097152 bytes"));
+        assert!(!output.contains("restored"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_nv_data_snapshots_before_writing() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_repair_restore_nv_snapshot");
+        let dir = root.join("entry");
+        let _ = std::fs::remove_dir_all(&root);
+        write_entry(
+            &dir,
+            crate::features::vault::BackupKind::NvData,
+            &[("modemst1", "modemst1.img"), ("modemst2", "modemst2.img")],
+        )?;
+        crate::features::vault::MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = VaultMockGuard;
+        mock_rooted_device("4");
+
+        let output = super::restore_nv_data("serial123", &dir);
+        assert!(output.contains("Pre-restore snapshot:"));
+        assert!(output.contains("modemst1 -- restored"));
+        assert!(output.contains("modemst2 -- restored"));
+
+        let snapshots = crate::features::vault::list_entries("serial123");
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].manifest.files.len(), 2);
+
+        let _ = std::fs::remove_dir_all(&root);
+        Ok(())
     }
 }
@@ -0,0 +1,583 @@
+/// Interactive serial console for AT and diagnostic ports.
+///
+/// A background reader thread splits incoming data into lines, tags
+/// unsolicited result codes (URCs) that arrive while no command is pending,
+/// and appends everything to a session log under `~/.foem/console/`.
+use super::at::{self, FinalResult};
+use super::repair::open_serial_port;
+use super::vault;
+use crate::exec;
+
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const BAUD_RATES: &[u32] = &[9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600];
+
+/// How long output after a command counts as its reply when no final
+/// result code arrives.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+impl Parity {
+    pub const ALL: &[Parity] = &[Self::None, Self::Odd, Self::Even];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Odd => "Odd",
+            Self::Even => "Even",
+        }
+    }
+
+    pub fn to_serialport(self) -> serialport::Parity {
+        match self {
+            Self::None => serialport::Parity::None,
+            Self::Odd => serialport::Parity::Odd,
+            Self::Even => serialport::Parity::Even,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+impl FlowControl {
+    pub const ALL: &[FlowControl] = &[Self::None, Self::Software, Self::Hardware];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Software => "XON/XOFF",
+            Self::Hardware => "RTS/CTS",
+        }
+    }
+
+    pub fn to_serialport(self) -> serialport::FlowControl {
+        match self {
+            Self::None => serialport::FlowControl::None,
+            Self::Software => serialport::FlowControl::Software,
+            Self::Hardware => serialport::FlowControl::Hardware,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Cr,
+    Lf,
+    CrLf,
+    None,
+}
+
+impl LineEnding {
+    pub const ALL: &[LineEnding] = &[Self::Cr, Self::Lf, Self::CrLf, Self::None];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Cr => "CR",
+            Self::Lf => "LF",
+            Self::CrLf => "CR+LF",
+            Self::None => "None",
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Cr => "\r",
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::None => "",
+        }
+    }
+}
+
+/// Serial line parameters; always 8 data bits and 1 stop bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    pub baud: u32,
+    pub parity: Parity,
+    pub flow_control: FlowControl,
+    pub line_ending: LineEnding,
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        Self {
+            baud: 115200,
+            parity: Parity::None,
+            flow_control: FlowControl::None,
+            line_ending: LineEnding::CrLf,
+        }
+    }
+}
+
+/// Names of the serial ports currently present.
+pub fn port_names() -> Vec<String> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Tx,
+    Rx,
+    Urc,
+    Info,
+}
+
+impl Direction {
+    fn tag(&self) -> &'static str {
+        match self {
+            Self::Tx => "TX ",
+            Self::Rx => "RX ",
+            Self::Urc => "URC",
+            Self::Info => "-- ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleEntry {
+    pub direction: Direction,
+    pub time_ms: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl ConsoleEntry {
+    fn new(direction: Direction, bytes: Vec<u8>) -> Self {
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            direction,
+            time_ms,
+            bytes,
+        }
+    }
+
+    /// Printable text with control and non-ASCII bytes escaped.
+    pub fn text(&self) -> String {
+        self.bytes.escape_ascii().to_string()
+    }
+
+    pub fn log_line(&self) -> String {
+        format!(
+            "[{}.{:03}] {} {}",
+            vault::utc_timestamp(self.time_ms / 1000),
+            self.time_ms % 1000,
+            self.direction.tag(),
+            self.text()
+        )
+    }
+}
+
+/// Classic 16-bytes-per-row hex dump with an ASCII column.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:08X}  {:<47}  |{}|", row * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Deadline for the reply to the last command sent as a line.
+#[derive(Debug, Default)]
+struct Awaiting(Mutex<Option<Instant>>);
+
+impl Awaiting {
+    fn start(&self, timeout: Duration) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + timeout);
+    }
+
+    /// Whether `text` belongs to the pending reply; a final result code ends it.
+    fn reply_line(&self, text: &str) -> bool {
+        let mut deadline = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match *deadline {
+            Some(until) if Instant::now() < until => {
+                if FinalResult::parse(text).is_some() {
+                    *deadline = None;
+                }
+                true
+            }
+            Some(_) => {
+                *deadline = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Splits the received byte stream into lines and classifies them.
+#[derive(Debug, Default)]
+struct LineSplitter {
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    fn push(&mut self, data: &[u8], awaiting: &Awaiting) -> Vec<ConsoleEntry> {
+        let mut entries = Vec::new();
+        for &byte in data {
+            if byte == b'\n' || byte == b'\r' {
+                if !self.partial.is_empty() {
+                    let line = std::mem::take(&mut self.partial);
+                    entries.push(classify(line, awaiting));
+                }
+            } else {
+                self.partial.push(byte);
+            }
+        }
+        entries
+    }
+
+    /// Emit data that never got a line terminator (prompts, binary output).
+    fn flush(&mut self, awaiting: &Awaiting) -> Option<ConsoleEntry> {
+        if self.partial.is_empty() {
+            return None;
+        }
+        Some(classify(std::mem::take(&mut self.partial), awaiting))
+    }
+}
+
+fn classify(line: Vec<u8>, awaiting: &Awaiting) -> ConsoleEntry {
+    let text = String::from_utf8_lossy(&line);
+    let text = text.trim();
+    let direction = if awaiting.reply_line(text) {
+        Direction::Rx
+    } else if at::is_unsolicited(text) {
+        Direction::Urc
+    } else {
+        Direction::Rx
+    };
+    ConsoleEntry::new(direction, line)
+}
+
+/// Shell-style command history with up/down navigation.
+#[derive(Debug, Default)]
+pub struct CommandHistory {
+    entries: Vec<String>,
+    cursor: Option<usize>,
+}
+
+impl CommandHistory {
+    const LIMIT: usize = 200;
+
+    pub fn push(&mut self, command: &str) {
+        self.cursor = None;
+        if command.trim().is_empty() || self.entries.last().map(String::as_str) == Some(command) {
+            return;
+        }
+        self.entries.push(command.to_string());
+        if self.entries.len() > Self::LIMIT {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn previous(&mut self) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let idx = self
+            .cursor
+            .map_or(self.entries.len() - 1, |c| c.saturating_sub(1));
+        self.cursor = Some(idx);
+        self.entries.get(idx).map(String::as_str)
+    }
+
+    /// Move towards the newest entry; `None` means back at an empty prompt.
+    pub fn next(&mut self) -> Option<&str> {
+        let idx = self.cursor? + 1;
+        if idx >= self.entries.len() {
+            self.cursor = None;
+            return None;
+        }
+        self.cursor = Some(idx);
+        self.entries.get(idx).map(String::as_str)
+    }
+}
+
+type SharedLog = Arc<Mutex<BufWriter<fs::File>>>;
+
+/// An open console: the port writer lives here, the reader on its own thread.
+pub struct ConsoleSession {
+    pub port_name: String,
+    pub log_path: PathBuf,
+    writer: Box<dyn serialport::SerialPort>,
+    entries: Arc<Mutex<Vec<ConsoleEntry>>>,
+    log: SharedLog,
+    awaiting: Arc<Awaiting>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl ConsoleSession {
+    pub fn open(port_name: &str, settings: LineSettings) -> Result<Self, String> {
+        let mut writer = open_serial_port(port_name, &settings)?;
+        writer
+            .set_timeout(Duration::from_millis(100))
+            .map_err(|e| format!("Cannot configure {}: {}", port_name, e))?;
+        let reader_port = writer
+            .try_clone()
+            .map_err(|e| format!("Cannot share {} with the reader: {}", port_name, e))?;
+
+        let dir = exec::foem_home().join("console");
+        fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+        let safe_name: String = port_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let log_path = dir.join(format!(
+            "{}-{}.log",
+            safe_name.trim_matches('_'),
+            vault::utc_timestamp(vault::now_secs())
+        ));
+        let file = fs::File::create(&log_path)
+            .map_err(|e| format!("Cannot create {}: {}", log_path.display(), e))?;
+
+        let mut session = Self {
+            port_name: port_name.to_string(),
+            log_path,
+            writer,
+            entries: Arc::new(Mutex::new(Vec::new())),
+            log: Arc::new(Mutex::new(BufWriter::new(file))),
+            awaiting: Arc::new(Awaiting::default()),
+            stop: Arc::new(AtomicBool::new(false)),
+            reader: None,
+        };
+        session.record(ConsoleEntry::new(
+            Direction::Info,
+            format!(
+                "Opened {} at {} baud, parity {}, flow {}",
+                port_name,
+                settings.baud,
+                settings.parity.label(),
+                settings.flow_control.label()
+            )
+            .into_bytes(),
+        ));
+
+        let (entries, log) = (Arc::clone(&session.entries), Arc::clone(&session.log));
+        let (awaiting, stop) = (Arc::clone(&session.awaiting), Arc::clone(&session.stop));
+        let reader =
+            std::thread::spawn(move || read_loop(reader_port, &entries, &log, &awaiting, &stop));
+        session.reader = Some(reader);
+        Ok(session)
+    }
+
+    fn record(&self, entry: ConsoleEntry) {
+        push_entry(&self.entries, &self.log, entry);
+    }
+
+    /// Send a command followed by `line_ending` and wait for its reply.
+    pub fn send_line(&mut self, text: &str, line_ending: LineEnding) -> Result<(), String> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.extend_from_slice(line_ending.suffix().as_bytes());
+        self.send_bytes(bytes)?;
+        self.awaiting.start(REPLY_TIMEOUT);
+        Ok(())
+    }
+
+    /// Send raw bytes given as hex, e.g. `7E 00 78 F0 7E`. Binary protocols
+    /// have no final result code, so no reply is awaited.
+    pub fn send_hex(&mut self, hex_text: &str) -> Result<(), String> {
+        let bytes = hex::decode(hex_text.replace([' ', '\n', '\r'], ""))
+            .map_err(|e| format!("Invalid hex: {}", e))?;
+        self.send_bytes(bytes)
+    }
+
+    fn send_bytes(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+        self.writer
+            .write_all(&bytes)
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Write to {} failed: {}", self.port_name, e))?;
+        self.record(ConsoleEntry::new(Direction::Tx, bytes));
+        Ok(())
+    }
+
+    /// Entries received since the last call.
+    pub fn drain(&self) -> Vec<ConsoleEntry> {
+        self.entries
+            .lock()
+            .map(|mut e| std::mem::take(&mut *e))
+            .unwrap_or_default()
+    }
+
+    pub fn close(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        if let Ok(mut log) = self.log.lock() {
+            let _ = log.flush();
+        }
+    }
+}
+
+impl Drop for ConsoleSession {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn push_entry(entries: &Mutex<Vec<ConsoleEntry>>, log: &SharedLog, entry: ConsoleEntry) {
+    if let Ok(mut log) = log.lock() {
+        let _ = writeln!(log, "{}", entry.log_line());
+    }
+    if let Ok(mut entries) = entries.lock() {
+        entries.push(entry);
+    }
+}
+
+fn read_loop(
+    mut port: impl Read,
+    entries: &Mutex<Vec<ConsoleEntry>>,
+    log: &SharedLog,
+    awaiting: &Awaiting,
+    stop: &AtomicBool,
+) {
+    let mut splitter = LineSplitter::default();
+    let mut buf = [0u8; 1024];
+    while !stop.load(Ordering::Relaxed) {
+        match port.read(&mut buf) {
+            Ok(0) => {}
+            Ok(n) => {
+                for entry in splitter.push(&buf[..n], awaiting) {
+                    push_entry(entries, log, entry);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                // The line went quiet: surface any unterminated output.
+                if let Some(entry) = splitter.flush(awaiting) {
+                    push_entry(entries, log, entry);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                push_entry(
+                    entries,
+                    log,
+                    ConsoleEntry::new(Direction::Info, format!("Read error: {}", e).into_bytes()),
+                );
+                break;
+            }
+        }
+    }
+    if let Ok(mut log) = log.lock() {
+        let _ = log.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_splitter_tags_urcs_outside_commands() {
+        let awaiting = Awaiting::default();
+        let mut splitter = LineSplitter::default();
+
+        let entries = splitter.push(b"\r\n+CREG: 1,\"00C3\",\"1A2B\"\r\n", &awaiting);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].direction, Direction::Urc);
+
+        // Solicited +CREG output belongs to the pending command.
+        awaiting.start(REPLY_TIMEOUT);
+        let entries = splitter.push(b"+CREG: 0,1\r\n\r\nOK", &awaiting);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].direction, Direction::Rx);
+        let entries = splitter.push(b"\r\nRING\r\n", &awaiting);
+        assert_eq!(entries[0].text(), "OK");
+        assert_eq!(entries[0].direction, Direction::Rx);
+        assert_eq!(entries[1].direction, Direction::Urc);
+    }
+
+    #[test]
+    fn test_expired_reply_wait_tags_urcs_again() {
+        let awaiting = Awaiting::default();
+        let mut splitter = LineSplitter::default();
+
+        // A command that never ends in OK/ERROR must not hide later URCs.
+        awaiting.start(Duration::ZERO);
+        let entries = splitter.push(b"RING\r\n+CMTI: \"SM\",3\r\n", &awaiting);
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.direction == Direction::Urc));
+    }
+
+    #[test]
+    fn test_line_splitter_flushes_unterminated_output() -> Result<(), Box<dyn std::error::Error>> {
+        let awaiting = Awaiting::default();
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"> ", &awaiting).is_empty());
+        let entry = splitter.flush(&awaiting).ok_or("no entry flushed")?;
+        assert_eq!(entry.bytes, b"> ");
+        assert!(splitter.flush(&awaiting).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_history_navigation() {
+        let mut history = CommandHistory::default();
+        assert_eq!(history.previous(), None);
+        history.push("AT");
+        history.push("AT+CSQ");
+        history.push("AT+CSQ");
+        history.push("  ");
+        assert_eq!(history.previous(), Some("AT+CSQ"));
+        assert_eq!(history.previous(), Some("AT"));
+        assert_eq!(history.previous(), Some("AT"));
+        assert_eq!(history.next(), Some("AT+CSQ"));
+        assert_eq!(history.next(), None);
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"AT+CGSN\r\n0123456789");
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "00000000  41 54 2B 43 47 53 4E 0D 0A 30 31 32 33 34 35 36  |AT+CGSN..0123456|"
+        );
+        assert!(lines[1].starts_with("00000010  37 38 39"));
+        assert!(lines[1].ends_with("|789|"));
+    }
+
+    #[test]
+    fn test_entry_text_escapes_control_bytes() {
+        let entry = ConsoleEntry::new(Direction::Tx, b"AT\r\n".to_vec());
+        assert_eq!(entry.text(), "AT\\r\\n");
+        assert!(entry.log_line().contains("] TX  AT\\r\\n"));
+    }
+}
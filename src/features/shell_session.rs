@@ -0,0 +1,391 @@
+/// Long-lived `adb shell` sessions, one per device.
+///
+/// Spawning `adb -s X shell` costs far more than most of the commands FOEM
+/// runs, so `adb_shell` keeps a shell open per serial and writes each command
+/// to its stdin. Output is framed by marker lines carrying a per-command
+/// nonce on both stdout and stderr, and the command's exit code travels in the
+/// closing stdout marker. A session that times out is killed, and one that has
+/// died is respawned on the next call.
+use super::batch::new_nonce;
+use crate::exec::{self, COMMAND_TIMEOUT};
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const ERROR_PREFIX: &str = "Failed to execute ADB";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Output of one command run in a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reply {
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+impl Reply {
+    /// Same contract as `exec::run`: trimmed stdout on success, otherwise
+    /// stderr, stdout or the exit status.
+    fn into_result(self) -> Result<String, String> {
+        if self.code == 0 {
+            return Ok(self.stdout.trim().to_string());
+        }
+        let stderr = self.stderr.trim();
+        let stdout = self.stdout.trim();
+        let message = if !stderr.is_empty() {
+            stderr.to_string()
+        } else if !stdout.is_empty() {
+            stdout.to_string()
+        } else {
+            format!("{ERROR_PREFIX}: command exited with status {}", self.code)
+        };
+        Err(exec::with_disconnect_hint(message))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SessionError {
+    /// The shell went away before the command started; safe to retry.
+    NotStarted(String),
+    /// The command may have run; the session must be discarded.
+    Failed(String),
+}
+
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<(Stream, String)>,
+}
+
+fn forward_lines(stream: Stream, pipe: impl Read + Send + 'static, tx: Sender<(Stream, String)>) {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buf);
+                    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+                    if tx.send((stream, line.to_string())).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+impl Session {
+    fn spawn(program: &str, args: &[&str]) -> Result<Self, String> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{ERROR_PREFIX}: {}", e))?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let (Some(stdin), Some(stdout), Some(stderr)) = (stdin, stdout, stderr) else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("{ERROR_PREFIX}: shell pipes not captured"));
+        };
+        let (tx, lines) = mpsc::channel();
+        forward_lines(Stream::Stdout, stdout, tx.clone());
+        forward_lines(Stream::Stderr, stderr, tx);
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Run `command` and wait for both of its closing markers.
+    fn execute(&mut self, command: &str, timeout: Duration) -> Result<Reply, SessionError> {
+        let n = new_nonce();
+        // The subshell keeps `cd`, variables and `exit` from leaking into the
+        // session; `</dev/null` stops commands from eating the framing input.
+        let script = format!(
+            "echo '{n} S'; echo '{n} S' >&2\n\
+             (\n{command}\n) </dev/null\n\
+             c=$?; echo; echo \"{n} E $c\"; echo >&2; echo '{n} e' >&2\n"
+        );
+        // A failed write means the shell is gone; its output is still drained
+        // below so the reason (e.g. "device not found") can be reported.
+        let write_error = self
+            .stdin
+            .write_all(script.as_bytes())
+            .and_then(|_| self.stdin.flush())
+            .err();
+
+        let prefix = format!("{n} ");
+        let deadline = Instant::now() + timeout;
+        let mut noise = Vec::new();
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let (mut out_open, mut err_open) = (false, false);
+        let mut code = None;
+        let mut err_done = false;
+        while code.is_none() || !err_done {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (stream, line) = match self.lines.recv_timeout(remaining) {
+                Ok(item) => item,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(SessionError::Failed(format!(
+                        "{ERROR_PREFIX}: timed out after {}s",
+                        timeout.as_secs()
+                    )));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    let detail = if noise.is_empty() { &stderr } else { &noise };
+                    let message = match (detail.join("\n").trim(), &write_error) {
+                        ("", Some(e)) => format!("{ERROR_PREFIX}: {}", e),
+                        ("", None) => format!("{ERROR_PREFIX}: shell session closed"),
+                        (text, _) => exec::with_disconnect_hint(text.to_string()),
+                    };
+                    return Err(if out_open || err_open {
+                        SessionError::Failed(message)
+                    } else {
+                        SessionError::NotStarted(message)
+                    });
+                }
+            };
+            // Without shell protocol v2 both streams arrive on stdout, so a
+            // marker is honoured on whichever stream it shows up.
+            match line.strip_prefix(&prefix) {
+                Some("S") => match stream {
+                    Stream::Stdout => out_open = true,
+                    Stream::Stderr => err_open = true,
+                },
+                Some("e") => err_done = true,
+                Some(marker) if marker.starts_with("E ") => {
+                    code = Some(marker[2..].trim().parse().unwrap_or(-1));
+                }
+                _ => match stream {
+                    Stream::Stdout if out_open && code.is_none() => stdout.push(line),
+                    Stream::Stderr if err_open && !err_done => stderr.push(line),
+                    _ if !out_open && !err_open => noise.push(line),
+                    _ => {}
+                },
+            }
+        }
+        Ok(Reply {
+            stdout: stdout.join("\n"),
+            stderr: stderr.join("\n"),
+            code: code.unwrap_or(-1),
+        })
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+type Slot = Arc<Mutex<Option<Session>>>;
+
+fn slot(serial: &str) -> Slot {
+    static SESSIONS: OnceLock<Mutex<HashMap<String, Slot>>> = OnceLock::new();
+    let mut sessions = SESSIONS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    sessions.entry(serial.to_string()).or_default().clone()
+}
+
+/// Run `args` in the shell session for `program` + `spawn_args`, restarting
+/// it once if it turns out to be dead before the command starts.
+fn run_in(
+    session: &mut Option<Session>,
+    program: &str,
+    spawn_args: &[&str],
+    command: &str,
+    timeout: Duration,
+) -> Result<String, String> {
+    let mut retried = false;
+    loop {
+        if !session.as_mut().is_some_and(Session::is_alive) {
+            *session = Some(Session::spawn(program, spawn_args)?);
+        }
+        let Some(live) = session.as_mut() else {
+            unreachable!("session was just spawned");
+        };
+        match live.execute(command, timeout) {
+            Ok(reply) => return reply.into_result(),
+            Err(SessionError::NotStarted(_)) if !retried => {
+                *session = None;
+                retried = true;
+            }
+            Err(SessionError::NotStarted(e) | SessionError::Failed(e)) => {
+                *session = None;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Run a shell command on `serial` through its persistent session. While the
+/// session is busy with another command, a one-off `adb shell` is used
+/// instead so long-running work never blocks quick queries.
+pub fn run(serial: &str, args: &[&str]) -> Result<String, String> {
+    let mut full_args = vec!["shell"];
+    full_args.extend_from_slice(args);
+    #[cfg(test)]
+    {
+        let mut mock_args = vec!["-s", serial];
+        mock_args.extend_from_slice(&full_args);
+        if let Some(result) = exec::mocked_run("adb", &mock_args, ERROR_PREFIX) {
+            return result;
+        }
+    }
+    let slot = slot(serial);
+    let Ok(mut session) = slot.try_lock() else {
+        return exec::run_with_serial("adb", serial, &full_args, ERROR_PREFIX);
+    };
+    // adb joins the arguments of `adb shell` with spaces; do the same.
+    run_in(
+        &mut session,
+        "adb",
+        &["-s", serial, "shell"],
+        &args.join(" "),
+        COMMAND_TIMEOUT,
+    )
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn shell() -> Result<Session, String> {
+        Session::spawn("sh", &[])
+    }
+
+    fn execute(s: &mut Session, command: &str) -> Result<Reply, String> {
+        s.execute(command, Duration::from_secs(5))
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    #[test]
+    fn test_execute_separates_streams_and_codes() -> Result<(), String> {
+        let mut s = shell()?;
+        let reply = execute(&mut s, "echo out; echo err >&2; exit 3")?;
+        assert_eq!(reply.stdout.trim(), "out");
+        assert_eq!(reply.stderr.trim(), "err");
+        assert_eq!(reply.code, 3);
+        assert_eq!(reply.into_result(), Err("err".to_string()));
+
+        // The session survives `exit` and keeps no state between commands.
+        let reply = execute(&mut s, "cd /; x=1")?;
+        assert_eq!(reply.code, 0);
+        let reply = execute(&mut s, "pwd; echo \"[$x]\"")?;
+        assert_ne!(reply.stdout.lines().next(), Some("/"));
+        assert!(reply.stdout.contains("[]"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_output_without_trailing_newline() -> Result<(), String> {
+        let mut s = shell()?;
+        let reply = execute(&mut s, "printf 'a\\nb'")?;
+        assert_eq!(reply.into_result(), Ok("a\nb".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_timeout() -> Result<(), String> {
+        let mut s = shell()?;
+        let err = s
+            .execute("sleep 5", Duration::from_millis(200))
+            .unwrap_err();
+        assert!(matches!(err, SessionError::Failed(ref m) if m.contains("timed out")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_in_restarts_dead_session() -> Result<(), String> {
+        let mut session = Some(shell()?);
+        assert_eq!(
+            run_in(&mut session, "sh", &[], "echo one", Duration::from_secs(5)),
+            Ok("one".to_string())
+        );
+        if let Some(s) = session.as_mut() {
+            let _ = s.child.kill();
+            let _ = s.child.wait();
+        }
+        assert_eq!(
+            run_in(&mut session, "sh", &[], "echo two", Duration::from_secs(5)),
+            Ok("two".to_string())
+        );
+
+        // A timed out session is dropped and replaced on the next call.
+        assert!(run_in(
+            &mut session,
+            "sh",
+            &[],
+            "sleep 5",
+            Duration::from_millis(200)
+        )
+        .is_err());
+        assert!(session.is_none());
+        assert_eq!(
+            run_in(
+                &mut session,
+                "sh",
+                &[],
+                "echo three",
+                Duration::from_secs(5)
+            ),
+            Ok("three".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_honours_command_mock() {
+        exec::MOCK_RUN_IMPL.with(|m| {
+            *m.borrow_mut() = Some(Box::new(|program, args, prefix| {
+                assert_eq!(program, "adb");
+                assert_eq!(args, ["-s", "SER", "shell", "getprop", "ro.x"]);
+                assert_eq!(prefix, ERROR_PREFIX);
+                Ok("mocked".to_string())
+            }));
+        });
+        let result = run("SER", &["getprop", "ro.x"]);
+        exec::MOCK_RUN_IMPL.with(|m| *m.borrow_mut() = None);
+        assert_eq!(result, Ok("mocked".to_string()));
+    }
+
+    #[test]
+    fn test_session_that_cannot_start_reports_stderr() {
+        let mut session = None;
+        let err = run_in(
+            &mut session,
+            "sh",
+            &["-c", "echo 'error: device not found' >&2; exit 1"],
+            "echo hi",
+            Duration::from_secs(5),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("error: device not found"), "{}", err);
+        assert!(err.contains("device disconnected"), "{}", err);
+    }
+}
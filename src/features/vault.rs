@@ -0,0 +1,535 @@
+/// Host-side backup vault for EFS, NV and modem data.
+///
+/// Backups are pulled off the device into `~/.foem/vault/<serial>/<timestamp>/`
+/// together with a JSON manifest, so a factory reset or a failed repair on the
+/// device cannot destroy the only copy.
+use super::{adb, batch};
+use crate::adaptive_engine::fingerprint;
+use crate::exec;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// What a vault entry contains; restore paths refuse entries of the wrong kind.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BackupKind {
+    Efs,
+    NvData,
+    Imei,
+    PartitionDump,
+    /// Logs collected by the modem crash analyzer; never restored.
+    CrashEvidence,
+    /// Sectors and GPTs read back in EDL before a Firehose write.
+    SectorSnapshot,
+}
+
+impl BackupKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Efs => "EFS",
+            Self::NvData => "NV data",
+            Self::Imei => "IMEI/EFS",
+            Self::PartitionDump => "Partition dump",
+            Self::CrashEvidence => "Modem crash evidence",
+            Self::SectorSnapshot => "EDL sector snapshot",
+        }
+    }
+}
+
+/// Identity of the device a backup was taken from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub serial: String,
+    pub model: String,
+    /// Adaptive-engine fingerprint (`model|release|platform`).
+    pub fingerprint: String,
+    pub build_fingerprint: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// One stored file. `size` and `sha256` always describe the file as stored.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VaultFile {
+    pub partition: String,
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(default)]
+    pub compression: Compression,
+    /// SHA-256 of the uncompressed image when the stored file is compressed.
+    #[serde(default)]
+    pub raw_sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VaultManifest {
+    pub kind: BackupKind,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub foem_version: String,
+    pub device: DeviceIdentity,
+    pub files: Vec<VaultFile>,
+    /// Free-form provenance, e.g. for automatic pre-restore snapshots.
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VaultEntry {
+    pub dir: PathBuf,
+    pub manifest: VaultManifest,
+}
+
+impl VaultEntry {
+    /// Short description used by the entry picker in the UI.
+    pub fn label(&self) -> String {
+        let partitions: Vec<&str> = self
+            .manifest
+            .files
+            .iter()
+            .map(|f| f.partition.as_str())
+            .collect();
+        format!(
+            "{} -- {} ({})",
+            utc_timestamp(self.manifest.created_at),
+            self.manifest.kind.label(),
+            partitions.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    pub static MOCK_VAULT_ROOT: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+pub fn vault_root() -> PathBuf {
+    #[cfg(test)]
+    {
+        if let Some(path) = MOCK_VAULT_ROOT.with(|m| m.borrow().clone()) {
+            return path;
+        }
+    }
+    exec::foem_home().join("vault")
+}
+
+/// Serials of network devices contain `:`, which is not a valid path character on Windows.
+pub(crate) fn sanitize_serial(serial: &str) -> String {
+    serial
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format epoch seconds as `YYYYMMDD-HHMMSS` (UTC).
+pub fn utc_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days conversion (proleptic Gregorian calendar).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        (rem % 3_600) / 60,
+        rem % 60
+    )
+}
+
+/// Read the identity values recorded in every manifest.
+pub fn device_identity(serial: &str) -> DeviceIdentity {
+    let values: Vec<String> = batch::run_commands(
+        serial,
+        &[
+            ("model", "getprop ro.product.model"),
+            ("release", "getprop ro.build.version.release"),
+            ("platform", "getprop ro.board.platform"),
+            ("build", "getprop ro.build.fingerprint"),
+        ],
+    )
+    .into_iter()
+    .map(|(_, res)| res.unwrap_or_default())
+    .collect();
+    DeviceIdentity {
+        serial: serial.to_string(),
+        model: values[0].clone(),
+        fingerprint: fingerprint(&values[0], &values[1], &values[2]),
+        build_fingerprint: values[3].clone(),
+    }
+}
+
+/// Create a fresh, empty entry directory for `serial`.
+pub fn new_entry_dir(serial: &str) -> Result<PathBuf, String> {
+    let base = vault_root()
+        .join(sanitize_serial(serial))
+        .join(utc_timestamp(now_secs()));
+    let mut dir = base.clone();
+    let mut n = 1;
+    while dir.exists() {
+        dir = PathBuf::from(format!("{}-{}", base.display(), n));
+        n += 1;
+    }
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Cannot create vault directory {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file =
+        fs::File::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Pull `remote` into the entry directory and describe it for the manifest.
+pub fn pull_file(
+    serial: &str,
+    dir: &Path,
+    remote: &str,
+    partition: &str,
+) -> Result<VaultFile, String> {
+    let file_name = remote.rsplit('/').next().unwrap_or(partition).to_string();
+    let local = dir.join(&file_name);
+    adb(serial, &["pull", remote, &local.to_string_lossy()])?;
+    let size = fs::metadata(&local)
+        .map_err(|e| format!("Pulled file missing ({}): {}", local.display(), e))?
+        .len();
+    Ok(VaultFile {
+        partition: partition.to_string(),
+        file: file_name,
+        size,
+        sha256: sha256_file(&local)?,
+        compression: Compression::None,
+        raw_sha256: None,
+    })
+}
+
+pub fn write_manifest(dir: &Path, manifest: &VaultManifest) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Cannot serialize manifest: {}", e))?;
+    fs::write(dir.join(MANIFEST_FILE), json)
+        .map_err(|e| format!("Cannot write manifest in {}: {}", dir.display(), e))
+}
+
+pub fn load_entry(dir: &Path) -> Result<VaultEntry, String> {
+    let text = fs::read_to_string(dir.join(MANIFEST_FILE))
+        .map_err(|e| format!("No readable manifest in {}: {}", dir.display(), e))?;
+    let manifest = serde_json::from_str(&text)
+        .map_err(|e| format!("Invalid manifest in {}: {}", dir.display(), e))?;
+    Ok(VaultEntry {
+        dir: dir.to_path_buf(),
+        manifest,
+    })
+}
+
+/// Check a file in an entry against the size and SHA-256 its manifest recorded.
+pub fn verify_file(dir: &Path, file: &VaultFile) -> Result<(), String> {
+    let path = dir.join(&file.file);
+    let size = fs::metadata(&path)
+        .map_err(|e| format!("{} is missing from the vault: {}", file.file, e))?
+        .len();
+    if size != file.size {
+        return Err(format!(
+            "{} is {} bytes, manifest says {}",
+            file.file, size, file.size
+        ));
+    }
+    let digest = sha256_file(&path)?;
+    if !digest.eq_ignore_ascii_case(&file.sha256) {
+        return Err(format!("{} failed its SHA-256 check", file.file));
+    }
+    Ok(())
+}
+
+/// `(model, release, platform)` of a `model|release|platform` fingerprint.
+fn fingerprint_parts(fingerprint: &str) -> (&str, &str, &str) {
+    let mut parts = fingerprint.split('|');
+    let mut next = || parts.next().unwrap_or_default();
+    (next(), next(), next())
+}
+
+/// Everything that makes `entry` unsafe to restore onto `current`.
+///
+/// Restoring modem data taken from another phone cannot be undone, so the
+/// serial, model and platform must match and every file must be intact. The
+/// Android release is not compared: an OTA changes it on the same handset,
+/// whose own backup is what a restore is usually for (see `restore_warnings`).
+pub fn restore_problems(entry: &VaultEntry, current: &DeviceIdentity) -> Vec<String> {
+    let mut problems = Vec::new();
+    let origin = &entry.manifest.device;
+    if origin.serial != current.serial {
+        problems.push(format!(
+            "Backup was taken from serial {}, connected device is {}",
+            origin.serial, current.serial
+        ));
+    }
+    let (model, _, platform) = fingerprint_parts(&origin.fingerprint);
+    let (current_model, _, current_platform) = fingerprint_parts(&current.fingerprint);
+    if (model, platform) != (current_model, current_platform) {
+        problems.push(format!(
+            "Backup was taken from {} ({}), connected device is {} ({})",
+            model, platform, current_model, current_platform
+        ));
+    }
+    if entry.manifest.files.is_empty() {
+        problems.push("Vault entry lists no files".to_string());
+    }
+    for file in &entry.manifest.files {
+        if let Err(e) = verify_file(&entry.dir, file) {
+            problems.push(e);
+        }
+    }
+    problems
+}
+
+/// Differences between `entry` and `current` that do not block a restore.
+pub fn restore_warnings(entry: &VaultEntry, current: &DeviceIdentity) -> Vec<String> {
+    let (_, release, _) = fingerprint_parts(&entry.manifest.device.fingerprint);
+    let (_, current_release, _) = fingerprint_parts(&current.fingerprint);
+    if release == current_release {
+        return Vec::new();
+    }
+    vec![format!(
+        "Backup was taken on Android {}, the device now runs {}",
+        release, current_release
+    )]
+}
+
+/// Most recent entry directory of `serial` that never got a manifest, i.e. an
+/// interrupted partition dump that can be resumed.
+pub fn latest_incomplete_dir(serial: &str) -> Option<PathBuf> {
+    let device_dir = vault_root().join(sanitize_serial(serial));
+    let mut dirs: Vec<PathBuf> = fs::read_dir(device_dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && !p.join(MANIFEST_FILE).exists())
+        .collect();
+    dirs.sort();
+    dirs.pop()
+}
+
+/// All readable entries for a device, newest first.
+pub fn list_entries(serial: &str) -> Vec<VaultEntry> {
+    let device_dir = vault_root().join(sanitize_serial(serial));
+    let mut entries: Vec<VaultEntry> = match fs::read_dir(&device_dir) {
+        Ok(rd) => rd
+            .filter_map(|e| e.ok())
+            .filter_map(|e| load_entry(&e.path()).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by(|a, b| {
+        b.manifest
+            .created_at
+            .cmp(&a.manifest.created_at)
+            .then_with(|| b.dir.cmp(&a.dir))
+    });
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct VaultRootGuard;
+    impl Drop for VaultRootGuard {
+        fn drop(&mut self) {
+            MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = None);
+        }
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(0), "19700101-000000");
+        assert_eq!(utc_timestamp(951_782_400), "20000229-000000");
+        assert_eq!(utc_timestamp(1_792_325_045), "20261018-120405");
+    }
+
+    #[test]
+    fn test_sanitize_serial() {
+        assert_eq!(sanitize_serial("ABC-123_x.y"), "ABC-123_x.y");
+        assert_eq!(sanitize_serial("192.168.1.5:5555"), "192.168.1.5_5555");
+    }
+
+    #[test]
+    fn test_manifest_roundtrip_and_listing() -> Result<(), Box<dyn std::error::Error>> {
+        let root = std::env::temp_dir().join("foem_vault_listing_test");
+        let _ = fs::remove_dir_all(&root);
+        MOCK_VAULT_ROOT.with(|m| *m.borrow_mut() = Some(root.clone()));
+        let _guard = VaultRootGuard;
+
+        let device = DeviceIdentity {
+            serial: "SER1".into(),
+            model: "Model".into(),
+            fingerprint: "Model|14|kona".into(),
+            build_fingerprint: "brand/model/device:14/ID/1:user/release-keys".into(),
+        };
+        for (created_at, kind) in [(100, BackupKind::Efs), (200, BackupKind::NvData)] {
+            let dir = new_entry_dir("SER1")?;
+            write_manifest(
+                &dir,
+                &VaultManifest {
+                    kind,
+                    created_at,
+                    foem_version: crate::VERSION.to_string(),
+                    device: device.clone(),
+                    files: vec![],
+                    note: None,
+                },
+            )?;
+        }
+
+        let entries = list_entries("SER1");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].manifest.kind, BackupKind::NvData);
+        assert_eq!(entries[1].manifest.device, device);
+        assert!(list_entries("OTHER").is_empty());
+
+        let _ = fs::remove_dir_all(&root);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pull_file_records_size_and_sha256() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_vault_pull_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        crate::exec::MOCK_RUN_IMPL.with(|mock| {
+            *mock.borrow_mut() = Some(Box::new(|program, args, _| {
+                assert_eq!(program, "adb");
+                assert_eq!(args[2], "pull");
+                fs::write(args[4], b"abc").map_err(|e| e.to_string())?;
+                Ok("1 file pulled".to_string())
+            }));
+        });
+
+        let file = pull_file("SER1", &dir, "/data/local/tmp/FOEM/modemst1.img", "modemst1");
+
+        crate::exec::MOCK_RUN_IMPL.with(|mock| {
+            *mock.borrow_mut() = None;
+        });
+
+        let file = file?;
+        assert_eq!(file.file, "modemst1.img");
+        assert_eq!(file.size, 3);
+        assert_eq!(
+            file.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_problems() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("foem_vault_restore_problems");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("fsg.img"), b"abc")?;
+
+        let device = DeviceIdentity {
+            serial: "SER1".into(),
+            model: "Model".into(),
+            fingerprint: "Model|14|kona".into(),
+            build_fingerprint: "fp".into(),
+        };
+        let mut entry = VaultEntry {
+            dir: dir.clone(),
+            manifest: VaultManifest {
+                kind: BackupKind::NvData,
+                created_at: 1,
+                foem_version: crate::VERSION.to_string(),
+                device: device.clone(),
+                files: vec![VaultFile {
+                    partition: "fsg".into(),
+                    file: "fsg.img".into(),
+                    size: 3,
+                    sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                        .into(),
+                    compression: Compression::None,
+                    raw_sha256: None,
+                }],
+                note: None,
+            },
+        };
+        assert!(restore_problems(&entry, &device).is_empty());
+
+        let other = DeviceIdentity {
+            serial: "SER2".into(),
+            fingerprint: "Other|14|mt6789".into(),
+            ..device.clone()
+        };
+        assert_eq!(restore_problems(&entry, &other).len(), 2);
+
+        // An OTA on the same handset only warrants a warning.
+        let updated = DeviceIdentity {
+            fingerprint: "Model|15|kona".into(),
+            ..device.clone()
+        };
+        assert!(restore_problems(&entry, &updated).is_empty());
+        assert_eq!(
+            restore_warnings(&entry, &updated),
+            vec!["Backup was taken on Android 14, the device now runs 15".to_string()]
+        );
+        assert!(restore_warnings(&entry, &device).is_empty());
+
+        fs::write(dir.join("fsg.img"), b"abd")?;
+        let problems = restore_problems(&entry, &device);
+        assert_eq!(problems, vec!["fsg.img failed its SHA-256 check".to_string()]);
+
+        entry.manifest.files[0].size = 4;
+        let problems = restore_problems(&entry, &device);
+        assert_eq!(problems, vec!["fsg.img is 3 bytes, manifest says 4".to_string()]);
+
+        let _ = fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}